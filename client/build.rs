@@ -24,7 +24,7 @@ fn main() {
 				cbindgen::Error::ParseSyntaxError { .. } => {
 					eprintln!("no bindings generated");
 				},
-				e => Err(e).unwrap(),
+				e => panic!("{e}"),
 			},
 			|bindings| {
 				bindings.write_to_file(out);
@@ -1,9 +1,12 @@
-#![allow(private_interfaces)]
+#![allow(clippy::missing_safety_doc, private_interfaces)]
 
-use crate::context::Context as ContextImpl;
+use crate::config::Key;
+use crate::context::{Aircraft, Context as ContextImpl, Runway, Stand};
 use crate::screen::Screen as ScreenImpl;
 use crate::{
-	ActivityState, ClickType, ConnectionState, ViewportGeo, ViewportNonGeo,
+	ActiveRunway, ActivityState, AircraftPosition, Capability, ClickType,
+	ColorVision, ConnectionState, RenderBackend, StandAssignment, ViewportGeo,
+	ViewportNonGeo,
 };
 
 use std::ffi::{c_char, CStr, CString};
@@ -110,6 +113,99 @@ pub extern "C" fn client_next_message(ctx: &mut Context) -> *const c_char {
 	}
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn client_set_aircraft(
+	ctx: &mut Context,
+	aircraft: *const AircraftPosition,
+	n: usize,
+) {
+	let aircraft = if aircraft.is_null() {
+		&[]
+	} else {
+		std::slice::from_raw_parts(aircraft, n)
+	};
+
+	ctx.ctx.set_aircraft(
+		aircraft
+			.iter()
+			.filter(|position| !position.callsign.is_null())
+			.map(|position| Aircraft {
+				callsign: CStr::from_ptr(position.callsign)
+					.to_string_lossy()
+					.into_owned(),
+				lat: position.lat,
+				lon: position.lon,
+			})
+			.collect(),
+	);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_set_active_runways(
+	ctx: &mut Context,
+	runways: *const ActiveRunway,
+	n: usize,
+) {
+	let runways = if runways.is_null() {
+		&[]
+	} else {
+		std::slice::from_raw_parts(runways, n)
+	};
+
+	ctx.ctx.set_active_runways(
+		runways
+			.iter()
+			.filter(|runway| !runway.icao.is_null() && !runway.designator.is_null())
+			.map(|runway| Runway {
+				icao: CStr::from_ptr(runway.icao).to_string_lossy().trim().into(),
+				designator: CStr::from_ptr(runway.designator)
+					.to_string_lossy()
+					.trim()
+					.into(),
+				departure: runway.departure,
+				arrival: runway.arrival,
+			})
+			.collect(),
+	);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_set_stands(
+	ctx: &mut Context,
+	stands: *const StandAssignment,
+	n: usize,
+) {
+	let stands = if stands.is_null() {
+		&[]
+	} else {
+		std::slice::from_raw_parts(stands, n)
+	};
+
+	ctx.ctx.set_stands(
+		stands
+			.iter()
+			.filter(|assignment| {
+				!assignment.callsign.is_null()
+					&& !assignment.icao.is_null()
+					&& !assignment.stand.is_null()
+			})
+			.map(|assignment| Stand {
+				callsign: CStr::from_ptr(assignment.callsign)
+					.to_string_lossy()
+					.into_owned(),
+				icao: CStr::from_ptr(assignment.icao)
+					.to_string_lossy()
+					.into_owned(),
+				stand: CStr::from_ptr(assignment.stand)
+					.to_string_lossy()
+					.trim()
+					.into(),
+			})
+			.filter(|stand| !stand.stand.is_empty())
+			.collect(),
+	);
+}
+
 #[no_mangle]
 pub extern "C" fn client_create_screen(
 	ctx: &'static mut Context,
@@ -158,6 +254,46 @@ pub unsafe extern "C" fn client_set_aerodrome(
 	}
 }
 
+#[no_mangle]
+pub extern "C" fn client_get_overlays(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.overlays())
+}
+
+#[no_mangle]
+pub extern "C" fn client_is_overlay_enabled(
+	screen: &mut Screen,
+	i: usize,
+) -> bool {
+	screen.screen.is_overlay_enabled(i)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_set_overlay(
+	screen: &mut Screen,
+	icao: *const c_char,
+	enabled: bool,
+) {
+	let Ok(icao) = CStr::from_ptr(icao).to_str() else {
+		return
+	};
+
+	screen.screen.set_overlay(icao, enabled);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_remove_overlay(
+	screen: &mut Screen,
+	icao: *const c_char,
+) {
+	let Ok(icao) = CStr::from_ptr(icao).to_str() else {
+		return
+	};
+
+	screen.screen.remove_overlay(icao);
+}
+
 #[no_mangle]
 pub extern "C" fn client_get_activity(screen: &mut Screen) -> ActivityState {
 	screen.screen.state()
@@ -188,6 +324,11 @@ pub extern "C" fn client_set_profile(screen: &mut Screen, i: usize) {
 	screen.screen.set_profile(i);
 }
 
+#[no_mangle]
+pub extern "C" fn client_select_suggested_profile(screen: &mut Screen) -> bool {
+	screen.screen.select_suggested_profile()
+}
+
 #[no_mangle]
 pub extern "C" fn client_get_presets(
 	screen: &mut Screen,
@@ -196,8 +337,136 @@ pub extern "C" fn client_get_presets(
 }
 
 #[no_mangle]
-pub extern "C" fn client_apply_preset(screen: &mut Screen, i: usize) {
-	screen.screen.apply_preset(i);
+pub extern "C" fn client_get_profile_id(screen: &mut Screen) -> *const c_char {
+	if let Some(id) = screen.screen.profile_id() {
+		let string = unsafe { CString::from_vec_unchecked(id.into_bytes()) };
+		let ptr = string.as_ptr();
+		screen.string = Some(string);
+		ptr
+	} else {
+		std::ptr::null()
+	}
+}
+
+/// Returns false if the selected profile is no longer that with the ID
+/// `profile`, or it has no preset named `preset`.
+#[no_mangle]
+pub unsafe extern "C" fn client_apply_preset(
+	screen: &mut Screen,
+	profile: *const c_char,
+	preset: *const c_char,
+) -> bool {
+	let (Ok(profile), Ok(preset)) = (
+		CStr::from_ptr(profile).to_str(),
+		CStr::from_ptr(preset).to_str(),
+	) else {
+		return false
+	};
+
+	screen.screen.apply_preset(profile, preset)
+}
+
+/// Lists each queued departure as its callsign and holding point.
+#[no_mangle]
+pub extern "C" fn client_get_departures(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.departures())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_queue_departure(
+	screen: &mut Screen,
+	callsign: *const c_char,
+	node: *const c_char,
+) -> bool {
+	let (Ok(callsign), Ok(node)) = (
+		CStr::from_ptr(callsign).to_str(),
+		CStr::from_ptr(node).to_str(),
+	) else {
+		return false
+	};
+
+	screen.screen.queue_departure(callsign, node)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_remove_departure(
+	screen: &mut Screen,
+	callsign: *const c_char,
+) {
+	let Ok(callsign) = CStr::from_ptr(callsign).to_str() else {
+		return
+	};
+
+	screen.screen.remove_departure(callsign);
+}
+
+#[no_mangle]
+pub extern "C" fn client_undo(screen: &mut Screen) -> bool {
+	screen.screen.undo()
+}
+
+#[no_mangle]
+pub extern "C" fn client_redo(screen: &mut Screen) -> bool {
+	screen.screen.redo()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_clear_block_edges(
+	screen: &mut Screen,
+	block: *const c_char,
+) -> bool {
+	let Ok(block) = CStr::from_ptr(block).to_str() else {
+		return false
+	};
+
+	screen.screen.clear_block_edges(block)
+}
+
+/// Connects to a local server if not connected.
+#[no_mangle]
+pub extern "C" fn client_toggle_simulation(screen: &mut Screen) -> bool {
+	screen.screen.toggle_simulation()
+}
+
+#[no_mangle]
+pub extern "C" fn client_restore_session(screen: &mut Screen) -> bool {
+	screen.screen.restore_session()
+}
+
+#[no_mangle]
+pub extern "C" fn client_reload_config(screen: &mut Screen) {
+	screen.screen.reload_config();
+}
+
+#[no_mangle]
+pub extern "C" fn client_take_over(screen: &mut Screen) {
+	screen.screen.take_over();
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_closures(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.closures())
+}
+
+#[no_mangle]
+pub extern "C" fn client_is_closure_active(
+	screen: &mut Screen,
+	i: usize,
+) -> bool {
+	screen.screen.is_closure_active(i)
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_closure(
+	screen: &mut Screen,
+	i: usize,
+	active: bool,
+) {
+	screen.screen.set_closure(i, active);
 }
 
 #[no_mangle]
@@ -217,6 +486,135 @@ pub extern "C" fn client_set_view(screen: &mut Screen, i: usize) {
 	screen.screen.set_view(i);
 }
 
+#[no_mangle]
+pub extern "C" fn client_handle_key(
+	screen: &mut Screen,
+	code: u32,
+	shift: bool,
+	ctrl: bool,
+) -> bool {
+	screen.screen.handle_key(Key { code, shift, ctrl })
+}
+
+/// Returns -1 if unknown.
+#[no_mangle]
+pub extern "C" fn client_get_latency(screen: &mut Screen) -> i32 {
+	screen
+		.screen
+		.latency()
+		.map(|latency| latency.min(i32::MAX as u32) as i32)
+		.unwrap_or(-1)
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_endpoint(screen: &mut Screen) -> *const c_char {
+	if let Some(endpoint) = screen.screen.endpoint() {
+		let string = unsafe { CString::from_vec_unchecked(endpoint.into_bytes()) };
+		let ptr = string.as_ptr();
+		screen.string = Some(string);
+		ptr
+	} else {
+		screen.string = None;
+		std::ptr::null()
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_status(screen: &mut Screen) -> *const c_char {
+	let string =
+		unsafe { CString::from_vec_unchecked(screen.screen.status().into_bytes()) };
+	let ptr = string.as_ptr();
+	screen.string = Some(string);
+	ptr
+}
+
+/// Returns -1 if `id` will not be reset.
+#[no_mangle]
+pub unsafe extern "C" fn client_get_reset_timer(
+	screen: &mut Screen,
+	id: *const c_char,
+) -> i32 {
+	let Ok(id) = CStr::from_ptr(id).to_str() else {
+		return -1
+	};
+
+	screen
+		.screen
+		.reset_in(id)
+		.map(|secs| secs.min(i32::MAX as u32) as i32)
+		.unwrap_or(-1)
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_timers(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.timers())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_extend_timer(
+	screen: &mut Screen,
+	id: *const c_char,
+	secs: u32,
+) -> bool {
+	let Ok(id) = CStr::from_ptr(id).to_str() else {
+		return false
+	};
+
+	screen.screen.extend_timer(id, secs)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_cancel_timer(
+	screen: &mut Screen,
+	id: *const c_char,
+) -> bool {
+	let Ok(id) = CStr::from_ptr(id).to_str() else {
+		return false
+	};
+
+	screen.screen.cancel_timer(id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn client_set_block_claim(
+	screen: &mut Screen,
+	block: *const c_char,
+	claim: bool,
+) -> bool {
+	let Ok(block) = CStr::from_ptr(block).to_str() else {
+		return false
+	};
+
+	screen.screen.set_block_claim(block, claim)
+}
+
+/// `i` indexes `client_get_controllers`.
+#[no_mangle]
+pub extern "C" fn client_request_handoff(screen: &mut Screen, i: usize) {
+	screen.screen.request_handoff(i);
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_handoff_request(
+	screen: &mut Screen,
+) -> *const c_char {
+	if let Some(requester) = screen.screen.handoff_request() {
+		let string = unsafe { CString::from_vec_unchecked(requester.into_bytes()) };
+		let ptr = string.as_ptr();
+		screen.string = Some(string);
+		ptr
+	} else {
+		std::ptr::null()
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn client_respond_handoff(screen: &mut Screen, accept: bool) {
+	screen.screen.respond_handoff(accept);
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn client_is_pilot_enabled(
 	screen: &mut Screen,
@@ -229,6 +627,124 @@ pub unsafe extern "C" fn client_is_pilot_enabled(
 	screen.screen.is_pilot_enabled(callsign)
 }
 
+#[no_mangle]
+pub extern "C" fn client_get_controllers(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.controllers())
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_observers(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.observers())
+}
+
+/// May exceed the length of `client_get_observers`.
+#[no_mangle]
+pub extern "C" fn client_get_observer_count(screen: &mut Screen) -> usize {
+	screen.screen.observer_count()
+}
+
+#[no_mangle]
+pub extern "C" fn client_supports(
+	screen: &mut Screen,
+	capability: Capability,
+) -> bool {
+	screen.screen.supports(capability)
+}
+
+/// GDI+ respects the alpha of config colours, but is slower than plain GDI.
+#[no_mangle]
+pub extern "C" fn client_set_render_backend(
+	screen: &mut Screen,
+	backend: RenderBackend,
+) {
+	screen.screen.set_render_backend(backend);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_color_vision(
+	screen: &mut Screen,
+	vision: ColorVision,
+) {
+	screen.screen.set_color_vision(vision);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_claim_viewport(screen: &mut Screen, claim: bool) {
+	screen.screen.set_claim_viewport(claim);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_aircraft_clearance(
+	screen: &mut Screen,
+	clearance: f64,
+) {
+	screen.screen.set_aircraft_clearance(clearance);
+}
+
+/// Relative to 96 DPI.
+#[no_mangle]
+pub extern "C" fn client_set_dpi_scale(screen: &mut Screen, scale: f64) {
+	screen.screen.set_dpi_scale(scale);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_click_tolerance(
+	screen: &mut Screen,
+	tolerance: f64,
+) {
+	screen.screen.set_click_tolerance(tolerance);
+}
+
+/// Both default to 1.
+#[no_mangle]
+pub extern "C" fn client_set_color_adjustment(
+	screen: &mut Screen,
+	brightness: f64,
+	contrast: f64,
+) {
+	screen.screen.set_color_adjustment(brightness, contrast);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_aircraft_labels(
+	screen: &mut Screen,
+	labels: bool,
+) {
+	screen.screen.set_aircraft_labels(labels);
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_audit_log(screen: &mut Screen, shown: bool) {
+	screen.screen.set_audit_log(shown);
+}
+
+#[no_mangle]
+pub extern "C" fn client_get_audit_log(
+	screen: &mut Screen,
+) -> *const *const c_char {
+	screen.load_strings(screen.screen.audit_log())
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_diagnostics(screen: &mut Screen, shown: bool) {
+	screen.screen.set_diagnostics(shown);
+}
+
+/// Returns a JSON object.
+#[no_mangle]
+pub extern "C" fn client_get_diagnostics(screen: &mut Screen) -> *const c_char {
+	let string = unsafe {
+		CString::from_vec_unchecked(screen.screen.diagnostics().into_bytes())
+	};
+	let ptr = string.as_ptr();
+	screen.string = Some(string);
+	ptr
+}
+
 #[repr(C)]
 pub union Viewport {
 	geo: ViewportGeo,
@@ -248,6 +764,21 @@ pub unsafe extern "C" fn client_draw_background(
 	}
 }
 
+/// Saves a PNG in the plugin directory.
+#[no_mangle]
+pub unsafe extern "C" fn client_export_png(
+	screen: &mut Screen,
+	hdc: HDC,
+	area: RECT,
+) -> bool {
+	screen.screen.export_png(hdc, area)
+}
+
+#[no_mangle]
+pub extern "C" fn client_tick_animation(screen: &mut Screen) -> bool {
+	screen.screen.tick_animation()
+}
+
 #[no_mangle]
 pub extern "C" fn client_draw_foreground(screen: &mut Screen, hdc: HDC) {
 	screen.screen.draw_foreground(hdc);
@@ -275,6 +806,16 @@ pub extern "C" fn client_get_click_regions(
 	regions.as_ptr()
 }
 
+#[no_mangle]
+pub extern "C" fn client_deselect(screen: &mut Screen) -> bool {
+	screen.screen.deselect()
+}
+
+#[no_mangle]
+pub extern "C" fn client_set_cursor(screen: &mut Screen, point: POINT) -> bool {
+	screen.screen.set_cursor(point)
+}
+
 #[no_mangle]
 pub extern "C" fn client_handle_click(
 	screen: &mut Screen,
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use chrono::{DateTime, Utc};
+
+use tracing::warn;
+
+// entries kept in memory, beyond which only the file has them
+const RECENT_LIMIT: usize = 100;
+
+/// What made a change to the lighting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+	/// the user of this client
+	Local,
+	/// another controller, by their ID
+	Remote(String),
+	/// a node or block resetting after its time
+	Timer,
+	/// an aircraft crossing a stopbar or a departure being released
+	Automation,
+}
+
+impl Display for Source {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Local => f.write_str("local"),
+			Self::Remote(controller) => f.write_str(controller),
+			Self::Timer => f.write_str("timer"),
+			Self::Automation => f.write_str("automation"),
+		}
+	}
+}
+
+pub struct Entry {
+	pub time: DateTime<Utc>,
+	pub source: Source,
+	pub change: String,
+}
+
+impl Display for Entry {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} {}: {}",
+			self.time.format("%H:%M:%SZ"),
+			self.source,
+			self.change,
+		)
+	}
+}
+
+/// Keeps the most recent entries, and appends every one to the session file.
+pub struct AuditLog {
+	path: PathBuf,
+	entries: VecDeque<Entry>,
+}
+
+impl AuditLog {
+	pub fn new(path: PathBuf) -> Self {
+		Self {
+			path,
+			entries: VecDeque::new(),
+		}
+	}
+
+	pub fn push(&mut self, source: Source, change: String) {
+		let entry = Entry {
+			time: Utc::now(),
+			source,
+			change,
+		};
+
+		if let Err(err) = self.append(&entry) {
+			warn!("failed to write audit log: {err}");
+		}
+
+		if self.entries.len() == RECENT_LIMIT {
+			self.entries.pop_front();
+		}
+		self.entries.push_back(entry);
+	}
+
+	fn append(&self, entry: &Entry) -> Result<()> {
+		if let Some(dir) = self.path.parent() {
+			std::fs::create_dir_all(dir)?;
+		}
+
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)?;
+		writeln!(
+			file,
+			"{} {}: {}",
+			entry.time.format("%FT%T%.3fZ"),
+			entry.source,
+			entry.change,
+		)?;
+
+		Ok(())
+	}
+
+	/// Oldest first.
+	pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+		self.entries.iter()
+	}
+}
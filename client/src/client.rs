@@ -1,50 +1,230 @@
-use crate::ipc::{Channel, Downstream, Upstream};
+use crate::audit::{AuditLog, Entry, Source};
+use crate::context::{Aircraft, Runway, Stand};
+use crate::ipc::{
+	Channel, Downstream, Refused, Scenery, Upstream, CAPABILITIES, IPC_VERSION,
+	PLUGIN_VERSION,
+};
+use crate::screen::contains;
+use crate::session::Session;
+use crate::simulation::distance;
 use crate::ActivityState;
 
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
 use bars_config::{
-	BlockCondition, BlockState, EdgeCondition, ElementCondition, NodeCondition,
-	ResetCondition,
+	BlockCondition, BlockState, EdgeCondition, ElementCondition, ElementKind,
+	NodeCondition, ResetCondition,
+};
+
+use bars_protocol::{
+	BlockState as IpcBlockState, Capability, Controller, Id, ObjectKind, Patch,
 };
 
-use bars_protocol::{BlockState as IpcBlockState, Patch};
+use bars_route::Graph;
+
+use serde::Serialize;
 
 use anyhow::Result;
 
+use chrono::Utc;
+
 use tracing::{debug, warn};
 
+/// How long a change by another controller is attributed to them.
+pub const ATTRIBUTION_TIME: Duration = Duration::from_secs(5);
+// operations which can be undone
+const HISTORY_LIMIT: usize = 50;
+// time after which a change the server has not confirmed is given up on
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+// multiple of the debounce after which changes are sent even if more are
+// still being made
+const PATCH_DEBOUNCE_LIMIT: u32 = 4;
+// metres from the nearest node of the block of its stand within which an
+// aircraft is led on
+const LEAD_ON_DISTANCE: f64 = 300.0;
+// interval at which the unattended preset is applied again while no
+// controller is connected
+const UNATTENDED_INTERVAL: Duration = Duration::from_secs(60);
+// interval at which the lighting of controlled aerodromes is saved
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct Client {
 	channel: Channel,
 	aerodromes: HashMap<String, Aerodrome>,
+	// directory of saved sessions, and how long after saving one is offered
+	sessions: PathBuf,
+	restore_window: Duration,
+	sessions_saved: Instant,
+	// directory of the audit logs of each session
+	audit: PathBuf,
+	// time for which changes wait for any following them
+	patch_debounce: Duration,
 }
 
 impl Client {
-	pub fn new(mut channel: Channel) -> Result<Self> {
-		channel.send(Upstream::Init)?;
+	pub fn new(
+		mut channel: Channel,
+		secret: Option<String>,
+		sessions: PathBuf,
+		audit: PathBuf,
+		restore_window: Duration,
+		patch_debounce: Duration,
+	) -> Result<Self> {
+		channel.send(Upstream::Init {
+			ipc_version: IPC_VERSION,
+			plugin_version: PLUGIN_VERSION.into(),
+			capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+		})?;
+		channel.send(Upstream::Authenticate { secret })?;
 
 		Ok(Self {
 			channel,
 			aerodromes: HashMap::new(),
+			sessions,
+			restore_window,
+			sessions_saved: Instant::now(),
+			audit,
+			patch_debounce,
 		})
 	}
 
-	pub fn disconnect(self) {}
+	pub fn disconnect(self) {
+		self.save_sessions();
+	}
+
+	// saves the lighting of each controlled aerodrome, except those with an
+	// earlier session still to be restored
+	fn save_sessions(&self) {
+		for (icao, aerodrome) in &self.aerodromes {
+			if aerodrome.state == ActivityState::Controlling
+				&& aerodrome.restorable.is_none()
+			{
+				if let Err(err) = aerodrome.session().save(&self.sessions, icao) {
+					warn!("failed to save session of {icao}: {err}");
+				}
+			}
+		}
+	}
+
+	// a session saved recently enough to be restored
+	fn load_session(&self, icao: &str) -> Option<Session> {
+		match Session::load(&self.sessions, icao) {
+			Ok(session) => {
+				session.filter(|session| session.age() < self.restore_window)
+			},
+			Err(err) => {
+				warn!("failed to load session of {icao}: {err}");
+				None
+			},
+		}
+	}
+
+	pub fn track_crossings(
+		&mut self,
+		aircraft: &[Aircraft],
+		release_delay: Duration,
+	) {
+		for aerodrome in self.aerodromes.values_mut() {
+			aerodrome.track_crossings(aircraft);
+			aerodrome.track_departures(aircraft, release_delay);
+			aerodrome.track_lead_ons(aircraft);
+		}
+	}
+
+	pub fn set_stands(&mut self, stands: &[Stand]) {
+		for (icao, aerodrome) in &mut self.aerodromes {
+			aerodrome.set_stands(
+				stands
+					.iter()
+					.filter(|stand| &stand.icao == icao)
+					.map(|stand| (stand.callsign.clone(), stand.stand.clone()))
+					.collect(),
+			);
+		}
+	}
+
+	pub fn set_active_runways(&mut self, runways: &[Runway]) -> Vec<String> {
+		let mut user_messages = Vec::new();
+
+		for (icao, aerodrome) in &mut self.aerodromes {
+			let active = |role: fn(&Runway) -> bool| {
+				let mut designators = runways
+					.iter()
+					.filter(|runway| &runway.icao == icao && role(runway))
+					.map(|runway| runway.designator.clone())
+					.collect::<Vec<_>>();
+				designators.sort();
+				designators
+			};
+
+			let departures = active(|runway| runway.departure);
+			let arrivals = active(|runway| runway.arrival);
+			if let Some(profile) = aerodrome.set_active_runways(departures, arrivals)
+			{
+				user_messages.push(format!(
+					"{icao}: profile {} suits the active runways and can be selected \
+					 with .bars profile",
+					aerodrome.config.profiles[profile].name,
+				));
+			}
+		}
+
+		user_messages
+	}
 
 	pub fn tick(&mut self) -> Result<Vec<String>> {
 		let mut user_messages = Vec::new();
 
 		while let Some(message) = self.channel.recv()? {
 			match message {
+				Downstream::Init {
+					rejected: Some(message),
+					..
+				} => return Err(Refused(message).into()),
+				Downstream::Init {
+					plugin_version,
+					capabilities,
+					..
+				} => {
+					debug!("host version {plugin_version} with {capabilities:?}");
+				},
 				Downstream::Config { data } => {
+					let path = self.audit.join(format!(
+						"{}-{}.log",
+						data.icao,
+						Utc::now().format("%Y%m%dT%H%M%SZ"),
+					));
+
 					self
 						.aerodromes
 						.entry(data.icao.clone())
-						.or_insert_with(|| Aerodrome::new(data));
+						.or_insert_with(|| Aerodrome::new(data, AuditLog::new(path)));
+				},
+				Downstream::Reload { data } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&data.icao) {
+						user_messages.push(format!("{}: config reloaded", data.icao));
+						aerodrome.reload(data);
+					}
 				},
 				Downstream::Control { icao, control } => {
+					let offer = control
+						&& self.aerodromes.get(&icao).is_some_and(|aerodrome| {
+							aerodrome.state != ActivityState::Controlling
+						});
+					let session = offer.then(|| self.load_session(&icao)).flatten();
+
 					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						if let Some(session) = session {
+							user_messages.push(format!(
+								"{icao}: lighting from {} min ago can be restored with .bars \
+								 restore",
+								session.age().as_secs() / 60,
+							));
+							aerodrome.restorable = Some(session);
+						}
+
 						aerodrome.state = if control {
 							ActivityState::Controlling
 						} else {
@@ -52,9 +232,13 @@ impl Client {
 						};
 					}
 				},
-				Downstream::Patch { icao, patch } => {
+				Downstream::Patch {
+					icao,
+					patch,
+					controller,
+				} => {
 					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
-						aerodrome.apply_patch(patch);
+						aerodrome.apply_patch(patch, controller);
 					}
 				},
 				Downstream::Aircraft { icao, aircraft } => {
@@ -62,6 +246,65 @@ impl Client {
 						aerodrome.aircraft = HashSet::from_iter(aircraft);
 					}
 				},
+				Downstream::Controllers { icao, controllers } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.controllers = controllers;
+					}
+				},
+				Downstream::Latency { icao, latency } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.latency = latency;
+						if latency.is_some() {
+							aerodrome.last_sync = Some(Instant::now());
+						}
+					}
+				},
+				Downstream::Endpoint { icao, server } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.connected_at = server.as_ref().map(|_| Instant::now());
+						aerodrome.endpoint = server;
+					}
+				},
+				Downstream::Diagnostics {
+					icao,
+					trackers,
+					reconnects,
+				} => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.trackers = trackers;
+						aerodrome.reconnects = reconnects;
+					}
+				},
+				Downstream::Presence {
+					icao,
+					observers,
+					observer_count,
+					connected,
+				} => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.observers = observers;
+						aerodrome.observer_count = observer_count;
+						aerodrome.connected = connected;
+					}
+				},
+				Downstream::Capabilities { icao, capabilities } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.capabilities = capabilities;
+					}
+				},
+				Downstream::Claims { icao, claims } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.set_claims(claims);
+					}
+				},
+				Downstream::HandoffRequest { icao, requester } => {
+					if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+						aerodrome.handoff_request = requester;
+					}
+				},
+				Downstream::Notice { icao, message } => {
+					user_messages.push(format!("{icao}: {message}"));
+				},
 				Downstream::Error {
 					icao,
 					message,
@@ -69,7 +312,7 @@ impl Client {
 				} => {
 					user_messages.push(format!(
 						"server: {icao}: {}",
-						message.as_ref().map(|s| s.as_str()).unwrap_or("error"),
+						message.as_deref().unwrap_or("error"),
 					));
 
 					if disconnect {
@@ -82,12 +325,29 @@ impl Client {
 		for (icao, aerodrome) in &mut self.aerodromes {
 			aerodrome.tick();
 
-			let (patch, scenery) = aerodrome.take_pending();
+			let window = self.restore_window;
+			aerodrome
+				.restorable
+				.take_if(|session| session.age() >= window);
+
+			if std::mem::take(&mut aerodrome.rolled_back) {
+				user_messages.push(format!(
+					"{icao}: changes not confirmed by the server were undone"
+				));
+				self.channel.send(Upstream::Resync { icao: icao.clone() })?;
+			}
+
+			if !aerodrome.is_pending_due(self.patch_debounce) {
+				continue
+			}
+
+			let (patch, preset, scenery) = aerodrome.take_pending();
 
-			if !patch.is_empty() {
+			if !patch.is_empty() || preset.is_some() {
 				self.channel.send(Upstream::Patch {
 					icao: icao.clone(),
-					patch,
+					patch: Box::new(patch),
+					preset,
 				})?;
 			}
 
@@ -99,6 +359,11 @@ impl Client {
 			}
 		}
 
+		if self.sessions_saved.elapsed() >= SESSION_SAVE_INTERVAL {
+			self.sessions_saved = Instant::now();
+			self.save_sessions();
+		}
+
 		Ok(user_messages)
 	}
 
@@ -110,6 +375,24 @@ impl Client {
 		self.channel.send(Upstream::Track { icao, track })
 	}
 
+	pub fn reload_config(&mut self, icao: String) -> Result<()> {
+		if self.aerodromes.contains_key(&icao) {
+			self.channel.send(Upstream::Reload { icao })
+		} else {
+			warn!("attempted to reload config of untracked aerodrome");
+			Ok(())
+		}
+	}
+
+	pub fn take_over(&mut self, icao: String) -> Result<()> {
+		if self.aerodromes.contains_key(&icao) {
+			self.channel.send(Upstream::TakeOver { icao })
+		} else {
+			warn!("attempted to take over untracked aerodrome");
+			Ok(())
+		}
+	}
+
 	pub fn set_controlling(&mut self, icao: String, control: bool) -> Result<()> {
 		if self.aerodromes.contains_key(&icao) {
 			self.channel.send(Upstream::Control { icao, control })
@@ -119,6 +402,50 @@ impl Client {
 		}
 	}
 
+	pub fn set_claim(
+		&mut self,
+		icao: String,
+		blocks: Vec<String>,
+		claim: bool,
+	) -> Result<()> {
+		if self.aerodromes.contains_key(&icao) {
+			self.channel.send(Upstream::Claim {
+				icao,
+				blocks,
+				claim,
+			})
+		} else {
+			warn!("attempted to claim blocks of untracked aerodrome");
+			Ok(())
+		}
+	}
+
+	pub fn request_handoff(
+		&mut self,
+		icao: String,
+		controller_id: String,
+	) -> Result<()> {
+		if self.aerodromes.contains_key(&icao) {
+			self.channel.send(Upstream::RequestHandoff {
+				icao,
+				controller_id,
+			})
+		} else {
+			warn!("attempted to request handoff of untracked aerodrome");
+			Ok(())
+		}
+	}
+
+	pub fn respond_handoff(&mut self, icao: String, accept: bool) -> Result<()> {
+		if let Some(aerodrome) = self.aerodromes.get_mut(&icao) {
+			aerodrome.handoff_request = None;
+			self.channel.send(Upstream::RespondHandoff { icao, accept })
+		} else {
+			warn!("attempted to respond to handoff of untracked aerodrome");
+			Ok(())
+		}
+	}
+
 	pub fn aerodrome(&self, icao: &String) -> Option<&Aerodrome> {
 		self.aerodromes.get(icao)
 	}
@@ -126,18 +453,95 @@ impl Client {
 	pub fn aerodrome_mut(&mut self, icao: &String) -> Option<&mut Aerodrome> {
 		self.aerodromes.get_mut(icao)
 	}
+
+	pub fn aerodromes(&self) -> impl Iterator<Item = &Aerodrome> {
+		self.aerodromes.values()
+	}
+}
+
+#[derive(Serialize)]
+pub struct Diagnostics {
+	pub server: Option<String>,
+	/// seconds since the connection was made
+	pub uptime: Option<u64>,
+	/// seconds since state or a heartbeat was last received
+	pub last_message_age: Option<u64>,
+	/// heartbeat round-trip time in milliseconds
+	pub rtt: Option<u32>,
+	pub reconnects: usize,
+	pub trackers: usize,
 }
 
 #[derive(Clone)]
 struct State<T> {
 	current: T,
 	pending: Option<T>,
+	// when the pending state was sent, from which the server has
+	// `CONFIRM_TIMEOUT` to confirm it
+	pending_at: Option<Instant>,
+	// state shown but never sent, until the server's state next arrives
+	preview: Option<T>,
 }
 
-impl<T> State<T> {
+impl<T: PartialEq> State<T> {
 	fn state(&self) -> &T {
-		self.pending.as_ref().unwrap_or(&self.current)
+		self
+			.pending
+			.as_ref()
+			.or(self.preview.as_ref())
+			.unwrap_or(&self.current)
+	}
+
+	fn set_pending(&mut self, state: T) {
+		self.pending = Some(state);
+		self.pending_at = None;
 	}
+
+	// starts waiting for confirmation of a pending state once it is sent
+	fn mark_sent(&mut self) {
+		if self.pending.is_some() && self.pending_at.is_none() {
+			self.pending_at = Some(Instant::now());
+		}
+	}
+
+	// drops a pending state sent longer ago than the server should take to
+	// confirm it, returning whether it differed from the current state
+	fn expire_pending(&mut self) -> bool {
+		if self.pending.is_none()
+			|| self
+				.pending_at
+				.is_none_or(|at| at.elapsed() < CONFIRM_TIMEOUT)
+		{
+			return false
+		}
+
+		let unconfirmed = self.is_unconfirmed();
+		self.pending = None;
+		unconfirmed
+	}
+
+	// whether a change has been sent which the server has yet to echo
+	fn is_unconfirmed(&self) -> bool {
+		self
+			.pending
+			.as_ref()
+			.is_some_and(|pending| *pending != self.current)
+	}
+}
+
+// an aircraft waiting to be released from a holding point
+struct Departure {
+	callsign: String,
+	node: usize,
+	// whether it has entered the target of the node since it was lowered
+	entered: bool,
+}
+
+// states of the nodes and blocks changed by an operation of the user, before
+// and after it
+struct Operation {
+	nodes: Vec<(usize, bool, bool)>,
+	blocks: Vec<(usize, BlockState, BlockState)>,
 }
 
 pub struct Aerodrome {
@@ -146,19 +550,49 @@ pub struct Aerodrome {
 
 	profile: usize,
 
-	node_ids: HashMap<String, usize>,
-	block_ids: HashMap<String, usize>,
+	// IDs shared with patches, by index, and the index of each
+	node_names: Vec<Id>,
+	block_names: Vec<Id>,
+	profile_names: Vec<Id>,
+	node_ids: HashMap<Id, usize>,
+	block_ids: HashMap<Id, usize>,
 
-	node_conns: Vec<[Vec<(usize, bool)>; 2]>,
-	node_blocks: Vec<[usize; 2]>,
-	children: HashMap<usize, Vec<usize>>,
+	graph: Graph,
 
 	nodes: Vec<State<bool>>,
 	blocks: Vec<State<BlockState>>,
+	// whether each closure is active, which is not shared with other clients
+	closures: Vec<bool>,
 
 	aircraft: HashSet<String>,
+	controllers: Vec<Controller>,
+	observers: Vec<String>,
+	observer_count: usize,
+	// time each controller connected, by callsign
+	connected: HashMap<String, u64>,
+	// features supported by the server
+	capabilities: Vec<Capability>,
+	// claimed blocks, and whether each is claimed by this client
+	claims: HashMap<usize, bool>,
+	latency: Option<u32>,
+	// server in use, if connected, and when it was connected to
+	endpoint: Option<String>,
+	connected_at: Option<Instant>,
+	// clients tracking the aerodrome, and times it has connected again
+	trackers: usize,
+	reconnects: usize,
+	// time state or a heartbeat was last received
+	last_sync: Option<Instant>,
+	// controller requesting a handoff from this client
+	handoff_request: Option<String>,
 
 	pending_patch: Patch,
+	pending_preset: Option<String>,
+	// when the first and last changes waiting to be sent were made
+	pending_at: Option<(Instant, Instant)>,
+	// whether unconfirmed changes have been rolled back since the state was
+	// last requested again
+	rolled_back: bool,
 	pending_nodes: Vec<usize>,
 	previous_edges: Vec<bool>,
 	node_dependencies: Vec<Vec<usize>>,
@@ -166,71 +600,116 @@ pub struct Aerodrome {
 
 	node_timers: Vec<(usize, Instant)>,
 	block_timers: Vec<(usize, Instant)>,
+	// aircraft which has entered the target of each lowered node which is lit
+	// again once crossed
+	crossings: HashMap<usize, String>,
+	undo: Vec<Operation>,
+	redo: Vec<Operation>,
+	// aircraft to be released in turn, and when the first is next to be
+	departures: VecDeque<Departure>,
+	release_at: Option<Instant>,
+	// stand assigned to each arriving aircraft, and the aircraft which have
+	// been led on to them
+	stands: HashMap<String, String>,
+	led_on: HashSet<(String, String)>,
+	// when the unattended preset was last applied, while no controller has
+	// been connected
+	unattended_at: Option<Instant>,
+	// lighting saved before the connection was lost, offered when control was
+	// taken
+	restorable: Option<Session>,
+	// runways active for departures and arrivals as last set while
+	// controlling, and the profile suggested for them
+	runways: Option<(Vec<String>, Vec<String>)>,
+	suggested_profile: Option<usize>,
+	// changes made, and what changes made now are attributed to
+	audit: AuditLog,
+	source: Source,
+	// times the config has been reloaded, by which indices may have changed
+	revision: usize,
+
+	// callsign of the other controller which last changed each node or block,
+	// and when
+	node_changes: HashMap<usize, (String, Instant)>,
+	block_changes: HashMap<usize, (String, Instant)>,
 }
 
 impl Aerodrome {
-	fn new(config: bars_config::Aerodrome) -> Self {
+	fn new(config: bars_config::Aerodrome, audit: AuditLog) -> Self {
 		let mut this = Self {
 			config,
 			state: ActivityState::None,
 			profile: 0,
+			node_names: Vec::new(),
+			block_names: Vec::new(),
+			profile_names: Vec::new(),
 			node_ids: HashMap::new(),
 			block_ids: HashMap::new(),
-			node_conns: Vec::new(),
-			node_blocks: Vec::new(),
-			children: HashMap::new(),
+			graph: Graph::default(),
 			nodes: Vec::new(),
 			blocks: Vec::new(),
+			closures: Vec::new(),
 			aircraft: HashSet::new(),
+			controllers: Vec::new(),
+			observers: Vec::new(),
+			observer_count: 0,
+			connected: HashMap::new(),
+			capabilities: Vec::new(),
+			claims: HashMap::new(),
+			latency: None,
+			endpoint: None,
+			connected_at: None,
+			trackers: 0,
+			reconnects: 0,
+			last_sync: None,
+			handoff_request: None,
 			pending_patch: Default::default(),
+			pending_preset: None,
+			pending_at: None,
+			rolled_back: false,
 			previous_edges: Vec::new(),
 			pending_nodes: Vec::new(),
 			node_dependencies: Vec::new(),
 			edge_dependencies: Vec::new(),
 			node_timers: Vec::new(),
 			block_timers: Vec::new(),
+			crossings: HashMap::new(),
+			undo: Vec::new(),
+			redo: Vec::new(),
+			departures: VecDeque::new(),
+			release_at: None,
+			stands: HashMap::new(),
+			led_on: HashSet::new(),
+			unattended_at: None,
+			restorable: None,
+			runways: None,
+			suggested_profile: None,
+			audit,
+			source: Source::Local,
+			revision: 0,
+			node_changes: HashMap::new(),
+			block_changes: HashMap::new(),
 		};
 
-		let mut borders = vec![0; this.config.nodes.len()];
-		this
-			.node_conns
-			.resize(this.config.nodes.len(), [Vec::new(), Vec::new()]);
-		this.node_blocks.resize(this.config.nodes.len(), [0; 2]);
+		this.profile_names = this
+			.config
+			.profiles
+			.iter()
+			.map(|profile| Id::from(profile.id.as_str()))
+			.collect();
 
-		for (i, node) in this.config.nodes.iter().enumerate() {
-			this.node_ids.insert(node.id.clone(), i);
+		this.graph = Graph::new(&this.config);
 
-			if let Some(parent) = node.parent {
-				this.children.entry(parent).or_default().push(i);
-			}
+		for (i, node) in this.config.nodes.iter().enumerate() {
+			let id = Id::from(node.id.as_str());
+			this.node_ids.insert(id.clone(), i);
+			this.node_names.push(id);
 		}
 
 		for (i, block) in this.config.blocks.iter().enumerate() {
-			this.block_ids.insert(block.id.clone(), i);
-
-			let conns = block
-				.nodes
-				.iter()
-				.copied()
-				.map(|node| (node, borders[node] > 0))
-				.collect::<Vec<_>>();
-
-			for node in block.nodes.iter().copied() {
-				let node_borders = &mut borders[node];
-
-				this.node_blocks[node][1] = i;
-				this.node_blocks[node][*node_borders] = i;
-
-				this.node_conns[node][*node_borders].extend(conns.iter().filter(
-					|(node_, _)| {
-						*node_ != node
-							&& !block.non_routes.contains(&(*node_, node))
-							&& !block.non_routes.contains(&(node, *node_))
-					},
-				));
-
-				*node_borders += 1;
-			}
+			let id = Id::from(block.id.as_str());
+			this.block_ids.insert(id.clone(), i);
+			this.block_names.push(id);
 		}
 
 		this
@@ -248,11 +727,153 @@ impl Aerodrome {
 			}
 		}
 
+		this.closures = this.config.closures.iter().map(|c| c.active).collect();
+
 		this.set_default_state(false);
 
 		this
 	}
 
+	// replaces the config with a changed version of it, carrying the lighting,
+	// timers and everything else over to the nodes, blocks, profiles and
+	// closures with the same IDs; those which are new are left as by default
+	fn reload(&mut self, config: bars_config::Aerodrome) {
+		let old =
+			std::mem::replace(self, Self::new(config, AuditLog::new(PathBuf::new())));
+
+		let nodes = old
+			.node_names
+			.iter()
+			.map(|id| self.node_ids.get(id).copied())
+			.collect::<Vec<_>>();
+		let blocks = old
+			.block_names
+			.iter()
+			.map(|id| self.block_ids.get(id).copied())
+			.collect::<Vec<_>>();
+		let profile = |i: usize| {
+			self
+				.profile_names
+				.iter()
+				.position(|id| *id == old.profile_names[i])
+		};
+		let block_state = |state: BlockState| match state {
+			BlockState::Route((a, b)) => {
+				Some(BlockState::Route((nodes[a]?, nodes[b]?)))
+			},
+			state => Some(state),
+		};
+
+		let current = profile(old.profile);
+		let suggested = old.suggested_profile.and_then(profile);
+
+		if let Some(i) = current {
+			self.profile = i;
+			self.set_default_state(false);
+		}
+		self.suggested_profile = suggested;
+
+		for (i, state) in old.nodes.into_iter().enumerate() {
+			if let Some(j) = nodes[i] {
+				self.nodes[j] = state;
+			}
+		}
+		for (i, state) in old.blocks.into_iter().enumerate() {
+			if let Some(j) = blocks[i] {
+				self.blocks[j] = State {
+					current: block_state(state.current).unwrap_or(BlockState::Clear),
+					pending: state.pending.and_then(block_state),
+					pending_at: state.pending_at,
+					preview: state.preview.and_then(block_state),
+				};
+			}
+		}
+		self.previous_edges = self.calculate_edges();
+
+		for (closure, active) in self.config.closures.iter().zip(&mut self.closures)
+		{
+			if let Some(i) =
+				old.config.closures.iter().position(|c| c.id == closure.id)
+			{
+				*active = old.closures[i];
+			}
+		}
+
+		self.node_timers = old
+			.node_timers
+			.into_iter()
+			.filter_map(|(i, at)| Some((nodes[i]?, at)))
+			.collect();
+		self.block_timers = old
+			.block_timers
+			.into_iter()
+			.filter_map(|(i, at)| Some((blocks[i]?, at)))
+			.collect();
+		self.crossings = old
+			.crossings
+			.into_iter()
+			.filter_map(|(i, callsign)| Some((nodes[i]?, callsign)))
+			.collect();
+		self.departures = old
+			.departures
+			.into_iter()
+			.filter_map(|departure| {
+				Some(Departure {
+					node: nodes[departure.node]?,
+					..departure
+				})
+			})
+			.collect();
+		self.claims = old
+			.claims
+			.into_iter()
+			.filter_map(|(i, own)| Some((blocks[i]?, own)))
+			.collect();
+		self.node_changes = old
+			.node_changes
+			.into_iter()
+			.filter_map(|(i, change)| Some((nodes[i]?, change)))
+			.collect();
+		self.block_changes = old
+			.block_changes
+			.into_iter()
+			.filter_map(|(i, change)| Some((blocks[i]?, change)))
+			.collect();
+		self.pending_nodes = old
+			.pending_nodes
+			.into_iter()
+			.filter_map(|i| nodes[i])
+			.collect();
+
+		// none of this refers to the config
+		self.state = old.state;
+		self.aircraft = old.aircraft;
+		self.controllers = old.controllers;
+		self.observers = old.observers;
+		self.observer_count = old.observer_count;
+		self.connected = old.connected;
+		self.capabilities = old.capabilities;
+		self.latency = old.latency;
+		self.endpoint = old.endpoint;
+		self.connected_at = old.connected_at;
+		self.trackers = old.trackers;
+		self.reconnects = old.reconnects;
+		self.last_sync = old.last_sync;
+		self.handoff_request = old.handoff_request;
+		self.pending_patch = old.pending_patch;
+		self.pending_preset = old.pending_preset;
+		self.pending_at = old.pending_at;
+		self.rolled_back = old.rolled_back;
+		self.release_at = old.release_at;
+		self.stands = old.stands;
+		self.led_on = old.led_on;
+		self.unattended_at = old.unattended_at;
+		self.restorable = old.restorable;
+		self.runways = old.runways;
+		self.audit = old.audit;
+		self.revision = old.revision + 1;
+	}
+
 	fn bs_ipc_to_conf(&self, state: IpcBlockState) -> Option<BlockState> {
 		Some(match state {
 			IpcBlockState::Clear => BlockState::Clear,
@@ -268,20 +889,36 @@ impl Aerodrome {
 			BlockState::Clear => IpcBlockState::Clear,
 			BlockState::Relax => IpcBlockState::Relax,
 			BlockState::Route((a, b)) => IpcBlockState::Route((
-				self.config.nodes[*a].id.clone(),
-				self.config.nodes[*b].id.clone(),
+				self.node_names[*a].clone(),
+				self.node_names[*b].clone(),
 			)),
 		}
 	}
 
-	fn apply_patch(&mut self, patch: Patch) {
+	fn apply_patch(&mut self, patch: Patch, controller: Option<String>) {
+		let now = Instant::now();
+		self.last_sync = Some(now);
+		self.clear_preview();
+
 		if let Some(profile) = patch.profile {
-			if let Some(i) = self.config.profiles.iter().position(|p| p.id == profile)
+			if let Some(i) =
+				self.config.profiles.iter().position(|p| *p.id == *profile)
 			{
+				if let Some(controller) = &controller {
+					let name = self.config.profiles[i].name.clone();
+					self.audit.push(
+						Source::Remote(controller.clone()),
+						format!("profile {name}"),
+					);
+				}
+
 				self.profile = i;
 
 				self.node_timers.clear();
 				self.block_timers.clear();
+				self.clear_history();
+				self.node_changes.clear();
+				self.block_changes.clear();
 			} else {
 				warn!("requested to set unknown profile");
 			}
@@ -289,6 +926,16 @@ impl Aerodrome {
 
 		for (id, state) in patch.nodes {
 			if let Some(i) = self.node_ids.get(&id).copied() {
+				if let Some(controller) = &controller {
+					if self.nodes[i].current != state {
+						self.node_changes.insert(i, (controller.clone(), now));
+						self.audit.push(
+							Source::Remote(controller.clone()),
+							self.describe_node(i, state),
+						);
+					}
+				}
+
 				self.nodes[i].current = state;
 				if self.nodes[i].pending == Some(state) {
 					self.nodes[i].pending = None;
@@ -304,6 +951,16 @@ impl Aerodrome {
 					continue
 				};
 
+				if let Some(controller) = &controller {
+					if self.blocks[i].current != state {
+						self.block_changes.insert(i, (controller.clone(), now));
+						self.audit.push(
+							Source::Remote(controller.clone()),
+							self.describe_block(i, &state),
+						);
+					}
+				}
+
 				self.blocks[i].current = state;
 				if self.blocks[i].pending == Some(state) {
 					self.blocks[i].pending = None;
@@ -317,28 +974,167 @@ impl Aerodrome {
 	fn tick(&mut self) {
 		let now = Instant::now();
 
+		self
+			.node_changes
+			.retain(|_, (_, at)| now.duration_since(*at) < ATTRIBUTION_TIME);
+		self
+			.block_changes
+			.retain(|_, (_, at)| now.duration_since(*at) < ATTRIBUTION_TIME);
+
 		while self.node_timers.first().map(|(_, time)| time < &now) == Some(true) {
 			let (node, _) = self.node_timers.remove(0);
-			self.set_node(node, true);
+			self.with_source(Source::Timer, |this| this.set_node(node, true));
 		}
 
 		while self.block_timers.first().map(|(_, time)| time < &now) == Some(true) {
 			let (block, _) = self.block_timers.remove(0);
-			self.set_block(block, BlockState::Clear);
+			self.with_source(Source::Timer, |this| {
+				this.set_block(block, BlockState::Clear)
+			});
+		}
+
+		// changes which the server has not confirmed are rolled back rather than
+		// left hiding the state as it is
+		let nodes = self.nodes.iter_mut().map(State::expire_pending);
+		let blocks = self.blocks.iter_mut().map(State::expire_pending);
+		if nodes
+			.chain(blocks)
+			.fold(false, |any, expired| any | expired)
+		{
+			warn!("rolling back unconfirmed changes");
+			self.rolled_back = true;
+		}
+
+		if self.is_unattended() {
+			if self
+				.unattended_at
+				.is_none_or(|at| now.duration_since(at) >= UNATTENDED_INTERVAL)
+			{
+				self.apply_unattended();
+				self.unattended_at = Some(now);
+			}
+		} else if self.unattended_at.take().is_some() {
+			self.clear_preview();
+		}
+
+		if self.release_at.is_some_and(|at| at < now) {
+			self.release_at = None;
+
+			if self.state == ActivityState::Controlling {
+				if let Some(departure) = self.departures.front() {
+					debug!("releasing {}", departure.callsign);
+					let node = departure.node;
+					self
+						.with_source(Source::Automation, |this| this.set_node(node, false));
+				}
+			}
+		}
+	}
+
+	// whether the state of the aerodrome is known, and no controller, this
+	// client included, is connected to it
+	fn is_unattended(&self) -> bool {
+		self.state == ActivityState::Observing
+			&& self.last_sync.is_some()
+			&& self.controllers.is_empty()
+			&& self.config.profiles[self.profile].unattended.is_some()
+	}
+
+	// previews the unattended preset of the profile, without sending it, as
+	// the lighting of an aerodrome which is not controlled
+	fn apply_unattended(&mut self) {
+		let Some(i) = self.config.profiles[self.profile].unattended else {
+			return
+		};
+		let preset = &self.config.profiles[self.profile].presets[i];
+
+		if self.unattended_at.is_none() {
+			self.audit.push(
+				Source::Automation,
+				format!("unattended preset {}", preset.name),
+			);
+		}
+
+		// explicit states take precedence over those for every other node or
+		// block
+		let mut nodes = vec![None; self.nodes.len()];
+		for (node, state) in &preset.nodes {
+			if (*node as u32) < u32::MAX {
+				nodes[*node] = Some(*state);
+			}
+		}
+		for (node, state) in &preset.nodes {
+			if *node as u32 == u32::MAX {
+				nodes.iter_mut().for_each(|n| *n = n.or(Some(*state)));
+			}
+		}
+
+		let mut blocks = vec![None; self.blocks.len()];
+		for (block, state) in &preset.blocks {
+			if (*block as u32) < u32::MAX {
+				blocks[*block] = Some(*state);
+			}
+		}
+		for (block, state) in &preset.blocks {
+			if *block as u32 == u32::MAX {
+				blocks.iter_mut().for_each(|b| *b = b.or(Some(*state)));
+			}
+		}
+
+		for (node, state) in self.nodes.iter_mut().zip(nodes) {
+			node.preview = state;
+		}
+		for (block, state) in self.blocks.iter_mut().zip(blocks) {
+			block.preview = state;
 		}
+
+		self.node_timers.clear();
+		self.block_timers.clear();
+	}
+
+	fn clear_preview(&mut self) {
+		self.nodes.iter_mut().for_each(|node| node.preview = None);
+		self
+			.blocks
+			.iter_mut()
+			.for_each(|block| block.preview = None);
+	}
+
+	// notes a change to be sent, which then waits for any following it
+	fn mark_pending(&mut self) {
+		let now = Instant::now();
+		let first = self.pending_at.map_or(now, |(first, _)| first);
+		self.pending_at = Some((first, now));
+	}
+
+	// whether the changes waiting to be sent have settled for `debounce`, or
+	// have waited long enough regardless
+	fn is_pending_due(&self, debounce: Duration) -> bool {
+		self.pending_at.is_none_or(|(first, last)| {
+			last.elapsed() >= debounce
+				|| debounce
+					.checked_mul(PATCH_DEBOUNCE_LIMIT)
+					.is_some_and(|limit| first.elapsed() >= limit)
+		})
 	}
 
-	fn take_pending(&mut self) -> (Patch, HashMap<String, bool>) {
+	fn take_pending(&mut self) -> (Patch, Option<String>, Scenery) {
+		self.pending_at = None;
+
+		self.nodes.iter_mut().for_each(State::mark_sent);
+		self.blocks.iter_mut().for_each(State::mark_sent);
+
 		let next_edges = self.calculate_edges();
 
 		let patch = std::mem::take(&mut self.pending_patch);
+		let preset = self.pending_preset.take();
 		let nodes = std::mem::take(&mut self.pending_nodes);
 		let mut scenery = HashMap::new();
 
 		if patch.profile.is_some() {
-			for element in &self.config.elements {
+			for (i, element) in self.config.elements.iter().enumerate() {
 				scenery.insert(
-					element.id.clone(),
+					i,
 					match element.condition {
 						ElementCondition::Fixed(state) => state,
 						ElementCondition::Edge(edge) => next_edges[edge],
@@ -349,10 +1145,7 @@ impl Aerodrome {
 		} else {
 			for i in nodes {
 				for element in &self.node_dependencies[i] {
-					scenery.insert(
-						self.config.elements[*element].id.clone(),
-						*self.nodes[i].state(),
-					);
+					scenery.insert(*element, *self.nodes[i].state());
 				}
 			}
 
@@ -361,7 +1154,7 @@ impl Aerodrome {
 			{
 				if prev != next {
 					for element in &self.edge_dependencies[i] {
-						scenery.insert(self.config.elements[*element].id.clone(), *next);
+						scenery.insert(*element, *next);
 					}
 				}
 			}
@@ -369,7 +1162,22 @@ impl Aerodrome {
 
 		self.previous_edges = next_edges;
 
-		(patch, scenery)
+		let scenery = scenery
+			.into_iter()
+			.map(|(i, state)| {
+				let element = &self.config.elements[i];
+				let kind = element.kind().map(|kind| match kind {
+					ElementKind::Stopbar => ObjectKind::Stopbar,
+					ElementKind::LeadOn => ObjectKind::LeadOn,
+					ElementKind::Rgl => ObjectKind::Rgl,
+					ElementKind::TaxiwaySegment => ObjectKind::TaxiwaySegment,
+				});
+
+				(element.id.clone(), (state, kind))
+			})
+			.collect();
+
+		(patch, preset, scenery)
 	}
 
 	fn calculate_edges(&self) -> Vec<bool> {
@@ -384,6 +1192,8 @@ impl Aerodrome {
 			State {
 				current: BlockState::Clear,
 				pending: None,
+				pending_at: None,
+				preview: None,
 			};
 			self.config.blocks.len()
 		];
@@ -396,19 +1206,21 @@ impl Aerodrome {
 					_ => true,
 				},
 				pending: None,
+				pending_at: None,
+				preview: None,
 			});
 		}
 
 		if patch {
 			self.pending_patch.nodes =
 				HashMap::from_iter(self.nodes.iter().enumerate().map(
-					|(node, state)| (self.config.nodes[node].id.clone(), *state.state()),
+					|(node, state)| (self.node_names[node].clone(), *state.state()),
 				));
 			self.pending_nodes = (0..self.nodes.len()).collect();
 			self.pending_patch.blocks = HashMap::from_iter(
 				self.blocks.iter().enumerate().map(|(block, state)| {
 					(
-						self.config.blocks[block].id.clone(),
+						self.block_names[block].clone(),
 						self.bs_conf_to_ipc(state.state()),
 					)
 				}),
@@ -422,12 +1234,17 @@ impl Aerodrome {
 	}
 
 	fn set_node_state(&mut self, node: usize, state: bool) {
-		self.nodes[node].pending = Some(state);
 		self
-			.pending_patch
+			.audit
+			.push(self.source.clone(), self.describe_node(node, state));
+
+		self.nodes[node].set_pending(state);
+		self
+			.pending_patch
 			.nodes
-			.insert(self.config.nodes[node].id.clone(), state);
+			.insert(self.node_names[node].clone(), state);
 		self.pending_nodes.push(node);
+		self.mark_pending();
 
 		self.node_timers.retain(|(node_, _)| node_ != &node);
 
@@ -442,12 +1259,71 @@ impl Aerodrome {
 		}
 	}
 
+	fn describe_node(&self, node: usize, state: bool) -> String {
+		let id = &self.config.nodes[node].id;
+		if state {
+			format!("node {id} lit")
+		} else {
+			format!("node {id} lowered")
+		}
+	}
+
+	fn describe_block(&self, block: usize, state: &BlockState) -> String {
+		let id = &self.config.blocks[block].id;
+		match state {
+			BlockState::Clear => format!("block {id} cleared"),
+			BlockState::Relax => format!("block {id} relaxed"),
+			BlockState::Route((a, b)) => format!(
+				"block {id} routed from {} to {}",
+				self.config.nodes[*a].id, self.config.nodes[*b].id,
+			),
+		}
+	}
+
+	// attributes the changes made by `change` to `source` rather than the user
+	fn with_source<R>(
+		&mut self,
+		source: Source,
+		change: impl FnOnce(&mut Self) -> R,
+	) -> R {
+		let previous = std::mem::replace(&mut self.source, source);
+		let result = change(self);
+		self.source = previous;
+		result
+	}
+
+	/// Oldest first.
+	pub fn audit_log(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+		self.audit.entries()
+	}
+
+	fn set_claims(&mut self, claims: HashMap<String, bool>) {
+		self.claims = claims
+			.into_iter()
+			.filter_map(|(id, own)| Some((*self.block_ids.get(id.as_str())?, own)))
+			.collect();
+	}
+
+	// whether a block is claimed by another controller, and cannot be changed
+	fn is_block_locked(&self, block: usize) -> bool {
+		self.claims.get(&block) == Some(&false)
+	}
+
 	fn set_block_state(&mut self, block: usize, state: BlockState) {
-		self.blocks[block].pending = Some(state);
-		self.pending_patch.blocks.insert(
-			self.config.blocks[block].id.clone(),
-			self.bs_conf_to_ipc(&state),
-		);
+		if self.is_block_locked(block) {
+			return
+		}
+
+		self
+			.audit
+			.push(self.source.clone(), self.describe_block(block, &state));
+
+		self.blocks[block].set_pending(state);
+		self
+			.pending_patch
+			.blocks
+			.insert(self.block_names[block].clone(), self.bs_conf_to_ipc(&state));
+		self.mark_pending();
 
 		self.block_timers.retain(|(block_, _)| block_ != &block);
 
@@ -466,6 +1342,149 @@ impl Aerodrome {
 		self.state
 	}
 
+	/// Returns the profile which suits the runways, if they have changed.
+	pub fn set_active_runways(
+		&mut self,
+		departures: Vec<String>,
+		arrivals: Vec<String>,
+	) -> Option<usize> {
+		if self.state != ActivityState::Controlling {
+			// suggested again once control is taken
+			self.runways = None;
+			self.suggested_profile = None;
+			return None
+		}
+
+		let runways = Some((departures, arrivals));
+		if self.runways == runways {
+			return None
+		}
+		self.runways = runways;
+		let (departures, arrivals) = self.runways.as_ref()?;
+
+		// the profile with the rule naming the most runways is preferred, or the
+		// first of those equally specific
+		self.suggested_profile = self
+			.config
+			.profiles
+			.iter()
+			.enumerate()
+			.filter_map(|(i, profile)| {
+				profile
+					.runways
+					.iter()
+					.filter(|rule| rule.matches(departures, arrivals))
+					.map(|rule| rule.runway_count())
+					.max()
+					.map(|count| (count, std::cmp::Reverse(i)))
+			})
+			.max()
+			.map(|(_, std::cmp::Reverse(i))| i);
+
+		self.suggested_profile()
+	}
+
+	pub fn suggested_profile(&self) -> Option<usize> {
+		self.suggested_profile.filter(|i| *i != self.profile)
+	}
+
+	pub fn node_reset_in(&self, node: usize) -> Option<Duration> {
+		Self::time_left(&self.node_timers, node)
+	}
+
+	pub fn block_reset_in(&self, block: usize) -> Option<Duration> {
+		Self::time_left(&self.block_timers, block)
+	}
+
+	pub fn timers(&self) -> Vec<(&str, Duration)> {
+		let now = Instant::now();
+		let nodes = self
+			.node_timers
+			.iter()
+			.map(|(i, at)| (self.config.nodes[*i].id.as_str(), *at));
+		let blocks = self
+			.block_timers
+			.iter()
+			.map(|(i, at)| (self.config.blocks[*i].id.as_str(), *at));
+
+		let mut timers = nodes.chain(blocks).collect::<Vec<_>>();
+		timers.sort_by_key(|(_, at)| *at);
+		timers
+			.into_iter()
+			.map(|(id, at)| (id, at.saturating_duration_since(now)))
+			.collect()
+	}
+
+	pub fn extend_node_timer(&mut self, node: usize, by: Duration) -> bool {
+		Self::extend_timer(&mut self.node_timers, node, by)
+	}
+
+	pub fn extend_block_timer(&mut self, block: usize, by: Duration) -> bool {
+		Self::extend_timer(&mut self.block_timers, block, by)
+	}
+
+	pub fn cancel_node_timer(&mut self, node: usize) -> bool {
+		Self::cancel_timer(&mut self.node_timers, node)
+	}
+
+	pub fn cancel_block_timer(&mut self, block: usize) -> bool {
+		Self::cancel_timer(&mut self.block_timers, block)
+	}
+
+	fn extend_timer(
+		timers: &mut [(usize, Instant)],
+		i: usize,
+		by: Duration,
+	) -> bool {
+		let Some((_, deadline)) = timers.iter_mut().find(|(j, _)| *j == i) else {
+			return false
+		};
+		*deadline += by;
+
+		// expiry is checked from the front, so the order must be kept
+		timers.sort_by_key(|(_, at)| *at);
+		true
+	}
+
+	fn cancel_timer(timers: &mut Vec<(usize, Instant)>, i: usize) -> bool {
+		let len = timers.len();
+		timers.retain(|(j, _)| *j != i);
+		timers.len() != len
+	}
+
+	pub fn is_node_unconfirmed(&self, node: usize) -> bool {
+		self.nodes[node].is_unconfirmed()
+	}
+
+	pub fn is_block_unconfirmed(&self, block: usize) -> bool {
+		self.blocks[block].is_unconfirmed()
+	}
+
+	pub fn node_changed_by(&self, node: usize) -> Option<(&str, Duration)> {
+		Self::changed_by(&self.node_changes, node)
+	}
+
+	pub fn block_changed_by(&self, block: usize) -> Option<(&str, Duration)> {
+		Self::changed_by(&self.block_changes, block)
+	}
+
+	fn changed_by(
+		changes: &HashMap<usize, (String, Instant)>,
+		i: usize,
+	) -> Option<(&str, Duration)> {
+		changes
+			.get(&i)
+			.map(|(controller, at)| (controller.as_str(), at.elapsed()))
+			.filter(|(_, age)| *age < ATTRIBUTION_TIME)
+	}
+
+	fn time_left(timers: &[(usize, Instant)], i: usize) -> Option<Duration> {
+		timers
+			.iter()
+			.find(|(j, _)| *j == i)
+			.map(|(_, deadline)| deadline.saturating_duration_since(Instant::now()))
+	}
+
 	pub fn profile(&self) -> usize {
 		self.profile
 	}
@@ -475,9 +1494,101 @@ impl Aerodrome {
 			return
 		}
 
-		self.profile = i;
-		self.pending_patch.profile = Some(self.config.profiles[i].id.clone());
+		let previous = std::mem::replace(&mut self.profile, i);
+
+		// nodes and blocks which behave the same in both profiles are left as
+		// they were, along with their timers, rather than reset with the rest;
+		// selecting the current profile again still resets everything
+		let (nodes, blocks) = if previous == i {
+			(Vec::new(), Vec::new())
+		} else {
+			(
+				self
+					.unchanged_nodes(previous)
+					.into_iter()
+					.map(|node| (node, *self.nodes[node].state()))
+					.collect(),
+				self
+					.unchanged_blocks(previous)
+					.into_iter()
+					.map(|block| (block, *self.blocks[block].state()))
+					.collect::<Vec<_>>(),
+			)
+		};
+
+		let mut node_timers = std::mem::take(&mut self.node_timers);
+		let mut block_timers = std::mem::take(&mut self.block_timers);
+		node_timers.retain(|(node, _)| nodes.iter().any(|(n, _)| n == node));
+		block_timers.retain(|(block, _)| blocks.iter().any(|(b, _)| b == block));
+
+		self.pending_patch.profile = Some(self.profile_names[i].clone());
+		self.mark_pending();
+		self.audit.push(
+			self.source.clone(),
+			format!("profile {}", self.config.profiles[i].name),
+		);
 		self.set_default_state(true);
+
+		for (node, state) in nodes {
+			self.nodes[node].current = state;
+			self
+				.pending_patch
+				.nodes
+				.insert(self.node_names[node].clone(), state);
+		}
+		for (block, state) in blocks {
+			self.blocks[block].current = state;
+			self
+				.pending_patch
+				.blocks
+				.insert(self.block_names[block].clone(), self.bs_conf_to_ipc(&state));
+		}
+
+		self.node_timers = node_timers;
+		self.block_timers = block_timers;
+
+		self.clear_history();
+	}
+
+	// nodes with the same condition in a previous profile as in the current one
+	fn unchanged_nodes(&self, previous: usize) -> Vec<usize> {
+		let (old, new) = (
+			&self.config.profiles[previous],
+			&self.config.profiles[self.profile],
+		);
+
+		(0..self.nodes.len())
+			.filter(|i| old.nodes[*i] == new.nodes[*i])
+			.collect()
+	}
+
+	// blocks with the same condition in a previous profile as in the current
+	// one, and whose edges are routed the same
+	fn unchanged_blocks(&self, previous: usize) -> Vec<usize> {
+		let (old, new) = (
+			&self.config.profiles[previous],
+			&self.config.profiles[self.profile],
+		);
+
+		let routes = |profile: &bars_config::Profile, block: usize| {
+			profile
+				.edges
+				.iter()
+				.enumerate()
+				.filter_map(|(i, condition)| match condition {
+					EdgeCondition::Router { block: b, routes } if *b == block => {
+						Some((i, routes.clone()))
+					},
+					_ => None,
+				})
+				.collect::<Vec<_>>()
+		};
+
+		(0..self.blocks.len())
+			.filter(|i| {
+				old.blocks[*i] == new.blocks[*i] && routes(old, *i) == routes(new, *i)
+			})
+			.collect()
 	}
 
 	pub fn apply_preset(&mut self, i: usize) {
@@ -491,13 +1602,13 @@ impl Aerodrome {
 
 		for (node, state) in &preset.nodes {
 			if (*node as u32) < u32::MAX {
-				self.nodes[*node].pending = Some(*state);
-				nodes.insert(self.config.nodes[*node].id.clone(), *state);
+				self.nodes[*node].set_pending(*state);
+				nodes.insert(self.node_names[*node].clone(), *state);
 			} else {
 				for node in 0..self.nodes.len() {
-					if !nodes.contains_key(&self.config.nodes[node].id) {
-						self.nodes[node].pending = Some(*state);
-						nodes.insert(self.config.nodes[node].id.clone(), *state);
+					if !nodes.contains_key(&self.node_names[node]) {
+						self.nodes[node].set_pending(*state);
+						nodes.insert(self.node_names[node].clone(), *state);
 					}
 				}
 			}
@@ -505,17 +1616,21 @@ impl Aerodrome {
 
 		for (block, state) in &preset.blocks {
 			if (*block as u32) < u32::MAX {
-				self.blocks[*block].pending = Some(*state);
-				blocks.insert(
-					self.config.blocks[*block].id.clone(),
-					self.bs_conf_to_ipc(state),
-				);
+				if self.is_block_locked(*block) {
+					continue
+				}
+
+				self.blocks[*block].set_pending(*state);
+				blocks
+					.insert(self.block_names[*block].clone(), self.bs_conf_to_ipc(state));
 			} else {
 				for block in 0..self.blocks.len() {
-					if !blocks.contains_key(&self.config.blocks[block].id) {
-						self.blocks[block].pending = Some(*state);
+					if !blocks.contains_key(&self.block_names[block])
+						&& !self.is_block_locked(block)
+					{
+						self.blocks[block].set_pending(*state);
 						blocks.insert(
-							self.config.blocks[block].id.clone(),
+							self.block_names[block].clone(),
 							self.bs_conf_to_ipc(state),
 						);
 					}
@@ -526,6 +1641,11 @@ impl Aerodrome {
 		self.pending_patch.nodes = nodes;
 		self.pending_nodes = preset.nodes.iter().map(|(i, _)| *i).collect();
 		self.pending_patch.blocks = blocks;
+		self.pending_preset = Some(preset.name.clone());
+		self
+			.audit
+			.push(self.source.clone(), format!("preset {}", preset.name));
+		self.mark_pending();
 
 		self.node_timers.clear();
 		self.block_timers.clear();
@@ -535,50 +1655,151 @@ impl Aerodrome {
 		&self.config
 	}
 
+	/// Indices of nodes and blocks may change between revisions.
+	pub fn revision(&self) -> usize {
+		self.revision
+	}
+
 	pub fn is_pilot_enabled(&self, callsign: &str) -> bool {
 		self.aircraft.contains(callsign)
 	}
 
+	pub fn controllers(&self) -> &[Controller] {
+		&self.controllers
+	}
+
+	pub fn observers(&self) -> &[String] {
+		&self.observers
+	}
+
+	pub fn observer_count(&self) -> usize {
+		self.observer_count
+	}
+
+	pub fn connected(&self, callsign: &str) -> Option<u64> {
+		self.connected.get(callsign).copied()
+	}
+
+	pub fn latency(&self) -> Option<u32> {
+		self.latency
+	}
+
+	pub fn endpoint(&self) -> Option<&str> {
+		self.endpoint.as_deref()
+	}
+
+	pub fn sync_age(&self) -> Option<Duration> {
+		self.last_sync.map(|at| at.elapsed())
+	}
+
+	pub fn diagnostics(&self) -> Diagnostics {
+		Diagnostics {
+			server: self.endpoint.clone(),
+			uptime: self.connected_at.map(|at| at.elapsed().as_secs()),
+			last_message_age: self.sync_age().map(|age| age.as_secs()),
+			rtt: self.latency,
+			reconnects: self.reconnects,
+			trackers: self.trackers,
+		}
+	}
+
+	pub fn supports(&self, capability: Capability) -> bool {
+		self.capabilities.contains(&capability)
+	}
+
+	pub fn handoff_request(&self) -> Option<&str> {
+		self.handoff_request.as_deref()
+	}
+
 	pub fn node_state(&self, node: usize) -> bool {
 		match self.config.profiles[self.profile].nodes[node] {
 			NodeCondition::Fixed { state } => state,
 			NodeCondition::Direct { .. } => *self.nodes[node].state(),
-			NodeCondition::Router => {
-				let blocks = &self.node_blocks[node];
-				blocks
-					.iter()
-					.any(|block| match self.blocks[*block].state() {
-						BlockState::Clear => true,
-						BlockState::Relax => false,
-						BlockState::Route((a, b)) => *a != node && *b != node,
-					})
-			},
+			NodeCondition::Router => self.graph.blocks(node).iter().any(|block| {
+				match self.blocks[*block].state() {
+					BlockState::Clear => true,
+					BlockState::Relax => false,
+					BlockState::Route((a, b)) => *a != node && *b != node,
+				}
+			}),
 		}
 	}
 
-	fn route_candidates(&self, block: usize) -> Vec<(usize, usize)> {
-		let BlockState::Route((ap, bp)) = *self.blocks[block].state() else {
-			return vec![]
-		};
+	pub fn block_state(&self, block: usize) -> BlockState {
+		*self.blocks[block].state()
+	}
+
+	pub fn is_closure_active(&self, closure: usize) -> bool {
+		self.closures.get(closure).copied().unwrap_or_default()
+	}
+
+	pub fn set_closure(&mut self, closure: usize, active: bool) {
+		if let Some(state) = self.closures.get_mut(closure) {
+			*state = active;
+		}
+	}
 
-		let mut routes = Vec::new();
+	// whether a route through a block between two of its nodes would pass along
+	// an edge of an active closure
+	fn is_route_closed(&self, block: usize, (a, b): (usize, usize)) -> bool {
+		let edges = &self.config.profiles[self.profile].edges;
 
-		let ao = vec![ap];
-		let bo = vec![bp];
-		let ac = self.children.get(&ap).unwrap_or(&ao);
-		let bc = self.children.get(&bp).unwrap_or(&bo);
+		self
+			.config
+			.closures
+			.iter()
+			.zip(&self.closures)
+			.filter(|(_, active)| **active)
+			.flat_map(|(closure, _)| &closure.edges)
+			.any(|edge| match &edges[*edge] {
+				EdgeCondition::Router { block: b_, routes } => {
+					*b_ == block && (routes.contains(&(a, b)) || routes.contains(&(b, a)))
+				},
+				_ => false,
+			})
+	}
+
+	pub fn block_edges(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+		let conditions = &self.config.profiles[self.profile].edges;
+		self.config.blocks[block]
+			.edges
+			.iter()
+			.copied()
+			.filter(move |edge| {
+				matches!(conditions[*edge], EdgeCondition::Router { block: b, .. } if b == block)
+			})
+	}
+
+	pub fn clear_block_edges(&mut self, block: usize) {
+		if block >= self.blocks.len() {
+			return
+		}
 
-		let non_routes = &self.config.blocks[block].non_routes;
+		self.set_block(block, BlockState::Clear);
 
-		for a in ac.iter().copied() {
-			for b in bc.iter().copied() {
-				if !non_routes.contains(&(a, b)) && !non_routes.contains(&(b, a)) {
-					routes.push((a, b));
+		let nodes = self.config.blocks[block]
+			.edges
+			.iter()
+			.filter_map(|edge| {
+				match self.config.profiles[self.profile].edges[*edge] {
+					EdgeCondition::Direct { node } => Some(node),
+					_ => None,
 				}
+			})
+			.collect::<HashSet<_>>();
+		for node in nodes {
+			if !*self.nodes[node].state() {
+				self.set_node(node, true);
 			}
 		}
+	}
+
+	fn route_candidates(&self, block: usize) -> Vec<(usize, usize)> {
+		let BlockState::Route((ap, bp)) = *self.blocks[block].state() else {
+			return vec![]
+		};
 
-		routes
+		self.graph.candidates(&self.config.blocks[block], (ap, bp))
 	}
 
 	pub fn edge_state(&self, edge: usize) -> bool {
@@ -606,7 +1827,7 @@ impl Aerodrome {
 						let mut matches = (HashSet::new(), HashSet::new());
 
 						let ao = vec![ap];
-						let ac = self.children.get(&ap).unwrap_or(&ao);
+						let ac = self.graph.children(ap).unwrap_or(&ao);
 						for (a, b) in routes.iter().copied() {
 							let (a, b) = if ac.contains(&a) { (a, b) } else { (b, a) };
 
@@ -620,7 +1841,7 @@ impl Aerodrome {
 						);
 
 						for (parent, cands) in [(ap, &mut cands.0), (bp, &mut cands.1)] {
-							let [b1, b2] = self.node_blocks[parent];
+							let [b1, b2] = self.graph.blocks(parent);
 							let adjacent = if b1 != block { b1 } else { b2 };
 
 							match *self.blocks[adjacent].state() {
@@ -668,94 +1889,282 @@ impl Aerodrome {
 						self.config.profiles[self.profile].nodes[**node]
 							== NodeCondition::Fixed { state: false }
 					})
-					.flat_map(|node| self.node_blocks[*node]),
+					.flat_map(|node| self.graph.blocks(*node)),
 			);
 		}
 	}
 
-	pub fn set_route(&mut self, (orgn, dest): (usize, usize)) {
-		if self.config.profiles[self.profile].nodes[orgn] != NodeCondition::Router
-			|| self.config.profiles[self.profile].nodes[dest] != NodeCondition::Router
-		{
-			return
+	/// Returns the blocks along the route, with the nodes each is routed
+	/// between.
+	pub fn find_route(
+		&self,
+		(orgn, dest): (usize, usize),
+		limit: usize,
+	) -> Option<Vec<(usize, (usize, usize))>> {
+		let route = self
+			.graph
+			.find_route(
+				&self.config.profiles[self.profile],
+				(orgn, dest),
+				|block, pair| !self.is_route_closed(block, pair),
+			)
+			.inspect_err(|err| debug!("routing error: {err:?}"))
+			.ok()?;
+
+		if route.iter().any(|(block, _)| self.is_block_locked(*block)) {
+			debug!("route passes through a claimed block");
+			return None
 		}
 
-		let mut nodes = VecDeque::from([(orgn, false, 0), (orgn, true, 0)]);
-		let mut visited = HashSet::from([(orgn, false), (orgn, true)]);
-		let mut chain = HashMap::new();
-		let mut list: Option<Vec<(usize, bool)>> = None;
-		let mut revisited = HashSet::new();
+		if route.len() > limit {
+			debug!("route passes through more than {limit} blocks");
+			return None
+		}
 
-		while let Some((node, direction, distance)) = nodes.pop_front() {
-			let condition = self.config.profiles[self.profile].nodes[node];
-			if condition == (NodeCondition::Fixed { state: true }) {
-				continue
+		Some(route)
+	}
+
+	pub fn set_route(
+		&mut self,
+		ends: (usize, usize),
+		limit: usize,
+	) -> Vec<usize> {
+		let route = self.find_route(ends, limit).unwrap_or_default();
+		for (block, route) in &route {
+			self.set_block_state(*block, BlockState::Route(*route));
+		}
+
+		route.into_iter().map(|(block, _)| block).collect()
+	}
+
+	/// Records the states changed, so that the change can be undone.
+	pub fn record<R>(&mut self, change: impl FnOnce(&mut Self) -> R) -> R {
+		let nodes = self
+			.nodes
+			.iter()
+			.map(|node| *node.state())
+			.collect::<Vec<_>>();
+		let blocks = self
+			.blocks
+			.iter()
+			.map(|block| *block.state())
+			.collect::<Vec<_>>();
+
+		let result = change(self);
+
+		let operation = Operation {
+			nodes: nodes
+				.into_iter()
+				.enumerate()
+				.filter_map(|(i, before)| {
+					let after = *self.nodes[i].state();
+					(before != after).then_some((i, before, after))
+				})
+				.collect(),
+			blocks: blocks
+				.into_iter()
+				.enumerate()
+				.filter_map(|(i, before)| {
+					let after = *self.blocks[i].state();
+					(before != after).then_some((i, before, after))
+				})
+				.collect(),
+		};
+
+		if !operation.nodes.is_empty() || !operation.blocks.is_empty() {
+			if self.undo.len() == HISTORY_LIMIT {
+				self.undo.remove(0);
 			}
+			self.undo.push(operation);
+			self.redo.clear();
+		}
 
-			let transparent = condition == NodeCondition::Fixed { state: false };
+		result
+	}
 
-			if node == dest {
-				if list.is_none() {
-					let mut prev = Some((node, direction));
-					let list = list.get_or_insert_default();
+	pub fn undo(&mut self) -> bool {
+		let Some(operation) = self.undo.pop() else {
+			return false
+		};
 
-					let mut i = 0;
+		self.revert(&operation, true);
+		self.redo.push(operation);
+		true
+	}
 
-					while let Some(item) = prev {
-						i += 1;
-						list.push(item);
-						prev = chain.get(&item).copied();
+	pub fn redo(&mut self) -> bool {
+		let Some(operation) = self.redo.pop() else {
+			return false
+		};
 
-						if i > 1000 {
-							warn!("overflow {chain:?} {visited:?} {nodes:?}");
-							return
-						}
-					}
+		self.revert(&operation, false);
+		self.undo.push(operation);
+		true
+	}
 
-					if distance > 1 {
-						continue
-					} else {
-						break
-					}
-				} else {
-					debug!("routing error");
-					return
-				}
+	// sets the states of an operation from before it if `back`, otherwise after,
+	// leaving those which have since been changed by anything else
+	fn revert(&mut self, operation: &Operation, back: bool) {
+		for &(node, before, after) in &operation.nodes {
+			let (from, to) = if back {
+				(after, before)
+			} else {
+				(before, after)
+			};
+			if *self.nodes[node].state() == from {
+				self.set_node_state(node, to);
+			}
+		}
+
+		for &(block, before, after) in &operation.blocks {
+			let (from, to) = if back {
+				(after, before)
+			} else {
+				(before, after)
+			};
+			if *self.blocks[block].state() == from {
+				self.set_block_state(block, to);
 			}
+		}
+	}
+
+	pub fn session(&self) -> Session {
+		let now = Instant::now();
+		let left = |at: &Instant| at.saturating_duration_since(now).as_secs_f64();
+
+		Session {
+			saved: Utc::now().timestamp_millis(),
+			profile: self.profile_names[self.profile].clone(),
+			nodes: (0..self.nodes.len())
+				.map(|i| (self.node_names[i].clone(), *self.nodes[i].state()))
+				.collect(),
+			blocks: (0..self.blocks.len())
+				.map(|i| {
+					let state = self.bs_conf_to_ipc(self.blocks[i].state());
+					(self.block_names[i].clone(), state)
+				})
+				.collect(),
+			node_timers: self
+				.node_timers
+				.iter()
+				.map(|(i, at)| (self.node_names[*i].clone(), left(at)))
+				.collect(),
+			block_timers: self
+				.block_timers
+				.iter()
+				.map(|(i, at)| (self.block_names[*i].clone(), left(at)))
+				.collect(),
+		}
+	}
 
-			for (next_node, next_dir) in &self.node_conns[node][direction as usize] {
-				let next_key = (*next_node, !next_dir);
-				let next = (*next_node, !next_dir, distance + !transparent as usize);
+	pub fn restore_session(&mut self) -> bool {
+		let Some(session) = self.restorable.take() else {
+			return false
+		};
+		let elapsed = session.age().as_secs_f64();
 
-				if visited.insert(next_key) {
-					chain.insert(next_key, (node, direction));
-					if transparent {
-						nodes.push_front(next);
-					} else {
-						nodes.push_back(next);
+		if let Some(i) = self
+			.config
+			.profiles
+			.iter()
+			.position(|profile| *profile.id == *session.profile)
+		{
+			if i != self.profile {
+				self.set_profile(i);
+			}
+		} else {
+			warn!("session has unknown profile {}", session.profile);
+		}
+
+		for (id, state) in &session.nodes {
+			if let Some(node) = self.node_ids.get(id).copied() {
+				if let NodeCondition::Direct { .. } =
+					self.config.profiles[self.profile].nodes[node]
+				{
+					if *self.nodes[node].state() != *state {
+						self.set_node_state(node, *state);
 					}
-				} else {
-					revisited.insert(next_key);
 				}
 			}
 		}
 
-		if let Some(list) = list {
-			if list[..list.len() - 1]
-				.iter()
-				.any(|key| revisited.contains(key))
-			{
-				debug!("routing error");
-				return
+		for (id, state) in session.blocks {
+			let Some(block) = self.block_ids.get(&id).copied() else {
+				continue
+			};
+			let Some(state) = self.bs_ipc_to_conf(state) else {
+				continue
+			};
+
+			if *self.blocks[block].state() != state {
+				self.set_block_state(block, state);
 			}
+		}
 
-			for pair in list.windows(2) {
-				let [(node2, _), (node1, direction1)] = pair else {
-					unreachable!()
-				};
+		// timers run on from when the session was saved, so any which would
+		// have expired since reset straight away
+		let now = Instant::now();
+		let deadline =
+			|secs: &f64| now + Duration::from_secs_f64((secs - elapsed).max(0.0));
+
+		self.node_timers = session
+			.node_timers
+			.iter()
+			.filter_map(|(id, secs)| Some((*self.node_ids.get(id)?, deadline(secs))))
+			.collect();
+		self.node_timers.sort_by_key(|(_, at)| *at);
+
+		self.block_timers = session
+			.block_timers
+			.iter()
+			.filter_map(|(id, secs)| Some((*self.block_ids.get(id)?, deadline(secs))))
+			.collect();
+		self.block_timers.sort_by_key(|(_, at)| *at);
+
+		self.clear_history();
+
+		true
+	}
+
+	// the recorded states are not meaningful in another profile
+	fn clear_history(&mut self) {
+		self.undo.clear();
+		self.redo.clear();
+	}
 
-				let block = self.node_blocks[*node1][*direction1 as usize];
-				self.set_block_state(block, BlockState::Route((*node1, *node2)));
+	pub fn track_crossings(&mut self, aircraft: &[Aircraft]) {
+		if self.state != ActivityState::Controlling {
+			self.crossings.clear();
+			return
+		}
+
+		for node in 0..self.nodes.len() {
+			if !self.config.nodes[node].reset_on_crossing || *self.nodes[node].state()
+			{
+				self.crossings.remove(&node);
+				continue
+			}
+
+			match self.crossings.get(&node) {
+				None => {
+					if let Some(aircraft) =
+						aircraft.iter().find(|a| self.is_in_target(node, a))
+					{
+						self.crossings.insert(node, aircraft.callsign.clone());
+					}
+				},
+				Some(callsign) => {
+					if !aircraft
+						.iter()
+						.any(|a| a.callsign == *callsign && self.is_in_target(node, a))
+					{
+						debug!("{callsign} crossed {}", self.config.nodes[node].id);
+						self.crossings.remove(&node);
+						self.with_source(Source::Automation, |this| {
+							this.set_node(node, true)
+						});
+					}
+				},
 			}
 		}
 	}
@@ -765,10 +2174,192 @@ impl Aerodrome {
 			return
 		}
 
-		if let NodeCondition::Direct { .. } =
-			self.config.profiles[self.profile].nodes[node]
-		{
-			self.set_node_state(node, state);
+		let children = match self.config.nodes[node].gang {
+			true => self.graph.children(node).cloned().unwrap_or_default(),
+			false => Vec::new(),
+		};
+
+		for node in std::iter::once(node).chain(children) {
+			if let NodeCondition::Direct { .. } =
+				self.config.profiles[self.profile].nodes[node]
+			{
+				self.set_node_state(node, state);
+			}
+		}
+	}
+
+	// whether an aircraft is within the geographic target of a node
+	fn is_in_target(&self, node: usize, aircraft: &Aircraft) -> bool {
+		let polygon = self.config.nodes[node]
+			.display
+			.target
+			.points
+			.iter()
+			.map(|point| (point.geo.lat as f64, point.geo.lon as f64))
+			.collect::<Vec<_>>();
+
+		polygon.len() > 2 && contains(&polygon, (aircraft.lat, aircraft.lon))
+	}
+
+	pub fn set_stands(&mut self, stands: HashMap<String, String>) {
+		// an aircraft assigned another stand is led on again
+		self
+			.led_on
+			.retain(|(callsign, stand)| stands.get(callsign) == Some(stand));
+		self.stands = stands;
+	}
+
+	// routes the block of the stand of each arriving aircraft from the node
+	// nearest to it, once it comes close and unless the block has been set
+	// since it was cleared
+	fn track_lead_ons(&mut self, aircraft: &[Aircraft]) {
+		if self.state != ActivityState::Controlling {
+			return
+		}
+
+		let centre = |node: usize| {
+			let points = &self.config.nodes[node].display.target.points;
+			(!points.is_empty()).then(|| {
+				let (lat, lon) = points.iter().fold((0.0, 0.0), |(lat, lon), point| {
+					(lat + point.geo.lat as f64, lon + point.geo.lon as f64)
+				});
+				(lat / points.len() as f64, lon / points.len() as f64)
+			})
+		};
+
+		let mut lead_ons = Vec::new();
+		for aircraft in aircraft {
+			let Some(stand) = self.stands.get(&aircraft.callsign) else {
+				continue
+			};
+			let key = (aircraft.callsign.clone(), stand.clone());
+			if self.led_on.contains(&key) {
+				continue
+			}
+
+			let Some(block) = self
+				.config
+				.blocks
+				.iter()
+				.position(|block| block.stands.contains(stand))
+			else {
+				continue
+			};
+
+			let nodes = self.config.blocks[block]
+				.nodes
+				.iter()
+				.copied()
+				.filter(|node| {
+					self.config.profiles[self.profile].nodes[*node]
+						== NodeCondition::Router
+				})
+				.filter_map(|node| Some((node, centre(node)?)))
+				.collect::<Vec<_>>();
+
+			let position = (aircraft.lat, aircraft.lon);
+			let Some(&(entry, at)) = nodes.iter().min_by(|a, b| {
+				distance(a.1, position).total_cmp(&distance(b.1, position))
+			}) else {
+				continue
+			};
+			if distance(at, position) > LEAD_ON_DISTANCE {
+				continue
+			}
+
+			if self.blocks[block].state() != &BlockState::Clear {
+				self.led_on.insert(key);
+				continue
+			}
+
+			// a block with no other node to route to is relaxed instead
+			let exit = nodes
+				.iter()
+				.filter(|(node, _)| {
+					*node != entry && !self.is_route_closed(block, (entry, *node))
+				})
+				.max_by(|a, b| distance(a.1, at).total_cmp(&distance(b.1, at)))
+				.map(|(node, _)| *node);
+			let state = match exit {
+				Some(exit) => BlockState::Route((entry, exit)),
+				None => BlockState::Relax,
+			};
+
+			lead_ons.push((key, block, state));
+		}
+
+		for ((callsign, stand), block, state) in lead_ons {
+			debug!("leading {callsign} on to {stand}");
+			self.with_source(Source::Automation, |this| {
+				this.set_block_state(block, state)
+			});
+			self.led_on.insert((callsign, stand));
+		}
+	}
+
+	pub fn departures(&self) -> Vec<(String, usize)> {
+		self
+			.departures
+			.iter()
+			.map(|departure| (departure.callsign.clone(), departure.node))
+			.collect()
+	}
+
+	/// Moves the aircraft to the back if already queued.
+	pub fn queue_departure(&mut self, callsign: &str, node: &str) -> bool {
+		let Some(node) = self.node_ids.get(node).copied() else {
+			return false
+		};
+
+		self.remove_departure(callsign);
+		self.departures.push_back(Departure {
+			callsign: callsign.to_string(),
+			node,
+			entered: false,
+		});
+
+		true
+	}
+
+	pub fn remove_departure(&mut self, callsign: &str) {
+		self
+			.departures
+			.retain(|departure| departure.callsign != callsign);
+	}
+
+	// releases the next departure a while after the first, which is released
+	// by the controller, has crossed its holding point
+	fn track_departures(
+		&mut self,
+		aircraft: &[Aircraft],
+		release_delay: Duration,
+	) {
+		let Some(lead) = self.departures.front() else {
+			return
+		};
+
+		if *self.nodes[lead.node].state() && !lead.entered {
+			return
+		}
+
+		let inside = aircraft
+			.iter()
+			.any(|a| a.callsign == lead.callsign && self.is_in_target(lead.node, a));
+
+		match (lead.entered, inside) {
+			(false, true) => {
+				if let Some(lead) = self.departures.front_mut() {
+					lead.entered = true;
+				}
+			},
+			(true, false) => {
+				debug!("{} departed", lead.callsign);
+				self.departures.pop_front();
+				if !self.departures.is_empty() {
+					self.release_at = Some(Instant::now() + release_delay);
+				}
+			},
+			_ => (),
 		}
 	}
 }
@@ -1,14 +1,32 @@
 use bars_config::{Aerodrome, Config};
+use bars_protocol::Encoding;
 
+use crate::ipc::Transport;
+use crate::{ColorVision, RenderBackend};
+
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
 
-use anyhow::Result;
+use native_tls::{Certificate, TlsConnector};
 
 use serde::{Deserialize, Serialize};
 
 use tracing::{debug, warn};
 
 const DEFAULT_PORT: u16 = 6866;
+const DEFAULT_SELECTION_TIMEOUT: f64 = 3.0;
+const DEFAULT_ROUTE_HOP_LIMIT: usize = 8;
+const DEFAULT_RELEASE_DELAY: f64 = 60.0;
+const DEFAULT_RESTORE_WINDOW: f64 = 600.0;
+const DEFAULT_PATCH_DEBOUNCE: f64 = 0.15;
+const DEFAULT_CLICK_TOLERANCE: f64 = 4.0;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
 
 fn default_port() -> u16 {
 	DEFAULT_PORT
@@ -23,8 +41,52 @@ pub struct LocalConfig {
 	pub token: Option<String>,
 	#[serde(default = "default_port")]
 	pub port: u16,
+	/// connection between proxied clients and the main instance
+	#[serde(default)]
+	pub ipc: Transport,
+	/// secret which proxied clients must present to the main instance
+	#[serde(default)]
+	pub proxy_secret: Option<String>,
 	#[serde(default = "default_server")]
 	pub server: String,
+	/// servers to fall back to in order when `server` cannot be reached
+	#[serde(default)]
+	pub fallback_servers: Vec<String>,
+	#[serde(default)]
+	pub encoding: Encoding,
+	/// PEM bundle of further root certificates, relative to the config directory
+	#[serde(default)]
+	pub ca_bundle: Option<PathBuf>,
+	/// skips verifying server certificates, which is only for local testing
+	#[serde(default)]
+	pub insecure_tls: bool,
+	/// seconds for which a node stays selected when routing
+	#[serde(default)]
+	pub selection_timeout: Option<f64>,
+	/// pixels by which a click may miss a target and still select it
+	#[serde(default)]
+	pub click_tolerance: Option<f64>,
+	/// most blocks which a route between two selected nodes may set
+	#[serde(default)]
+	pub route_hop_limit: Option<usize>,
+	/// seconds after a queued departure crosses before the next is released
+	#[serde(default)]
+	pub release_delay: Option<f64>,
+	/// seconds for which changes wait to be sent together
+	#[serde(default)]
+	pub patch_debounce: Option<f64>,
+	/// seconds after losing the connection within which lighting is restorable
+	#[serde(default)]
+	pub restore_window: Option<f64>,
+	/// actions bound to keys, such as `F5` or `Ctrl+Shift+F12`
+	#[serde(default)]
+	pub keys: HashMap<String, KeyAction>,
+	/// palette for screens which have not chosen their own
+	#[serde(default)]
+	pub color_vision: ColorVision,
+	/// renderer for screens which have not chosen their own
+	#[serde(default)]
+	pub render_backend: RenderBackend,
 }
 
 impl LocalConfig {
@@ -37,6 +99,162 @@ impl LocalConfig {
 			Ok(Self::default())
 		}
 	}
+
+	pub fn selection_timeout(&self) -> Duration {
+		let secs = self.selection_timeout.unwrap_or(DEFAULT_SELECTION_TIMEOUT);
+		Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+			warn!("invalid selection timeout {secs}");
+			Duration::from_secs_f64(DEFAULT_SELECTION_TIMEOUT)
+		})
+	}
+
+	pub fn click_tolerance(&self) -> f64 {
+		let pixels = self.click_tolerance.unwrap_or(DEFAULT_CLICK_TOLERANCE);
+		if pixels.is_finite() && pixels >= 0.0 {
+			pixels
+		} else {
+			warn!("invalid click tolerance {pixels}");
+			DEFAULT_CLICK_TOLERANCE
+		}
+	}
+
+	pub fn release_delay(&self) -> Duration {
+		let secs = self.release_delay.unwrap_or(DEFAULT_RELEASE_DELAY);
+		Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+			warn!("invalid release delay {secs}");
+			Duration::from_secs_f64(DEFAULT_RELEASE_DELAY)
+		})
+	}
+
+	pub fn patch_debounce(&self) -> Duration {
+		let secs = self.patch_debounce.unwrap_or(DEFAULT_PATCH_DEBOUNCE);
+		Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+			warn!("invalid patch debounce {secs}");
+			Duration::from_secs_f64(DEFAULT_PATCH_DEBOUNCE)
+		})
+	}
+
+	pub fn restore_window(&self) -> Duration {
+		let secs = self.restore_window.unwrap_or(DEFAULT_RESTORE_WINDOW);
+		Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+			warn!("invalid restore window {secs}");
+			Duration::from_secs_f64(DEFAULT_RESTORE_WINDOW)
+		})
+	}
+
+	pub fn servers(&self) -> Vec<String> {
+		std::iter::once(&self.server)
+			.chain(&self.fallback_servers)
+			.cloned()
+			.collect()
+	}
+
+	pub fn tls(&self, dir: &Path) -> Result<TlsConnector> {
+		let mut builder = TlsConnector::builder();
+
+		if let Some(path) = &self.ca_bundle {
+			let bundle = std::fs::read_to_string(dir.join(path))?;
+			let mut n = 0;
+			for pem in bundle.split_inclusive(PEM_END) {
+				if pem.contains(PEM_BEGIN) {
+					builder.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+					n += 1;
+				}
+			}
+
+			if n == 0 {
+				bail!("no certificates in {}", path.display())
+			}
+			debug!("trusting {n} certificates from {}", path.display());
+		}
+
+		if self.insecure_tls {
+			warn!("server certificates are not being verified");
+			builder
+				.danger_accept_invalid_certs(true)
+				.danger_accept_invalid_hostnames(true);
+		}
+
+		Ok(builder.build()?)
+	}
+
+	pub fn route_hop_limit(&self) -> usize {
+		self.route_hop_limit.unwrap_or(DEFAULT_ROUTE_HOP_LIMIT)
+	}
+
+	pub fn key_bindings(&self) -> HashMap<Key, KeyAction> {
+		self
+			.keys
+			.iter()
+			.filter_map(|(name, action)| match name.parse() {
+				Ok(key) => Some((key, action.clone())),
+				Err(err) => {
+					warn!("invalid key binding {name:?}: {err}");
+					None
+				},
+			})
+			.collect()
+	}
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Key {
+	/// Windows virtual-key code
+	pub code: u32,
+	pub shift: bool,
+	pub ctrl: bool,
+}
+
+impl FromStr for Key {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		const VK_F1: u32 = 0x70;
+
+		let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+		let Some(name) = parts.pop() else {
+			bail!("no key")
+		};
+
+		let mut key = Self {
+			code: 0,
+			shift: false,
+			ctrl: false,
+		};
+
+		for modifier in parts {
+			match modifier.to_ascii_lowercase().as_str() {
+				"shift" => key.shift = true,
+				"ctrl" => key.ctrl = true,
+				_ => bail!("unknown modifier {modifier:?}"),
+			}
+		}
+
+		let n = name
+			.strip_prefix(['F', 'f'])
+			.and_then(|n| n.parse::<u32>().ok())
+			.filter(|n| (1..=24).contains(n));
+		let Some(n) = n else {
+			bail!("{name:?} is not a function key")
+		};
+
+		key.code = VK_F1 + n - 1;
+		Ok(key)
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAction {
+	/// applies the preset of the current profile with the name
+	Preset(String),
+	/// selects the profile with the name
+	Profile(String),
+	NextProfile,
+	NextView,
+	PreviousView,
+	Undo,
+	Redo,
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -70,10 +288,11 @@ pub struct ConfigSource {
 pub struct ConfigManager {
 	sources: Vec<(ConfigSource, Option<Config>)>,
 	base: PathBuf,
+	http: reqwest::Client,
 }
 
 impl ConfigManager {
-	pub fn new(mapping: ConfigMapping) -> Self {
+	pub fn new(mapping: ConfigMapping, http: reqwest::Client) -> Self {
 		Self {
 			sources: mapping
 				.config
@@ -81,6 +300,7 @@ impl ConfigManager {
 				.map(|source| (source, None))
 				.collect(),
 			base: mapping.base,
+			http,
 		}
 	}
 
@@ -98,7 +318,8 @@ impl ConfigManager {
 			debug!("fetching uncached source {:?}", source.src);
 
 			let data = if source.src.contains("://") {
-				reqwest::get(&source.src).await?.bytes().await?.to_vec()
+				let response = self.http.get(&source.src).send().await?;
+				response.bytes().await?.to_vec()
 			} else {
 				let path = self.base.join(&source.src);
 				tokio::fs::read(path).await?
@@ -122,4 +343,17 @@ impl ConfigManager {
 
 		Ok(Some(config.aerodromes.swap_remove(i)))
 	}
+
+	/// Bypasses the cache.
+	pub async fn reload(&mut self, icao: &String) -> Result<Option<Aerodrome>> {
+		if let Some((_, config)) = self
+			.sources
+			.iter_mut()
+			.find(|(source, _)| source.aerodromes.contains(icao))
+		{
+			*config = None;
+		}
+
+		self.load(icao).await
+	}
 }
@@ -1,15 +1,17 @@
 use crate::client::Client;
-use crate::config::{ConfigMapping, LocalConfig};
-use crate::ipc::Channel;
-use crate::screen::Screen;
-use crate::server::{ConnectOptions, Server};
-use crate::ConnectionState;
-
-use std::collections::VecDeque;
+use crate::config::{ConfigMapping, Key, KeyAction, LocalConfig};
+use crate::ipc::{Channel, Refused};
+use crate::screen::{Screen, StyleCache};
+use crate::server::{ConnectOptions, Server, Stopped};
+use crate::simulation::Simulation;
+use crate::{ColorVision, ConnectionState, RenderBackend};
+
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 
@@ -21,13 +23,59 @@ use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::fmt::time::ChronoUtc;
 use tracing_subscriber::FmtSubscriber;
 
+// most times that the worker is started again after panicking within
+// `RESTART_WINDOW`, beyond which it is left stopped
+const MAX_RESTARTS: usize = 3;
+const RESTART_WINDOW: Duration = Duration::from_secs(600);
+
+// how the worker of this instance was last started, so that it can be again
+#[derive(Clone)]
+enum Spawn {
+	Direct { callsign: String, controlling: bool },
+	Local,
+}
+
 pub struct Context {
 	server: Option<Server>,
+	spawn: Option<Spawn>,
+	// times that the worker was started again
+	restarts: VecDeque<Instant>,
 	client: Option<Client>,
 	messages: VecDeque<String>,
 	dir: PathBuf,
 	state: ConnectionState,
 	tracked: Vec<String>,
+	selection_timeout: Duration,
+	click_tolerance: f64,
+	route_hop_limit: usize,
+	release_delay: Duration,
+	restore_window: Duration,
+	patch_debounce: Duration,
+	keys: HashMap<Key, KeyAction>,
+	color_vision: ColorVision,
+	render_backend: RenderBackend,
+	aircraft: Vec<Aircraft>,
+	simulation: Option<Simulation>,
+	styles: Rc<StyleCache>,
+}
+
+pub struct Stand {
+	pub callsign: String,
+	pub icao: String,
+	pub stand: String,
+}
+
+pub struct Runway {
+	pub icao: String,
+	pub designator: String,
+	pub departure: bool,
+	pub arrival: bool,
+}
+
+pub struct Aircraft {
+	pub callsign: String,
+	pub lat: f64,
+	pub lon: f64,
 }
 
 impl Context {
@@ -110,24 +158,47 @@ impl Context {
 
 	#[instrument(level = "trace")]
 	fn try_new(dir: &str) -> Result<Self> {
+		let config = LocalConfig::load(Path::new(dir))
+			.inspect_err(|err| warn!("local config: {err}"))
+			.unwrap_or_default();
+
 		Ok(Self {
 			server: None,
+			spawn: None,
+			restarts: VecDeque::new(),
 			client: None,
 			messages: VecDeque::new(),
 			dir: dir.into(),
 			state: ConnectionState::Disconnected,
 			tracked: Vec::new(),
+			selection_timeout: config.selection_timeout(),
+			click_tolerance: config.click_tolerance(),
+			route_hop_limit: config.route_hop_limit(),
+			release_delay: config.release_delay(),
+			restore_window: config.restore_window(),
+			patch_debounce: config.patch_debounce(),
+			keys: config.key_bindings(),
+			color_vision: config.color_vision,
+			render_backend: config.render_backend,
+			aircraft: Vec::new(),
+			simulation: None,
+			styles: Rc::default(),
 		})
 	}
 
 	#[instrument(level = "trace", skip(self))]
 	pub fn tick(&mut self) {
 		if let Some(server) = self.server.as_mut() {
-			if server.is_cancelled() {
-				debug!("disconnecting due to server cancellation");
+			if let Some(stopped) = server.stopped() {
+				debug!("disconnecting due to server cancellation ({stopped:?})");
 				self.disconnect();
-				self.add_message("disconnected".into());
-				self.state = ConnectionState::Poisoned;
+
+				if stopped == Stopped::Panicked && self.restart() {
+					self.add_message("reconnected after an internal error".into());
+				} else {
+					self.add_message("disconnected".into());
+					self.state = ConnectionState::Poisoned;
+				}
 			}
 		}
 
@@ -140,15 +211,65 @@ impl Context {
 				},
 				Err(err) => {
 					warn!("{err}");
+					if let Some(err) = err.downcast_ref::<Refused>() {
+						self.add_message(err.to_string());
+					}
 					self.disconnect();
 					self.state = ConnectionState::Poisoned;
 				},
 			}
 		}
+
+		if let (Some(simulation), Some(client)) =
+			(self.simulation.as_mut(), self.client.as_ref())
+		{
+			if let Some(aerodrome) = client.aerodrome(simulation.icao()) {
+				simulation.tick(aerodrome);
+			}
+		}
+	}
+
+	// starts the worker again as it was last started, with the aerodromes
+	// tracked as they were, unless it has panicked too often recently
+	fn restart(&mut self) -> bool {
+		let now = Instant::now();
+		while self
+			.restarts
+			.front()
+			.is_some_and(|restart| now - *restart > RESTART_WINDOW)
+		{
+			self.restarts.pop_front();
+		}
+
+		if self.restarts.len() >= MAX_RESTARTS {
+			warn!("worker panicked too often to restart");
+			return false
+		}
+
+		self.restarts.push_back(now);
+		info!("restarting worker");
+
+		match self.spawn.clone() {
+			Some(Spawn::Direct {
+				callsign,
+				controlling,
+			}) => self.connect_direct(&callsign, controlling),
+			Some(Spawn::Local) => self.connect_local(),
+			None => return false,
+		}
+
+		self.client.is_some()
 	}
 
 	fn load_config(&mut self) -> Option<LocalConfig> {
 		LocalConfig::load(&self.dir)
+			.inspect(|config| {
+				self.selection_timeout = config.selection_timeout();
+				self.route_hop_limit = config.route_hop_limit();
+				self.release_delay = config.release_delay();
+				self.restore_window = config.restore_window();
+				self.patch_debounce = config.patch_debounce();
+			})
 			.inspect_err(|err| {
 				error!("{err}");
 				self.add_message("failed to load config".into());
@@ -158,6 +279,7 @@ impl Context {
 
 	fn create_server(
 		&mut self,
+		config: &LocalConfig,
 		options: Option<ConnectOptions>,
 	) -> Option<Channel> {
 		let mapping = match ConfigMapping::load(&self.dir) {
@@ -169,7 +291,16 @@ impl Context {
 			},
 		};
 
-		match Server::new(options, mapping) {
+		let tls = match config.tls(&self.dir) {
+			Ok(tls) => tls,
+			Err(err) => {
+				warn!("{err}");
+				self.add_message("failed to load certificates".into());
+				return None
+			},
+		};
+
+		match Server::new(options, mapping, tls) {
 			Ok((server, channel)) => {
 				self.server = Some(server);
 				Some(channel)
@@ -182,8 +313,21 @@ impl Context {
 		}
 	}
 
-	fn create_client(&mut self, channel: Channel) -> Option<()> {
-		match Client::new(channel) {
+	fn create_client(
+		&mut self,
+		channel: Channel,
+		secret: Option<String>,
+	) -> Option<()> {
+		let sessions = self.dir.join("sessions");
+		let audit = self.dir.join("audit");
+		match Client::new(
+			channel,
+			secret,
+			sessions,
+			audit,
+			self.restore_window,
+			self.patch_debounce,
+		) {
 			Ok(mut client) => {
 				for tracked in &self.tracked {
 					let _ = client.set_tracking(tracked.clone(), true);
@@ -210,26 +354,34 @@ impl Context {
 		}
 
 		self.state = ConnectionState::Poisoned;
+		self.spawn = Some(Spawn::Direct {
+			callsign: callsign.into(),
+			controlling,
+		});
 
 		let Some(config) = self.load_config() else {
 			return
 		};
 
-		let Some(token) = config.token else {
+		let Some(token) = config.token.clone() else {
 			self.add_message("unauthenticated".into());
 			return
 		};
 
 		let options = ConnectOptions {
-			server: config.server,
+			servers: config.servers(),
 			token,
+			dir: self.dir.clone(),
 			port: config.port,
+			transport: config.ipc,
+			proxy_secret: config.proxy_secret.clone(),
 			callsign: callsign.into(),
 			controlling,
+			encoding: config.encoding,
 		};
 
-		if let Some(channel) = self.create_server(Some(options)) {
-			if self.create_client(channel).is_some() {
+		if let Some(channel) = self.create_server(&config, Some(options)) {
+			if self.create_client(channel, None).is_some() {
 				self.state = ConnectionState::ConnectedDirect;
 			}
 		}
@@ -243,14 +395,15 @@ impl Context {
 		}
 
 		self.state = ConnectionState::Poisoned;
+		self.spawn = None;
 
 		let Some(config) = self.load_config() else {
 			return
 		};
 
-		match Channel::connect(config.port) {
+		match Channel::connect(config.ipc, config.port) {
 			Ok(channel) => {
-				if self.create_client(channel).is_some() {
+				if self.create_client(channel, config.proxy_secret).is_some() {
 					self.state = ConnectionState::ConnectedProxy;
 				}
 			},
@@ -273,9 +426,14 @@ impl Context {
 		}
 
 		self.state = ConnectionState::Poisoned;
+		self.spawn = Some(Spawn::Local);
+
+		let Some(config) = self.load_config() else {
+			return
+		};
 
-		if let Some(channel) = self.create_server(None) {
-			if self.create_client(channel).is_some() {
+		if let Some(channel) = self.create_server(&config, None) {
+			if self.create_client(channel, None).is_some() {
 				self.state = ConnectionState::ConnectedLocal;
 			}
 		}
@@ -284,6 +442,7 @@ impl Context {
 	#[instrument(level = "trace", skip(self))]
 	pub fn disconnect(&mut self) {
 		self.state = ConnectionState::Disconnected;
+		self.simulation = None;
 
 		if let Some(server) = self.server.take() {
 			server.stop();
@@ -308,10 +467,102 @@ impl Context {
 		self.messages.push_back(message)
 	}
 
-	pub fn create_screen(&mut self, geo: bool) -> Screen {
+	pub fn create_screen(&mut self, geo: bool) -> Screen<'_> {
 		Screen::new(self, geo)
 	}
 
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn selection_timeout(&self) -> Duration {
+		self.selection_timeout
+	}
+
+	pub fn route_hop_limit(&self) -> usize {
+		self.route_hop_limit
+	}
+
+	pub fn click_tolerance(&self) -> f64 {
+		self.click_tolerance
+	}
+
+	pub fn color_vision(&self) -> ColorVision {
+		self.color_vision
+	}
+
+	pub fn render_backend(&self) -> RenderBackend {
+		self.render_backend
+	}
+
+	pub fn key_action(&self, key: &Key) -> Option<&KeyAction> {
+		self.keys.get(key)
+	}
+
+	pub fn style_cache(&self) -> Rc<StyleCache> {
+		self.styles.clone()
+	}
+
+	pub fn aircraft(&self) -> &[Aircraft] {
+		&self.aircraft
+	}
+
+	pub fn set_aircraft(&mut self, aircraft: Vec<Aircraft>) {
+		self.aircraft = aircraft;
+		if let Some(simulation) = &self.simulation {
+			self.aircraft.extend(simulation.aircraft());
+		}
+
+		if let Some(client) = &mut self.client {
+			client.track_crossings(&self.aircraft, self.release_delay);
+		}
+	}
+
+	pub fn set_active_runways(&mut self, runways: Vec<Runway>) {
+		if let Some(client) = &mut self.client {
+			for message in client.set_active_runways(&runways) {
+				self.add_message(message);
+			}
+		}
+	}
+
+	pub fn set_stands(&mut self, stands: Vec<Stand>) {
+		if let Some(client) = &mut self.client {
+			client.set_stands(&stands);
+		}
+	}
+
+	pub fn simulation(&self) -> Option<&str> {
+		self
+			.simulation
+			.as_ref()
+			.map(|simulation| simulation.icao().as_str())
+	}
+
+	/// Never runs while connected to a network, whose lighting it would affect.
+	pub fn simulate(&mut self, icao: Option<String>) {
+		let Some(icao) = icao else {
+			self.simulation = None;
+			return
+		};
+
+		match self.state {
+			ConnectionState::ConnectedDirect | ConnectionState::ConnectedProxy => {
+				self.add_message("disconnect from the network to simulate".into());
+				return
+			},
+			ConnectionState::ConnectedLocal => (),
+			ConnectionState::Disconnected | ConnectionState::Poisoned => {
+				self.connect_local();
+				if self.state != ConnectionState::ConnectedLocal {
+					return
+				}
+			},
+		}
+
+		self.simulation = Some(Simulation::new(icao));
+	}
+
 	pub fn client(&self) -> Option<&Client> {
 		self.client.as_ref()
 	}
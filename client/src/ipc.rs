@@ -1,25 +1,78 @@
 use std::collections::HashMap;
-use std::fmt::{self, Debug, Formatter};
-use std::io::{ErrorKind, Write};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{Ipv4Addr, TcpStream};
+use std::os::windows::io::AsRawHandle;
 
-use bars_protocol::Patch;
+use bars_protocol::{Capability, Controller, ObjectKind, Patch};
 
 use anyhow::{bail, Result};
 
 use serde::{Deserialize, Serialize};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::windows::named_pipe::NamedPipeServer;
 use tokio::net::TcpStream as AsyncTcpStream;
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 use tracing::trace;
 
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::Pipes::PeekNamedPipe;
+
+// largest message accepted over a stream, each of which is preceded by its
+// length as a little-endian u32
+const MAX_PACKET: usize = 0x100_0000;
+
+/// Must be the same in each instance for them to understand one another.
+pub const IPC_VERSION: u32 = 1;
+pub const PLUGIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Behaviour which others may rely on without a change of `IPC_VERSION`.
+pub const CAPABILITIES: &[&str] = &["queue", "failover", "renew-key"];
+
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+	#[default]
+	Tcp,
+	/// needs no free port nor firewall exception
+	NamedPipe,
+}
+
+/// Keyed on `port`, so that instances configured apart stay apart.
+pub fn pipe_name(port: u16) -> String {
+	format!(r"\\.\pipe\bars-{port}")
+}
+
+pub type Scenery = HashMap<String, (bool, Option<ObjectKind>)>;
+
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl Display for Refused {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl std::error::Error for Refused {}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Upstream {
-	Init,
+	/// Kept first and unchanged, so that any version can read it.
+	Init {
+		ipc_version: u32,
+		plugin_version: String,
+		capabilities: Vec<String>,
+	},
+	Authenticate {
+		secret: Option<String>,
+	},
 	Track {
 		icao: String,
 		track: bool,
@@ -30,11 +83,35 @@ pub enum Upstream {
 	},
 	Patch {
 		icao: String,
-		patch: Patch,
+		#[serde(with = "json")]
+		patch: Box<Patch>,
+		preset: Option<String>,
 	},
 	Scenery {
 		icao: String,
-		scenery: HashMap<String, bool>,
+		scenery: Scenery,
+	},
+	Claim {
+		icao: String,
+		blocks: Vec<String>,
+		claim: bool,
+	},
+	RequestHandoff {
+		icao: String,
+		controller_id: String,
+	},
+	RespondHandoff {
+		icao: String,
+		accept: bool,
+	},
+	TakeOver {
+		icao: String,
+	},
+	Resync {
+		icao: String,
+	},
+	Reload {
+		icao: String,
 	},
 }
 
@@ -45,6 +122,12 @@ impl Upstream {
 			Self::Control { icao, .. } => icao,
 			Self::Patch { icao, .. } => icao,
 			Self::Scenery { icao, .. } => icao,
+			Self::Claim { icao, .. } => icao,
+			Self::RequestHandoff { icao, .. } => icao,
+			Self::RespondHandoff { icao, .. } => icao,
+			Self::TakeOver { icao } => icao,
+			Self::Resync { icao } => icao,
+			Self::Reload { icao } => icao,
 			_ => return None,
 		})
 	}
@@ -52,37 +135,123 @@ impl Upstream {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Downstream {
+	/// Kept first and unchanged, so that any version can read it.
+	Init {
+		ipc_version: u32,
+		plugin_version: String,
+		capabilities: Vec<String>,
+		rejected: Option<String>,
+	},
 	Config {
 		data: bars_config::Aerodrome,
 	},
+	Reload {
+		data: bars_config::Aerodrome,
+	},
 	Control {
 		icao: String,
 		control: bool,
 	},
 	Patch {
 		icao: String,
+		#[serde(with = "json")]
 		patch: Patch,
+		/// callsign of the other controller which made the change
+		controller: Option<String>,
 	},
 	Aircraft {
 		icao: String,
 		aircraft: Vec<String>,
 	},
+	Controllers {
+		icao: String,
+		controllers: Vec<Controller>,
+	},
+	Latency {
+		icao: String,
+		latency: Option<u32>,
+	},
+	Diagnostics {
+		icao: String,
+		trackers: usize,
+		reconnects: usize,
+	},
+	Endpoint {
+		icao: String,
+		server: Option<String>,
+	},
+	Presence {
+		icao: String,
+		observers: Vec<String>,
+		observer_count: usize,
+		connected: HashMap<String, u64>,
+	},
+	Capabilities {
+		icao: String,
+		capabilities: Vec<Capability>,
+	},
+	Claims {
+		icao: String,
+		claims: HashMap<String, bool>,
+	},
+	HandoffRequest {
+		icao: String,
+		requester: Option<String>,
+	},
 	Error {
 		icao: String,
 		message: Option<String>,
 		disconnect: bool,
 	},
+	Notice {
+		icao: String,
+		message: String,
+	},
 }
 
 impl Downstream {
-	pub fn icao(&self) -> &String {
-		match self {
+	pub fn icao(&self) -> Option<&String> {
+		Some(match self {
+			Self::Init { .. } => return None,
 			Self::Config { data } => &data.icao,
+			Self::Reload { data } => &data.icao,
 			Self::Control { icao, .. } => icao,
 			Self::Patch { icao, .. } => icao,
 			Self::Aircraft { icao, .. } => icao,
+			Self::Controllers { icao, .. } => icao,
+			Self::Latency { icao, .. } => icao,
+			Self::Diagnostics { icao, .. } => icao,
+			Self::Endpoint { icao, .. } => icao,
+			Self::Presence { icao, .. } => icao,
+			Self::Capabilities { icao, .. } => icao,
+			Self::Claims { icao, .. } => icao,
+			Self::HandoffRequest { icao, .. } => icao,
 			Self::Error { icao, .. } => icao,
-		}
+			Self::Notice { icao, .. } => icao,
+		})
+	}
+}
+
+// patch extras are arbitrary JSON, which bincode cannot deserialise, so
+// patches are carried as JSON text
+mod json {
+	use serde::de::{DeserializeOwned, Error as _};
+	use serde::ser::Error as _;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+	pub fn serialize<T: Serialize, S: Serializer>(
+		patch: &T,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		let json = serde_json::to_string(patch).map_err(S::Error::custom)?;
+		serializer.serialize_str(&json)
+	}
+
+	pub fn deserialize<'de, T: DeserializeOwned, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<T, D::Error> {
+		let json = String::deserialize(deserializer)?;
+		serde_json::from_str(&json).map_err(D::Error::custom)
 	}
 }
 
@@ -90,7 +259,10 @@ struct HideConfig<'a>(&'a Downstream);
 
 impl<'a> Debug for HideConfig<'a> {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-		if matches!(self.0, Downstream::Config { .. }) {
+		if matches!(
+			self.0,
+			Downstream::Config { .. } | Downstream::Reload { .. }
+		) {
 			f.debug_struct("Config").finish_non_exhaustive()
 		} else {
 			write!(f, "{:?}", self.0)
@@ -103,14 +275,66 @@ pub enum Channel {
 		rx: UnboundedReceiver<Downstream>,
 		tx: UnboundedSender<Upstream>,
 	},
-	Tcp(TcpStream),
+	Tcp {
+		stream: TcpStream,
+		// bytes received but not yet making up a whole message
+		buf: Vec<u8>,
+	},
+	Pipe {
+		pipe: File,
+		buf: Vec<u8>,
+	},
 }
 
 impl Channel {
-	pub fn connect(port: u16) -> Result<Self> {
-		let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
-		stream.set_nonblocking(true)?;
-		Ok(Self::Tcp(stream))
+	pub fn connect(transport: Transport, port: u16) -> Result<Self> {
+		match transport {
+			Transport::Tcp => {
+				let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
+				stream.set_nonblocking(true)?;
+				Ok(Self::Tcp {
+					stream,
+					buf: Vec::new(),
+				})
+			},
+			Transport::NamedPipe => {
+				let pipe = OpenOptions::new()
+					.read(true)
+					.write(true)
+					.open(pipe_name(port))?;
+				Ok(Self::Pipe {
+					pipe,
+					buf: Vec::new(),
+				})
+			},
+		}
+	}
+
+	fn write_frame(stream: &mut impl Write, message: &Upstream) -> Result<()> {
+		let n = bincode::serialized_size(message)? as u32;
+		stream.write_all(&n.to_le_bytes())?;
+		bincode::serialize_into(stream, message)?;
+		Ok(())
+	}
+
+	// takes the first message from `buf`, if it has been received in full
+	fn take_frame(buf: &mut Vec<u8>) -> Result<Option<Downstream>> {
+		let Some(len) = buf.first_chunk::<4>().copied() else {
+			return Ok(None)
+		};
+		let len = u32::from_le_bytes(len) as usize;
+		if len > MAX_PACKET {
+			bail!("oversized packet")
+		}
+		if buf.len() < 4 + len {
+			return Ok(None)
+		}
+
+		let message = bincode::deserialize(&buf[4..4 + len])?;
+		buf.drain(..4 + len);
+
+		trace!("cch rx: {:?}", HideConfig(&message));
+		Ok(Some(message))
 	}
 
 	pub fn send(&mut self, message: Upstream) -> Result<()> {
@@ -120,11 +344,8 @@ impl Channel {
 			Self::Mpsc { tx, .. } => {
 				tx.send(message)?;
 			},
-			Self::Tcp(stream) => {
-				let n = bincode::serialized_size(&message)? as u32;
-				stream.write_all(&n.to_le_bytes())?;
-				bincode::serialize_into(stream, &message)?;
-			},
+			Self::Tcp { stream, .. } => Self::write_frame(stream, &message)?,
+			Self::Pipe { pipe, .. } => Self::write_frame(pipe, &message)?,
 		}
 
 		Ok(())
@@ -140,18 +361,41 @@ impl Channel {
 				Err(TryRecvError::Empty) => Ok(None),
 				Err(_) => bail!("disconnected"),
 			},
-			Self::Tcp(stream) => {
-				let mut buf = [0];
-				match stream.peek(&mut buf) {
-					Ok(0) => return Ok(None),
-					Ok(_) => (),
-					Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
-					Err(err) => return Err(err.into()),
+			Self::Tcp { stream, buf } => {
+				// messages may arrive over several reads, and several at once
+				let mut chunk = [0; 4096];
+				loop {
+					match stream.read(&mut chunk) {
+						Ok(0) => bail!("disconnected"),
+						Ok(n) => buf.extend_from_slice(&chunk[..n]),
+						Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+						Err(err) if err.kind() == ErrorKind::Interrupted => (),
+						Err(err) => return Err(err.into()),
+					}
 				}
 
-				let message = bincode::deserialize_from(stream)?;
-				trace!("cch rx: {:?}", HideConfig(&message));
-				Ok(Some(message))
+				Self::take_frame(buf)
+			},
+			Self::Pipe { pipe, buf } => {
+				// pipes cannot be read without blocking, so only what has
+				// already arrived is read
+				let mut available = 0;
+				unsafe {
+					PeekNamedPipe(
+						HANDLE(pipe.as_raw_handle()),
+						None,
+						0,
+						None,
+						Some(&mut available as *mut u32),
+						None,
+					)?;
+				}
+
+				let start = buf.len();
+				buf.resize(start + available as usize, 0);
+				pipe.read_exact(&mut buf[start..])?;
+
+				Self::take_frame(buf)
 			},
 		}
 	}
@@ -163,6 +407,7 @@ pub enum ServerChannel {
 		tx: UnboundedSender<Downstream>,
 	},
 	Tcp(AsyncTcpStream),
+	Pipe(NamedPipeServer),
 }
 
 impl ServerChannel {
@@ -181,11 +426,16 @@ impl ServerChannel {
 		Ok(())
 	}
 
-	async fn send_tcp<T: AsyncWriteExt + Unpin>(
+	async fn send_stream<T: AsyncWriteExt + Unpin>(
 		tx: &mut T,
 		message: Downstream,
 	) -> Result<()> {
 		let data = bincode::serialize(&message)?;
+		if data.len() > MAX_PACKET {
+			bail!("oversized packet")
+		}
+
+		tx.write_u32_le(data.len() as u32).await?;
 		tx.write_all(&data).await?;
 		Ok(())
 	}
@@ -207,9 +457,11 @@ impl ServerChannel {
 		}
 	}
 
-	async fn recv_tcp<T: AsyncReadExt + Unpin>(rx: &mut T) -> Result<Upstream> {
+	async fn recv_stream<T: AsyncReadExt + Unpin>(
+		rx: &mut T,
+	) -> Result<Upstream> {
 		let n = rx.read_u32_le().await?;
-		if n > 0x100_0000 {
+		if n as usize > MAX_PACKET {
 			bail!("oversized packet");
 		} else {
 			let mut buf = vec![0; n as usize];
@@ -231,6 +483,13 @@ impl ServerChannel {
 					ServerChannelWriteHalf::Tcp(tx),
 				)
 			},
+			Self::Pipe(pipe) => {
+				let (rx, tx) = tokio::io::split(pipe);
+				(
+					ServerChannelReadHalf::Pipe(rx),
+					ServerChannelWriteHalf::Pipe(tx),
+				)
+			},
 		}
 	}
 }
@@ -238,6 +497,7 @@ impl ServerChannel {
 pub enum ServerChannelReadHalf {
 	Mpsc(UnboundedReceiver<Upstream>),
 	Tcp(OwnedReadHalf),
+	Pipe(ReadHalf<NamedPipeServer>),
 }
 
 impl ServerChannelReadHalf {
@@ -246,8 +506,9 @@ impl ServerChannelReadHalf {
 			Self::Mpsc(rx) => ServerChannel::recv_mpsc(rx).await,
 			Self::Tcp(rx) => {
 				rx.readable().await?;
-				ServerChannel::recv_tcp(rx).await
+				ServerChannel::recv_stream(rx).await
 			},
+			Self::Pipe(rx) => ServerChannel::recv_stream(rx).await,
 		}?;
 		trace!("sch rx: {message:?}");
 		Ok(message)
@@ -257,6 +518,7 @@ impl ServerChannelReadHalf {
 pub enum ServerChannelWriteHalf {
 	Mpsc(UnboundedSender<Downstream>),
 	Tcp(OwnedWriteHalf),
+	Pipe(WriteHalf<NamedPipeServer>),
 }
 
 impl ServerChannelWriteHalf {
@@ -265,7 +527,8 @@ impl ServerChannelWriteHalf {
 
 		match self {
 			Self::Mpsc(tx) => ServerChannel::send_mpsc(tx, message).await,
-			Self::Tcp(tx) => ServerChannel::send_tcp(tx, message).await,
+			Self::Tcp(tx) => ServerChannel::send_stream(tx, message).await,
+			Self::Pipe(tx) => ServerChannel::send_stream(tx, message).await,
 		}
 	}
 }
@@ -1,10 +1,16 @@
 mod api;
+mod audit;
 mod client;
 mod config;
 mod context;
 mod ipc;
 mod screen;
 mod server;
+mod session;
+mod simulation;
+mod snapshot;
+
+use std::ffi::c_char;
 
 use serde::{Deserialize, Serialize};
 
@@ -50,6 +56,47 @@ pub enum ActivityState {
 	Controlling,
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[repr(C)]
+pub enum Capability {
+	Intensity,
+	Ownership,
+	BinaryFrames,
+}
+
+impl From<Capability> for bars_protocol::Capability {
+	fn from(capability: Capability) -> Self {
+		match capability {
+			Capability::Intensity => Self::Intensity,
+			Capability::Ownership => Self::Ownership,
+			Capability::BinaryFrames => Self::BinaryFrames,
+		}
+	}
+}
+
+#[derive(
+	Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(C)]
+pub enum RenderBackend {
+	#[default]
+	Gdi,
+	GdiPlus,
+}
+
+#[derive(
+	Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(C)]
+pub enum ColorVision {
+	#[default]
+	Normal,
+	Protanopia,
+	Deuteranopia,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct ViewportGeo {
@@ -64,6 +111,35 @@ pub struct ViewportGeo {
 pub struct ViewportNonGeo {
 	origin: [f64; 2],
 	size: [f64; 2],
+	/// Scale applied to the view after fitting it to the viewport.
+	zoom: f64,
+	/// Offset in pixels applied after `zoom`.
+	pan: [f64; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AircraftPosition {
+	callsign: *const c_char,
+	lat: f64,
+	lon: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct StandAssignment {
+	callsign: *const c_char,
+	icao: *const c_char,
+	stand: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveRunway {
+	icao: *const c_char,
+	designator: *const c_char,
+	departure: bool,
+	arrival: bool,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -1,23 +1,175 @@
-use crate::client::Aerodrome;
+use crate::client::{Aerodrome, ATTRIBUTION_TIME};
+use crate::config::{Key, KeyAction};
 use crate::context::Context;
-use crate::{ActivityState, ClickType, ViewportGeo, ViewportNonGeo};
+use crate::snapshot::Image;
+use crate::{
+	ActivityState, Capability, ClickType, ColorVision, ConnectionState,
+	RenderBackend, ViewportGeo, ViewportNonGeo,
+};
 
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
-use std::time::{Duration, Instant};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::rc::{Rc, Weak};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
 
 use bars_config::{
-	BlockDisplay, BlockState, Color, EdgeCondition, EdgeDisplay, FillStyle, Geo,
-	GeoPoint, NodeCondition, NodeDisplay, Path, Point,
+	BlockDisplay, BlockState, ClosureDisplay, Color, EdgeCondition, EdgeDisplay,
+	FillStyle, Geo, GeoPoint, NodeCondition, NodeDisplay, Path, Point,
 };
 
+use chrono::{DateTime, Utc};
+
 use tracing::{trace, warn};
 
 use windows::Win32::Foundation::{COLORREF, POINT, RECT};
 use windows::Win32::Graphics::Gdi::{self, HBRUSH, HDC, HPEN};
+use windows::Win32::Graphics::GdiPlus;
+use windows::Win32::System::Com::IStream;
+use windows::Win32::UI::Shell;
+
+// distance from the position of an aircraft to the corners of its symbol
+const AIRCRAFT_SIZE: f64 = 4.0;
+const TEXT_HEIGHT: f64 = 12.0;
+const COUNTDOWN_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xff,
+	b: 0xff,
+	a: 0xff,
+};
+// most recent changes listed in the audit log overlay
+const AUDIT_LINES: usize = 8;
+const AUDIT_COLOR: Color = Color {
+	r: 0xe0,
+	g: 0xe0,
+	b: 0xe0,
+	a: 0xff,
+};
+const DIAGNOSTICS_COLOR: Color = Color {
+	r: 0xe0,
+	g: 0xe0,
+	b: 0x80,
+	a: 0xff,
+};
+const HOVER_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xff,
+	b: 0xff,
+	a: 0x40,
+};
+const AIRCRAFT_COLOR: Color = Color {
+	r: 0x40,
+	g: 0xe0,
+	b: 0xff,
+	a: 0x80,
+};
+// marks aircraft taxiing without stopbars shown in their simulator, which
+// must be given instructions by voice alone
+const NO_PILOT_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xa0,
+	b: 0x20,
+	a: 0xc0,
+};
+// colours of changes by other controllers, chosen by callsign
+const ATTRIBUTION_COLORS: [Color; 6] = [
+	Color {
+		r: 0xff,
+		g: 0x60,
+		b: 0x60,
+		a: 0x80,
+	},
+	Color {
+		r: 0x60,
+		g: 0xa0,
+		b: 0xff,
+		a: 0x80,
+	},
+	Color {
+		r: 0xff,
+		g: 0x60,
+		b: 0xff,
+		a: 0x80,
+	},
+	Color {
+		r: 0x60,
+		g: 0xff,
+		b: 0xff,
+		a: 0x80,
+	},
+	Color {
+		r: 0xff,
+		g: 0xff,
+		b: 0x60,
+		a: 0x80,
+	},
+	Color {
+		r: 0xa0,
+		g: 0x80,
+		b: 0xff,
+		a: 0x80,
+	},
+];
+const PENDING_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xb0,
+	b: 0x00,
+	a: 0x60,
+};
+const PREVIEW_COLOR: Color = Color {
+	r: 0x00,
+	g: 0xff,
+	b: 0x80,
+	a: 0x30,
+};
+// blocks of a route are highlighted for a while once it has been set
+const CONFIRM_COLOR: Color = Color {
+	r: 0x00,
+	g: 0xff,
+	b: 0x80,
+	a: 0x70,
+};
+const CONFIRM_TIME: Duration = Duration::from_millis(1000);
+const FLASH_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xff,
+	b: 0xff,
+	a: 0x90,
+};
+// nodes which change state are alternately highlighted and not for each
+// interval until the flash time has passed
+const FLASH_INTERVAL: Duration = Duration::from_millis(150);
+const FLASH_TIME: Duration = Duration::from_millis(1200);
+// edges of a block which has a route set light up one after another in the
+// direction of taxi, each this long after the one before
+const SEQUENCE_STEP: Duration = Duration::from_millis(120);
+// the minimap of a view is fitted into a square of this size in the bottom
+// right of the viewport
+const MINIMAP_SIZE: f64 = 160.0;
+const MINIMAP_MARGIN: f64 = 8.0;
+const MINIMAP_BORDER_COLOR: Color = Color {
+	r: 0x80,
+	g: 0x80,
+	b: 0x80,
+	a: 0xff,
+};
+const MINIMAP_VIEW_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xff,
+	b: 0xff,
+	a: 0x20,
+};
+const MINIMAP_VISIBLE_COLOR: Color = Color {
+	r: 0xff,
+	g: 0xff,
+	b: 0xff,
+	a: 0x60,
+};
 
-const DESELECT_AFTER: Duration = Duration::from_secs(3);
-
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq)]
 enum Target {
 	#[default]
 	None,
@@ -25,24 +177,145 @@ enum Target {
 	Block(u16),
 }
 
-struct Style {
+/// Styles are referred to by their index in the config.
+trait Renderer {
+	fn load_styles(&mut self, styles: &[bars_config::Style]);
+
+	fn clear_styles(&mut self);
+
+	fn has_styles(&self) -> bool;
+
+	unsafe fn begin(&self, hdc: HDC);
+
+	unsafe fn end(&self);
+
+	unsafe fn fill_rect(&self, rect: RECT, color: Color);
+
+	unsafe fn draw_path(&self, style: usize, points: &[(f64, f64)]);
+
+	/// The left edge of the text is vertically centred on `point`.
+	unsafe fn draw_text(&self, point: (f64, f64), text: &str, color: Color);
+
+	/// Filled only if the renderer can blend it with what is underneath.
+	unsafe fn highlight(&self, points: &[(f64, f64)], color: Color);
+
+	fn load_images(&mut self, images: &[&[u8]]);
+
+	/// `corners` are the top left, top right and bottom left.
+	unsafe fn draw_image(
+		&self,
+		image: usize,
+		corners: [(f64, f64); 3],
+		opacity: u8,
+	);
+}
+
+unsafe fn draw_gdi_text(
+	hdc: HDC,
+	(x, y): (f64, f64),
+	text: &str,
+	color: Color,
+) {
+	let text = text.encode_utf16().collect::<Vec<_>>();
+
+	Gdi::SelectObject(hdc, Gdi::GetStockObject(Gdi::DEFAULT_GUI_FONT));
+	Gdi::SetTextColor(hdc, GdiStyle::color(color));
+	Gdi::SetBkMode(hdc, Gdi::TRANSPARENT);
+	let _ = Gdi::TextOutW(
+		hdc,
+		x.round() as i32,
+		(y - TEXT_HEIGHT * 0.5).round() as i32,
+		&text,
+	);
+}
+
+fn new_renderer(
+	backend: RenderBackend,
+	cache: Rc<StyleCache>,
+) -> Box<dyn Renderer> {
+	match backend {
+		RenderBackend::Gdi => Box::new(GdiRenderer::new(cache)),
+		RenderBackend::GdiPlus => match GdiPlusRenderer::new(cache.clone()) {
+			Some(renderer) => Box::new(renderer),
+			None => {
+				warn!("GDI+ is unavailable; falling back to GDI");
+				Box::new(GdiRenderer::new(cache))
+			},
+		},
+	}
+}
+
+// identifies a style by its content, as stroke widths cannot be hashed
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct StyleKey(u32, Color, FillStyle, Color);
+
+impl StyleKey {
+	fn of(style: &bars_config::Style) -> Self {
+		Self(
+			style.stroke_width.to_bits(),
+			style.stroke_color,
+			style.fill_style,
+			style.fill_color,
+		)
+	}
+}
+
+/// Shared between screens, so that identical styles use the same GDI objects.
+#[derive(Default)]
+pub struct StyleCache {
+	gdi: RefCell<HashMap<StyleKey, Weak<GdiStyle>>>,
+	gdi_plus: RefCell<HashMap<StyleKey, Weak<GdiPlusStyle>>>,
+}
+
+impl StyleCache {
+	fn get<S>(
+		cache: &RefCell<HashMap<StyleKey, Weak<S>>>,
+		style: &bars_config::Style,
+		new: impl FnOnce(&bars_config::Style) -> S,
+	) -> Rc<S> {
+		let key = StyleKey::of(style);
+		let mut cache = cache.borrow_mut();
+
+		if let Some(shared) = cache.get(&key).and_then(Weak::upgrade) {
+			return shared
+		}
+
+		cache.retain(|_, shared| shared.strong_count() > 0);
+
+		let shared = Rc::new(new(style));
+		cache.insert(key, Rc::downgrade(&shared));
+		shared
+	}
+
+	fn gdi(&self, style: &bars_config::Style) -> Rc<GdiStyle> {
+		Self::get(&self.gdi, style, |style| unsafe { GdiStyle::new(style) })
+	}
+
+	fn gdi_plus(&self, style: &bars_config::Style) -> Rc<GdiPlusStyle> {
+		Self::get(&self.gdi_plus, style, |style| unsafe {
+			GdiPlusStyle::new(style)
+		})
+	}
+}
+
+struct GdiStyle {
 	brush: HBRUSH,
 	pen: HPEN,
 	filled: bool,
 }
 
-impl Style {
-	unsafe fn new(style: &bars_config::Style) -> Self {
-		fn color(color: Color) -> COLORREF {
-			COLORREF(
-				((color.b as u32) << 16) | ((color.g as u32) << 8) | color.r as u32,
-			)
-		}
+impl GdiStyle {
+	fn color(color: Color) -> COLORREF {
+		COLORREF(
+			((color.b as u32) << 16) | ((color.g as u32) << 8) | color.r as u32,
+		)
+	}
 
+	unsafe fn new(style: &bars_config::Style) -> Self {
 		let brush = if style.fill_style == FillStyle::None {
 			HBRUSH(Gdi::GetStockObject(Gdi::NULL_BRUSH).0)
 		} else if style.fill_style == FillStyle::Solid {
-			Gdi::CreateSolidBrush(color(style.fill_color))
+			Gdi::CreateSolidBrush(Self::color(style.fill_color))
 		} else {
 			Gdi::CreateHatchBrush(
 				match style.fill_style {
@@ -54,7 +327,7 @@ impl Style {
 					FillStyle::HatchCross => Gdi::HS_CROSS,
 					FillStyle::HatchDiagonalCross => Gdi::HS_DIAGCROSS,
 				},
-				color(style.fill_color),
+				Self::color(style.fill_color),
 			)
 		};
 
@@ -62,7 +335,7 @@ impl Style {
 			Gdi::CreatePen(
 				Gdi::PS_SOLID,
 				style.stroke_width.ceil() as i32,
-				color(style.stroke_color),
+				Self::color(style.stroke_color),
 			)
 		} else {
 			HPEN(Gdi::GetStockObject(Gdi::NULL_PEN).0)
@@ -81,7 +354,7 @@ impl Style {
 	}
 }
 
-impl Drop for Style {
+impl Drop for GdiStyle {
 	fn drop(&mut self) {
 		unsafe {
 			let _ = Gdi::DeleteObject(self.brush.into());
@@ -90,262 +363,1626 @@ impl Drop for Style {
 	}
 }
 
-pub struct Screen<'a> {
-	context: &'a mut Context,
-	icao: Option<String>,
-	view: Option<usize>,
-	transform: Transform,
-	targets: Option<Lookup2d<Target>>,
-	click_regions: Vec<RECT>,
-	selected: Option<(usize, Instant)>,
-	styles: Vec<Style>,
-	refresh_required: bool,
-	last_controlling: bool,
-	last_data: bool,
-	last_profile: usize,
+// draws with plain GDI, which is fast but aliased and ignores alpha
+struct GdiRenderer {
+	cache: Rc<StyleCache>,
+	styles: Vec<Rc<GdiStyle>>,
+	hdc: Cell<HDC>,
 }
 
-impl<'a> Screen<'a> {
-	pub fn new(context: &'a mut Context, geo: bool) -> Self {
+impl GdiRenderer {
+	fn new(cache: Rc<StyleCache>) -> Self {
 		Self {
-			context,
-			icao: None,
-			view: (!geo).then_some(0),
-			transform: Transform::new(),
-			targets: None,
-			click_regions: Vec::new(),
-			selected: None,
+			cache,
 			styles: Vec::new(),
-			refresh_required: true,
-			last_controlling: false,
-			last_data: false,
-			last_profile: usize::MAX,
+			hdc: Cell::new(HDC::default()),
 		}
 	}
 }
 
-impl Screen<'_> {
-	fn data(&self) -> Option<&Aerodrome> {
-		self.icao.as_ref().and_then(|icao| {
-			self
-				.context
-				.client()
-				.and_then(|client| client.aerodrome(icao))
-		})
-	}
-
-	fn data_mut(&mut self) -> Option<&mut Aerodrome> {
-		self.icao.as_ref().and_then(|icao| {
-			self
-				.context
-				.client_mut()
-				.and_then(|client| client.aerodrome_mut(icao))
-		})
-	}
-
-	pub fn aerodrome(&self) -> Option<&str> {
-		self.icao.as_ref().map(|s| s.as_str())
+impl Renderer for GdiRenderer {
+	fn load_styles(&mut self, styles: &[bars_config::Style]) {
+		self.styles = styles.iter().map(|style| self.cache.gdi(style)).collect();
 	}
 
-	pub fn set_aerodrome(&mut self, icao: Option<&str>) {
-		if let Some(icao) = &self.icao {
-			self.context.untrack_aerodrome(icao);
-		}
-		if let Some(icao) = &icao {
-			self.context.track_aerodrome(icao.to_string());
-		}
-
-		self.icao = icao.map(|s| s.to_string());
-
-		if let Some(targets) = self.targets.as_mut() {
-			targets.clear(Target::None);
-		}
+	fn clear_styles(&mut self) {
 		self.styles.clear();
-
-		self.refresh_required = true;
-		self.last_controlling = false;
-		self.last_profile = usize::MAX;
 	}
 
-	pub fn state(&self) -> ActivityState {
-		self
-			.data()
-			.map(|aerodrome| aerodrome.state())
-			.unwrap_or(ActivityState::None)
+	fn has_styles(&self) -> bool {
+		!self.styles.is_empty()
 	}
 
-	pub fn set_state(&mut self, state: ActivityState) {
-		if state == ActivityState::None {
-			return
-		}
-
-		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
-			if let Err(err) =
-				c.set_controlling(icao.clone(), state == ActivityState::Controlling)
-			{
-				warn!("failed to set state: {err}");
-			}
-
-			self.refresh_required = true;
-		}
+	unsafe fn begin(&self, hdc: HDC) {
+		self.hdc.set(hdc);
 	}
 
-	pub fn profiles(&self) -> Vec<String> {
-		self
-			.data()
-			.map(|aerodrome| {
-				aerodrome
-					.config()
-					.profiles
-					.iter()
-					.map(|profile| profile.name.clone())
-					.collect()
-			})
-			.unwrap_or(Vec::new())
+	unsafe fn end(&self) {
+		self.hdc.set(HDC::default());
 	}
 
-	pub fn profile(&self) -> usize {
-		self
-			.data()
-			.map(|aerodrome| aerodrome.profile())
-			.unwrap_or(0)
-	}
+	unsafe fn fill_rect(&self, rect: RECT, color: Color) {
+		let hdc = self.hdc.get();
 
-	pub fn set_profile(&mut self, i: usize) {
-		self.data_mut().map(|aerodrome| aerodrome.set_profile(i));
-		self.refresh_required = true;
+		let style = self.cache.gdi(&bars_config::Style {
+			stroke_width: 0.0,
+			stroke_color: Color::default(),
+			fill_style: FillStyle::Solid,
+			fill_color: color,
+		});
+		style.apply(hdc);
+		let _ = Gdi::Rectangle(hdc, rect.left, rect.top, rect.right, rect.bottom);
 	}
 
-	pub fn presets(&self) -> Vec<String> {
-		self
-			.data()
-			.map(|aerodrome| {
-				aerodrome.config().profiles[aerodrome.profile()]
-					.presets
-					.iter()
-					.map(|preset| preset.name.clone())
-					.collect()
-			})
-			.unwrap_or(Vec::new())
-	}
+	unsafe fn draw_path(&self, style: usize, points: &[(f64, f64)]) {
+		let Some(style) = self.styles.get(style) else {
+			return
+		};
 
-	// bug: if profile changes between preset() and apply_preset(...), wrong
-	// preset will be applied
-	pub fn apply_preset(&mut self, i: usize) {
-		self.data_mut().map(|aerodrome| aerodrome.apply_preset(i));
-	}
+		let hdc = self.hdc.get();
+		style.apply(hdc);
 
-	pub fn views(&self) -> Vec<String> {
-		self
-			.data()
-			.map(|aerodrome| {
-				aerodrome
-					.config()
-					.views
-					.iter()
-					.map(|view| view.name.clone())
-					.collect()
+		let points = points
+			.iter()
+			.map(|(x, y)| POINT {
+				x: x.round() as i32,
+				y: y.round() as i32,
 			})
-			.unwrap_or(Vec::new())
-	}
-
-	pub fn view(&self) -> usize {
-		self.view.unwrap_or(0)
-	}
-
-	pub fn set_view(&mut self, i: usize) {
-		if let Some(view) = self.view.as_mut() {
-			*view = i;
-			self.refresh_required = true;
-		}
-	}
-
-	pub fn is_pilot_enabled(&self, callsign: &str) -> bool {
-		self
-			.data()
-			.map(|aerodrome| aerodrome.is_pilot_enabled(callsign))
-			.unwrap_or(false)
-	}
+			.collect::<Vec<_>>();
 
-	fn load_styles(&mut self) {
-		self.styles = if let Some(data) = self.data() {
-			data
-				.config()
-				.styles
-				.iter()
-				.map(|style| unsafe { Style::new(style) })
-				.collect()
+		if style.filled {
+			let _ = Gdi::Polygon(hdc, points.as_slice());
 		} else {
-			return
-		};
+			let _ = Gdi::Polyline(hdc, points.as_slice());
+		}
 	}
 
-	fn project_points<T: Transformable>(&self, points: &[T]) -> Vec<(f64, f64)> {
-		points
-			.iter()
-			.map(|p| p.transform(&self.transform))
-			.collect()
+	unsafe fn draw_text(&self, point: (f64, f64), text: &str, color: Color) {
+		draw_gdi_text(self.hdc.get(), point, text, color);
 	}
 
-	unsafe fn draw_path<T: Clone + Debug + Transformable>(
-		&self,
-		hdc: HDC,
-		path: &Path<T>,
-	) {
-		if path.style >= self.styles.len() {
-			return
-		}
+	unsafe fn highlight(&self, points: &[(f64, f64)], color: Color) {
+		let hdc = self.hdc.get();
 
-		let style = &self.styles[path.style];
+		let style = self.cache.gdi(&bars_config::Style {
+			stroke_width: 1.0,
+			stroke_color: color,
+			fill_style: FillStyle::None,
+			fill_color: Color::default(),
+		});
 		style.apply(hdc);
 
-		let points = path
-			.points
+		let points = points
 			.iter()
-			.map(|p| p.transform(&self.transform))
 			.map(|(x, y)| POINT {
 				x: x.round() as i32,
 				y: y.round() as i32,
 			})
 			.collect::<Vec<_>>();
-
-		if style.filled {
-			let _ = Gdi::Polygon(hdc, points.as_slice());
-		} else {
-			let _ = Gdi::Polyline(hdc, points.as_slice());
-		}
+		let _ = Gdi::Polygon(hdc, points.as_slice());
 	}
 
-	fn setup_targets<'a, T: Clone + Debug + Transformable + 'a>(
+	// plain GDI can neither rotate nor blend images, so rasters are only drawn
+	// with GDI+
+	fn load_images(&mut self, _images: &[&[u8]]) {}
+
+	unsafe fn draw_image(
 		&self,
-		size: [f64; 2],
-		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
-		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
-		targets: &mut Lookup2d<Target>,
+		_image: usize,
+		_corners: [(f64, f64); 3],
+		_opacity: u8,
 	) {
-		let width = size[0].round() as usize;
-		let height = size[1].round() as usize;
+	}
+}
 
-		if targets.width == width && targets.data.len() == width * height {
-			targets.clear(Target::None);
-		} else {
-			*targets = Lookup2d::new(Target::None, width, height);
-		}
+struct GdiPlusStyle {
+	brush: *mut GdiPlus::GpBrush,
+	pen: *mut GdiPlus::GpPen,
+}
 
-		for (i, block) in blocks.enumerate() {
-			let points = self.project_points(&block.target.points);
-			targets.add_poly(Target::Block(i as u16), &points);
-		}
+impl GdiPlusStyle {
+	fn color(color: Color) -> u32 {
+		((color.a as u32) << 24)
+			| ((color.r as u32) << 16)
+			| ((color.g as u32) << 8)
+			| color.b as u32
+	}
 
-		let Some(aerodrome) = self.data() else { return };
-		let profile = &aerodrome.config().profiles[aerodrome.profile()];
+	unsafe fn new(style: &bars_config::Style) -> Self {
+		let fill = Self::color(style.fill_color);
+
+		let mut brush = std::ptr::null_mut();
+		let hatch = match style.fill_style {
+			FillStyle::None => None,
+			FillStyle::Solid => {
+				let mut solid = std::ptr::null_mut();
+				GdiPlus::GdipCreateSolidFill(fill, &mut solid);
+				brush = solid.cast();
+				None
+			},
+			FillStyle::HatchHorizontal => Some(GdiPlus::HatchStyleHorizontal),
+			FillStyle::HatchVertical => Some(GdiPlus::HatchStyleVertical),
+			FillStyle::HatchForwardDiagonal => {
+				Some(GdiPlus::HatchStyleForwardDiagonal)
+			},
+			FillStyle::HatchBackwardDiagonal => {
+				Some(GdiPlus::HatchStyleBackwardDiagonal)
+			},
+			FillStyle::HatchCross => Some(GdiPlus::HatchStyleCross),
+			FillStyle::HatchDiagonalCross => Some(GdiPlus::HatchStyleDiagonalCross),
+		};
+		if let Some(hatch) = hatch {
+			let mut hatched = std::ptr::null_mut();
+			GdiPlus::GdipCreateHatchBrush(hatch, fill, 0, &mut hatched);
+			brush = hatched.cast();
+		}
 
-		for (i, node) in nodes.enumerate() {
-			if !matches!(profile.nodes[i], NodeCondition::Fixed { .. }) {
-				let points = self.project_points(&node.target.points);
-				targets.add_poly(Target::Node(i as u16), &points);
+		let mut pen = std::ptr::null_mut();
+		if style.stroke_width > 0.0 {
+			GdiPlus::GdipCreatePen1(
+				Self::color(style.stroke_color),
+				style.stroke_width,
+				GdiPlus::UnitPixel,
+				&mut pen,
+			);
+			if !pen.is_null() {
+				GdiPlus::GdipSetPenLineJoin(pen, GdiPlus::LineJoinRound);
 			}
 		}
+
+		Self { brush, pen }
+	}
+}
+
+impl Drop for GdiPlusStyle {
+	fn drop(&mut self) {
+		unsafe {
+			if !self.brush.is_null() {
+				GdiPlus::GdipDeleteBrush(self.brush);
+			}
+			if !self.pen.is_null() {
+				GdiPlus::GdipDeletePen(self.pen);
+			}
+		}
+	}
+}
+
+// a decoded image, which GDI+ reads from its stream for as long as it exists
+struct GdiPlusImage {
+	image: *mut GdiPlus::GpImage,
+	_stream: IStream,
+}
+
+impl GdiPlusImage {
+	unsafe fn new(data: &[u8]) -> Option<Self> {
+		let stream = Shell::SHCreateMemStream(Some(data))?;
+
+		let mut bitmap = std::ptr::null_mut();
+		let status = GdiPlus::GdipCreateBitmapFromStream(&stream, &mut bitmap);
+		if status != GdiPlus::Ok || bitmap.is_null() {
+			warn!("failed to decode raster: status {}", status.0);
+			return None
+		}
+
+		Some(Self {
+			image: bitmap.cast(),
+			_stream: stream,
+		})
+	}
+}
+
+impl Drop for GdiPlusImage {
+	fn drop(&mut self) {
+		unsafe {
+			GdiPlus::GdipDisposeImage(self.image);
+		}
+	}
+}
+
+// draws with GDI+, which anti-aliases and blends by the alpha of each colour
+struct GdiPlusRenderer {
+	cache: Rc<StyleCache>,
+	styles: Vec<Rc<GdiPlusStyle>>,
+	images: Vec<Option<GdiPlusImage>>,
+	graphics: Cell<*mut GdiPlus::GpGraphics>,
+}
+
+impl GdiPlusRenderer {
+	fn new(cache: Rc<StyleCache>) -> Option<Self> {
+		// GDI+ stays initialised until the process exits, as shutting it down
+		// from a DLL is unsafe
+		static TOKEN: OnceLock<Option<usize>> = OnceLock::new();
+
+		TOKEN
+			.get_or_init(|| unsafe {
+				let input = GdiPlus::GdiplusStartupInput {
+					GdiplusVersion: 1,
+					..Default::default()
+				};
+				let mut token = 0;
+				let status =
+					GdiPlus::GdiplusStartup(&mut token, &input, std::ptr::null_mut());
+
+				if status == GdiPlus::Ok {
+					Some(token)
+				} else {
+					warn!("failed to start GDI+: status {}", status.0);
+					None
+				}
+			})
+			.as_ref()?;
+
+		Some(Self {
+			cache,
+			styles: Vec::new(),
+			images: Vec::new(),
+			graphics: Cell::new(std::ptr::null_mut()),
+		})
+	}
+
+	fn points(points: &[(f64, f64)]) -> Vec<GdiPlus::PointF> {
+		points
+			.iter()
+			.map(|&(x, y)| GdiPlus::PointF {
+				X: x as f32,
+				Y: y as f32,
+			})
+			.collect()
+	}
+
+	unsafe fn draw_styled(&self, style: &GdiPlusStyle, points: &[(f64, f64)]) {
+		let graphics = self.graphics.get();
+		if graphics.is_null() || points.is_empty() {
+			return
+		}
+
+		let points = Self::points(points);
+		let count = points.len() as i32;
+
+		if !style.brush.is_null() {
+			GdiPlus::GdipFillPolygon(
+				graphics,
+				style.brush,
+				points.as_ptr(),
+				count,
+				GdiPlus::FillModeAlternate,
+			);
+			if !style.pen.is_null() {
+				GdiPlus::GdipDrawPolygon(graphics, style.pen, points.as_ptr(), count);
+			}
+		} else if !style.pen.is_null() {
+			GdiPlus::GdipDrawLines(graphics, style.pen, points.as_ptr(), count);
+		}
+	}
+}
+
+impl Renderer for GdiPlusRenderer {
+	fn load_styles(&mut self, styles: &[bars_config::Style]) {
+		self.styles = styles
+			.iter()
+			.map(|style| self.cache.gdi_plus(style))
+			.collect();
+	}
+
+	fn clear_styles(&mut self) {
+		self.styles.clear();
+		self.images.clear();
+	}
+
+	fn has_styles(&self) -> bool {
+		!self.styles.is_empty()
+	}
+
+	unsafe fn begin(&self, hdc: HDC) {
+		self.end();
+
+		let mut graphics = std::ptr::null_mut();
+		if GdiPlus::GdipCreateFromHDC(hdc, &mut graphics) != GdiPlus::Ok {
+			warn!("failed to create GDI+ graphics");
+			return
+		}
+
+		GdiPlus::GdipSetSmoothingMode(graphics, GdiPlus::SmoothingModeAntiAlias);
+		GdiPlus::GdipSetPixelOffsetMode(graphics, GdiPlus::PixelOffsetModeHalf);
+		self.graphics.set(graphics);
+	}
+
+	unsafe fn end(&self) {
+		let graphics = self.graphics.replace(std::ptr::null_mut());
+		if !graphics.is_null() {
+			GdiPlus::GdipDeleteGraphics(graphics);
+		}
+	}
+
+	unsafe fn fill_rect(&self, rect: RECT, color: Color) {
+		let graphics = self.graphics.get();
+		if graphics.is_null() {
+			return
+		}
+
+		let mut brush = std::ptr::null_mut();
+		GdiPlus::GdipCreateSolidFill(GdiPlusStyle::color(color), &mut brush);
+		if brush.is_null() {
+			return
+		}
+
+		GdiPlus::GdipFillRectangle(
+			graphics,
+			brush.cast(),
+			rect.left as f32,
+			rect.top as f32,
+			(rect.right - rect.left) as f32,
+			(rect.bottom - rect.top) as f32,
+		);
+		GdiPlus::GdipDeleteBrush(brush.cast());
+	}
+
+	unsafe fn draw_path(&self, style: usize, points: &[(f64, f64)]) {
+		if let Some(style) = self.styles.get(style) {
+			self.draw_styled(style, points);
+		}
+	}
+
+	unsafe fn draw_text(&self, point: (f64, f64), text: &str, color: Color) {
+		let graphics = self.graphics.get();
+		if graphics.is_null() {
+			return
+		}
+
+		// text is drawn with GDI on the device context under the graphics
+		let mut hdc = HDC::default();
+		if GdiPlus::GdipGetDC(graphics, &mut hdc) == GdiPlus::Ok {
+			draw_gdi_text(hdc, point, text, color);
+			GdiPlus::GdipReleaseDC(graphics, hdc);
+		}
+	}
+
+	unsafe fn highlight(&self, points: &[(f64, f64)], color: Color) {
+		let style = self.cache.gdi_plus(&bars_config::Style {
+			stroke_width: 1.0,
+			stroke_color: Color {
+				a: color.a.saturating_mul(2),
+				..color
+			},
+			fill_style: FillStyle::Solid,
+			fill_color: color,
+		});
+
+		self.draw_styled(&style, points);
+	}
+
+	fn load_images(&mut self, images: &[&[u8]]) {
+		self.images = images
+			.iter()
+			.map(|data| unsafe { GdiPlusImage::new(data) })
+			.collect();
+	}
+
+	unsafe fn draw_image(
+		&self,
+		image: usize,
+		corners: [(f64, f64); 3],
+		opacity: u8,
+	) {
+		let graphics = self.graphics.get();
+		let Some(Some(image)) = self.images.get(image) else {
+			return
+		};
+		if graphics.is_null() || opacity == 0 {
+			return
+		}
+
+		let mut width = 0;
+		let mut height = 0;
+		GdiPlus::GdipGetImageWidth(image.image, &mut width);
+		GdiPlus::GdipGetImageHeight(image.image, &mut height);
+
+		// scales the alpha of every pixel by the opacity
+		let mut attributes = std::ptr::null_mut();
+		if opacity < 0xff {
+			GdiPlus::GdipCreateImageAttributes(&mut attributes);
+			if !attributes.is_null() {
+				let mut matrix = GdiPlus::ColorMatrix::default();
+				for i in 0..5 {
+					matrix.m[i * 6] = 1.0;
+				}
+				matrix.m[18] = opacity as f32 / 255.0;
+
+				GdiPlus::GdipSetImageAttributesColorMatrix(
+					attributes,
+					GdiPlus::ColorAdjustTypeBitmap,
+					true,
+					&matrix,
+					std::ptr::null(),
+					GdiPlus::ColorMatrixFlagsDefault,
+				);
+			}
+		}
+
+		let points = Self::points(&corners);
+		GdiPlus::GdipDrawImagePointsRect(
+			graphics,
+			image.image,
+			points.as_ptr(),
+			points.len() as i32,
+			0.0,
+			0.0,
+			width as f32,
+			height as f32,
+			GdiPlus::UnitPixel,
+			attributes,
+			0,
+			std::ptr::null_mut(),
+		);
+
+		if !attributes.is_null() {
+			GdiPlus::GdipDisposeImageAttributes(attributes);
+		}
+	}
+}
+
+impl Drop for GdiPlusRenderer {
+	fn drop(&mut self) {
+		unsafe { self.end() }
+	}
+}
+
+// another aerodrome drawn beneath the selected one on a geo screen, with its
+// own renderer as style indices are specific to each config
+struct Overlay {
+	icao: String,
+	enabled: bool,
+	renderer: Box<dyn Renderer>,
+}
+
+pub struct Screen<'a> {
+	context: &'a mut Context,
+	icao: Option<String>,
+	overlays: Vec<Overlay>,
+	view: Option<usize>,
+	transform: Transform,
+	targets: TargetIndex,
+	// transform, view, and profile which the targets were built for
+	targets_key: Option<(Transform, Option<usize>, usize)>,
+	click_tolerance: f64,
+	// physical pixels per logical pixel of the display, by which sizes in
+	// pixels are scaled
+	dpi_scale: f64,
+	// applied to config colours, so that they suit the display
+	brightness: f64,
+	contrast: f64,
+	color_vision: ColorVision,
+	aircraft_labels: bool,
+	// whether recent changes are listed in the corner of the screen
+	audit_log: bool,
+	// whether the state of the connection is listed in the corner of the screen
+	diagnostics: bool,
+	// logical size of the viewport, as last set
+	viewport_size: (f64, f64),
+	hovered: Target,
+	// areas of the viewport where clicks go to the screen rather than to
+	// EuroScope, before those around aircraft are left out
+	claimed_regions: Vec<RECT>,
+	click_regions: Vec<RECT>,
+	// whether a view claims the whole viewport while controlling, rather than
+	// only the areas with targets
+	claim_viewport: bool,
+	// distance in pixels around aircraft on geo screens left to EuroScope
+	aircraft_clearance: f64,
+	selected: Option<(usize, Instant)>,
+	// blocks of the route which clicking the hovered node would set
+	preview: Vec<usize>,
+	// blocks of the route last set, and when
+	confirmation: Option<(Vec<usize>, Instant)>,
+	// node states as of the last animation tick, and the nodes which have
+	// changed state since with when they did
+	node_states: Vec<bool>,
+	flashes: Vec<(usize, Instant)>,
+	// block states as of the last animation tick, and the blocks which have
+	// had a route set since with when they did
+	block_states: Vec<BlockState>,
+	sequences: Vec<(usize, Instant)>,
+	// area of the viewport covered by the minimap, and its transform from map
+	// coordinates
+	minimap: Option<(RECT, Transform)>,
+	backend: RenderBackend,
+	renderer: Box<dyn Renderer>,
+	refresh_required: bool,
+	last_controlling: bool,
+	last_data: bool,
+	last_profile: usize,
+	last_revision: Option<usize>,
+}
+
+impl<'a> Screen<'a> {
+	pub fn new(context: &'a mut Context, geo: bool) -> Self {
+		let cache = context.style_cache();
+		let color_vision = context.color_vision();
+		let backend = context.render_backend();
+		let click_tolerance = context.click_tolerance();
+
+		Self {
+			context,
+			icao: None,
+			overlays: Vec::new(),
+			view: (!geo).then_some(0),
+			transform: Transform::new(),
+			targets: TargetIndex::default(),
+			targets_key: None,
+			click_tolerance,
+			dpi_scale: 1.0,
+			brightness: 1.0,
+			contrast: 1.0,
+			color_vision,
+			aircraft_labels: true,
+			audit_log: false,
+			diagnostics: false,
+			viewport_size: (0.0, 0.0),
+			hovered: Target::None,
+			claimed_regions: Vec::new(),
+			click_regions: Vec::new(),
+			claim_viewport: true,
+			aircraft_clearance: 0.0,
+			selected: None,
+			preview: Vec::new(),
+			confirmation: None,
+			node_states: Vec::new(),
+			flashes: Vec::new(),
+			block_states: Vec::new(),
+			sequences: Vec::new(),
+			minimap: None,
+			backend,
+			renderer: new_renderer(backend, cache),
+			refresh_required: true,
+			last_controlling: false,
+			last_data: false,
+			last_profile: usize::MAX,
+			last_revision: None,
+		}
+	}
+}
+
+impl Screen<'_> {
+	fn data(&self) -> Option<&Aerodrome> {
+		self.icao.as_ref().and_then(|icao| {
+			self
+				.context
+				.client()
+				.and_then(|client| client.aerodrome(icao))
+		})
+	}
+
+	fn data_mut(&mut self) -> Option<&mut Aerodrome> {
+		self.icao.as_ref().and_then(|icao| {
+			self
+				.context
+				.client_mut()
+				.and_then(|client| client.aerodrome_mut(icao))
+		})
+	}
+
+	pub fn aerodrome(&self) -> Option<&str> {
+		self.icao.as_deref()
+	}
+
+	pub fn set_aerodrome(&mut self, icao: Option<&str>) {
+		if let Some(icao) = &self.icao {
+			self.context.untrack_aerodrome(icao);
+		}
+		if let Some(icao) = &icao {
+			self.context.track_aerodrome(icao.to_string());
+		}
+
+		self.icao = icao.map(|s| s.to_string());
+
+		self.clear_aerodrome();
+	}
+
+	// forgets everything derived from the config of the aerodrome
+	fn clear_aerodrome(&mut self) {
+		self.node_states.clear();
+		self.flashes.clear();
+		self.block_states.clear();
+		self.sequences.clear();
+		self.confirmation = None;
+
+		self.clear_targets();
+		self.renderer.clear_styles();
+
+		self.refresh_required = true;
+		self.last_controlling = false;
+		self.last_profile = usize::MAX;
+	}
+
+	// clears what refers to nodes and blocks by index once the config has been
+	// reloaded, as they may have changed
+	fn follow_reload(&mut self) {
+		let revision = self.data().map(|aerodrome| aerodrome.revision());
+		let last = std::mem::replace(&mut self.last_revision, revision);
+		if revision.is_some() && last.is_some() && revision != last {
+			self.selected = None;
+			self.preview.clear();
+			self.hovered = Target::None;
+			self.clear_aerodrome();
+		}
+	}
+
+	pub fn reload_config(&mut self) {
+		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
+			if let Err(err) = c.reload_config(icao.clone()) {
+				warn!("failed to reload config: {err}");
+			}
+		}
+	}
+
+	pub fn take_over(&mut self) {
+		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
+			if let Err(err) = c.take_over(icao.clone()) {
+				warn!("failed to take over: {err}");
+			}
+		}
+	}
+
+	pub fn overlays(&self) -> Vec<String> {
+		self
+			.overlays
+			.iter()
+			.map(|overlay| overlay.icao.clone())
+			.collect()
+	}
+
+	pub fn is_overlay_enabled(&self, i: usize) -> bool {
+		self.overlays.get(i).is_some_and(|overlay| overlay.enabled)
+	}
+
+	/// Only geo screens draw overlays.
+	pub fn set_overlay(&mut self, icao: &str, enabled: bool) {
+		if self.view.is_some() {
+			return
+		}
+
+		if let Some(overlay) = self.overlays.iter_mut().find(|o| o.icao == icao) {
+			overlay.enabled = enabled;
+		} else {
+			self.context.track_aerodrome(icao.to_string());
+			self.overlays.push(Overlay {
+				icao: icao.to_string(),
+				enabled,
+				renderer: new_renderer(self.backend, self.context.style_cache()),
+			});
+		}
+	}
+
+	pub fn remove_overlay(&mut self, icao: &str) {
+		if let Some(i) = self.overlays.iter().position(|o| o.icao == icao) {
+			let overlay = self.overlays.remove(i);
+			self.context.untrack_aerodrome(&overlay.icao);
+		}
+	}
+
+	pub fn state(&self) -> ActivityState {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.state())
+			.unwrap_or(ActivityState::None)
+	}
+
+	pub fn set_state(&mut self, state: ActivityState) {
+		if state == ActivityState::None {
+			return
+		}
+
+		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
+			if let Err(err) =
+				c.set_controlling(icao.clone(), state == ActivityState::Controlling)
+			{
+				warn!("failed to set state: {err}");
+			}
+
+			self.refresh_required = true;
+		}
+	}
+
+	pub fn profiles(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.config()
+					.profiles
+					.iter()
+					.map(|profile| profile.name.clone())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn profile(&self) -> usize {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.profile())
+			.unwrap_or(0)
+	}
+
+	pub fn set_profile(&mut self, i: usize) {
+		if let Some(aerodrome) = self.data_mut() {
+			aerodrome.set_profile(i)
+		}
+
+		// the selection is kept for a route to be set from it in the new profile
+		let routable = |node| {
+			self.data().is_some_and(|aerodrome| {
+				aerodrome.config().profiles[aerodrome.profile()].nodes[node]
+					== NodeCondition::Router
+			})
+		};
+		if let Some((node, _)) = self.selected {
+			if !routable(node) {
+				self.selected = None;
+				self.preview.clear();
+			}
+		}
+
+		self.refresh_required = true;
+	}
+
+	pub fn select_suggested_profile(&mut self) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		match self
+			.data()
+			.and_then(|aerodrome| aerodrome.suggested_profile())
+		{
+			Some(i) => {
+				self.set_profile(i);
+				true
+			},
+			None => false,
+		}
+	}
+
+	pub fn presets(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome.config().profiles[aerodrome.profile()]
+					.presets
+					.iter()
+					.map(|preset| preset.name.clone())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn profile_id(&self) -> Option<String> {
+		self.data().map(|aerodrome| {
+			aerodrome.config().profiles[aerodrome.profile()].id.clone()
+		})
+	}
+
+	// applies the preset named `preset` of the profile with the ID `profile`,
+	// unless another profile is selected since the presets were listed
+	pub fn apply_preset(&mut self, profile: &str, preset: &str) -> bool {
+		let Some(aerodrome) = self.data_mut() else {
+			return false
+		};
+
+		let selected = &aerodrome.config().profiles[aerodrome.profile()];
+		if selected.id != profile {
+			return false
+		}
+
+		let Some(i) = selected.presets.iter().position(|p| p.name == preset) else {
+			return false
+		};
+
+		aerodrome.record(|aerodrome| aerodrome.apply_preset(i));
+		true
+	}
+
+	pub fn departures(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.departures()
+					.into_iter()
+					.map(|(callsign, node)| {
+						format!("{callsign} {}", aerodrome.config().nodes[node].id)
+					})
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn queue_departure(&mut self, callsign: &str, node: &str) -> bool {
+		self
+			.data_mut()
+			.is_some_and(|aerodrome| aerodrome.queue_departure(callsign, node))
+	}
+
+	pub fn remove_departure(&mut self, callsign: &str) {
+		if let Some(aerodrome) = self.data_mut() {
+			aerodrome.remove_departure(callsign)
+		}
+	}
+
+	pub fn undo(&mut self) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		self.data_mut().is_some_and(|aerodrome| aerodrome.undo())
+	}
+
+	pub fn redo(&mut self) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		self.data_mut().is_some_and(|aerodrome| aerodrome.redo())
+	}
+
+	pub fn restore_session(&mut self) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		self
+			.data_mut()
+			.is_some_and(|aerodrome| aerodrome.restore_session())
+	}
+
+	pub fn clear_block_edges(&mut self, id: &str) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		let Some(aerodrome) = self.data_mut() else {
+			return false
+		};
+		let Some(block) = aerodrome
+			.config()
+			.blocks
+			.iter()
+			.position(|block| block.id == id)
+		else {
+			return false
+		};
+
+		aerodrome.record(|aerodrome| aerodrome.clear_block_edges(block));
+		true
+	}
+
+	pub fn toggle_simulation(&mut self) -> bool {
+		let Some(icao) = self.icao.clone() else {
+			return false
+		};
+
+		if self.context.simulation() == Some(icao.as_str()) {
+			self.context.simulate(None);
+		} else {
+			self.context.simulate(Some(icao));
+		}
+
+		self.refresh_required = true;
+		self.context.simulation().is_some()
+	}
+
+	pub fn closures(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.config()
+					.closures
+					.iter()
+					.map(|closure| closure.id.clone())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn is_closure_active(&self, i: usize) -> bool {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.is_closure_active(i))
+			.unwrap_or_default()
+	}
+
+	pub fn set_closure(&mut self, i: usize, active: bool) {
+		if let Some(aerodrome) = self.data_mut() {
+			aerodrome.set_closure(i, active)
+		}
+	}
+
+	pub fn views(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.config()
+					.views
+					.iter()
+					.map(|view| view.name.clone())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn view(&self) -> usize {
+		self.view.unwrap_or(0)
+	}
+
+	pub fn set_view(&mut self, i: usize) {
+		if let Some(view) = self.view.as_mut() {
+			*view = i;
+			self.refresh_required = true;
+		}
+	}
+
+	pub fn handle_key(&mut self, key: Key) -> bool {
+		let Some(action) = self.context.key_action(&key).cloned() else {
+			return false
+		};
+
+		match action {
+			KeyAction::Preset(name) if self.is_controlling() => {
+				let profile = self.profile_id().unwrap_or_default();
+				if !self.apply_preset(&profile, &name) {
+					warn!("no preset {name:?} for key {key:?}");
+				}
+			},
+			KeyAction::Profile(name) if self.is_controlling() => {
+				match self.profiles().iter().position(|profile| *profile == name) {
+					Some(i) => self.set_profile(i),
+					None => warn!("no profile {name:?} for key {key:?}"),
+				}
+			},
+			KeyAction::NextProfile if self.is_controlling() => {
+				let n = self.profiles().len();
+				if n > 0 {
+					self.set_profile((self.profile() + 1) % n);
+				}
+			},
+			KeyAction::NextView | KeyAction::PreviousView if self.view.is_some() => {
+				let n = self.views().len();
+				if n > 0 {
+					let step = match action {
+						KeyAction::PreviousView => n - 1,
+						_ => 1,
+					};
+					self.set_view((self.view() + step) % n);
+				}
+			},
+			KeyAction::Undo => {
+				self.undo();
+			},
+			KeyAction::Redo => {
+				self.redo();
+			},
+			_ => (),
+		}
+
+		true
+	}
+
+	pub fn latency(&self) -> Option<u32> {
+		self.data().and_then(|aerodrome| aerodrome.latency())
+	}
+
+	pub fn endpoint(&self) -> Option<String> {
+		self.data()?.endpoint().map(str::to_string)
+	}
+
+	pub fn set_block_claim(&mut self, block: &str, claim: bool) -> bool {
+		let Target::Block(_) = self.find_target(block) else {
+			return false
+		};
+
+		let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref())
+		else {
+			return false
+		};
+
+		match c.set_claim(icao.clone(), vec![block.into()], claim) {
+			Ok(()) => true,
+			Err(err) => {
+				warn!("failed to set claim: {err}");
+				false
+			},
+		}
+	}
+
+	// requests a handoff from the controller at index `i` of `controllers`
+	pub fn request_handoff(&mut self, i: usize) {
+		let Some(controller_id) = self.data().and_then(|aerodrome| {
+			Some(aerodrome.controllers().get(i)?.controller_id.clone())
+		}) else {
+			return
+		};
+
+		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
+			if let Err(err) = c.request_handoff(icao.clone(), controller_id) {
+				warn!("failed to request handoff: {err}");
+			}
+		}
+	}
+
+	pub fn handoff_request(&self) -> Option<String> {
+		self
+			.data()
+			.and_then(|aerodrome| aerodrome.handoff_request())
+			.map(String::from)
+	}
+
+	pub fn respond_handoff(&mut self, accept: bool) {
+		if let Some((c, icao)) = self.context.client_mut().zip(self.icao.as_ref()) {
+			if let Err(err) = c.respond_handoff(icao.clone(), accept) {
+				warn!("failed to respond to handoff: {err}");
+			}
+		}
+	}
+
+	pub fn is_pilot_enabled(&self, callsign: &str) -> bool {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.is_pilot_enabled(callsign))
+			.unwrap_or(false)
+	}
+
+	pub fn controllers(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.controllers()
+					.iter()
+					.map(|controller| {
+						let callsign = controller
+							.callsign
+							.as_ref()
+							.unwrap_or(&controller.controller_id);
+						let details = controller
+							.position
+							.map(|position| position.name().to_string())
+							.into_iter()
+							.chain(controller.cid.map(|cid| cid.to_string()))
+							.chain(
+								aerodrome
+									.connected(callsign)
+									.and_then(|time| DateTime::from_timestamp_millis(time as i64))
+									.map(|time| format!("since {}", time.format("%H%Mz"))),
+							)
+							.collect::<Vec<_>>();
+
+						if details.is_empty() {
+							callsign.clone()
+						} else {
+							format!("{callsign} ({})", details.join(", "))
+						}
+					})
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn observers(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.observers().to_vec())
+			.unwrap_or_default()
+	}
+
+	pub fn observer_count(&self) -> usize {
+		self
+			.data()
+			.map(|aerodrome| aerodrome.observer_count())
+			.unwrap_or_default()
+	}
+
+	pub fn supports(&self, capability: Capability) -> bool {
+		self
+			.data()
+			.is_some_and(|aerodrome| aerodrome.supports(capability.into()))
+	}
+
+	pub fn set_render_backend(&mut self, backend: RenderBackend) {
+		self.backend = backend;
+		let cache = self.context.style_cache();
+		self.renderer = new_renderer(backend, cache.clone());
+		for overlay in &mut self.overlays {
+			overlay.renderer = new_renderer(backend, cache.clone());
+		}
+		self.refresh_required = true;
+	}
+
+	// the node or block with the ID `id`
+	fn find_target(&self, id: &str) -> Target {
+		let Some(aerodrome) = self.data() else {
+			return Target::None
+		};
+		let config = aerodrome.config();
+
+		if let Some(i) = config.nodes.iter().position(|n| n.id == id) {
+			Target::Node(i as u16)
+		} else if let Some(i) = config.blocks.iter().position(|b| b.id == id) {
+			Target::Block(i as u16)
+		} else {
+			Target::None
+		}
+	}
+
+	pub fn reset_in(&self, id: &str) -> Option<u32> {
+		let aerodrome = self.data()?;
+
+		let left = match self.find_target(id) {
+			Target::Node(i) => aerodrome.node_reset_in(i as usize),
+			Target::Block(i) => aerodrome.block_reset_in(i as usize),
+			Target::None => None,
+		}?;
+
+		Some(left.as_secs_f64().ceil() as u32)
+	}
+
+	pub fn timers(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.timers()
+					.into_iter()
+					.map(|(id, left)| format!("{id} {}s", left.as_secs_f64().ceil()))
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn extend_timer(&mut self, id: &str, secs: u32) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		let target = self.find_target(id);
+		let by = Duration::from_secs(secs.into());
+		self.data_mut().is_some_and(|aerodrome| match target {
+			Target::Node(i) => aerodrome.extend_node_timer(i as usize, by),
+			Target::Block(i) => aerodrome.extend_block_timer(i as usize, by),
+			Target::None => false,
+		})
+	}
+
+	pub fn cancel_timer(&mut self, id: &str) -> bool {
+		if !self.is_controlling() {
+			return false
+		}
+
+		let target = self.find_target(id);
+		self.data_mut().is_some_and(|aerodrome| match target {
+			Target::Node(i) => aerodrome.cancel_node_timer(i as usize),
+			Target::Block(i) => aerodrome.cancel_block_timer(i as usize),
+			Target::None => false,
+		})
+	}
+
+	fn connection_label(&self) -> &'static str {
+		match self.context.connection_state() {
+			ConnectionState::Disconnected => "disconnected",
+			ConnectionState::ConnectedDirect => "connected",
+			ConnectionState::ConnectedProxy => "connected via proxy",
+			ConnectionState::ConnectedLocal => "local",
+			ConnectionState::Poisoned => "failed",
+		}
+	}
+
+	pub fn status(&self) -> String {
+		let mut parts = vec![self.connection_label().to_string()];
+
+		if let Some(icao) = &self.icao {
+			parts.push(icao.clone());
+		}
+
+		if let Some(aerodrome) = self.data() {
+			parts.push(aerodrome.config().profiles[self.profile()].name.clone());
+
+			parts.push(match aerodrome.controllers().len() {
+				1 => "1 other controller".into(),
+				n => format!("{n} other controllers"),
+			});
+
+			parts.push(match aerodrome.sync_age() {
+				Some(age) => format!("synced {}s ago", age.as_secs()),
+				None => "not synced".into(),
+			});
+		}
+
+		parts.join(" | ")
+	}
+
+	pub fn set_claim_viewport(&mut self, claim: bool) {
+		self.claim_viewport = claim;
+		self.refresh_required = true;
+	}
+
+	pub fn set_aircraft_clearance(&mut self, clearance: f64) {
+		self.aircraft_clearance = if clearance.is_finite() {
+			clearance.max(0.0)
+		} else {
+			0.0
+		};
+	}
+
+	pub fn set_dpi_scale(&mut self, scale: f64) {
+		let scale = if scale.is_finite() && scale > 0.0 {
+			scale
+		} else {
+			1.0
+		};
+
+		if scale != self.dpi_scale {
+			self.dpi_scale = scale;
+			self.clear_styles();
+		}
+	}
+
+	fn click_tolerance(&self) -> f64 {
+		self.click_tolerance * self.dpi_scale
+	}
+
+	pub fn set_click_tolerance(&mut self, tolerance: f64) {
+		self.click_tolerance = if tolerance.is_finite() {
+			tolerance.max(0.0)
+		} else {
+			0.0
+		};
+		self.refresh_required = true;
+	}
+
+	/// `contrast` scales the distance from mid-grey before `brightness` applies.
+	pub fn set_color_adjustment(&mut self, brightness: f64, contrast: f64) {
+		let sanitise = |factor: f64| {
+			if factor.is_finite() {
+				factor.max(0.0)
+			} else {
+				1.0
+			}
+		};
+
+		self.brightness = sanitise(brightness);
+		self.contrast = sanitise(contrast);
+		self.clear_styles();
+	}
+
+	pub fn set_color_vision(&mut self, vision: ColorVision) {
+		self.color_vision = vision;
+		self.clear_styles();
+	}
+
+	fn clear_styles(&mut self) {
+		self.renderer.clear_styles();
+		for overlay in &mut self.overlays {
+			overlay.renderer.clear_styles();
+		}
+		self.refresh_required = true;
+	}
+
+	fn adjust_color(&self, color: Color) -> Color {
+		let color = remap_color(self.color_vision, color);
+
+		let adjust = |channel: u8| {
+			let value = channel as f64 / 255.0;
+			let value = ((value - 0.5) * self.contrast + 0.5) * self.brightness;
+			(value.clamp(0.0, 1.0) * 255.0).round() as u8
+		};
+
+		Color {
+			r: adjust(color.r),
+			g: adjust(color.g),
+			b: adjust(color.b),
+			a: color.a,
+		}
+	}
+
+	pub unsafe fn export_png(&mut self, hdc: HDC, area: RECT) -> bool {
+		let name = format!(
+			"bars-{}-{}.png",
+			self.icao.as_deref().unwrap_or("screen"),
+			Utc::now().format("%Y%m%dT%H%M%SZ"),
+		);
+		let path = self.context.dir().join(name);
+
+		match Image::capture(hdc, area).and_then(|image| image.save_png(&path)) {
+			Ok(()) => {
+				let message = format!("saved snapshot to {}", path.display());
+				self.context.add_message(message);
+				true
+			},
+			Err(err) => {
+				warn!("failed to save snapshot: {err}");
+				self
+					.context
+					.add_message(format!("failed to save snapshot: {err}"));
+				false
+			},
+		}
+	}
+
+	pub fn set_aircraft_labels(&mut self, labels: bool) {
+		self.aircraft_labels = labels;
+	}
+
+	pub fn set_audit_log(&mut self, shown: bool) {
+		self.audit_log = shown;
+	}
+
+	pub fn audit_log(&self) -> Vec<String> {
+		self
+			.data()
+			.map(|aerodrome| {
+				aerodrome
+					.audit_log()
+					.rev()
+					.map(|entry| entry.to_string())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	pub fn set_diagnostics(&mut self, shown: bool) {
+		self.diagnostics = shown;
+	}
+
+	pub fn diagnostics(&self) -> String {
+		let aerodromes = self
+			.context
+			.client()
+			.map(|client| {
+				client
+					.aerodromes()
+					.map(|aerodrome| {
+						(aerodrome.config().icao.clone(), aerodrome.diagnostics())
+					})
+					.collect::<BTreeMap<_, _>>()
+			})
+			.unwrap_or_default();
+
+		serde_json::json!({
+			"connection": self.connection_label(),
+			"aerodromes": aerodromes,
+		})
+		.to_string()
+	}
+
+	fn load_styles(&mut self) {
+		let Some(icao) = self.icao.clone() else {
+			return
+		};
+		self.load_styles_of(&icao);
+	}
+
+	fn load_styles_of(&mut self, icao: &String) {
+		let Some(aerodrome) = self
+			.context
+			.client()
+			.and_then(|client| client.aerodrome(icao))
+		else {
+			return
+		};
+
+		let styles = aerodrome
+			.config()
+			.styles
+			.iter()
+			.map(|style| bars_config::Style {
+				stroke_width: style.stroke_width * self.dpi_scale as f32,
+				stroke_color: self.adjust_color(style.stroke_color),
+				fill_color: self.adjust_color(style.fill_color),
+				..style.clone()
+			})
+			.collect::<Vec<_>>();
+
+		self.renderer.load_styles(&styles);
+
+		let images = aerodrome
+			.config()
+			.rasters
+			.iter()
+			.map(|raster| raster.image.as_slice())
+			.collect::<Vec<_>>();
+		self.renderer.load_images(&images);
+	}
+
+	fn project_points<T: Transformable>(&self, points: &[T]) -> Vec<(f64, f64)> {
+		points
+			.iter()
+			.map(|p| p.transform(&self.transform))
+			.collect()
+	}
+
+	unsafe fn draw_path<T: Clone + Debug + Transformable>(&self, path: &Path<T>) {
+		let points = self.project_points(&path.points);
+		self.renderer.draw_path(path.style, &points);
+	}
+
+	fn setup_targets<'a, T: Clone + Debug + Transformable + 'a>(
+		&self,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+	) -> TargetIndex {
+		let mut targets = blocks
+			.enumerate()
+			.map(|(i, block)| {
+				let mut points = self.project_points(&block.target.points);
+				if points.len() < 3 {
+					if let Some(aerodrome) = self.data() {
+						points = self.block_edge_target(aerodrome, i);
+					}
+				}
+
+				(Target::Block(i as u16), points)
+			})
+			.collect::<Vec<_>>();
+
+		if let Some(aerodrome) = self.data() {
+			let profile = &aerodrome.config().profiles[aerodrome.profile()];
+
+			for (i, node) in nodes.enumerate() {
+				if !matches!(profile.nodes[i], NodeCondition::Fixed { .. }) {
+					let points = self.project_points(&node.target.points);
+					targets.push((Target::Node(i as u16), points));
+				}
+			}
+		}
+
+		TargetIndex::new(targets)
+	}
+
+	// rebuilds the targets if the transform, view, or profile has changed since
+	// they were last built
+	fn update_targets(&mut self) {
+		let key = (self.transform, self.view, self.profile());
+		if self.targets_key == Some(key) {
+			return
+		}
+
+		let Some(aerodrome) = self.data() else { return };
+
+		let targets = if let Some(view) = self.view {
+			let Some(view) = aerodrome.config().views.get(view) else {
+				return
+			};
+			let map = &aerodrome.config().maps[view.map];
+
+			self.setup_targets(map.nodes.iter(), map.blocks.iter())
+		} else {
+			self.setup_targets(
+				aerodrome.config().nodes.iter().map(|node| &node.display),
+				aerodrome.config().blocks.iter().map(|block| &block.display),
+			)
+		};
+
+		self.targets = targets;
+		self.targets_key = Some(key);
+	}
+
+	fn clear_targets(&mut self) {
+		self.targets = TargetIndex::default();
+		self.targets_key = None;
+		self.hovered = Target::None;
+		self.preview.clear();
+	}
+
+	// a rectangle around the lights of the edges of a block, for blocks drawn
+	// without a target of their own
+	fn block_edge_target(
+		&self,
+		aerodrome: &Aerodrome,
+		block: usize,
+	) -> Vec<(f64, f64)> {
+		let config = aerodrome.config();
+		let map = self
+			.view
+			.and_then(|view| config.views.get(view))
+			.map(|view| &config.maps[view.map]);
+
+		let points = config.blocks[block]
+			.edges
+			.iter()
+			.flat_map(|edge| match map {
+				Some(map) => map
+					.edges
+					.get(*edge)
+					.into_iter()
+					.flat_map(|display| &display.on)
+					.flat_map(|path| self.project_points(&path.points))
+					.collect::<Vec<_>>(),
+				None => config.edges[*edge]
+					.display
+					.on
+					.iter()
+					.flat_map(|path| self.project_points(&path.points))
+					.collect(),
+			})
+			.collect::<Vec<_>>();
+
+		let bounds = Bounds::of(&points);
+		if bounds.min.0 > bounds.max.0 {
+			return Vec::new()
+		}
+
+		let padding = self.click_tolerance();
+		let (min, max) = (
+			(bounds.min.0 - padding, bounds.min.1 - padding),
+			(bounds.max.0 + padding, bounds.max.1 + padding),
+		);
+		vec![min, (max.0, min.1), max, (min.0, max.1)]
+	}
+
+	// projected points of the target of a node or block
+	fn target_points(
+		&self,
+		aerodrome: &Aerodrome,
+		target: Target,
+	) -> Option<Vec<(f64, f64)>> {
+		let config = aerodrome.config();
+
+		if let Target::Block(i) = target {
+			let block = config.blocks.get(i as usize)?;
+			let own = match self.view {
+				Some(view) => {
+					let map = &config.maps[config.views.get(view)?.map];
+					map.blocks.get(i as usize)?.target.points.len()
+				},
+				None => block.display.target.points.len(),
+			};
+
+			if own < 3 {
+				return Some(self.block_edge_target(aerodrome, i as usize))
+			}
+		}
+
+		Some(match (self.view, target) {
+			(_, Target::None) => return None,
+			(None, Target::Node(i)) => self
+				.project_points(&config.nodes.get(i as usize)?.display.target.points),
+			(None, Target::Block(i)) => self
+				.project_points(&config.blocks.get(i as usize)?.display.target.points),
+			(Some(view), target) => {
+				let map = &config.maps[config.views.get(view)?.map];
+				match target {
+					Target::Node(i) => {
+						self.project_points(&map.nodes.get(i as usize)?.target.points)
+					},
+					Target::Block(i) => {
+						self.project_points(&map.blocks.get(i as usize)?.target.points)
+					},
+					Target::None => return None,
+				}
+			},
+		})
+	}
+
+	// blocks of the route from the selected node to the hovered one, if any
+	fn route_preview(&self) -> Vec<usize> {
+		let (Some((node, _)), Target::Node(target)) = (self.selected, self.hovered)
+		else {
+			return Vec::new()
+		};
+
+		if node == target as usize {
+			return Vec::new()
+		}
+
+		self
+			.data()
+			.and_then(|aerodrome| {
+				aerodrome
+					.find_route((node, target as usize), self.context.route_hop_limit())
+			})
+			.map(|route| route.into_iter().map(|(block, _)| block).collect())
+			.unwrap_or_default()
 	}
 
 	fn is_controlling(&self) -> bool {
@@ -355,56 +1992,112 @@ impl Screen<'_> {
 			.unwrap_or_default()
 	}
 
-	pub fn draw_background_geo(&mut self, _hdc: HDC, viewport: ViewportGeo) {
-		const CELL_SIZE: usize = 20;
-		const THRESHOLD: usize = 100;
-
+	pub fn draw_background_geo(&mut self, hdc: HDC, viewport: ViewportGeo) {
 		let instant_start = std::time::Instant::now();
 
 		let _ = self.is_background_refresh_required();
 
-		if self.styles.is_empty() {
+		if !self.renderer.has_styles() {
 			self.load_styles();
 		}
 
-		self.click_regions.clear();
-		self.transform = Transform::new_geo(viewport);
+		self.claimed_regions.clear();
+		self.transform = Transform::new_geo(viewport, self.dpi_scale);
+
+		self.draw_rasters(hdc);
 
 		if !self.is_controlling() {
+			self.clear_targets();
 			return
 		}
 
-		let mut targets = self.targets.take().unwrap_or_default();
+		self.update_targets();
+		self.claimed_regions = self.target_regions(viewport.size);
 
+		trace!("bg {:?}", instant_start.elapsed());
+	}
+
+	fn draw_rasters(&self, hdc: HDC) {
 		let Some(aerodrome) = self.data() else { return };
+		let rasters = &aerodrome.config().rasters;
+		if rasters.is_empty() {
+			return
+		}
 
-		self.setup_targets(
-			viewport.size,
-			aerodrome.config().nodes.iter().map(|node| &node.display),
-			aerodrome.config().blocks.iter().map(|block| &block.display),
-			&mut targets,
-		);
+		unsafe { self.renderer.begin(hdc) };
+
+		for (i, raster) in rasters.iter().enumerate() {
+			let transform =
+				|lat: f32, lon: f32| self.transform.transform((lat as f64, lon as f64));
+			let nw = transform(raster.north, raster.west);
+			let ne = transform(raster.north, raster.east);
+			let sw = transform(raster.south, raster.west);
+			let se = transform(raster.south, raster.east);
+
+			// rotated anticlockwise on screen, where y increases downwards
+			let (sin, cos) = (raster.rotation as f64).to_radians().sin_cos();
+			let centre = ((nw.0 + se.0) * 0.5, (nw.1 + se.1) * 0.5);
+			let rotate = |(x, y): (f64, f64)| {
+				let (dx, dy) = (x - centre.0, y - centre.1);
+				(
+					centre.0 + dx * cos + dy * sin,
+					centre.1 - dx * sin + dy * cos,
+				)
+			};
+
+			unsafe {
+				self.renderer.draw_image(
+					i,
+					[rotate(nw), rotate(ne), rotate(sw)],
+					raster.opacity,
+				);
+			}
+		}
 
-		// this isn't very good
+		unsafe { self.renderer.end() };
+	}
+
+	// cells of the viewport in which enough of a grid of points hit a target,
+	// merged along each row
+	fn target_regions(&self, size: [f64; 2]) -> Vec<RECT> {
+		const CELL_SIZE: usize = 20;
+		const SAMPLE_STEP: usize = 4;
+		const THRESHOLD: usize = 6;
+
+		let mut regions = Vec::new();
 
-		let width = viewport.size[0].round() as usize;
-		let height = viewport.size[1].round() as usize;
+		let scaled =
+			|n: usize| ((n as f64 * self.dpi_scale).round() as usize).max(1);
+		let cell_size = scaled(CELL_SIZE);
+		let sample_step = scaled(SAMPLE_STEP);
 
-		for by in 0..height / CELL_SIZE {
-			let cy = by * CELL_SIZE;
+		let width = size[0].round() as usize;
+		let height = size[1].round() as usize;
+
+		for by in 0..height / cell_size {
+			let cy = by * cell_size;
 
 			let mut startx = 0;
 
-			for bx in 0..width / CELL_SIZE {
-				let cx = bx * CELL_SIZE;
+			for bx in 0..width / cell_size {
+				let cx = bx * cell_size;
+
+				let cell = Bounds {
+					min: (cx as f64, cy as f64),
+					max: ((cx + cell_size) as f64, (cy + cell_size) as f64),
+				};
 
 				let mut n = 0;
-				'a: for x in 0..CELL_SIZE {
-					for y in 0..CELL_SIZE {
-						if !matches!(targets.sample(cx + x, cy + y), Target::None) {
-							n += 1;
-							if n > THRESHOLD {
-								break 'a
+				if self.targets.intersects(&cell) {
+					'a: for x in (sample_step / 2..cell_size).step_by(sample_step) {
+						for y in (sample_step / 2..cell_size).step_by(sample_step) {
+							let point = ((cx + x) as f64, (cy + y) as f64);
+							let target = self.targets.sample(point, self.click_tolerance());
+							if !matches!(target, Target::None) {
+								n += 1;
+								if n > THRESHOLD {
+									break 'a
+								}
 							}
 						}
 					}
@@ -412,11 +2105,11 @@ impl Screen<'_> {
 
 				if n <= THRESHOLD {
 					if startx < bx {
-						self.click_regions.push(RECT {
-							left: (startx * CELL_SIZE) as i32,
+						regions.push(RECT {
+							left: (startx * cell_size) as i32,
 							top: cy as i32,
 							right: cx as i32,
-							bottom: (cy + CELL_SIZE) as i32,
+							bottom: (cy + cell_size) as i32,
 						});
 					}
 
@@ -424,19 +2117,44 @@ impl Screen<'_> {
 				}
 			}
 
-			if startx < width / CELL_SIZE {
-				self.click_regions.push(RECT {
-					left: (startx * CELL_SIZE) as i32,
+			if startx < width / cell_size {
+				regions.push(RECT {
+					left: (startx * cell_size) as i32,
 					top: cy as i32,
 					right: width as i32,
-					bottom: (cy + CELL_SIZE) as i32,
+					bottom: (cy + cell_size) as i32,
 				});
 			}
 		}
 
-		self.targets = Some(targets);
+		regions
+	}
+
+	// leaves the area around each aircraft on geo screens to EuroScope, so that
+	// radar targets over BARS targets can still be clicked
+	fn update_click_regions(&mut self) {
+		self.click_regions.clone_from(&self.claimed_regions);
 
-		trace!("bg {:?}", instant_start.elapsed());
+		if self.view.is_some() || self.aircraft_clearance <= 0.0 {
+			return
+		}
+
+		let clearance = self.aircraft_clearance * self.dpi_scale;
+		for aircraft in self.context.aircraft() {
+			let (x, y) = self.transform.transform((aircraft.lat, aircraft.lon));
+			let hole = RECT {
+				left: (x - clearance).floor() as i32,
+				top: (y - clearance).floor() as i32,
+				right: (x + clearance).ceil() as i32,
+				bottom: (y + clearance).ceil() as i32,
+			};
+
+			self.click_regions = self
+				.click_regions
+				.iter()
+				.flat_map(|region| subtract(region, &hole))
+				.collect();
+		}
 	}
 
 	pub fn draw_background_non_geo(
@@ -448,155 +2166,890 @@ impl Screen<'_> {
 
 		let _ = self.is_background_refresh_required();
 
-		if self.styles.is_empty() {
+		if !self.renderer.has_styles() {
 			self.load_styles();
 		}
 
-		self.click_regions.clear();
+		self.claimed_regions.clear();
+
+		let Some(aerodrome) = self.data() else { return };
+		let Some(view) = aerodrome.config().views.get(self.view.unwrap()) else {
+			return
+		};
+
+		self.transform = Transform::new_view(viewport, view.bounds);
 
 		if self.is_controlling() {
-			self.click_regions.push(RECT {
-				left: 0 as i32,
-				top: 0 as i32,
-				right: viewport.size[0] as i32,
-				bottom: viewport.size[1] as i32,
-			});
+			self.update_targets();
+
+			if self.claim_viewport {
+				self.claimed_regions.push(RECT {
+					left: 0,
+					top: 0,
+					right: viewport.size[0] as i32,
+					bottom: viewport.size[1] as i32,
+				});
+			} else {
+				self.claimed_regions = self.target_regions(viewport.size);
+			}
+		} else {
+			self.clear_targets();
 		}
 
-		let mut targets = self.targets.take().unwrap_or_default();
+		self.minimap = self.minimap_layout(viewport);
+		if let Some((rect, _)) = self.minimap {
+			self.claimed_regions.push(rect);
+		}
 
 		let Some(aerodrome) = self.data() else { return };
 		let Some(view) = aerodrome.config().views.get(self.view.unwrap()) else {
 			return
 		};
 
-		self.setup_targets(
-			viewport.size,
-			aerodrome.config().maps[view.map]
-				.nodes
-				.iter()
-				.map(|node| node),
-			aerodrome.config().maps[view.map]
-				.blocks
+		let map = &aerodrome.config().maps[view.map];
+
+		unsafe {
+			self.renderer.begin(hdc);
+			self.renderer.fill_rect(
+				RECT {
+					left: viewport.origin[0] as i32,
+					top: viewport.origin[1] as i32,
+					right: viewport.size[0] as i32,
+					bottom: viewport.size[1] as i32,
+				},
+				self.adjust_color(map.background),
+			);
+
+			for path in &map.base {
+				self.draw_path(path);
+			}
+
+			self.draw_minimap(aerodrome, viewport);
+
+			self.renderer.end();
+		}
+
+		trace!("bg {:?}", instant_start.elapsed());
+	}
+
+	// places the minimap of the current view, covering its map and every view
+	// of it, if there are other views of the map to jump to and it would fit
+	fn minimap_layout(
+		&self,
+		viewport: ViewportNonGeo,
+	) -> Option<(RECT, Transform)> {
+		let config = self.data()?.config();
+		let map = config.views.get(self.view?)?.map;
+
+		let views = config.views.iter().filter(|view| view.map == map);
+		if views.clone().count() < 2 {
+			return None
+		}
+
+		let corners = |bounds: bars_config::Box| {
+			Bounds::of(&[
+				(bounds.min.x as f64, bounds.min.y as f64),
+				(bounds.max.x as f64, bounds.max.y as f64),
+			])
+		};
+
+		let extent = config.maps[map]
+			.base
+			.iter()
+			.flat_map(|path| &path.points)
+			.map(|point| (point.x as f64, point.y as f64))
+			.fold(Bounds::EMPTY, |extent, point| {
+				extent.union(&Bounds::of(&[point]))
+			});
+		let extent =
+			views.fold(extent, |extent, view| extent.union(&corners(view.bounds)));
+
+		let w = extent.max.0 - extent.min.0;
+		let h = extent.max.1 - extent.min.1;
+		if !(w > 0.0 && h > 0.0) {
+			return None
+		}
+
+		let margin = MINIMAP_MARGIN * self.dpi_scale;
+		let scale = MINIMAP_SIZE * self.dpi_scale / w.max(h);
+		let (width, height) = (w * scale, h * scale);
+		let left = viewport.size[0] - margin - width;
+		let top = viewport.size[1] - margin - height;
+		if left < viewport.size[0] * 0.5 || top < viewport.size[1] * 0.5 {
+			return None
+		}
+
+		let transform = Transform::new_view(
+			ViewportNonGeo {
+				origin: [left, top],
+				size: [width, height],
+				zoom: 1.0,
+				pan: [left, top],
+			},
+			bars_config::Box {
+				min: Point {
+					x: extent.min.0 as f32,
+					y: extent.min.1 as f32,
+				},
+				max: Point {
+					x: extent.max.0 as f32,
+					y: extent.max.1 as f32,
+				},
+			},
+		);
+
+		let rect = RECT {
+			left: left.floor() as i32,
+			top: top.floor() as i32,
+			right: (left + width).ceil() as i32,
+			bottom: (top + height).ceil() as i32,
+		};
+
+		Some((rect, transform))
+	}
+
+	// draws the base of the map in the minimap, marking each of its views and
+	// the part of the current one which is visible
+	unsafe fn draw_minimap(
+		&self,
+		aerodrome: &Aerodrome,
+		viewport: ViewportNonGeo,
+	) {
+		let Some((rect, transform)) = self.minimap else {
+			return
+		};
+		let Some(view) = self.view else { return };
+
+		let config = aerodrome.config();
+		let map = config.views[view].map;
+
+		self.renderer.fill_rect(
+			RECT {
+				left: rect.left - 1,
+				top: rect.top - 1,
+				right: rect.right + 1,
+				bottom: rect.bottom + 1,
+			},
+			MINIMAP_BORDER_COLOR,
+		);
+		self
+			.renderer
+			.fill_rect(rect, self.adjust_color(config.maps[map].background));
+
+		for path in &config.maps[map].base {
+			let points = path
+				.points
 				.iter()
-				.map(|block| block),
-			&mut targets,
+				.map(|point| point.transform(&transform))
+				.collect::<Vec<_>>();
+			self.renderer.draw_path(path.style, &points);
+		}
+
+		let project = |min: (f64, f64), max: (f64, f64)| {
+			[
+				transform.transform(min),
+				transform.transform((max.0, min.1)),
+				transform.transform(max),
+				transform.transform((min.0, max.1)),
+			]
+		};
+
+		for other in config.views.iter().filter(|other| other.map == map) {
+			let bounds = other.bounds;
+			let points = project(
+				(bounds.min.x as f64, bounds.min.y as f64),
+				(bounds.max.x as f64, bounds.max.y as f64),
+			);
+			self.renderer.highlight(&points, MINIMAP_VIEW_COLOR);
+		}
+
+		let (Some(min), Some(max)) = (
+			self.transform.invert((0.0, 0.0)),
+			self.transform.invert((viewport.size[0], viewport.size[1])),
+		) else {
+			return
+		};
+		self
+			.renderer
+			.highlight(&project(min, max), MINIMAP_VISIBLE_COLOR);
+	}
+
+	fn is_in_minimap(&self, point: POINT) -> bool {
+		self.minimap.is_some_and(|(rect, _)| {
+			(rect.left..rect.right).contains(&point.x)
+				&& (rect.top..rect.bottom).contains(&point.y)
+		})
+	}
+
+	// the smallest view of the current map containing the point of the minimap,
+	// if any
+	fn minimap_view(&self, point: POINT) -> Option<usize> {
+		if !self.is_in_minimap(point) {
+			return None
+		}
+
+		let (_, transform) = self.minimap?;
+
+		let (x, y) =
+			transform.invert((point.x as f64 + 0.5, point.y as f64 + 0.5))?;
+		let (x, y) = (x as f32, y as f32);
+
+		let config = self.data()?.config();
+		let map = config.views.get(self.view?)?.map;
+
+		config
+			.views
+			.iter()
+			.enumerate()
+			.filter(|(_, view)| {
+				view.map == map
+					&& (view.bounds.min.x..=view.bounds.max.x).contains(&x)
+					&& (view.bounds.min.y..=view.bounds.max.y).contains(&y)
+			})
+			.min_by(|(_, a), (_, b)| {
+				let area = |view: &bars_config::View| {
+					(view.bounds.max.x - view.bounds.min.x)
+						* (view.bounds.max.y - view.bounds.min.y)
+				};
+				area(a).total_cmp(&area(b))
+			})
+			.map(|(i, _)| i)
+	}
+
+	fn draw_items<'a, T: Clone + Debug + Transformable + 'a>(
+		&self,
+		aerodrome: &Aerodrome,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+		edges: impl Iterator<Item = &'a EdgeDisplay<T>>,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+		selected: Option<usize>,
+		unlit: &[usize],
+	) {
+		for (i, edge) in edges.enumerate() {
+			if let EdgeCondition::Fixed { state: false } =
+				aerodrome.config().profiles[aerodrome.profile()].edges[i]
+			{
+				continue
+			}
+
+			let display = if aerodrome.edge_state(i) && !unlit.contains(&i) {
+				&edge.on
+			} else {
+				&edge.off
+			};
+
+			for path in display {
+				unsafe {
+					self.draw_path(path);
+				}
+			}
+		}
+
+		for (i, block) in blocks.enumerate() {
+			let display = match aerodrome.block_state(i) {
+				BlockState::Clear => continue,
+				BlockState::Relax => &block.relax,
+				BlockState::Route(_) => &block.route,
+			};
+
+			for path in display {
+				unsafe {
+					self.draw_path(path);
+				}
+			}
+		}
+
+		for (i, node) in nodes.enumerate() {
+			if aerodrome.config().nodes[i].parent.is_some() {
+				continue
+			}
+
+			if aerodrome.config().profiles[aerodrome.profile()].nodes[i]
+				== (NodeCondition::Fixed { state: false })
+			{
+				continue
+			}
+
+			let display = if aerodrome.node_state(i) {
+				&node.on
+			} else {
+				&node.off
+			};
+
+			for path in display {
+				unsafe {
+					self.draw_path(path);
+				}
+			}
+
+			if selected == Some(i) {
+				for path in &node.selected {
+					unsafe {
+						self.draw_path(path);
+					}
+				}
+			}
+		}
+	}
+
+	// labels each target which will be reset automatically with the seconds
+	// remaining
+	fn draw_countdowns<'a, T: Clone + Debug + Transformable + 'a>(
+		&self,
+		aerodrome: &Aerodrome,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+	) {
+		let nodes = nodes
+			.enumerate()
+			.map(|(i, node)| (aerodrome.node_reset_in(i), &node.target));
+		let blocks = blocks
+			.enumerate()
+			.map(|(i, block)| (aerodrome.block_reset_in(i), &block.target));
+
+		for (left, target) in nodes.chain(blocks) {
+			let Some(left) = left else { continue };
+
+			let bounds = Bounds::of(&self.project_points(&target.points));
+			if bounds.min.0 > bounds.max.0 {
+				continue
+			}
+
+			let secs = left.as_secs_f64().ceil();
+			let point = (bounds.max.0 + 4.0 * self.dpi_scale, bounds.centre().1);
+			unsafe {
+				self
+					.renderer
+					.draw_text(point, &format!("{secs}s"), COUNTDOWN_COLOR);
+			}
+		}
+	}
+
+	// highlights each target with a change which the server has yet to confirm,
+	// pulsing on alternate seconds
+	fn draw_pending<'a, T: Clone + Debug + Transformable + 'a>(
+		&self,
+		aerodrome: &Aerodrome,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+	) {
+		let pulse = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.map(|time| time.as_secs() % 2 == 0)
+			.unwrap_or(true);
+		if !pulse {
+			return
+		}
+
+		let nodes = nodes
+			.enumerate()
+			.map(|(i, node)| (aerodrome.is_node_unconfirmed(i), &node.target));
+		let blocks = blocks
+			.enumerate()
+			.map(|(i, block)| (aerodrome.is_block_unconfirmed(i), &block.target));
+
+		for (unconfirmed, target) in nodes.chain(blocks) {
+			if unconfirmed && !target.points.is_empty() {
+				unsafe {
+					self
+						.renderer
+						.highlight(&self.project_points(&target.points), PENDING_COLOR);
+				}
+			}
+		}
+	}
+
+	// highlights each target recently changed by another controller in their
+	// colour, fading out as the change ages
+	fn draw_changes<'a, T: Clone + Debug + Transformable + 'a>(
+		&self,
+		aerodrome: &Aerodrome,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+	) {
+		let nodes = nodes
+			.enumerate()
+			.map(|(i, node)| (aerodrome.node_changed_by(i), &node.target));
+		let blocks = blocks
+			.enumerate()
+			.map(|(i, block)| (aerodrome.block_changed_by(i), &block.target));
+
+		for (change, target) in nodes.chain(blocks) {
+			let Some((controller, age)) = change else {
+				continue
+			};
+			if target.points.is_empty() {
+				continue
+			}
+
+			let mut hasher = DefaultHasher::new();
+			controller.hash(&mut hasher);
+			let color =
+				ATTRIBUTION_COLORS[hasher.finish() as usize % ATTRIBUTION_COLORS.len()];
+
+			let left = 1.0 - age.as_secs_f64() / ATTRIBUTION_TIME.as_secs_f64();
+			let color = Color {
+				a: (color.a as f64 * left.clamp(0.0, 1.0)).round() as u8,
+				..color
+			};
+
+			unsafe {
+				self
+					.renderer
+					.highlight(&self.project_points(&target.points), color);
+			}
+		}
+	}
+
+	// draws a symbol for each aircraft, at its position on geo screens or at the
+	// block it occupies in views, marking those taxiing without the pilot
+	// client while connected to a network
+	fn draw_aircraft(&self, aerodrome: &Aerodrome) {
+		let config = aerodrome.config();
+		let networked = matches!(
+			self.context.connection_state(),
+			ConnectionState::ConnectedDirect | ConnectionState::ConnectedProxy
 		);
 
-		self.transform = Transform::new_view(viewport, view.bounds);
-		self.targets = Some(targets);
+		for aircraft in self.context.aircraft() {
+			let position = (aircraft.lat, aircraft.lon);
+			let block = config.blocks.iter().position(|block| {
+				let polygon = block
+					.display
+					.target
+					.points
+					.iter()
+					.map(|gp| (gp.geo.lat as f64, gp.geo.lon as f64))
+					.collect::<Vec<_>>();
+				contains(&polygon, position)
+			});
+
+			let (x, y) = if let Some(view) = self.view {
+				let Some(i) = block else { continue };
+
+				let map = &config.maps[config.views[view].map];
+				let Some(block) = map.blocks.get(i) else {
+					continue
+				};
+
+				let bounds = Bounds::of(&self.project_points(&block.target.points));
+				if bounds.min.0 > bounds.max.0 {
+					continue
+				}
+
+				bounds.centre()
+			} else {
+				self.transform.transform(position)
+			};
+
+			let size = AIRCRAFT_SIZE * self.dpi_scale;
+			let symbol = [(x, y - size), (x + size, y), (x, y + size), (x - size, y)];
+
+			unsafe {
+				self.renderer.highlight(&symbol, AIRCRAFT_COLOR);
+
+				if networked
+					&& block.is_some()
+					&& !aerodrome.is_pilot_enabled(&aircraft.callsign)
+				{
+					let size = size * 2.0;
+					self.renderer.highlight(
+						&[
+							(x - size, y - size),
+							(x + size, y - size),
+							(x + size, y + size),
+							(x - size, y + size),
+						],
+						NO_PILOT_COLOR,
+					);
+				}
+
+				if self.aircraft_labels {
+					self.renderer.draw_text(
+						(x + size + 2.0 * self.dpi_scale, y),
+						&aircraft.callsign,
+						AIRCRAFT_COLOR,
+					);
+				}
+			}
+		}
+	}
 
-		let Some(aerodrome) = self.data() else { return };
-		let Some(view) = aerodrome.config().views.get(self.view.unwrap()) else {
+	// lists the most recent changes upwards from the bottom-left corner
+	fn draw_audit_log(&self, aerodrome: &Aerodrome) {
+		if !self.audit_log {
 			return
-		};
+		}
 
-		let map = &aerodrome.config().maps[view.map];
+		let x = 8.0 * self.dpi_scale;
+		let mut y = self.viewport_size.1 - 8.0 * self.dpi_scale - TEXT_HEIGHT * 0.5;
 
-		unsafe {
-			Style::new(&bars_config::Style {
-				stroke_width: 0.0,
-				stroke_color: Color::default(),
-				fill_style: FillStyle::Solid,
-				fill_color: map.background,
-			})
-			.apply(hdc);
-			let _ = Gdi::Rectangle(
-				hdc,
-				viewport.origin[0] as i32,
-				viewport.origin[1] as i32,
-				viewport.size[0] as i32,
-				viewport.size[1] as i32,
-			);
+		for entry in aerodrome.audit_log().rev().take(AUDIT_LINES) {
+			unsafe {
+				self
+					.renderer
+					.draw_text((x, y), &entry.to_string(), AUDIT_COLOR);
+			}
+			y -= TEXT_HEIGHT + 2.0;
+		}
+	}
+
+	// lists the state of the connection downwards from the top-left corner
+	fn draw_diagnostics(&self, aerodrome: &Aerodrome) {
+		if !self.diagnostics {
+			return
 		}
 
-		for path in &map.base {
+		let diagnostics = aerodrome.diagnostics();
+		let or_none = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+		let lines = [
+			format!("server: {}", or_none(diagnostics.server)),
+			format!(
+				"uptime: {}",
+				or_none(diagnostics.uptime.map(|secs| format!("{secs}s"))),
+			),
+			format!(
+				"last message: {}",
+				or_none(
+					diagnostics
+						.last_message_age
+						.map(|secs| format!("{secs}s ago"))
+				),
+			),
+			format!(
+				"rtt: {}",
+				or_none(diagnostics.rtt.map(|rtt| format!("{rtt}ms"))),
+			),
+			format!("reconnects: {}", diagnostics.reconnects),
+			format!("trackers: {}", diagnostics.trackers),
+		];
+
+		let x = 8.0 * self.dpi_scale;
+		let mut y = 8.0 * self.dpi_scale + TEXT_HEIGHT * 0.5;
+
+		for line in lines {
 			unsafe {
-				self.draw_path(hdc, path);
+				self.renderer.draw_text((x, y), &line, DIAGNOSTICS_COLOR);
 			}
+			y += TEXT_HEIGHT + 2.0;
 		}
-
-		trace!("bg {:?}", instant_start.elapsed());
 	}
 
-	fn draw_items<'a, T: Clone + Debug + Transformable + 'a>(
+	// drawn beneath everything else, so that lights remain visible over them
+	fn draw_closures<'a, T: Clone + Debug + Transformable + 'a>(
 		&self,
 		aerodrome: &Aerodrome,
-		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
-		edges: impl Iterator<Item = &'a EdgeDisplay<T>>,
-		hdc: HDC,
+		closures: impl Iterator<Item = &'a ClosureDisplay<T>>,
 	) {
-		for (i, edge) in edges.enumerate() {
-			if let EdgeCondition::Fixed { state: false } =
-				aerodrome.config().profiles[self.profile()].edges[i]
-			{
+		for (i, closure) in closures.enumerate() {
+			if !aerodrome.is_closure_active(i) {
 				continue
 			}
 
-			let display = if aerodrome.edge_state(i) {
-				&edge.on
-			} else {
-				&edge.off
-			};
-
-			for path in display {
+			for path in &closure.area {
 				unsafe {
-					self.draw_path(hdc, path);
+					self.draw_path(path);
 				}
 			}
 		}
+	}
 
-		for (i, node) in nodes.enumerate() {
-			if aerodrome.config().nodes[i].parent.is_some() {
-				continue
-			}
+	fn draw_geo(
+		&self,
+		aerodrome: &Aerodrome,
+		selected: Option<usize>,
+		unlit: &[usize],
+	) {
+		let config = aerodrome.config();
 
-			if aerodrome.config().profiles[self.profile()].nodes[i]
-				== (NodeCondition::Fixed { state: false })
+		self.draw_closures(
+			aerodrome,
+			config.closures.iter().map(|closure| &closure.display),
+		);
+		self.draw_items(
+			aerodrome,
+			config.nodes.iter().map(|node| &node.display),
+			config.edges.iter().map(|edge| &edge.display),
+			config.blocks.iter().map(|block| &block.display),
+			selected,
+			unlit,
+		);
+		self.draw_changes(
+			aerodrome,
+			config.nodes.iter().map(|node| &node.display),
+			config.blocks.iter().map(|block| &block.display),
+		);
+		self.draw_pending(
+			aerodrome,
+			config.nodes.iter().map(|node| &node.display),
+			config.blocks.iter().map(|block| &block.display),
+		);
+		self.draw_countdowns(
+			aerodrome,
+			config.nodes.iter().map(|node| &node.display),
+			config.blocks.iter().map(|block| &block.display),
+		);
+	}
+
+	// overlays are drawn with the screen's renderer swapped for their own, so
+	// that the drawing helpers can be shared
+	fn draw_overlays(&mut self, hdc: HDC) {
+		if self.view.is_some() {
+			return
+		}
+
+		for i in 0..self.overlays.len() {
+			if !self.overlays[i].enabled
+				|| self.icao.as_ref() == Some(&self.overlays[i].icao)
 			{
 				continue
 			}
 
-			let display = if aerodrome.node_state(i) {
-				&node.on
-			} else {
-				&node.off
-			};
+			let icao = self.overlays[i].icao.clone();
+			std::mem::swap(&mut self.renderer, &mut self.overlays[i].renderer);
 
-			for path in display {
+			if !self.renderer.has_styles() {
+				self.load_styles_of(&icao);
+			}
+
+			if let Some(aerodrome) = self
+				.context
+				.client()
+				.and_then(|client| client.aerodrome(&icao))
+			{
+				unsafe {
+					self.renderer.begin(hdc);
+				}
+				self.draw_geo(aerodrome, None, &[]);
 				unsafe {
-					self.draw_path(hdc, path);
+					self.renderer.end();
+				}
+			}
+
+			std::mem::swap(&mut self.renderer, &mut self.overlays[i].renderer);
+		}
+	}
+
+	/// Returns whether any nodes are still flashing.
+	pub fn tick_animation(&mut self) -> bool {
+		self.follow_reload();
+
+		self.flashes.retain(|(_, at)| at.elapsed() < FLASH_TIME);
+		self
+			.confirmation
+			.take_if(|(_, at)| at.elapsed() >= CONFIRM_TIME);
+
+		let Some(aerodrome) = self.data() else {
+			self.node_states.clear();
+			self.block_states.clear();
+			self.sequences.clear();
+			return !self.flashes.is_empty() || self.confirmation.is_some()
+		};
+
+		let config = aerodrome.config();
+		let node_states = (0..config.nodes.len())
+			.map(|i| aerodrome.node_state(i))
+			.collect::<Vec<_>>();
+		let block_states = (0..config.blocks.len())
+			.map(|i| aerodrome.block_state(i))
+			.collect::<Vec<_>>();
+		let sequence_times = (0..config.blocks.len())
+			.map(|i| SEQUENCE_STEP * aerodrome.block_edges(i).count() as u32)
+			.collect::<Vec<_>>();
+
+		let now = Instant::now();
+
+		// nothing has changed if the nodes were not known at the last tick
+		if node_states.len() == self.node_states.len() {
+			for (i, (old, new)) in
+				self.node_states.iter().zip(&node_states).enumerate()
+			{
+				if old != new {
+					self.flashes.retain(|(node, _)| *node != i);
+					self.flashes.push((i, now));
 				}
 			}
+		}
+
+		self.node_states = node_states;
+
+		self
+			.sequences
+			.retain(|(block, at)| at.elapsed() < sequence_times[*block]);
 
-			if self.selected.map(|(n, _)| n == i).unwrap_or_default()
-				&& self.selected.unwrap().1.elapsed() < DESELECT_AFTER
+		if block_states.len() == self.block_states.len() {
+			for (i, (old, new)) in
+				self.block_states.iter().zip(&block_states).enumerate()
 			{
-				for path in &node.selected {
-					unsafe {
-						self.draw_path(hdc, path);
+				if old != new {
+					self.sequences.retain(|(block, _)| *block != i);
+					if let BlockState::Route(_) = new {
+						self.sequences.push((i, now));
 					}
 				}
 			}
 		}
+
+		self.block_states = block_states;
+
+		!self.flashes.is_empty()
+			|| !self.sequences.is_empty()
+			|| self.confirmation.is_some()
+	}
+
+	// edges which are on but yet to light up in the sequence of their block,
+	// ordered by distance from the node through which the route enters it
+	fn unlit_edges(&self, aerodrome: &Aerodrome) -> Vec<usize> {
+		let mut unlit = Vec::new();
+		for (block, at) in &self.sequences {
+			let BlockState::Route((entry, _)) = aerodrome.block_state(*block) else {
+				continue
+			};
+
+			let Some(origin) = self
+				.target_points(aerodrome, Target::Node(entry as u16))
+				.filter(|points| !points.is_empty())
+				.map(|points| Bounds::of(&points).centre())
+			else {
+				continue
+			};
+
+			let mut edges = aerodrome
+				.block_edges(*block)
+				.filter(|edge| aerodrome.edge_state(*edge))
+				.filter_map(|edge| {
+					let (x, y) = self.edge_centre(aerodrome, edge)?;
+					Some((edge, (x - origin.0).hypot(y - origin.1)))
+				})
+				.collect::<Vec<_>>();
+			edges.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+			let lit =
+				(at.elapsed().as_millis() / SEQUENCE_STEP.as_millis()) as usize + 1;
+			unlit.extend(edges.iter().skip(lit).map(|(edge, _)| *edge));
+		}
+
+		unlit
+	}
+
+	// centre of the projected points of an edge when on
+	fn edge_centre(
+		&self,
+		aerodrome: &Aerodrome,
+		edge: usize,
+	) -> Option<(f64, f64)> {
+		let config = aerodrome.config();
+
+		let points = match self.view {
+			None => config
+				.edges
+				.get(edge)?
+				.display
+				.on
+				.iter()
+				.flat_map(|path| self.project_points(&path.points))
+				.collect::<Vec<_>>(),
+			Some(view) => config.maps[config.views.get(view)?.map]
+				.edges
+				.get(edge)?
+				.on
+				.iter()
+				.flat_map(|path| self.project_points(&path.points))
+				.collect(),
+		};
+
+		(!points.is_empty()).then(|| Bounds::of(&points).centre())
 	}
 
 	pub fn draw_foreground(&mut self, hdc: HDC) {
 		let instant_start = std::time::Instant::now();
 
+		self.follow_reload();
+
+		self.update_click_regions();
+
+		self.draw_overlays(hdc);
+
 		let Some(aerodrome) = self.data() else { return };
 
+		unsafe {
+			self.renderer.begin(hdc);
+		}
+
+		let selected = self
+			.selected
+			.filter(|(_, at)| at.elapsed() < self.context.selection_timeout())
+			.map(|(node, _)| node);
+
+		let unlit = self.unlit_edges(aerodrome);
+
 		if let Some(view) = self.view {
 			let map = &aerodrome.config().maps[aerodrome.config().views[view].map];
 
-			self.draw_items(aerodrome, map.nodes.iter(), map.edges.iter(), hdc);
-		} else {
+			self.draw_closures(aerodrome, map.closures.iter());
 			self.draw_items(
 				aerodrome,
-				aerodrome.config().nodes.iter().map(|node| &node.display),
-				aerodrome.config().edges.iter().map(|edge| &edge.display),
-				hdc,
+				map.nodes.iter(),
+				map.edges.iter(),
+				map.blocks.iter(),
+				selected,
+				&unlit,
 			);
+			self.draw_changes(aerodrome, map.nodes.iter(), map.blocks.iter());
+			self.draw_pending(aerodrome, map.nodes.iter(), map.blocks.iter());
+			self.draw_countdowns(aerodrome, map.nodes.iter(), map.blocks.iter());
+		} else {
+			self.draw_geo(aerodrome, selected, &unlit);
+		}
+
+		for (node, at) in &self.flashes {
+			let interval = at.elapsed().as_millis() / FLASH_INTERVAL.as_millis();
+			if !interval.is_multiple_of(2) {
+				continue
+			}
+
+			let target = Target::Node(*node as u16);
+			if let Some(points) = self.target_points(aerodrome, target) {
+				if !points.is_empty() {
+					unsafe {
+						self.renderer.highlight(&points, FLASH_COLOR);
+					}
+				}
+			}
+		}
+
+		self.draw_aircraft(aerodrome);
+		self.draw_audit_log(aerodrome);
+		self.draw_diagnostics(aerodrome);
+
+		if selected.is_some() {
+			for block in &self.preview {
+				let target = Target::Block(*block as u16);
+				if let Some(points) = self.target_points(aerodrome, target) {
+					unsafe {
+						self.renderer.highlight(&points, PREVIEW_COLOR);
+					}
+				}
+			}
+		}
+
+		for block in self.confirmation.iter().flat_map(|(blocks, _)| blocks) {
+			let target = Target::Block(*block as u16);
+			if let Some(points) = self.target_points(aerodrome, target) {
+				unsafe {
+					self.renderer.highlight(&points, CONFIRM_COLOR);
+				}
+			}
+		}
+
+		if let Some(points) = self.target_points(aerodrome, self.hovered) {
+			unsafe {
+				self.renderer.highlight(&points, HOVER_COLOR);
+			}
+		}
+
+		unsafe {
+			self.renderer.end();
 		}
 
 		if instant_start.elapsed() > Duration::from_millis(1) {
@@ -605,10 +3058,13 @@ impl Screen<'_> {
 	}
 
 	pub fn set_viewport_geo(&mut self, viewport: ViewportGeo) {
-		self.transform = Transform::new_geo(viewport);
+		self.viewport_size = (viewport.size[0], viewport.size[1]);
+		self.transform = Transform::new_geo(viewport, self.dpi_scale);
 	}
 
 	pub fn set_viewport_non_geo(&mut self, viewport: ViewportNonGeo) {
+		self.viewport_size = (viewport.size[0], viewport.size[1]);
+
 		let Some(aerodrome) = self.data() else { return };
 		let Some(view) = self.view else { return };
 
@@ -620,27 +3076,68 @@ impl Screen<'_> {
 		&self.click_regions
 	}
 
+	pub fn deselect(&mut self) -> bool {
+		self.preview.clear();
+		self.selected.take().is_some()
+	}
+
+	/// Returns whether the hovered target or previewed route has changed.
+	pub fn set_cursor(&mut self, point: POINT) -> bool {
+		self.follow_reload();
+
+		let target = if self.is_in_minimap(point) {
+			Target::None
+		} else {
+			self.targets.sample(
+				(point.x as f64 + 0.5, point.y as f64 + 0.5),
+				self.click_tolerance(),
+			)
+		};
+
+		if std::mem::replace(&mut self.hovered, target) == target {
+			return false
+		}
+
+		self.preview = self.route_preview();
+
+		true
+	}
+
 	pub fn handle_click(
 		&mut self,
 		point: POINT,
 		click: ClickType,
 	) -> Option<String> {
-		let target = self
-			.targets
-			.as_ref()
-			.map(|targets| *targets.sample(point.x as usize, point.y as usize))
-			.unwrap_or(Target::None);
+		self.follow_reload();
+
+		// clicking the minimap jumps to the view under the cursor
+		if self.is_in_minimap(point) {
+			if let Some(view) = self.minimap_view(point) {
+				if click == ClickType::Primary && self.view != Some(view) {
+					self.set_view(view);
+				}
+			}
+
+			return None
+		}
+
+		let target = self.targets.sample(
+			(point.x as f64 + 0.5, point.y as f64 + 0.5),
+			self.click_tolerance(),
+		);
 
 		let selection = self.selected.take();
+		self.preview.clear();
 		let geo = self.view.is_none();
+		let timeout = self.context.selection_timeout();
+		let limit = self.context.route_hop_limit();
 
-		let Some(data) = self.data_mut() else {
-			return None
-		};
+		let data = self.data_mut()?;
 
 		match target {
 			Target::None => {
-				if geo {
+				// right-clicking away from targets deselects
+				if geo && click == ClickType::Primary {
 					self.selected = selection;
 				}
 
@@ -651,12 +3148,17 @@ impl Screen<'_> {
 					match data.config().profiles[data.profile()].nodes[id as usize] {
 						NodeCondition::Fixed { .. } => (),
 						NodeCondition::Direct { .. } => {
-							data.set_node(id as usize, !data.node_state(id as usize));
+							let state = !data.node_state(id as usize);
+							data.record(|data| data.set_node(id as usize, state));
 						},
 						NodeCondition::Router => {
 							if let Some((node, at)) = selection {
-								if at.elapsed() < DESELECT_AFTER {
-									data.set_route((node, id as usize));
+								if at.elapsed() < timeout {
+									let blocks = data
+										.record(|data| data.set_route((node, id as usize), limit));
+									if !blocks.is_empty() {
+										self.confirmation = Some((blocks, Instant::now()));
+									}
 								}
 							}
 
@@ -670,13 +3172,11 @@ impl Screen<'_> {
 				}
 			},
 			Target::Block(id) => {
-				data.set_block(
-					id as usize,
-					match click {
-						ClickType::Primary => BlockState::Clear,
-						ClickType::Auxiliary => BlockState::Relax,
-					},
-				);
+				let state = match click {
+					ClickType::Primary => BlockState::Clear,
+					ClickType::Auxiliary => BlockState::Relax,
+				};
+				data.record(|data| data.set_block(id as usize, state));
 
 				None
 			},
@@ -685,6 +3185,8 @@ impl Screen<'_> {
 
 	#[must_use]
 	pub fn is_background_refresh_required(&mut self) -> bool {
+		self.follow_reload();
+
 		let controlling = self.is_controlling();
 		let data = self.data().is_some();
 		let profile = self.profile();
@@ -707,18 +3209,23 @@ impl Drop for Screen<'_> {
 		if let Some(icao) = &self.icao {
 			self.context.untrack_aerodrome(icao);
 		}
+		for overlay in &self.overlays {
+			self.context.untrack_aerodrome(&overlay.icao);
+		}
 	}
 }
 
-#[derive(Debug, Default)]
-struct Transform(f64, f64, f64, f64, f64, f64);
+// an affine transform to pixels, followed by the scale of pixel offsets in
+// geo points for the DPI of the display
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transform(f64, f64, f64, f64, f64, f64, f64);
 
 impl Transform {
 	fn new() -> Self {
-		Self::default()
+		Self(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0)
 	}
 
-	fn new_geo(viewport: ViewportGeo) -> Self {
+	fn new_geo(viewport: ViewportGeo, scale: f64) -> Self {
 		let sin = viewport.rotation.sin();
 		let cos = viewport.rotation.cos();
 
@@ -732,6 +3239,7 @@ impl Transform {
 			viewport.scaling[0] * -sin,
 			viewport.scaling[1] * cos,
 			klon * cos - klat * sin,
+			scale,
 		)
 	}
 
@@ -750,13 +3258,23 @@ impl Transform {
 			(scale, (viewport.size[0] - bounds_w * scale) * 0.5, 0.0)
 		};
 
+		let (zoom, pan) = if viewport.zoom.is_finite()
+			&& viewport.zoom > 0.0
+			&& viewport.pan.iter().all(|pan| pan.is_finite())
+		{
+			(viewport.zoom, viewport.pan)
+		} else {
+			(1.0, [0.0; 2])
+		};
+
 		Self(
-			scale,
+			scale * zoom,
 			0.0,
-			scale * -bounds.min.x as f64 + offset_x,
+			(scale * -bounds.min.x as f64 + offset_x) * zoom + pan[0],
 			0.0,
-			scale,
-			scale * -bounds.min.y as f64 + offset_y,
+			scale * zoom,
+			(scale * -bounds.min.y as f64 + offset_y) * zoom + pan[1],
+			1.0,
 		)
 	}
 
@@ -767,13 +3285,30 @@ impl Transform {
 		)
 	}
 
+	// the point which transforms to `(x, y)`, if the transform is invertible
+	fn invert(&self, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+		let det = self.0 * self.4 - self.1 * self.3;
+		if det == 0.0 || !det.is_finite() {
+			return None
+		}
+
+		let (x, y) = (x - self.2, y - self.5);
+		Some((
+			(x * self.4 - y * self.1) / det,
+			(y * self.0 - x * self.3) / det,
+		))
+	}
+
 	fn transform_geo(&self, geo: &Geo) -> (f64, f64) {
 		self.transform((geo.lat as f64, geo.lon as f64))
 	}
 
 	fn transform_geo_point(&self, gp: &GeoPoint) -> (f64, f64) {
 		let (x, y) = self.transform_geo(&gp.geo);
-		(x + gp.offset.x as f64, y + gp.offset.y as f64)
+		(
+			x + gp.offset.x as f64 * self.6,
+			y + gp.offset.y as f64 * self.6,
+		)
 	}
 
 	fn transform_point(&self, point: &Point) -> (f64, f64) {
@@ -797,61 +3332,320 @@ impl Transformable for GeoPoint {
 	}
 }
 
-#[derive(Default)]
-struct Lookup2d<T> {
-	data: Vec<T>,
-	width: usize,
+// entries per node of a `TargetIndex`
+const FANOUT: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+struct Bounds {
+	min: (f64, f64),
+	max: (f64, f64),
 }
 
-impl<T: Copy> Lookup2d<T> {
-	fn new(item: T, width: usize, height: usize) -> Self {
+impl Bounds {
+	const EMPTY: Self = Self {
+		min: (f64::INFINITY, f64::INFINITY),
+		max: (f64::NEG_INFINITY, f64::NEG_INFINITY),
+	};
+
+	fn of(points: &[(f64, f64)]) -> Self {
+		points.iter().fold(Self::EMPTY, |bounds, &point| {
+			bounds.union(&Self {
+				min: point,
+				max: point,
+			})
+		})
+	}
+
+	fn union(&self, other: &Self) -> Self {
 		Self {
-			data: vec![item; width * height],
-			width,
+			min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+			max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
 		}
 	}
 
-	fn sample(&self, x: usize, y: usize) -> &T {
-		&self.data[(x + y * self.width).min(self.data.len() - 1)]
+	fn intersects(&self, other: &Self) -> bool {
+		self.min.0 <= other.max.0
+			&& other.min.0 <= self.max.0
+			&& self.min.1 <= other.max.1
+			&& other.min.1 <= self.max.1
 	}
 
-	fn clear(&mut self, item: T) {
-		self.data.fill(item);
+	fn centre(&self) -> (f64, f64) {
+		(
+			(self.min.0 + self.max.0) * 0.5,
+			(self.min.1 + self.max.1) * 0.5,
+		)
 	}
+}
 
-	fn add_poly(&mut self, item: T, points: &[(f64, f64)]) {
-		let (min, max) = points
-			.iter()
-			.map(|(_, y)| y.max(0.0).round() as usize)
-			.fold((usize::MAX, 0), |(min, max), y| (min.min(y), max.max(y)));
-		let max_y = self.data.len() / self.width - 1;
+struct TargetPolygon {
+	// position in the order of addition
+	order: usize,
+	target: Target,
+	points: Vec<(f64, f64)>,
+}
 
-		let min = min.min(max_y);
-		let max = max.min(max_y);
+// target polygons in a static R-tree, which is packed once and then only
+// queried; where targets overlap, the one added last is hit
+#[derive(Default)]
+struct TargetIndex {
+	// polygons in tree order
+	items: Vec<TargetPolygon>,
+	// bounds of each item, then of each run of `FANOUT` entries in the level
+	// below, up to a root level of at most `FANOUT` entries
+	levels: Vec<Vec<Bounds>>,
+}
 
-		let mut intersections = Vec::new();
-		for y in min..=max {
-			let yf = y as f64 + 0.5;
+impl TargetIndex {
+	fn new(targets: Vec<(Target, Vec<(f64, f64)>)>) -> Self {
+		let mut items = targets
+			.into_iter()
+			.enumerate()
+			.filter(|(_, (_, points))| points.len() >= 3)
+			.map(|(order, (target, points))| {
+				let polygon = TargetPolygon {
+					order,
+					target,
+					points,
+				};
+				(Bounds::of(&polygon.points), polygon)
+			})
+			.collect::<Vec<_>>();
 
-			for i in 0..points.len() {
-				let (x1, y1) = points[i];
-				let (x2, y2) = points[(i + 1) % points.len()];
+		// sort-tile-recursive packing: vertical slices by x, each sorted by y, so
+		// that consecutive runs of items are close together
+		let slices = (items.len().div_ceil(FANOUT) as f64).sqrt().ceil() as usize;
+		let slice_len = FANOUT * slices.max(1);
+		items.sort_by(|a, b| a.0.centre().0.total_cmp(&b.0.centre().0));
+		for slice in items.chunks_mut(slice_len) {
+			slice.sort_by(|a, b| a.0.centre().1.total_cmp(&b.0.centre().1));
+		}
 
-				if (y1 > yf) != (y2 > yf) {
-					intersections.push(x1 + (x2 - x1) * (yf - y1) / (y2 - y1));
-				}
-			}
+		let (bounds, items) = items.into_iter().unzip();
+		let mut levels: Vec<Vec<Bounds>> = vec![bounds];
+		while levels.last().unwrap().len() > FANOUT {
+			let level = levels
+				.last()
+				.unwrap()
+				.chunks(FANOUT)
+				.map(|run| run.iter().fold(Bounds::EMPTY, |a, b| a.union(b)))
+				.collect();
+			levels.push(level);
+		}
+
+		Self { items, levels }
+	}
 
-			intersections.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	// calls `f` with the index of each item whose bounds intersect `area`
+	fn visit(&self, area: &Bounds, f: &mut impl FnMut(usize)) {
+		if let Some(top) = self.levels.len().checked_sub(1) {
+			self.visit_level(top, 0..self.levels[top].len(), area, f);
+		}
+	}
 
-			for pair in intersections.chunks_exact(2) {
-				let x1 = ((pair[0] - 0.5).round() as usize).min(self.width - 1);
-				let x2 = ((pair[1] - 0.5).round() as usize).min(self.width - 1);
+	fn visit_level(
+		&self,
+		level: usize,
+		range: Range<usize>,
+		area: &Bounds,
+		f: &mut impl FnMut(usize),
+	) {
+		for i in range {
+			if !self.levels[level][i].intersects(area) {
+				continue
+			}
 
-				self.data[y * self.width..][..self.width][x1..=x2].fill(item);
+			if level == 0 {
+				f(i);
+			} else {
+				let start = i * FANOUT;
+				let end = (start + FANOUT).min(self.levels[level - 1].len());
+				self.visit_level(level - 1, start..end, area, f);
 			}
+		}
+	}
+
+	fn intersects(&self, area: &Bounds) -> bool {
+		let mut any = false;
+		self.visit(area, &mut |_| any = true);
+		any
+	}
+
+	// finds the target containing `point`, or failing that the nearest one
+	// within `tolerance`
+	fn sample(&self, point: (f64, f64), tolerance: f64) -> Target {
+		let mut hit: Option<(f64, usize, Target)> = None;
+
+		self.visit(
+			&Bounds {
+				min: (point.0 - tolerance, point.1 - tolerance),
+				max: (point.0 + tolerance, point.1 + tolerance),
+			},
+			&mut |i| {
+				let polygon = &self.items[i];
+				let distance = distance(&polygon.points, point);
+				if distance <= tolerance
+					&& hit.is_none_or(|(hit, order, _)| {
+						distance < hit || (distance == hit && polygon.order > order)
+					}) {
+					hit = Some((distance, polygon.order, polygon.target));
+				}
+			},
+		);
+
+		hit.map(|(_, _, target)| target).unwrap_or_default()
+	}
+}
+
+// distance from `point` to the nearest edge of `polygon`, or zero if inside it
+// moves the greens which are confused with reds to blues, keeping their
+// saturation and lightness; reds are also lightened for protanopia, where they
+// look dark
+fn remap_color(vision: ColorVision, color: Color) -> Color {
+	const BLUE: f64 = 210.0;
+	const GREENS: Range<f64> = 75.0..170.0;
+
+	let lift = match vision {
+		ColorVision::Normal => return color,
+		ColorVision::Protanopia => 0.15,
+		ColorVision::Deuteranopia => 0.0,
+	};
+
+	let [r, g, b] = [color.r, color.g, color.b].map(|c| c as f64 / 255.0);
+	let max = r.max(g).max(b);
+	let min = r.min(g).min(b);
+	let chroma = max - min;
+	let l = (max + min) * 0.5;
+
+	// greys have no hue to confuse
+	if chroma < 0.05 {
+		return color
+	}
+
+	let s = chroma / (1.0 - (2.0 * l - 1.0).abs());
+	let h = 60.0
+		* if max == r {
+			((g - b) / chroma).rem_euclid(6.0)
+		} else if max == g {
+			(b - r) / chroma + 2.0
+		} else {
+			(r - g) / chroma + 4.0
+		};
+
+	let (h, l) = if GREENS.contains(&h) {
+		(BLUE, l)
+	} else if !(30.0..330.0).contains(&h) {
+		(h, (l + lift).min(1.0))
+	} else {
+		return color
+	};
+
+	let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+	let x = chroma * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+	let (r, g, b) = match (h / 60.0) as u32 {
+		0 => (chroma, x, 0.0),
+		1 => (x, chroma, 0.0),
+		2 => (0.0, chroma, x),
+		3 => (0.0, x, chroma),
+		4 => (x, 0.0, chroma),
+		_ => (chroma, 0.0, x),
+	};
+	let m = l - chroma * 0.5;
+	let channel = |c: f64| ((c + m).clamp(0.0, 1.0) * 255.0).round() as u8;
+
+	Color {
+		r: channel(r),
+		g: channel(g),
+		b: channel(b),
+		a: color.a,
+	}
+}
+
+// the parts of `rect` outside of `hole`
+fn subtract(rect: &RECT, hole: &RECT) -> Vec<RECT> {
+	if hole.left >= rect.right
+		|| hole.right <= rect.left
+		|| hole.top >= rect.bottom
+		|| hole.bottom <= rect.top
+	{
+		return vec![*rect]
+	}
+
+	let mut parts = Vec::new();
+
+	if hole.top > rect.top {
+		parts.push(RECT {
+			bottom: hole.top,
+			..*rect
+		});
+	}
+	if hole.bottom < rect.bottom {
+		parts.push(RECT {
+			top: hole.bottom,
+			..*rect
+		});
+	}
+
+	let top = rect.top.max(hole.top);
+	let bottom = rect.bottom.min(hole.bottom);
+	if hole.left > rect.left {
+		parts.push(RECT {
+			top,
+			bottom,
+			right: hole.left,
+			..*rect
+		});
+	}
+	if hole.right < rect.right {
+		parts.push(RECT {
+			top,
+			bottom,
+			left: hole.right,
+			..*rect
+		});
+	}
 
-			intersections.clear();
+	parts
+}
+
+fn distance(polygon: &[(f64, f64)], point: (f64, f64)) -> f64 {
+	if contains(polygon, point) {
+		return 0.0
+	}
+
+	let (x, y) = point;
+	let mut nearest = f64::INFINITY;
+
+	for i in 0..polygon.len() {
+		let (x1, y1) = polygon[i];
+		let (x2, y2) = polygon[(i + 1) % polygon.len()];
+
+		let (dx, dy) = (x2 - x1, y2 - y1);
+		let length = dx * dx + dy * dy;
+		let t = if length > 0.0 {
+			(((x - x1) * dx + (y - y1) * dy) / length).clamp(0.0, 1.0)
+		} else {
+			0.0
+		};
+
+		nearest = nearest.min((x - x1 - t * dx).hypot(y - y1 - t * dy));
+	}
+
+	nearest
+}
+
+// even-odd test of whether `polygon` contains `point`
+pub(crate) fn contains(polygon: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+	let mut inside = false;
+
+	for i in 0..polygon.len() {
+		let (x1, y1) = polygon[i];
+		let (x2, y2) = polygon[(i + 1) % polygon.len()];
+
+		if (y1 > y) != (y2 > y) && x < x1 + (x2 - x1) * (y - y1) / (y2 - y1) {
+			inside = !inside;
 		}
 	}
+
+	inside
 }
@@ -1,56 +1,116 @@
-use crate::config::{ConfigManager, ConfigMapping};
-use crate::ipc::{Channel, Downstream, ServerChannel, Upstream};
+use crate::config::{ConfigManager, ConfigMapping, LocalConfig};
+use crate::ipc::{
+	Channel, Downstream, Scenery, ServerChannel, ServerChannelReadHalf,
+	ServerChannelWriteHalf, Transport, Upstream, CAPABILITIES, IPC_VERSION,
+	PLUGIN_VERSION,
+};
 
-use std::collections::{HashMap, HashSet};
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::net::Ipv4Addr;
-use std::sync::Arc;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as SyncMutex, Weak};
 use std::thread::{Builder as ThreadBuilder, JoinHandle};
 use std::time::{Duration, Instant};
 
 use bars_config::Aerodrome;
 use bars_protocol::{
-	Downstream as NetDownstream, Patch, State, Upstream as NetUpstream,
+	Capability, CloseReason, Controller, Downstream as NetDownstream, Encoding,
+	ErrorCode, Patch, Ping, Sequence, SharedState, State,
+	Upstream as NetUpstream, Versions,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
+use futures::future::FutureExt;
 use futures::sink::SinkExt;
-use futures::stream::StreamExt;
+use futures::stream::{SplitSink, SplitStream, StreamExt};
+
+use native_tls::TlsConnector;
+
+use sha1::{Digest, Sha1};
+
+use subtle::ConstantTimeEq;
 
+use tokio::net::windows::named_pipe::ServerOptions;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Builder as RuntimeBuilder;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle as TaskHandle;
+use tokio::time::MissedTickBehavior;
 
 use tokio_tungstenite::tungstenite::http::Uri;
 use tokio_tungstenite::tungstenite::Message;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
 
 use tracing::{debug, error, trace, warn};
 
-const SOCKET_POLL_TIMEOUT: Duration = Duration::from_millis(100);
 const STATE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+// state is still polled for observers once aircraft are pushed, but less often
+const PRESENCE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const PATCH_COALESCE_DELAY: Duration = Duration::from_millis(100);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// time without any message from the server after which the connection is
+// taken to be dead, such as when it is half-open
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+// back-off for servers which report rate limiting without a retry time
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(5);
+// delay before reconnecting to a server which closed the connection to restart
+const RESTART_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+// delay before reconnecting over a connection found to be dead
+const DEAD_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// attempts to connect to a server before failing over to the next
+const CONNECT_ATTEMPTS: usize = 2;
+// delay before waiting again for a pipe client after an error, doubled on
+// each consecutive error
+const PIPE_RETRY_DELAY: Duration = Duration::from_millis(100);
+// consecutive errors waiting for a pipe client after which it is given up
+const PIPE_CONNECT_ATTEMPTS: u32 = 8;
+// time allowed for connections to close on shutdown before they are dropped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub struct ConnectOptions {
-	pub server: String,
+	/// servers in order of preference
+	pub servers: Vec<String>,
 	pub token: String,
+	/// directory of `local.toml`, from which an expired token is read again
+	pub dir: PathBuf,
 	pub port: u16,
+	/// transport on which proxied clients are accepted
+	pub transport: Transport,
+	/// secret which proxied clients must present, if any
+	pub proxy_secret: Option<String>,
 	pub callsign: String,
 	pub controlling: bool,
+	pub encoding: Encoding,
+}
+
+/// Reason that the worker stopped without being told to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stopped {
+	/// it could not start, or gave up serving
+	Failed,
+	/// it panicked, and may be started again
+	Panicked,
 }
 
 pub struct Server {
 	thread: JoinHandle<()>,
 	shutdown: oneshot::Sender<()>,
-	cancelled: oneshot::Receiver<()>,
+	cancelled: oneshot::Receiver<Stopped>,
 }
 
 impl Server {
 	pub fn new(
 		connect: Option<ConnectOptions>,
 		mapping: ConfigMapping,
+		tls: TlsConnector,
 	) -> Result<(Self, Channel)> {
 		let (channel, server_channel) = crate::ipc::mpsc_pair();
 
@@ -67,13 +127,41 @@ impl Server {
 				runtime.block_on(async {
 					debug!("worker thread spawned");
 
-					if let Err(err) = Worker::run(connect, server_channel, mapping).await
+					let (stop, stopped) = oneshot::channel();
+					match Worker::run(connect, server_channel, mapping, tls, stopped)
+						.await
 					{
-						error!("{err}");
-						let _ = ctx.send(());
-					} else {
-						let _ = srx.await;
-						debug!("shutdown signal received");
+						Ok(mut serving) => {
+							tokio::select! {
+								_ = srx => {
+									debug!("shutdown signal received");
+
+									let _ = stop.send(());
+									if tokio::time::timeout(SHUTDOWN_TIMEOUT, serving)
+										.await
+										.is_err()
+									{
+										warn!("connections not closed in time");
+									}
+								},
+								// the remaining tasks are dropped with the runtime,
+								// closing their connections
+								res = &mut serving => match res {
+									Err(err) if err.is_panic() => {
+										error!("worker panicked");
+										let _ = ctx.send(Stopped::Panicked);
+									},
+									_ => {
+										error!("worker stopped");
+										let _ = ctx.send(Stopped::Failed);
+									},
+								},
+							}
+						},
+						Err(err) => {
+							error!("{err}");
+							let _ = ctx.send(Stopped::Failed);
+						},
 					}
 				})
 			})?;
@@ -88,11 +176,13 @@ impl Server {
 		))
 	}
 
-	pub fn is_cancelled(&mut self) -> bool {
-		matches!(
-			self.cancelled.try_recv(),
-			Ok(()) | Err(TryRecvError::Closed)
-		)
+	/// A thread which ended without saying why is taken to have panicked.
+	pub fn stopped(&mut self) -> Option<Stopped> {
+		match self.cancelled.try_recv() {
+			Ok(stopped) => Some(stopped),
+			Err(TryRecvError::Closed) => Some(Stopped::Panicked),
+			Err(TryRecvError::Empty) => None,
+		}
 	}
 
 	pub fn stop(self) {
@@ -112,6 +202,68 @@ impl Server {
 #[derive(Clone)]
 struct Worker {
 	broadcast: Sender<Downstream>,
+	arbiter: Arc<SyncMutex<Arbiter>>,
+}
+
+// which of the clients of this instance may change each aerodrome, the others
+// observing it
+#[derive(Default)]
+struct Arbiter {
+	next_client: usize,
+	// clients tracking each aerodrome in the order that they began to, the
+	// first of which may change it
+	trackers: HashMap<String, Vec<usize>>,
+	// whether this instance controls each aerodrome, as last sent to clients
+	controlling: HashMap<String, bool>,
+}
+
+impl Arbiter {
+	fn register(&mut self) -> usize {
+		self.next_client += 1;
+		self.next_client
+	}
+
+	fn is_writer(&self, icao: &str, client: usize) -> bool {
+		self
+			.trackers
+			.get(icao)
+			.and_then(|trackers| trackers.first())
+			.is_some_and(|writer| *writer == client)
+	}
+
+	// updates the trackers of an aerodrome, returning whether its writer has
+	// changed
+	fn track(&mut self, icao: &str, client: usize, track: bool) -> bool {
+		let trackers = self.trackers.entry(icao.into()).or_default();
+		let writer = trackers.first().copied();
+
+		trackers.retain(|other| *other != client);
+		if track {
+			trackers.push(client);
+		}
+
+		let changed = trackers.first().copied() != writer;
+		if trackers.is_empty() {
+			self.trackers.remove(icao);
+			self.controlling.remove(icao);
+		}
+
+		changed
+	}
+
+	// makes a client the writer of an aerodrome which it tracks, returning
+	// whether it was not already
+	fn take_over(&mut self, icao: &str, client: usize) -> bool {
+		let Some(trackers) = self.trackers.get_mut(icao) else {
+			return false
+		};
+		let Some(i) = trackers.iter().position(|other| *other == client) else {
+			return false
+		};
+
+		trackers[..=i].rotate_right(1);
+		i > 0
+	}
 }
 
 impl Worker {
@@ -119,36 +271,78 @@ impl Worker {
 		connect: Option<ConnectOptions>,
 		channel: ServerChannel,
 		mapping: ConfigMapping,
-	) -> Result<()> {
+		tls: TlsConnector,
+		stopped: oneshot::Receiver<()>,
+	) -> Result<TaskHandle<()>> {
 		let (tx, rx) = mpsc::unbounded_channel();
 
 		let this = Self {
 			broadcast: Sender::new(16),
+			arbiter: Arc::default(),
 		};
 
-		this.handle_stream(channel, tx.clone()).await?;
+		// the client of this instance shares its memory, so needs no secret
+		this.handle_stream(channel, None, tx.clone());
 
 		if let Some(options) = &connect {
-			this.bind(options.port, tx).await?;
-		}
+			debug!(
+				"connecting as {} ({})",
+				options.callsign,
+				if options.controlling {
+					"controller"
+				} else {
+					"observer"
+				},
+			);
 
-		tokio::spawn(async move {
-			let _ = this.serve(connect, mapping, rx).await;
-		});
+			this
+				.bind(
+					options.transport,
+					options.port,
+					options.proxy_secret.clone(),
+					tx,
+				)
+				.await?;
+		}
 
-		Ok(())
+		Ok(tokio::spawn(async move {
+			let _ = this.serve(connect, mapping, tls, rx, stopped).await;
+		}))
 	}
 
 	async fn serve(
 		&self,
 		connect: Option<ConnectOptions>,
 		mapping: ConfigMapping,
+		tls: TlsConnector,
 		mut rx: UnboundedReceiver<Upstream>,
+		mut stopped: oneshot::Receiver<()>,
 	) -> Result<()> {
+		let http = reqwest::Client::builder()
+			.use_preconfigured_tls(tls.clone())
+			.build()?;
+
 		let mut aerodromes = HashMap::new();
-		let config = Arc::new(Mutex::new(ConfigManager::new(mapping)));
+		let config =
+			Arc::new(Mutex::new(ConfigManager::new(mapping, http.clone())));
+		let endpoints = connect
+			.as_ref()
+			.map(|options| Arc::new(Endpoints::new(options, tls, http)));
+		// panics of the aerodromes' tasks, which would otherwise be lost
+		let (panicked, mut panics) = mpsc::unbounded_channel();
+
+		loop {
+			let message = tokio::select! {
+				message = rx.recv() => message,
+				_ = &mut stopped => None,
+				// the worker panics in turn, so that it is restarted
+				Some(panic) = panics.recv() => {
+					error!("aerodrome task panicked");
+					std::panic::resume_unwind(panic)
+				},
+			};
+			let Some(message) = message else { break };
 
-		while let Some(message) = rx.recv().await {
 			let Some(icao) = message.icao() else {
 				warn!("unknown message forwarded to local handler");
 				break
@@ -158,8 +352,10 @@ impl Worker {
 				let aerodrome = AerodromeManager::new(
 					icao,
 					&connect,
+					endpoints.clone(),
 					config.clone(),
 					self.broadcast.clone(),
+					panicked.clone(),
 				)
 				.await?;
 				aerodromes.insert(icao.clone(), aerodrome);
@@ -177,14 +373,48 @@ impl Worker {
 					aerodrome.control(control).await;
 					Ok(())
 				},
-				Upstream::Patch { icao, patch } => {
+				Upstream::Patch {
+					icao,
+					patch,
+					preset,
+				} => {
 					debug!("patching {icao}");
-					aerodrome.patch(patch).await
+					aerodrome.patch(*patch, preset).await
 				},
 				Upstream::Scenery { icao, scenery } => {
 					debug!("updating {icao}");
 					aerodrome.scenery(scenery).await
 				},
+				Upstream::Claim {
+					icao,
+					blocks,
+					claim,
+				} => {
+					debug!(
+						"{} {icao} blocks",
+						if claim { "claiming" } else { "releasing" }
+					);
+					aerodrome.claim(blocks, claim).await
+				},
+				Upstream::RequestHandoff {
+					icao,
+					controller_id,
+				} => {
+					debug!("requesting handoff of {icao} from {controller_id}");
+					aerodrome.request_handoff(controller_id).await
+				},
+				Upstream::RespondHandoff { icao, accept } => {
+					debug!("responding to handoff of {icao} ({accept})");
+					aerodrome.respond_handoff(accept).await
+				},
+				Upstream::Resync { icao } => {
+					debug!("resynchronising {icao}");
+					aerodrome.resync().await
+				},
+				Upstream::Reload { icao } => {
+					debug!("reloading config of {icao}");
+					aerodrome.reload(&config).await
+				},
 				_ => Ok(()),
 			};
 
@@ -195,14 +425,30 @@ impl Worker {
 			}
 		}
 
+		// the server is told of each controller leaving, rather than seeing it
+		// vanish
+		debug!("closing {} aerodrome connections", aerodromes.len());
+		let closing = aerodromes.values().map(AerodromeManager::shutdown);
+		for res in futures::future::join_all(closing).await {
+			if let Err(err) = res {
+				warn!("{err}");
+			}
+		}
+
 		Ok(())
 	}
 
 	async fn bind(
 		&self,
+		transport: Transport,
 		port: u16,
+		secret: Option<String>,
 		server_tx: UnboundedSender<Upstream>,
 	) -> Result<()> {
+		if transport == Transport::NamedPipe {
+			return self.bind_pipe(port, secret, server_tx)
+		}
+
 		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
 
 		let state = self.clone();
@@ -212,37 +458,169 @@ impl Worker {
 					debug!("accepted {remote}");
 
 					let channel = ServerChannel::Tcp(stream);
-					if let Err(err) =
-						state.handle_stream(channel, server_tx.clone()).await
-					{
-						debug!("{err}");
+					state.handle_stream(channel, secret.clone(), server_tx.clone());
+				}
+			}
+		});
+
+		Ok(())
+	}
+
+	fn bind_pipe(
+		&self,
+		port: u16,
+		secret: Option<String>,
+		server_tx: UnboundedSender<Upstream>,
+	) -> Result<()> {
+		let name = crate::ipc::pipe_name(port);
+		// as with a port, only one main instance can hold the pipe
+		let mut pipe = ServerOptions::new()
+			.first_pipe_instance(true)
+			.create(&name)?;
+
+		let state = self.clone();
+		tokio::spawn(async move {
+			let mut failures = 0;
+			loop {
+				if let Err(err) = pipe.connect().await {
+					failures += 1;
+					if failures >= PIPE_CONNECT_ATTEMPTS {
+						warn!("failed to accept pipe clients: {err}");
+						break
 					}
+
+					debug!("{err}");
+					tokio::time::sleep(PIPE_RETRY_DELAY * (1 << (failures - 1))).await;
+					continue
 				}
+				failures = 0;
+
+				debug!("accepted pipe client");
+
+				// each client has its own instance of the pipe, so another is
+				// created for the next
+				let next = match ServerOptions::new().create(&name) {
+					Ok(next) => next,
+					Err(err) => {
+						warn!("failed to create pipe: {err}");
+						break
+					},
+				};
+
+				let channel = ServerChannel::Pipe(std::mem::replace(&mut pipe, next));
+				state.handle_stream(channel, secret.clone(), server_tx.clone());
 			}
 		});
 
 		Ok(())
 	}
 
-	async fn handle_stream(
+	fn handle_stream(
 		&self,
 		stream: ServerChannel,
+		secret: Option<String>,
 		server_tx: UnboundedSender<Upstream>,
-	) -> Result<()> {
+	) {
 		let (mut stream_rx, mut stream_tx) = stream.into_split();
+
+		// clients are served only once known to be compatible and permitted,
+		// which is awaited apart so as not to hold up the worker
+		let this = self.clone();
+		tokio::spawn(async move {
+			match Self::handshake(&mut stream_rx, &mut stream_tx, secret).await {
+				Ok(()) => this.serve_stream(stream_rx, stream_tx, server_tx),
+				Err(err) => warn!("{err}"),
+			}
+		});
+	}
+
+	// exchanges versions with a client, refusing it if they differ or if it
+	// does not present the secret
+	async fn handshake(
+		stream_rx: &mut ServerChannelReadHalf,
+		stream_tx: &mut ServerChannelWriteHalf,
+		secret: Option<String>,
+	) -> Result<()> {
+		// a first message which cannot be read is from another version
+		let mut rejected = match stream_rx.recv().await {
+			Ok(Upstream::Init {
+				ipc_version: IPC_VERSION,
+				plugin_version,
+				capabilities,
+			}) => {
+				debug!("client version {plugin_version} with {capabilities:?}");
+				None
+			},
+			Ok(Upstream::Init { plugin_version, .. }) => Some(format!(
+				"plugin version {plugin_version} is incompatible with version \
+				 {PLUGIN_VERSION} in the main instance"
+			)),
+			_ => Some(format!(
+				"plugin is incompatible with version {PLUGIN_VERSION} in the main \
+				 instance"
+			)),
+		};
+
+		// the secret follows only if the versions match, else it may not be
+		// readable
+		if rejected.is_none() {
+			let permitted = match stream_rx.recv().await? {
+				Upstream::Authenticate { secret: presented } => {
+					match (&secret, presented) {
+						(None, _) => true,
+						(Some(secret), Some(presented)) => {
+							secrets_equal(secret.as_bytes(), presented.as_bytes())
+						},
+						(Some(_), None) => false,
+					}
+				},
+				_ => false,
+			};
+
+			if !permitted {
+				rejected = Some(
+					"refused by the main instance: check proxy_secret in local.toml"
+						.into(),
+				);
+			}
+		}
+
+		stream_tx
+			.send(Downstream::Init {
+				ipc_version: IPC_VERSION,
+				plugin_version: PLUGIN_VERSION.into(),
+				capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+				rejected: rejected.clone(),
+			})
+			.await?;
+
+		match rejected {
+			Some(message) => bail!("refused client: {message}"),
+			None => Ok(()),
+		}
+	}
+
+	fn serve_stream(
+		&self,
+		mut stream_rx: ServerChannelReadHalf,
+		mut stream_tx: ServerChannelWriteHalf,
+		server_tx: UnboundedSender<Upstream>,
+	) {
 		let mut ipc_rx = self.broadcast.subscribe();
 
-		let tracked = Arc::new(Mutex::new(HashSet::new()));
+		let tracked = Arc::new(Mutex::new(HashSet::<String>::new()));
+		let client = self.arbiter.lock().unwrap().register();
 
 		{
+			let this = self.clone();
 			let tracked = tracked.clone();
 			let server_tx = server_tx.clone();
 
 			tokio::spawn(async move {
-				while let Ok(message) = ipc_rx.recv().await {
+				while let Ok(mut message) = ipc_rx.recv().await {
 					let mut tracked = tracked.lock().await;
 
-					if !tracked.contains(message.icao()) {
+					if !message.icao().is_some_and(|icao| tracked.contains(icao)) {
 						continue
 					}
 
@@ -253,12 +631,20 @@ impl Worker {
 					} = &message
 					{
 						debug_assert!(tracked.remove(icao));
+						this.track(icao, client, false);
 						let _ = server_tx.send(Upstream::Track {
 							icao: icao.clone(),
 							track: false,
 						});
 					}
 
+					// only the writer of an aerodrome is told that it controls it
+					if let Downstream::Control { icao, control } = &mut message {
+						let mut arbiter = this.arbiter.lock().unwrap();
+						arbiter.controlling.insert(icao.clone(), *control);
+						*control &= arbiter.is_writer(icao, client);
+					}
+
 					if let Err(err) = stream_tx.send(message).await {
 						debug!("{err}");
 						break
@@ -269,6 +655,7 @@ impl Worker {
 			});
 		}
 
+		let this = self.clone();
 		tokio::spawn(async move {
 			loop {
 				let message = match stream_rx.recv().await {
@@ -277,6 +664,7 @@ impl Worker {
 						let mut tracked = tracked.lock().await;
 
 						for icao in tracked.drain() {
+							this.track(&icao, client, false);
 							let _ = server_tx.send(Upstream::Track { icao, track: false });
 						}
 
@@ -285,7 +673,7 @@ impl Worker {
 				};
 
 				match &message {
-					Upstream::Init => continue,
+					Upstream::Init { .. } | Upstream::Authenticate { .. } => continue,
 					Upstream::Track { icao, track } => {
 						let mut tracked = tracked.lock().await;
 
@@ -298,6 +686,29 @@ impl Worker {
 								continue
 							}
 						}
+
+						this.track(icao, client, *track);
+					},
+					Upstream::TakeOver { icao } => {
+						let changed = this.arbiter.lock().unwrap().take_over(icao, client);
+						if changed {
+							debug!("client {client} took over {icao}");
+							this.reassign(icao);
+						}
+
+						continue
+					},
+					// observers may not change the aerodrome
+					Upstream::Control { icao, .. }
+					| Upstream::Patch { icao, .. }
+					| Upstream::Scenery { icao, .. }
+					| Upstream::Claim { icao, .. }
+					| Upstream::RequestHandoff { icao, .. }
+					| Upstream::RespondHandoff { icao, .. } => {
+						if !this.arbiter.lock().unwrap().is_writer(icao, client) {
+							debug!("dropping change to {icao} from observing client");
+							continue
+						}
 					},
 					_ => (),
 				}
@@ -305,45 +716,64 @@ impl Worker {
 				let _ = server_tx.send(message);
 			}
 		});
+	}
 
-		Ok(())
+	fn track(&self, icao: &str, client: usize, track: bool) {
+		let changed = self.arbiter.lock().unwrap().track(icao, client, track);
+		if changed {
+			self.reassign(icao);
+		}
+	}
+
+	// tells the clients of an aerodrome again whether they control it, once its
+	// writer has changed
+	fn reassign(&self, icao: &str) {
+		let control = self.arbiter.lock().unwrap().controlling.get(icao).copied();
+		if let Some(control) = control {
+			let _ = self.broadcast.send(Downstream::Control {
+				icao: icao.into(),
+				control,
+			});
+		}
 	}
 }
 
-#[derive(Clone)]
-struct AerodromeManager {
-	data: Arc<Mutex<AerodromeManagerData>>,
-	server: Option<(String, String)>,
-	icao: String,
-	broadcast: Sender<Downstream>,
+// compares secrets in a time which depends on neither where they differ nor
+// their lengths, by comparing digests of them, so that they cannot be guessed
+// a byte at a time
+fn secrets_equal(a: &[u8], b: &[u8]) -> bool {
+	Sha1::digest(a).ct_eq(&Sha1::digest(b)).into()
 }
 
-struct AerodromeManagerData {
-	config: Option<Aerodrome>,
-	controlling: bool,
-	trackers: usize,
-	state: Patch,
-	socket: Option<Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
+// servers in order of preference, shared by the aerodromes so that they fail
+// over together
+struct Endpoints {
+	// each server as configured, with its address after the scheme's `http` or
+	// `ws`, such as `s://example.com`
+	servers: Vec<(String, String)>,
+	// index of the server in use
+	active: AtomicUsize,
+	// key to connect with, renewed from `local.toml` when it expires
+	key: SyncMutex<String>,
+	dir: PathBuf,
+	tls: TlsConnector,
+	// client for requests to the server, sharing its TLS settings
+	http: reqwest::Client,
+	// connections which further aerodromes can join
+	pool: SyncMutex<Vec<Weak<Connection>>>,
 }
 
-impl AerodromeManager {
-	async fn new(
-		icao: &str,
-		options: &Option<ConnectOptions>,
-		config: Arc<Mutex<ConfigManager>>,
-		broadcast: Sender<Downstream>,
-	) -> Result<Self> {
-		let this = Self {
-			data: Arc::new(Mutex::new(AerodromeManagerData {
-				config: None,
-				controlling: false,
-				trackers: 0,
-				state: Patch::default(),
-				socket: None,
-			})),
-			server: options.as_ref().map(|options| {
-				let secure = options
-					.server
+impl Endpoints {
+	fn new(
+		options: &ConnectOptions,
+		tls: TlsConnector,
+		http: reqwest::Client,
+	) -> Self {
+		let servers = options
+			.servers
+			.iter()
+			.map(|server| {
+				let secure = server
 					.parse::<Uri>()
 					.ok()
 					.map(|uri| {
@@ -351,265 +781,1422 @@ impl AerodromeManager {
 							|| uri.port_u16() == Some(443)
 					})
 					.unwrap_or_default();
-				let base = options
-					.server
+				let base = server
 					.split_once("://")
 					.map(|s| s.1)
-					.unwrap_or(&options.server)
+					.unwrap_or(server)
 					.trim_end_matches('/');
 
 				(
+					server.clone(),
 					format!("{}://{base}", if secure { "s" } else { "" }),
-					options.token.clone(),
 				)
-			}),
-			icao: icao.into(),
-			broadcast: broadcast.clone(),
-		};
-
-		{
-			let icao = icao.to_string();
-			let this = this.clone();
-			tokio::spawn(async move {
-				match config.lock().await.load(&icao).await {
-					Ok(None) => (),
-					Ok(Some(config)) => {
-						{
-							this.data.lock().await.config = Some(config);
-						}
-						this.sync_clients().await;
-					},
-					Err(err) => warn!("failed to load config: {err}"),
-				}
-			});
+			})
+			.collect();
+
+		Self {
+			servers,
+			active: AtomicUsize::new(0),
+			key: SyncMutex::new(options.token.clone()),
+			dir: options.dir.clone(),
+			tls,
+			http,
+			pool: SyncMutex::new(Vec::new()),
 		}
+	}
 
-		Ok(this)
+	fn key(&self) -> String {
+		self.key.lock().unwrap().clone()
 	}
 
-	fn broadcast(&self, message: Downstream) {
-		if self.broadcast.send(message).is_err() {
-			warn!("broadcast channel full");
+	// replaces a key which has expired with the token now in `local.toml`,
+	// returning the key to use instead, if any, which may have already been
+	// renewed for another connection
+	fn renew_key(&self, expired: &str) -> Result<Option<String>> {
+		let mut key = self.key.lock().unwrap();
+		if *key == expired {
+			match LocalConfig::load(&self.dir)?.token {
+				Some(token) if token != expired => *key = token,
+				_ => return Ok(None),
+			}
 		}
+
+		Ok(Some(key.clone()))
 	}
 
-	async fn sync_clients(&self) {
-		let data = self.data.lock().await;
-		if let Some(config) = &data.config {
-			self.broadcast(Downstream::Config {
-				data: config.clone(),
-			});
-			self.broadcast(Downstream::Control {
-				icao: self.icao.clone(),
-				control: data.controlling,
-			});
-			self.broadcast(Downstream::Patch {
-				icao: self.icao.clone(),
-				patch: data.state.clone(),
-			});
+	// lets further aerodromes join a connection, once its server has said that
+	// it supports them
+	fn offer(&self, connection: &Arc<Connection>) {
+		let mut pool = self.pool.lock().unwrap();
+		pool.retain(|pooled| pooled.strong_count() > 0);
+		// the aerodrome connected to may have joined the connection again
+		if !pool
+			.iter()
+			.any(|pooled| pooled.as_ptr() == Arc::as_ptr(connection))
+		{
+			pool.push(Arc::downgrade(connection));
 		}
 	}
 
-	async fn connect(&self) -> Result<()> {
-		let mut data = self.data.lock().await;
+	// joins an aerodrome to a pooled connection to the server in use, if there
+	// is one still open
+	fn join(
+		&self,
+		icao: &str,
+	) -> Option<(Arc<Socket>, UnboundedReceiver<Incoming>)> {
+		let active = self.active.load(Ordering::SeqCst);
+		let mut pool = self.pool.lock().unwrap();
+		pool.retain(|pooled| pooled.strong_count() > 0);
+		pool
+			.iter()
+			.filter_map(Weak::upgrade)
+			.filter(|connection| connection.server == active)
+			.find_map(|connection| connection.join(icao))
+	}
 
-		if data.socket.is_some() {
-			warn!("aerodrome connection attempted whilst connected");
-			return Ok(())
+	// connects to the server in use, or failing that to each following it in
+	// turn, returning the index of the server connected to
+	async fn connect(&self, query: &str, key: &str) -> Result<(usize, WsStream)> {
+		let start = self.active.load(Ordering::SeqCst);
+		let len = self.servers.len();
+
+		let mut error = None;
+		for i in (start..len).chain(0..start) {
+			let (server, base) = &self.servers[i];
+			for attempt in 1..=CONNECT_ATTEMPTS {
+				debug!("connecting socket ws{base}/connect?{query}");
+
+				let endpoint = format!("ws{base}/connect?{query}&key={key}");
+				let connector = Connector::NativeTls(self.tls.clone());
+				let connecting = tokio_tungstenite::connect_async_tls_with_config(
+					endpoint,
+					None,
+					false,
+					Some(connector),
+				);
+				match connecting.await {
+					Ok((stream, _)) => {
+						self.active.store(i, Ordering::SeqCst);
+						return Ok((i, stream))
+					},
+					Err(err) => {
+						warn!("connecting to {server} ({attempt}): {err}");
+						error = Some(err);
+					},
+				}
+			}
 		}
 
-		if let Some((server, key)) = &self.server {
-			let state_endpoint = format!("http{server}/state?airport={}", self.icao);
-			let connect_endpoint =
-				format!("ws{server}/connect?airport={}&key={}", self.icao, key);
+		match error {
+			Some(err) => Err(err.into()),
+			None => bail!("no server configured"),
+		}
+	}
+}
 
-			debug!(
-				"connecting socket {}",
-				connect_endpoint.rsplit_once("&key=").unwrap().0,
-			);
+#[derive(Clone)]
+struct AerodromeManager {
+	data: Arc<Mutex<AerodromeManagerData>>,
+	server: Option<(Arc<Endpoints>, Encoding)>,
+	callsign: Option<String>,
+	icao: String,
+	broadcast: Sender<Downstream>,
+	panicked: UnboundedSender<Panic>,
+}
 
-			let socket = tokio_tungstenite::connect_async(connect_endpoint).await?.0;
-			let socket = Arc::new(Mutex::new(socket));
-			data.socket = Some(socket.clone());
+type Panic = Box<dyn Any + Send>;
 
-			let socket = socket.clone();
-			let this = self.clone();
-			tokio::spawn(async move {
-				use std::sync::atomic::{AtomicUsize, Ordering};
-				static COUNTER: AtomicUsize = AtomicUsize::new(0);
+struct AerodromeManagerData {
+	config: Option<Aerodrome>,
+	controlling: bool,
+	trackers: usize,
+	// connections made to the server, including the first
+	connections: usize,
+	state: SharedState,
+	// local changes waiting to be sent, if a send is scheduled or the socket is
+	// down, in which case they are replayed once reconnected
+	pending: Option<Patch>,
+	// preset to announce with the pending changes
+	pending_preset: Option<String>,
+	// last changes sent, resent if the server throttles them
+	sent: Option<(Patch, Option<String>)>,
+	// scenery updates made whilst the socket is down
+	queued_scenery: Scenery,
+	// time until which the server has asked for changes to be held back
+	throttled_until: Option<Instant>,
+	// presets announced by this client whose echo is yet to be received
+	announced: VecDeque<String>,
+	// other controllers connected to the aerodrome
+	controllers: Vec<Controller>,
+	// this client's controller ID, as given by the server
+	controller_id: Option<String>,
+	// controller holding each claimed block
+	claims: HashMap<String, String>,
+	// features supported by the server
+	capabilities: Vec<Capability>,
+	// round-trip time of the last heartbeat, in milliseconds
+	latency: Option<u32>,
+	// server connected to, as configured
+	endpoint: Option<String>,
+	// controller requesting a handoff from this client
+	handoff: Option<String>,
+	// whether this client has handed off to another controller
+	handed_off: bool,
+	socket: Option<Arc<Socket>>,
+}
 
-				let mut last_state_poll = Instant::now();
+impl AerodromeManagerData {
+	fn is_throttled(&self) -> bool {
+		self
+			.throttled_until
+			.is_some_and(|until| until > Instant::now())
+	}
 
-				let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+	// versions local changes as the next writes by this client
+	fn stamp(&self, patch: &mut Patch) {
+		let controller_id = self.controller_id.as_deref().unwrap_or_default();
+		self.state.stamp(patch, controller_id);
+	}
 
-				loop {
-					let socket_arc = &socket;
+	// callsign of a connected controller, falling back to its ID
+	fn controller_name(&self, id: &String) -> String {
+		self
+			.controllers
+			.iter()
+			.find(|controller| &controller.controller_id == id)
+			.and_then(|controller| controller.callsign.clone())
+			.unwrap_or_else(|| id.clone())
+	}
+}
 
-					let mut socket = socket.lock().await;
-					match tokio::time::timeout(SOCKET_POLL_TIMEOUT, socket.next()).await {
-						Ok(Some(Ok(Message::Text(message)))) => {
-							type Message = NetDownstream<Option<Patch>>;
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type SocketSink = SplitSink<WsStream, Message>;
+type SocketStream = SplitStream<WsStream>;
+
+// aerodromes on a connection, with the channel passing on the messages of each
+type Members = HashMap<String, (Weak<Socket>, UnboundedSender<Incoming>)>;
+
+// a connection to the server, read by one task and written by another, so
+// that messages are sent in the order they are queued without a lock being
+// held over the network; it carries one aerodrome, or several if the server
+// supports multiplexing them
+struct Connection {
+	tx: UnboundedSender<Outgoing>,
+	// aerodrome which the connection was made for, whose messages are not
+	// wrapped
+	primary: String,
+	// index of the server connected to
+	server: usize,
+	// none once the connection has been closed or lost, so that no aerodrome
+	// joins it
+	members: SyncMutex<Option<Members>>,
+}
 
-							let Ok(data) = serde_json::from_str::<Message>(message.as_str())
-							else {
-								warn!("net downstream deserialisation failed");
-								continue
-							};
+// messages to the writer task of a connection
+enum Outgoing {
+	Message(NetUpstream),
+	// sends a close message and closes the connection, then reports the result
+	Close(CloseReason, oneshot::Sender<Result<()>>),
+}
 
-							trace!("ws rx ({n}): {data:?}");
+// what a connection passes on to each aerodrome on it
+enum Incoming {
+	Message(NetDownstream<Option<Patch>>),
+	// round-trip time of a heartbeat
+	Latency(Duration),
+	// nothing has been received for too long
+	Dead,
+	// the connection failed, for the reason given
+	Failed(String),
+}
 
-							let res = match data {
-								NetDownstream::Heartbeat => {
-									Self::send(&mut socket, &NetUpstream::HeartbeatAck).await
-								},
-								NetDownstream::Close => {
-									warn!("server-initiated graceful close");
-									this
-										.disconnect_forced(
-											socket_arc,
-											"server closed connection".into(),
-										)
-										.await;
-
-									break
-								},
-								NetDownstream::Error { message } => {
-									warn!("server: {message}");
-									Ok(())
-								},
-								state @ NetDownstream::InitialState { .. }
-								| state @ NetDownstream::SharedStateUpdate { .. } => {
-									let (patch, control) = match state {
-										NetDownstream::InitialState {
-											connection_type,
-											patch,
-											..
-										} => (patch, Some(connection_type == "controller")),
-										NetDownstream::SharedStateUpdate { patch, .. } => {
-											(patch, None)
-										},
-										_ => unreachable!(),
-									};
-									let patch = patch.unwrap_or_default();
-
-									let mut data = this.data.lock().await;
-
-									data.state.apply_patch(patch.clone());
-									this.broadcast(Downstream::Patch {
-										icao: this.icao.clone(),
-										patch,
-									});
-
-									if let Some(control) = control {
-										data.controlling = control;
-										this.broadcast(Downstream::Control {
-											icao: this.icao.clone(),
-											control,
-										});
-									}
+impl Connection {
+	fn queue(&self, outgoing: Outgoing) -> Result<()> {
+		if self.tx.send(outgoing).is_err() {
+			bail!("connection closed");
+		}
 
-									Ok(())
-								},
-								NetDownstream::StateUpdate { .. }
-								| NetDownstream::HeartbeatAck
-								| NetDownstream::ControllerConnect { .. }
-								| NetDownstream::ControllerDisconnect { .. }
-								| NetDownstream::Other => Ok(()),
-							};
+		Ok(())
+	}
 
-							if let Err(err) = res {
-								this
-									.disconnect_forced(
-										socket_arc,
-										format!("server messaging error: {err}"),
-									)
-									.await;
+	// adds an aerodrome to the connection, unless it has been closed
+	fn join(
+		self: &Arc<Self>,
+		icao: &str,
+	) -> Option<(Arc<Socket>, UnboundedReceiver<Incoming>)> {
+		let mut members = self.members.lock().unwrap();
+		let members = members.as_mut()?;
 
-								break
-							}
-						},
-						Ok(Some(Ok(_))) => (),
-						Ok(Some(Err(err))) => {
-							warn!("socket closed with error: {err}");
-							this
-								.disconnect_forced(
-									socket_arc,
-									format!("server connection error: {err}"),
-								)
-								.await;
+		let socket = Arc::new(Socket {
+			connection: self.clone(),
+			icao: icao.into(),
+		});
+		let (tx, rx) = mpsc::unbounded_channel();
+		members.insert(icao.into(), (Arc::downgrade(&socket), tx));
 
-							break
-						},
-						Ok(None) => {
-							debug!("socket closed");
-							this
-								.disconnect_forced(
-									socket_arc,
-									format!("connection closed unexpectedly"),
-								)
-								.await;
+		Some((socket, rx))
+	}
 
-							break
+	fn dispatch(&self, icao: &str, incoming: Incoming) {
+		let members = self.members.lock().unwrap();
+		match members.as_ref().and_then(|members| members.get(icao)) {
+			Some((_, tx)) => {
+				let _ = tx.send(incoming);
+			},
+			None => debug!("ignoring message for {icao}: not on the connection"),
+		}
+	}
+
+	// passes on the close of an aerodrome which the server has already left
+	fn close(&self, icao: &str, reason: Option<CloseReason>) {
+		let mut members = self.members.lock().unwrap();
+		let Some(map) = members.as_mut() else { return };
+		let Some((_, tx)) = map.remove(icao) else {
+			return
+		};
+		// the server closes the connection with its last aerodrome
+		if map.is_empty() {
+			*members = None;
+		}
+
+		let _ = tx.send(Incoming::Message(NetDownstream::Close(reason)));
+	}
+
+	fn broadcast(&self, incoming: impl Fn() -> Incoming) {
+		if let Some(members) = &*self.members.lock().unwrap() {
+			for (_, tx) in members.values() {
+				let _ = tx.send(incoming());
+			}
+		}
+	}
+
+	// tells every aerodrome on the connection that it can no longer be used
+	fn lose(&self, incoming: impl Fn() -> Incoming) {
+		let members = self.members.lock().unwrap().take();
+		for (_, tx) in members.into_iter().flatten().map(|(_, member)| member) {
+			let _ = tx.send(incoming());
+		}
+	}
+
+	// reads messages from the server, passing on those of each aerodrome, and
+	// keeps the connection alive
+	async fn read(
+		self: Arc<Self>,
+		mut stream: SocketStream,
+		endpoints: Arc<Endpoints>,
+		encoding: Encoding,
+		upgraded: Arc<AtomicBool>,
+		// key which the connection is authenticated with
+		mut key: String,
+	) {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+		// heartbeat timestamps are relative to the connection
+		let connected = Instant::now();
+		// heartbeats are sent on their own schedule, however busy the socket
+		let mut heartbeat = tokio::time::interval_at(
+			tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+			HEARTBEAT_INTERVAL,
+		);
+		heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		// any message shows that the connection is alive, not only the
+		// acknowledgement of a heartbeat
+		let mut last_received = Instant::now();
+
+		let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+
+		loop {
+			let frame = tokio::select! {
+				frame = stream.next() => frame,
+				_ = heartbeat.tick() => {
+					if last_received.elapsed() > HEARTBEAT_TIMEOUT {
+						warn!("nothing received from server: connection dead");
+						self.lose(|| Incoming::Dead);
+
+						break
+					}
+
+					let ping = Ping {
+						timestamp: connected.elapsed().as_millis() as u64,
+					};
+					let heartbeat = NetUpstream::Heartbeat(Some(ping));
+					if let Err(err) = self.queue(Outgoing::Message(heartbeat)) {
+						let message = format!("server messaging error: {err}");
+						self.lose(|| Incoming::Failed(message.clone()));
+
+						break
+					}
+
+					continue
+				},
+			};
+
+			if let Some(Ok(_)) = frame {
+				last_received = Instant::now();
+			}
+
+			let frame = match frame {
+				Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => frame,
+				Some(Ok(_)) => continue,
+				Some(Err(err)) => {
+					warn!("socket closed with error: {err}");
+					let message = format!("server connection error: {err}");
+					self.lose(|| Incoming::Failed(message.clone()));
+
+					break
+				},
+				None => {
+					debug!("socket closed");
+					let message = "connection closed unexpectedly";
+					self.lose(|| Incoming::Failed(message.into()));
+
+					break
+				},
+			};
+
+			let binary = frame.is_binary();
+			let frame_encoding = if binary { encoding } else { Encoding::Json };
+
+			if upgraded.swap(binary, Ordering::Relaxed) != binary {
+				debug!("server accepted {} encoding", frame_encoding.name());
+			}
+
+			let Ok(data) = frame_encoding
+				.decode::<NetDownstream<Option<Patch>>>(&frame.into_data())
+			else {
+				warn!("net downstream deserialisation failed");
+				continue
+			};
+
+			trace!("ws rx ({n}): {data:?}");
+
+			let res = match data {
+				NetDownstream::Heartbeat => {
+					self.queue(Outgoing::Message(NetUpstream::HeartbeatAck))
+				},
+				NetDownstream::HeartbeatAck(Some(ping)) => {
+					let sent = Duration::from_millis(ping.timestamp);
+					let latency = connected.elapsed().saturating_sub(sent);
+					trace!("heartbeat round trip {latency:?}");
+
+					self.broadcast(|| Incoming::Latency(latency));
+					Ok(())
+				},
+				NetDownstream::HeartbeatAck(None) => Ok(()),
+				// every aerodrome on the connection is closed with it
+				NetDownstream::Close(reason) => {
+					self.lose(|| Incoming::Message(NetDownstream::Close(reason)));
+
+					break
+				},
+				// the key belongs to the connection rather than any one aerodrome
+				NetDownstream::Error {
+					code: ErrorCode::AuthExpired,
+					..
+				} => {
+					let renewed = endpoints
+						.renew_key(&key)
+						.inspect_err(|err| warn!("failed to renew key: {err}"))
+						.ok()
+						.flatten();
+					match renewed {
+						Some(renewed) => {
+							debug!("renewing expired key");
+							key = renewed.clone();
+							let message = NetUpstream::Authenticate { key: renewed };
+							self.queue(Outgoing::Message(message))
+						},
+						None => {
+							let message = "token expired: update local.toml";
+							self.lose(|| Incoming::Failed(message.into()));
+
+							break
+						},
+					}
+				},
+				NetDownstream::Multiplexed { airport, message } => {
+					// the server has already left an aerodrome which it closes, so
+					// the aerodrome must not leave it again
+					match *message {
+						NetDownstream::Close(reason) => self.close(&airport, reason),
+						message => self.dispatch(&airport, Incoming::Message(message)),
+					}
+					Ok(())
+				},
+				message => {
+					// further aerodromes can join once the server has said that it
+					// supports them
+					if let NetDownstream::InitialState { capabilities, .. } = &message {
+						if capabilities.contains(&Capability::Multiplex) {
+							endpoints.offer(&self);
+						}
+					}
+
+					self.dispatch(&self.primary, Incoming::Message(message));
+					Ok(())
+				},
+			};
+
+			if let Err(err) = res {
+				let message = format!("server messaging error: {err}");
+				self.lose(|| Incoming::Failed(message.clone()));
+
+				break
+			}
+		}
+	}
+}
+
+// an aerodrome's use of a connection, which may be shared with others
+struct Socket {
+	connection: Arc<Connection>,
+	icao: String,
+}
+
+impl Socket {
+	fn send(&self, message: NetUpstream) -> Result<()> {
+		let message = if self.icao == self.connection.primary {
+			message
+		} else {
+			NetUpstream::Multiplexed {
+				airport: self.icao.clone(),
+				message: Box::new(message),
+			}
+		};
+
+		self.connection.queue(Outgoing::Message(message))
+	}
+
+	// leaves the connection, closing it if no other aerodrome is on it, and
+	// returns a receiver which reports once the close has been sent
+	fn leave(&self, reason: CloseReason) -> oneshot::Receiver<Result<()>> {
+		let (done, closed) = oneshot::channel();
+
+		let mut members = self.connection.members.lock().unwrap();
+		// the connection may be lost, or the aerodrome have left it already
+		let Some(map) = members.as_mut().filter(|map| {
+			map
+				.get(&self.icao)
+				.is_some_and(|(socket, _)| std::ptr::eq(socket.as_ptr(), self))
+		}) else {
+			let _ = done.send(Ok(()));
+			return closed
+		};
+		map.remove(&self.icao);
+
+		if map.is_empty() {
+			*members = None;
+			drop(members);
+
+			// the receiver fails if the writer has already stopped
+			let _ = self.connection.queue(Outgoing::Close(reason, done));
+		} else {
+			drop(members);
+
+			// a wrapped close leaves only this aerodrome, even if it is the one
+			// connected to
+			let message = NetUpstream::Multiplexed {
+				airport: self.icao.clone(),
+				message: Box::new(NetUpstream::Close(Some(reason))),
+			};
+			let _ = done.send(self.connection.queue(Outgoing::Message(message)));
+		}
+
+		closed
+	}
+}
+
+impl AerodromeManager {
+	async fn new(
+		icao: &str,
+		options: &Option<ConnectOptions>,
+		endpoints: Option<Arc<Endpoints>>,
+		config: Arc<Mutex<ConfigManager>>,
+		broadcast: Sender<Downstream>,
+		panicked: UnboundedSender<Panic>,
+	) -> Result<Self> {
+		let this = Self {
+			data: Arc::new(Mutex::new(AerodromeManagerData {
+				config: None,
+				controlling: false,
+				trackers: 0,
+				connections: 0,
+				state: SharedState::default(),
+				pending: None,
+				pending_preset: None,
+				sent: None,
+				queued_scenery: Scenery::new(),
+				throttled_until: None,
+				announced: VecDeque::new(),
+				controllers: Vec::new(),
+				controller_id: None,
+				claims: HashMap::new(),
+				capabilities: Vec::new(),
+				latency: None,
+				endpoint: None,
+				handoff: None,
+				handed_off: false,
+				socket: None,
+			})),
+			server: options
+				.as_ref()
+				.zip(endpoints)
+				.map(|(options, endpoints)| (endpoints, options.encoding)),
+			callsign: options.as_ref().map(|options| options.callsign.clone()),
+			icao: icao.into(),
+			broadcast: broadcast.clone(),
+			panicked,
+		};
+
+		{
+			let icao = icao.to_string();
+			let loaded = this.clone();
+			this.spawn(async move {
+				match config.lock().await.load(&icao).await {
+					Ok(None) => (),
+					Ok(Some(config)) => {
+						{
+							loaded.data.lock().await.config = Some(config);
+						}
+						loaded.sync_clients().await;
+					},
+					Err(err) => warn!("failed to load config: {err}"),
+				}
+			});
+		}
+
+		Ok(this)
+	}
+
+	// spawns a task of the aerodrome, passing on any panic to the worker
+	fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+		let panicked = self.panicked.clone();
+		tokio::spawn(async move {
+			if let Err(panic) = AssertUnwindSafe(task).catch_unwind().await {
+				let _ = panicked.send(panic);
+			}
+		});
+	}
+
+	fn broadcast(&self, message: Downstream) {
+		if self.broadcast.send(message).is_err() {
+			warn!("broadcast channel full");
+		}
+	}
+
+	async fn sync_clients(&self) {
+		let data = self.data.lock().await;
+		if let Some(config) = &data.config {
+			self.broadcast(Downstream::Config {
+				data: config.clone(),
+			});
+			self.broadcast(Downstream::Control {
+				icao: self.icao.clone(),
+				control: data.controlling,
+			});
+			self.broadcast(Downstream::Patch {
+				icao: self.icao.clone(),
+				patch: data.state.state().clone(),
+				controller: None,
+			});
+			self.broadcast(Downstream::Controllers {
+				icao: self.icao.clone(),
+				controllers: data.controllers.clone(),
+			});
+			self.broadcast_claims(&data);
+			self.broadcast(Downstream::Latency {
+				icao: self.icao.clone(),
+				latency: data.latency,
+			});
+			self.broadcast(Downstream::Endpoint {
+				icao: self.icao.clone(),
+				server: data.endpoint.clone(),
+			});
+			self.broadcast_diagnostics(&data);
+		}
+	}
+
+	fn broadcast_diagnostics(&self, data: &AerodromeManagerData) {
+		self.broadcast(Downstream::Diagnostics {
+			icao: self.icao.clone(),
+			trackers: data.trackers,
+			reconnects: data.connections.saturating_sub(1),
+		});
+	}
+
+	fn set_latency(&self, data: &mut AerodromeManagerData, latency: Option<u32>) {
+		data.latency = latency;
+		self.broadcast(Downstream::Latency {
+			icao: self.icao.clone(),
+			latency,
+		});
+	}
+
+	fn set_endpoint(
+		&self,
+		data: &mut AerodromeManagerData,
+		server: Option<String>,
+	) {
+		data.endpoint = server.clone();
+		self.broadcast(Downstream::Endpoint {
+			icao: self.icao.clone(),
+			server,
+		});
+	}
+
+	fn set_capabilities(
+		&self,
+		data: &mut AerodromeManagerData,
+		capabilities: Vec<Capability>,
+	) {
+		self.broadcast(Downstream::Capabilities {
+			icao: self.icao.clone(),
+			capabilities: capabilities.clone(),
+		});
+		data.capabilities = capabilities;
+	}
+
+	fn set_handoff(&self, data: &mut AerodromeManagerData, id: Option<String>) {
+		self.broadcast(Downstream::HandoffRequest {
+			icao: self.icao.clone(),
+			requester: id.as_ref().map(|id| data.controller_name(id)),
+		});
+		data.handoff = id;
+	}
+
+	fn set_controlling(&self, data: &mut AerodromeManagerData, control: bool) {
+		data.controlling = control;
+		self.broadcast(Downstream::Control {
+			icao: self.icao.clone(),
+			control,
+		});
+	}
+
+	// updates the list of other controllers, ignoring this client's own
+	// connection
+	fn set_controllers(
+		&self,
+		data: &mut AerodromeManagerData,
+		f: impl FnOnce(&mut Vec<Controller>),
+	) {
+		f(&mut data.controllers);
+
+		let own_id = data.controller_id.as_ref();
+		data.controllers.retain(|controller| {
+			Some(&controller.controller_id) != own_id
+				&& (controller.callsign.is_none()
+					|| controller.callsign != self.callsign)
+		});
+
+		self.broadcast(Downstream::Controllers {
+			icao: self.icao.clone(),
+			controllers: data.controllers.clone(),
+		});
+	}
+
+	fn set_claims(
+		&self,
+		data: &mut AerodromeManagerData,
+		f: impl FnOnce(&mut HashMap<String, String>),
+	) {
+		f(&mut data.claims);
+		self.broadcast_claims(data);
+	}
+
+	fn broadcast_claims(&self, data: &AerodromeManagerData) {
+		self.broadcast(Downstream::Claims {
+			icao: self.icao.clone(),
+			claims: data
+				.claims
+				.iter()
+				.map(|(block, owner)| {
+					(block.clone(), Some(owner) == data.controller_id.as_ref())
+				})
+				.collect(),
+		});
+	}
+
+	async fn connect(&self) -> Result<()> {
+		let mut data = self.data.lock().await;
+
+		if data.socket.is_some() {
+			warn!("aerodrome connection attempted whilst connected");
+			return Ok(())
+		}
+
+		if let Some((endpoints, encoding)) = &self.server {
+			let encoding = *encoding;
+
+			// aerodromes share a connection where the server allows it
+			let (socket, rx) = match endpoints.join(&self.icao) {
+				Some((socket, rx)) => {
+					debug!(
+						"joining {} over the connection to {}",
+						self.icao, socket.connection.primary,
+					);
+					socket.connection.queue(Outgoing::Message(
+						NetUpstream::Multiplexed {
+							airport: self.icao.clone(),
+							message: Box::new(NetUpstream::Join),
+						},
+					))?;
+
+					(socket, rx)
+				},
+				None => {
+					let mut query = format!("airport={}", self.icao);
+					if encoding != Encoding::Json {
+						query += &format!("&{}={}", Encoding::QUERY_PARAM, encoding.name());
+					}
+					if let Some(callsign) = &self.callsign {
+						query += &format!("&callsign={callsign}");
+					}
+
+					let key = endpoints.key();
+					let (i, stream) = endpoints.connect(&query, &key).await?;
+					let connection = self.open(endpoints, i, stream, encoding, key);
+					let Some(joined) = connection.join(&self.icao) else {
+						bail!("connection closed");
+					};
+
+					joined
+				},
+			};
+
+			let (server, base) = &endpoints.servers[socket.connection.server];
+			let state_endpoint = format!("http{base}/state?airport={}", self.icao);
+
+			if socket.connection.server > 0 {
+				self.broadcast(Downstream::Notice {
+					icao: self.icao.clone(),
+					message: format!("connected to fallback server {server}"),
+				});
+			}
+			self.set_endpoint(&mut data, Some(server.clone()));
+			data.connections += 1;
+			self.broadcast_diagnostics(&data);
+
+			data.socket = Some(socket.clone());
+			data.announced.clear();
+
+			// whether the server pushes aircraft, so that polling is unnecessary
+			let aircraft_pushed = Arc::new(AtomicBool::new(false));
+
+			{
+				let socket = socket.clone();
+				let http = endpoints.http.clone();
+				let aircraft_pushed = aircraft_pushed.clone();
+				let this = self.clone();
+				self.spawn(async move {
+					this
+						.poll_state(socket, http, state_endpoint, aircraft_pushed)
+						.await
+				});
+			}
+
+			let this = self.clone();
+			self.spawn(async move {
+				this.receive(socket, rx, aircraft_pushed).await;
+			});
+		}
+
+		Ok(())
+	}
+
+	// opens a connection made for this aerodrome, with tasks writing to and
+	// reading from it
+	fn open(
+		&self,
+		endpoints: &Arc<Endpoints>,
+		server: usize,
+		stream: WsStream,
+		encoding: Encoding,
+		key: String,
+	) -> Arc<Connection> {
+		let (mut sink, stream) = stream.split();
+		let (tx, mut rx) = mpsc::unbounded_channel();
+		let connection = Arc::new(Connection {
+			tx,
+			primary: self.icao.clone(),
+			server,
+			members: SyncMutex::new(Some(HashMap::new())),
+		});
+
+		// upstream messages are JSON until the server has sent one in the
+		// requested encoding
+		let upgraded = Arc::new(AtomicBool::new(false));
+
+		{
+			let connection = Arc::downgrade(&connection);
+			let upgraded = upgraded.clone();
+			self.spawn(async move {
+				while let Some(outgoing) = rx.recv().await {
+					let encoding = if upgraded.load(Ordering::Relaxed) {
+						encoding
+					} else {
+						Encoding::Json
+					};
+
+					match outgoing {
+						Outgoing::Message(message) => {
+							let Err(err) = Self::write(&mut sink, encoding, &message).await
+							else {
+								continue
+							};
+
+							if let Some(connection) = connection.upgrade() {
+								let message = format!("server messaging error: {err}");
+								connection.lose(|| Incoming::Failed(message.clone()));
+							}
+
+							break
+						},
+						Outgoing::Close(reason, done) => {
+							let message = NetUpstream::Close(Some(reason));
+							let res = match Self::write(&mut sink, encoding, &message).await {
+								Ok(()) => sink.close().await.map_err(Into::into),
+								Err(err) => Err(err),
+							};
+							let _ = done.send(res);
+
+							return
+						},
+					}
+				}
+
+				// every handle to the connection has been dropped
+				let _ = sink.close().await;
+			});
+		}
+
+		self.spawn(connection.clone().read(
+			stream,
+			endpoints.clone(),
+			encoding,
+			upgraded,
+			key,
+		));
+
+		connection
+	}
+
+	// handles the messages of the aerodrome passed on by its connection, until
+	// it leaves the connection or the connection is lost
+	async fn receive(
+		&self,
+		socket: Arc<Socket>,
+		mut rx: UnboundedReceiver<Incoming>,
+		aircraft_pushed: Arc<AtomicBool>,
+	) {
+		let mut last_sequence = None;
+
+		while let Some(incoming) = rx.recv().await {
+			let data = match incoming {
+				Incoming::Message(data) => data,
+				Incoming::Latency(latency) => {
+					let mut data = self.data.lock().await;
+					self.set_latency(&mut data, Some(latency.as_millis() as u32));
+					continue
+				},
+				Incoming::Dead => {
+					if self.drop_socket(&socket).await {
+						self.broadcast(Downstream::Notice {
+							icao: self.icao.clone(),
+							message: "server stopped responding, reconnecting".into(),
+						});
+						self.reconnect_after(DEAD_RECONNECT_DELAY);
+					}
+
+					break
+				},
+				Incoming::Failed(message) => {
+					self.disconnect_forced(&socket, message).await;
+
+					break
+				},
+			};
+
+			let res = match data {
+				NetDownstream::Close(reason) => {
+					let reason = reason.unwrap_or_default();
+					warn!("server-initiated graceful close: {reason}");
+					self
+						.disconnect_forced(
+							&socket,
+							format!("server closed connection: {reason}"),
+						)
+						.await;
+
+					if reason == CloseReason::ServerRestart {
+						self.reconnect_after(RESTART_RECONNECT_DELAY);
+					}
+
+					break
+				},
+				NetDownstream::Error { code, detail } => {
+					let message = match &detail {
+						Some(detail) => format!("{code}: {detail}"),
+						None => code.to_string(),
+					};
+					warn!("server: {message}");
+
+					match code {
+						ErrorCode::Unauthorized | ErrorCode::UnknownAirport => {
+							self.disconnect_forced(&socket, message).await;
+
+							break
+						},
+						ErrorCode::StaleState => socket.send(NetUpstream::ResyncRequest),
+						ErrorCode::RateLimited => {
+							self.throttle(RATE_LIMIT_BACKOFF).await;
+							Ok(())
 						},
-						Err(_) => {
-							if last_state_poll.elapsed() > STATE_POLL_INTERVAL {
-								debug!("interval poll state for {}", this.icao);
+						_ => {
+							self.broadcast(Downstream::Error {
+								icao: self.icao.clone(),
+								message: Some(message),
+								disconnect: false,
+							});
+							Ok(())
+						},
+					}
+				},
+				state @ (NetDownstream::InitialState { .. }
+				| NetDownstream::SharedStateUpdate { .. }
+				| NetDownstream::PresetApplied { .. }
+				| NetDownstream::ProfileChanged { .. }
+				| NetDownstream::Snapshot { .. }) => {
+					let mut notice = None;
+					let mut profile_change = None;
+					// controller which made the change
+					let mut author = None;
+					// full state replaces what was known, rather than merging
+					let mut snapshot = false;
+					let (patch, control, sequence) = match state {
+						NetDownstream::InitialState {
+							connection_type,
+							patch,
+							controllers,
+							claims,
+							controller_id,
+							sequence,
+							capabilities,
+							..
+						} => {
+							let mut data = self.data.lock().await;
+							data.controller_id = controller_id;
+							self.set_capabilities(&mut data, capabilities);
+							self.set_controllers(&mut data, |list| *list = controllers);
+							self.set_claims(&mut data, |map| *map = claims);
+							drop(data);
+
+							last_sequence = sequence;
+							snapshot = true;
+							let control = connection_type == "controller";
+							(patch, Some(control), Sequence::Next)
+						},
+						NetDownstream::SharedStateUpdate {
+							patch,
+							controller_id,
+							sequence,
+							..
+						} => {
+							author = Some(controller_id);
+							let sequence = Sequence::check(&mut last_sequence, sequence);
+							(patch, None, sequence)
+						},
+						NetDownstream::PresetApplied {
+							preset,
+							patch,
+							controller_id,
+							sequence,
+							..
+						} => {
+							author = Some(controller_id.clone());
+							notice = Some((controller_id, preset));
+							let sequence = Sequence::check(&mut last_sequence, sequence);
+							(patch, None, sequence)
+						},
+						NetDownstream::ProfileChanged {
+							profile,
+							patch,
+							controller_id,
+							sequence,
+							..
+						} => {
+							author = Some(controller_id.clone());
+							profile_change = Some((controller_id, profile));
+							let sequence = Sequence::check(&mut last_sequence, sequence);
+							(patch, None, sequence)
+						},
+						NetDownstream::Snapshot {
+							patch,
+							claims,
+							sequence,
+							..
+						} => {
+							debug!("resynchronised at {sequence}");
+							let mut data = self.data.lock().await;
+							self.set_claims(&mut data, |map| *map = claims);
+							drop(data);
+
+							last_sequence = Some(sequence);
+							snapshot = true;
+							(patch, None, Sequence::Next)
+						},
+						_ => unreachable!(),
+					};
+
+					if sequence == Sequence::Stale {
+						continue
+					}
 
-								last_state_poll = Instant::now();
+					let mut patch = patch.unwrap_or_default();
 
-								let response = match reqwest::get(&state_endpoint).await {
-									Ok(response) => response,
-									Err(err) => {
-										warn!("failed to fetch state: {err}");
-										continue
-									},
-								};
+					let mut data = self.data.lock().await;
+
+					if let Some(config) = &data.config {
+						let warnings;
+						(patch, warnings) =
+							patch.validate(config, data.state.state().profile.as_deref());
+						for warning in warnings {
+							warn!("ignoring {warning}");
+						}
+					}
+
+					let patch = if snapshot {
+						data.state.replace(patch.clone());
+						patch
+					} else {
+						data.state.merge(patch)
+					};
+
+					// changes are attributed only to other controllers
+					let controller = author
+						.filter(|author| data.controller_id.as_ref() != Some(author))
+						.map(|author| data.controller_name(&author));
+
+					self.broadcast(Downstream::Patch {
+						icao: self.icao.clone(),
+						patch,
+						controller,
+					});
+
+					if let Some(control) = control {
+						data.controlling = control;
+						self.broadcast(Downstream::Control {
+							icao: self.icao.clone(),
+							control,
+						});
+					}
+
+					if let Some((controller, preset)) = notice {
+						if data.announced.front() == Some(&preset) {
+							data.announced.pop_front();
+						} else {
+							self.broadcast(Downstream::Notice {
+								icao: self.icao.clone(),
+								message: format!("{controller} applied preset {preset}"),
+							});
+						}
+					}
+
+					// a profile change resets the whole display, so it is never
+					// applied without telling the user who made it
+					if let Some((controller, profile)) = profile_change {
+						if data.controller_id.as_ref() != Some(&controller) {
+							self.broadcast(Downstream::Notice {
+								icao: self.icao.clone(),
+								message: format!(
+									"{} changed profile to {profile}",
+									data.controller_name(&controller),
+								),
+							});
+						}
+					}
 
-								let Ok(data) = response.json::<State>().await else {
-									warn!("net state deserialisation failed");
-									continue
-								};
+					drop(data);
 
-								this.broadcast(Downstream::Aircraft {
-									icao: this.icao.clone(),
-									aircraft: data.pilots,
-								});
+					// changes queued whilst disconnected are replayed against
+					// the initial state
+					if control.is_some() {
+						let replaying = self.clone();
+						self.spawn(async move {
+							if let Err(err) = replaying.replay().await {
+								warn!("{err}");
 							}
-						},
+						});
 					}
-				}
+
+					self.resync_if_gap(&socket, sequence)
+				},
+				NetDownstream::StateUpdate { sequence, .. } => {
+					let sequence = Sequence::check(&mut last_sequence, sequence);
+					self.resync_if_gap(&socket, sequence)
+				},
+				NetDownstream::Throttled { retry_after } => {
+					self.throttle(Duration::from_millis(retry_after)).await;
+					Ok(())
+				},
+				NetDownstream::ControllerConnect(controller) => {
+					let mut data = self.data.lock().await;
+					self.set_controllers(&mut data, |list| {
+						list.retain(|c| c.controller_id != controller.controller_id);
+						list.push(controller);
+					});
+					Ok(())
+				},
+				NetDownstream::ControllerDisconnect { controller_id } => {
+					let mut data = self.data.lock().await;
+					self.set_controllers(&mut data, |list| {
+						list.retain(|c| c.controller_id != controller_id);
+					});
+					self.set_claims(&mut data, |map| {
+						map.retain(|_, owner| *owner != controller_id);
+					});
+					Ok(())
+				},
+				NetDownstream::BlocksClaimed {
+					blocks,
+					controller_id,
+				} => {
+					let mut data = self.data.lock().await;
+					self.set_claims(&mut data, |map| {
+						for block in blocks {
+							map.insert(block, controller_id.clone());
+						}
+					});
+					Ok(())
+				},
+				NetDownstream::BlocksReleased {
+					blocks,
+					controller_id,
+				} => {
+					let mut data = self.data.lock().await;
+					self.set_claims(&mut data, |map| {
+						for block in blocks {
+							if map.get(&block) == Some(&controller_id) {
+								map.remove(&block);
+							}
+						}
+					});
+					Ok(())
+				},
+				NetDownstream::ClaimRejected { conflicts } => {
+					let data = self.data.lock().await;
+					let mut conflicts = conflicts
+						.into_iter()
+						.map(|(block, owner)| {
+							format!("{block} ({})", data.controller_name(&owner))
+						})
+						.collect::<Vec<_>>();
+					conflicts.sort();
+
+					self.broadcast(Downstream::Notice {
+						icao: self.icao.clone(),
+						message: format!(
+							"claim refused: held by another controller: {}",
+							conflicts.join(", "),
+						),
+					});
+					Ok(())
+				},
+				NetDownstream::HandoffRequested { requester, holder } => {
+					let mut data = self.data.lock().await;
+					if data.controller_id.as_ref() == Some(&holder) {
+						self.set_handoff(&mut data, Some(requester));
+					}
+					Ok(())
+				},
+				NetDownstream::HandoffCompleted { requester, holder } => {
+					let mut data = self.data.lock().await;
+					let own_id = data.controller_id.clone();
+					if own_id.as_ref() == Some(&holder) {
+						if data.handoff.as_ref() == Some(&requester) {
+							self.set_handoff(&mut data, None);
+						}
+
+						data.handed_off = true;
+						self.set_controlling(&mut data, false);
+						self.broadcast(Downstream::Notice {
+							icao: self.icao.clone(),
+							message: format!(
+								"handed off to {}",
+								data.controller_name(&requester),
+							),
+						});
+					} else if own_id.as_ref() == Some(&requester) {
+						self.set_controlling(&mut data, true);
+						self.broadcast(Downstream::Notice {
+							icao: self.icao.clone(),
+							message: format!("{} handed off", data.controller_name(&holder),),
+						});
+					}
+					Ok(())
+				},
+				NetDownstream::HandoffDenied { requester, holder } => {
+					let mut data = self.data.lock().await;
+					let own_id = data.controller_id.clone();
+					if own_id.as_ref() == Some(&holder) {
+						if data.handoff.as_ref() == Some(&requester) {
+							self.set_handoff(&mut data, None);
+						}
+					} else if own_id.as_ref() == Some(&requester) {
+						self.set_controlling(&mut data, true);
+						self.broadcast(Downstream::Notice {
+							icao: self.icao.clone(),
+							message: format!(
+								"{} denied handoff",
+								data.controller_name(&holder),
+							),
+						});
+					}
+					Ok(())
+				},
+				NetDownstream::AircraftUpdate { aircraft } => {
+					if !aircraft_pushed.swap(true, Ordering::Relaxed) {
+						debug!("server pushes aircraft: polling reduced");
+					}
+
+					self.broadcast(Downstream::Aircraft {
+						icao: self.icao.clone(),
+						aircraft: aircraft
+							.into_iter()
+							.map(|aircraft| aircraft.callsign)
+							.collect(),
+					});
+					Ok(())
+				},
+				// handled by the connection
+				NetDownstream::Heartbeat
+				| NetDownstream::HeartbeatAck(_)
+				| NetDownstream::Multiplexed { .. } => Ok(()),
+				NetDownstream::Other(_) => {
+					debug!("ignoring unrecognised message");
+					Ok(())
+				},
+			};
+
+			if let Err(err) = res {
+				self
+					.disconnect_forced(&socket, format!("server messaging error: {err}"))
+					.await;
+
+				break
+			}
+		}
+	}
+
+	// polls the state of the aerodrome for as long as `socket` is in use, apart
+	// from the socket's read loop so that a slow response does not hold up
+	// messages
+	async fn poll_state(
+		&self,
+		socket: Arc<Socket>,
+		http: reqwest::Client,
+		endpoint: String,
+		aircraft_pushed: Arc<AtomicBool>,
+	) {
+		loop {
+			let interval = if aircraft_pushed.load(Ordering::Relaxed) {
+				PRESENCE_POLL_INTERVAL
+			} else {
+				STATE_POLL_INTERVAL
+			};
+			tokio::time::sleep(interval).await;
+
+			if !self.is_current(&socket).await {
+				break
+			}
+
+			debug!("interval poll state for {}", self.icao);
+
+			let response = match http.get(&endpoint).send().await {
+				Ok(response) => response,
+				Err(err) => {
+					warn!("failed to fetch state: {err}");
+					continue
+				},
+			};
+
+			let Ok(data) = response.json::<State>().await else {
+				warn!("net state deserialisation failed");
+				continue
+			};
+
+			// the connection may have been lost whilst waiting
+			if !self.is_current(&socket).await {
+				break
+			}
+
+			if !aircraft_pushed.load(Ordering::Relaxed) {
+				self.broadcast(Downstream::Aircraft {
+					icao: self.icao.clone(),
+					aircraft: data.pilots,
+				});
+			}
+
+			self.broadcast(Downstream::Presence {
+				icao: self.icao.clone(),
+				observer_count: data.observer_count.max(data.observers.len()),
+				observers: data.observers,
+				connected: data.connected,
 			});
 		}
+	}
 
-		Ok(())
+	// whether `socket` is still the one in use
+	async fn is_current(&self, socket: &Arc<Socket>) -> bool {
+		let data = self.data.lock().await;
+		data
+			.socket
+			.as_ref()
+			.is_some_and(|current| Arc::ptr_eq(current, socket))
+	}
+
+	// sends any changes still held back, then closes the connection
+	async fn shutdown(&self) -> Result<()> {
+		if self.data.lock().await.socket.is_none() {
+			return Ok(())
+		}
+
+		self.flush().await?;
+		self.disconnect().await
 	}
 
 	async fn disconnect(&self) -> Result<()> {
 		debug!("disconnecting socket");
 
-		if let Some(socket) = &self.data.lock().await.socket.take() {
-			let mut socket = socket.lock().await;
+		let mut data = self.data.lock().await;
+		self.set_controllers(&mut data, Vec::clear);
+		self.set_claims(&mut data, HashMap::clear);
+		self.set_latency(&mut data, None);
+		self.set_endpoint(&mut data, None);
+		self.set_handoff(&mut data, None);
+		self.set_capabilities(&mut data, Vec::new());
+		data.pending = None;
+		data.pending_preset = None;
+		data.sent = None;
+		data.queued_scenery.clear();
+		data.throttled_until = None;
+
+		let reason = if std::mem::take(&mut data.handed_off) {
+			CloseReason::PositionHandoff
+		} else {
+			CloseReason::ControllerLogoff
+		};
+
+		if let Some(socket) = data.socket.take() {
+			drop(data);
 
-			Self::send(&mut socket, &NetUpstream::Close).await?;
-			socket.close(None).await?;
+			socket.leave(reason).await??;
 		}
 
 		Ok(())
 	}
 
-	async fn disconnect_forced(
-		&self,
-		socket_arc: &Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
-		message: String,
-	) {
-		let mut data = self.data.lock().await;
-		if data
-			.socket
-			.as_ref()
-			.map(|socket| Arc::ptr_eq(socket, socket_arc))
-			.unwrap_or_default()
-		{
-			data.socket = None;
+	async fn disconnect_forced(&self, socket_arc: &Arc<Socket>, message: String) {
+		if self.drop_socket(socket_arc).await {
 			self.broadcast(Downstream::Error {
 				icao: self.icao.clone(),
 				message: Some(message),
@@ -622,14 +2209,73 @@ impl AerodromeManager {
 		}
 	}
 
-	async fn send(
-		socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+	// forgets a socket which can no longer be used, along with everything
+	// learnt over it, returning whether it was the current one
+	async fn drop_socket(&self, socket_arc: &Arc<Socket>) -> bool {
+		let mut data = self.data.lock().await;
+		if !data
+			.socket
+			.as_ref()
+			.is_some_and(|socket| Arc::ptr_eq(socket, socket_arc))
+		{
+			return false
+		}
+
+		data.socket = None;
+		// other aerodromes may still be using the connection
+		let _ = socket_arc.leave(CloseReason::Other);
+		self.set_controllers(&mut data, Vec::clear);
+		self.set_claims(&mut data, HashMap::clear);
+		self.set_latency(&mut data, None);
+		self.set_endpoint(&mut data, None);
+		self.set_handoff(&mut data, None);
+		self.set_capabilities(&mut data, Vec::new());
+		data.sent = None;
+		data.throttled_until = None;
+		data.handed_off = false;
+
+		true
+	}
+
+	// connects again after `delay` if the aerodrome is still tracked
+	fn reconnect_after(&self, delay: Duration) {
+		let this = self.clone();
+		self.spawn(async move {
+			tokio::time::sleep(delay).await;
+
+			{
+				let data = this.data.lock().await;
+				if data.trackers == 0 || data.socket.is_some() {
+					return
+				}
+			}
+
+			debug!("reconnecting to {}", this.icao);
+			if let Err(err) = this.connect().await {
+				this.broadcast(Downstream::Error {
+					icao: this.icao.clone(),
+					message: Some(format!("server connection failed: {err}")),
+					disconnect: true,
+				});
+			}
+		});
+	}
+
+	async fn write(
+		sink: &mut SocketSink,
+		encoding: Encoding,
 		message: &NetUpstream,
 	) -> Result<()> {
 		trace!("ws tx: {message:?}");
 
-		if let Ok(data) = serde_json::to_string(message) {
-			socket.send(Message::Text(data.into())).await?;
+		if let Ok(data) = encoding.encode(message) {
+			let frame = if encoding.is_binary() {
+				Message::Binary(data.into())
+			} else {
+				Message::Text(String::from_utf8(data)?.into())
+			};
+
+			sink.send(frame).await?;
 		} else {
 			#[cfg(debug_assertions)]
 			panic!("net upstream serialisation failed");
@@ -638,6 +2284,15 @@ impl AerodromeManager {
 		Ok(())
 	}
 
+	fn resync_if_gap(&self, socket: &Socket, sequence: Sequence) -> Result<()> {
+		if sequence == Sequence::Gap {
+			warn!("missed state messages for {}: resynchronising", self.icao);
+			socket.send(NetUpstream::ResyncRequest)?;
+		}
+
+		Ok(())
+	}
+
 	async fn track(&self, track: bool) -> Result<()> {
 		let trackers = {
 			let mut data = self.data.lock().await;
@@ -646,6 +2301,7 @@ impl AerodromeManager {
 			} else if data.trackers > 0 {
 				data.trackers -= 1;
 			}
+			self.broadcast_diagnostics(&data);
 
 			data.trackers
 		};
@@ -682,28 +2338,289 @@ impl AerodromeManager {
 		}
 	}
 
-	async fn patch(&self, patch: Patch) -> Result<()> {
+	async fn patch(
+		&self,
+		mut patch: Patch,
+		preset: Option<String>,
+	) -> Result<()> {
 		let mut data = self.data.lock().await;
-		if let Some(socket) = &data.socket {
-			let mut socket = socket.lock().await;
-			Self::send(&mut socket, &NetUpstream::SharedStateUpdate { patch }).await
+		let throttled = data.is_throttled();
+		if let (Some(socket), Some(preset), false) =
+			(data.socket.clone(), &preset, throttled)
+		{
+			// presets are announced immediately and in full, carrying any changes
+			// still held back with them
+			if let Some(mut pending) = data.pending.take() {
+				pending.apply_patch(patch);
+				patch = pending;
+			}
+
+			data.stamp(&mut patch);
+
+			let preset = preset.clone();
+			data.announced.push_back(preset.clone());
+			data.sent = Some((patch.clone(), Some(preset.clone())));
+
+			socket.send(NetUpstream::PresetApplied { preset, patch })
+		} else if data.socket.is_some() {
+			// whilst throttled, presets wait with everything else
+			if preset.is_some() {
+				data.pending_preset = preset;
+			}
+
+			// changes are held briefly so that rapid successive changes (such as
+			// repeated clicks) are sent as one, or not at all if they cancel out
+			if let Some(pending) = &mut data.pending {
+				pending.apply_patch(patch);
+				return Ok(())
+			}
+
+			data.pending = Some(patch);
+
+			// a flush is already scheduled for the end of throttling
+			if throttled {
+				return Ok(())
+			}
+
+			let this = self.clone();
+			self.spawn(async move {
+				tokio::time::sleep(PATCH_COALESCE_DELAY).await;
+
+				if let Err(err) = this.flush().await {
+					warn!("{err}");
+				}
+			});
+
+			Ok(())
 		} else {
-			data.state.apply_patch(patch.clone());
+			patch.minimize(data.state.state());
+			if patch.is_empty() {
+				return Ok(())
+			}
+
+			// whilst the socket is down, changes are shown locally and queued to be
+			// replayed once reconnected
+			if self.server.is_some() {
+				if preset.is_some() {
+					data.pending_preset = preset;
+				}
+
+				match &mut data.pending {
+					Some(pending) => pending.apply_patch(patch.clone()),
+					None => data.pending = Some(patch.clone()),
+				}
+			}
+
+			data.stamp(&mut patch);
+			let patch = data.state.merge(patch);
 			self.broadcast(Downstream::Patch {
 				icao: self.icao.clone(),
 				patch,
+				controller: None,
 			});
 			Ok(())
 		}
 	}
 
-	async fn scenery(&self, scenery: HashMap<String, bool>) -> Result<()> {
-		if let Some(socket) = &self.data.lock().await.socket {
-			let mut socket = socket.lock().await;
-			for (object_id, state) in scenery {
-				let message = NetUpstream::StateUpdate { object_id, state };
-				Self::send(&mut socket, &message).await?;
+	async fn flush(&self) -> Result<()> {
+		let mut data = self.data.lock().await;
+		if data.is_throttled() {
+			return Ok(())
+		}
+
+		// kept until reconnected if the socket is down
+		let Some(socket) = data.socket.clone() else {
+			return Ok(())
+		};
+
+		let Some(mut patch) = data.pending.take() else {
+			return Ok(())
+		};
+		let preset = data.pending_preset.take();
+
+		patch.minimize(data.state.state());
+		if patch.is_empty() && preset.is_none() {
+			trace!("pending patch for {} had no effect", self.icao);
+			return Ok(())
+		}
+
+		data.stamp(&mut patch);
+		data.sent = Some((patch.clone(), preset.clone()));
+
+		let message = if let Some(preset) = preset {
+			data.announced.push_back(preset.clone());
+			NetUpstream::PresetApplied { preset, patch }
+		} else {
+			NetUpstream::SharedStateUpdate { patch }
+		};
+
+		// queued without waiting on the socket, and before the data is unlocked
+		// so that changes are sent in the order they were stamped
+		socket.send(message)
+	}
+
+	// sends the changes queued whilst the socket was down, once the state they
+	// apply to has been received, dropping any no longer valid
+	async fn replay(&self) -> Result<()> {
+		let mut data = self.data.lock().await;
+		let scenery = std::mem::take(&mut data.queued_scenery);
+		if data.pending.is_none() && scenery.is_empty() {
+			return Ok(())
+		}
+
+		if let (Some(patch), Some(config)) = (data.pending.take(), &data.config) {
+			let profile = data.state.state().profile.as_deref();
+			let (patch, warnings) = patch.validate(config, profile);
+			for warning in warnings {
+				warn!("dropping queued {warning}");
+			}
+			data.pending = Some(patch);
+		}
+		drop(data);
+
+		debug!("replaying changes queued for {}", self.icao);
+		self.broadcast(Downstream::Notice {
+			icao: self.icao.clone(),
+			message: "reconnected: resending changes made whilst disconnected".into(),
+		});
+
+		self.scenery(scenery).await?;
+		self.flush().await
+	}
+
+	// holds back changes for `retry_after`, requeueing the last changes sent
+	// beneath any made since
+	async fn throttle(&self, retry_after: Duration) {
+		let mut data = self.data.lock().await;
+		let resume = data.throttled_until.is_none();
+		data.throttled_until = Some(Instant::now() + retry_after);
+
+		if let Some((mut patch, preset)) = data.sent.take() {
+			// versioned again when resent, after anything received meanwhile
+			patch.versions = Versions::default();
+			if let Some(pending) = data.pending.take() {
+				patch.apply_patch(pending);
+			}
+			data.pending = Some(patch);
+
+			if preset.is_some() && data.announced.back() == preset.as_ref() {
+				data.announced.pop_back();
+			}
+			if data.pending_preset.is_none() {
+				data.pending_preset = preset;
+			}
+		}
+
+		warn!("throttled by server for {retry_after:?}");
+		if resume {
+			self.broadcast(Downstream::Notice {
+				icao: self.icao.clone(),
+				message: "server busy: changes held back".into(),
+			});
+		}
+
+		let this = self.clone();
+		self.spawn(async move {
+			tokio::time::sleep(retry_after).await;
+
+			let mut data = this.data.lock().await;
+			if data.is_throttled() {
+				// throttled again in the meantime, which scheduled another flush
+				return
+			}
+			data.throttled_until = None;
+			drop(data);
+
+			if let Err(err) = this.flush().await {
+				warn!("{err}");
+			}
+		});
+	}
+
+	async fn claim(&self, blocks: Vec<String>, claim: bool) -> Result<()> {
+		let data = self.data.lock().await;
+
+		// without a server there is no other controller to claim blocks from
+		if let Some(socket) = &data.socket {
+			if !data.capabilities.contains(&Capability::Ownership) {
+				self.broadcast(Downstream::Notice {
+					icao: self.icao.clone(),
+					message: "server does not support claiming blocks".into(),
+				});
+
+				return Ok(())
+			}
+
+			let message = if claim {
+				NetUpstream::ClaimBlocks { blocks }
+			} else {
+				NetUpstream::ReleaseBlocks { blocks }
+			};
+
+			socket.send(message)?;
+		}
+
+		Ok(())
+	}
+
+	async fn reload(&self, config: &Mutex<ConfigManager>) -> Result<()> {
+		let Some(config) = config.lock().await.reload(&self.icao).await? else {
+			return Ok(())
+		};
+
+		self.data.lock().await.config = Some(config.clone());
+		self.broadcast(Downstream::Reload { data: config });
+
+		Ok(())
+	}
+
+	async fn resync(&self) -> Result<()> {
+		let socket = self.data.lock().await.socket.clone();
+		match socket {
+			Some(socket) => socket.send(NetUpstream::ResyncRequest)?,
+			// without a server the state held here is the state as it is
+			None => self.sync_clients().await,
+		}
+
+		Ok(())
+	}
+
+	async fn request_handoff(&self, holder: String) -> Result<()> {
+		let mut data = self.data.lock().await;
+		if let Some(socket) = data.socket.clone() {
+			// stand by until the holder responds
+			self.set_controlling(&mut data, false);
+
+			socket.send(NetUpstream::HandoffRequest { holder })?;
+		}
+
+		Ok(())
+	}
+
+	async fn respond_handoff(&self, accept: bool) -> Result<()> {
+		let mut data = self.data.lock().await;
+		if let (Some(socket), Some(requester)) =
+			(data.socket.clone(), data.handoff.take())
+		{
+			socket.send(NetUpstream::HandoffResponse { requester, accept })?;
+		}
+
+		Ok(())
+	}
+
+	async fn scenery(&self, scenery: Scenery) -> Result<()> {
+		let mut data = self.data.lock().await;
+		if let Some(socket) = &data.socket {
+			for (object_id, (state, kind)) in scenery {
+				socket.send(NetUpstream::StateUpdate {
+					object_id,
+					state,
+					kind,
+				})?;
 			}
+		} else if self.server.is_some() {
+			// later updates to an object replace earlier ones
+			data.queued_scenery.extend(scenery);
 		}
 
 		Ok(())
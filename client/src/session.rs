@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bars_protocol::{BlockState, Id};
+
+use anyhow::Result;
+
+use chrono::Utc;
+
+use serde::{Deserialize, Serialize};
+
+/// Lighting as last controlled, restorable after the connection is lost.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Session {
+	/// milliseconds since the Unix epoch
+	pub saved: i64,
+	pub profile: Id,
+	pub nodes: HashMap<Id, bool>,
+	pub blocks: HashMap<Id, BlockState>,
+	/// seconds from saving until each node or block was to be reset
+	pub node_timers: HashMap<Id, f64>,
+	pub block_timers: HashMap<Id, f64>,
+}
+
+impl Session {
+	fn path(dir: &Path, icao: &str) -> PathBuf {
+		dir.join(format!("{icao}.json"))
+	}
+
+	pub fn load(dir: &Path, icao: &str) -> Result<Option<Self>> {
+		let p = Self::path(dir, icao);
+		if std::fs::exists(&p)? {
+			let s = std::fs::read_to_string(&p)?;
+			Ok(Some(serde_json::from_str(&s)?))
+		} else {
+			Ok(None)
+		}
+	}
+
+	pub fn save(&self, dir: &Path, icao: &str) -> Result<()> {
+		std::fs::create_dir_all(dir)?;
+		std::fs::write(Self::path(dir, icao), serde_json::to_string(self)?)?;
+		Ok(())
+	}
+
+	pub fn age(&self) -> Duration {
+		let ms = Utc::now().timestamp_millis().saturating_sub(self.saved);
+		Duration::from_millis(ms.max(0) as u64)
+	}
+}
@@ -0,0 +1,234 @@
+use crate::client::Aerodrome;
+use crate::context::Aircraft;
+use crate::screen::contains;
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// time between aircraft entering, while there are fewer than the most
+const SPAWN_INTERVAL: Duration = Duration::from_secs(45);
+const MAX_AIRCRAFT: usize = 4;
+// metres per second, around 15 knots
+const TAXI_SPEED: f64 = 7.7;
+// furthest in metres between the ends of edges joined into one route
+const JOIN_DISTANCE: f64 = 15.0;
+// most edges along which an aircraft taxis
+const ROUTE_EDGES: usize = 12;
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// Aircraft taxiing along the edges of an aerodrome, holding at lit stopbars.
+pub struct Simulation {
+	icao: String,
+	aircraft: Vec<SimulatedAircraft>,
+	spawned: Option<Instant>,
+	ticked: Instant,
+	count: usize,
+	seed: u64,
+}
+
+struct SimulatedAircraft {
+	callsign: String,
+	// latitude and longitude of each point along the route
+	route: Vec<(f64, f64)>,
+	// segment of the route the aircraft is on, and metres along it
+	segment: usize,
+	along: f64,
+}
+
+impl SimulatedAircraft {
+	fn position(&self) -> (f64, f64) {
+		let (a, b) = (self.route[self.segment], self.route[self.segment + 1]);
+		let length = distance(a, b);
+		let t = if length > 0.0 {
+			(self.along / length).min(1.0)
+		} else {
+			0.0
+		};
+
+		(a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+	}
+
+	// moves the aircraft along its route, returning false once it has reached
+	// the end
+	fn advance(&mut self, mut metres: f64) -> bool {
+		while self.segment + 1 < self.route.len() {
+			let length =
+				distance(self.route[self.segment], self.route[self.segment + 1]);
+			if self.along + metres <= length {
+				self.along += metres;
+				return true
+			}
+
+			// left at the end of the last segment
+			if self.segment + 2 == self.route.len() {
+				self.along = length;
+				return false
+			}
+
+			metres -= length - self.along;
+			self.segment += 1;
+			self.along = 0.0;
+		}
+
+		false
+	}
+}
+
+impl Simulation {
+	pub fn new(icao: String) -> Self {
+		let seed = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|time| time.as_nanos() as u64)
+			.unwrap_or_default();
+
+		Self {
+			icao,
+			aircraft: Vec::new(),
+			spawned: None,
+			ticked: Instant::now(),
+			count: 0,
+			seed: seed | 1,
+		}
+	}
+
+	pub fn icao(&self) -> &String {
+		&self.icao
+	}
+
+	pub fn aircraft(&self) -> impl Iterator<Item = Aircraft> + '_ {
+		self.aircraft.iter().map(|aircraft| {
+			let (lat, lon) = aircraft.position();
+			Aircraft {
+				callsign: aircraft.callsign.clone(),
+				lat,
+				lon,
+			}
+		})
+	}
+
+	pub fn tick(&mut self, aerodrome: &Aerodrome) {
+		let now = Instant::now();
+		let metres = now.duration_since(self.ticked).as_secs_f64() * TAXI_SPEED;
+		self.ticked = now;
+
+		let config = aerodrome.config();
+		let stopbars = (0..config.nodes.len())
+			.filter(|node| aerodrome.node_state(*node))
+			.map(|node| {
+				config.nodes[node]
+					.display
+					.target
+					.points
+					.iter()
+					.map(|point| (point.geo.lat as f64, point.geo.lon as f64))
+					.collect::<Vec<_>>()
+			})
+			.filter(|polygon| polygon.len() > 2)
+			.collect::<Vec<_>>();
+
+		self.aircraft.retain_mut(|aircraft| {
+			let (segment, along) = (aircraft.segment, aircraft.along);
+			let from = aircraft.position();
+			let moving = aircraft.advance(metres);
+			let to = aircraft.position();
+
+			let held = stopbars
+				.iter()
+				.any(|polygon| !contains(polygon, from) && contains(polygon, to));
+			if held {
+				(aircraft.segment, aircraft.along) = (segment, along);
+			}
+
+			moving || held
+		});
+
+		let due = self
+			.spawned
+			.is_none_or(|at| now.duration_since(at) >= SPAWN_INTERVAL);
+		if due && self.aircraft.len() < MAX_AIRCRAFT {
+			self.spawned = Some(now);
+
+			if let Some(route) = self.route(aerodrome) {
+				self.count += 1;
+				self.aircraft.push(SimulatedAircraft {
+					callsign: format!("SIM{}", self.count),
+					route,
+					segment: 0,
+					along: 0.0,
+				});
+			}
+		}
+	}
+
+	// joins the lights of randomly chosen edges, each starting near where the
+	// last ended, into a route
+	fn route(&mut self, aerodrome: &Aerodrome) -> Option<Vec<(f64, f64)>> {
+		let mut edges = aerodrome
+			.config()
+			.edges
+			.iter()
+			.flat_map(|edge| &edge.display.on)
+			.map(|path| {
+				path
+					.points
+					.iter()
+					.map(|point| (point.geo.lat as f64, point.geo.lon as f64))
+					.collect::<Vec<_>>()
+			})
+			.filter(|points| points.len() > 1)
+			.collect::<Vec<_>>();
+
+		if edges.is_empty() {
+			return None
+		}
+
+		let mut route = edges.swap_remove(self.random(edges.len()));
+		if self.random(2) == 0 {
+			route.reverse();
+		}
+
+		for _ in 1..ROUTE_EDGES {
+			let end = *route.last()?;
+			let next = edges
+				.iter()
+				.enumerate()
+				.filter_map(|(i, points)| {
+					if distance(points[0], end) < JOIN_DISTANCE {
+						Some((i, false))
+					} else if distance(*points.last()?, end) < JOIN_DISTANCE {
+						Some((i, true))
+					} else {
+						None
+					}
+				})
+				.collect::<Vec<_>>();
+
+			if next.is_empty() {
+				break
+			}
+
+			let (i, reversed) = next[self.random(next.len())];
+			let mut points = edges.swap_remove(i);
+			if reversed {
+				points.reverse();
+			}
+			route.extend(points);
+		}
+
+		Some(route)
+	}
+
+	// xorshift, which is enough to vary the traffic
+	fn random(&mut self, n: usize) -> usize {
+		self.seed ^= self.seed << 13;
+		self.seed ^= self.seed >> 7;
+		self.seed ^= self.seed << 17;
+		(self.seed % n as u64) as usize
+	}
+}
+
+/// Approximate metres between two positions, which are close together.
+pub fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+	let dlat = b.0 - a.0;
+	let dlon = (b.1 - a.1) * ((a.0 + b.0) / 2.0).to_radians().cos();
+	(dlat * dlat + dlon * dlon).sqrt() * METRES_PER_DEGREE
+}
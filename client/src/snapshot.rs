@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Gdi::{self, BITMAPINFO, BITMAPINFOHEADER, HDC};
+
+/// Pixels of an image as 8-bit RGBA, row by row from the top.
+pub struct Image {
+	width: u32,
+	height: u32,
+	pixels: Vec<u8>,
+}
+
+impl Image {
+	/// Copies `area` of a device context.
+	pub unsafe fn capture(hdc: HDC, area: RECT) -> Result<Self> {
+		let width = area.right - area.left;
+		let height = area.bottom - area.top;
+		if width <= 0 || height <= 0 {
+			bail!("nothing to capture")
+		}
+
+		let mem = Gdi::CreateCompatibleDC(Some(hdc));
+		let bitmap = Gdi::CreateCompatibleBitmap(hdc, width, height);
+
+		let previous = Gdi::SelectObject(mem, bitmap.into());
+		let copied = Gdi::BitBlt(
+			mem,
+			0,
+			0,
+			width,
+			height,
+			Some(hdc),
+			area.left,
+			area.top,
+			Gdi::SRCCOPY,
+		);
+		Gdi::SelectObject(mem, previous);
+
+		// a negative height requests rows from the top down
+		let mut info = BITMAPINFO {
+			bmiHeader: BITMAPINFOHEADER {
+				biSize: size_of::<BITMAPINFOHEADER>() as u32,
+				biWidth: width,
+				biHeight: -height,
+				biPlanes: 1,
+				biBitCount: 32,
+				biCompression: Gdi::BI_RGB.0,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let mut pixels = vec![0; width as usize * height as usize * 4];
+		let lines = Gdi::GetDIBits(
+			mem,
+			bitmap,
+			0,
+			height as u32,
+			Some(pixels.as_mut_ptr().cast()),
+			&mut info,
+			Gdi::DIB_RGB_COLORS,
+		);
+
+		let _ = Gdi::DeleteObject(bitmap.into());
+		let _ = Gdi::DeleteDC(mem);
+
+		copied?;
+		if lines != height {
+			bail!("failed to read captured pixels")
+		}
+
+		// GDI gives BGR with an undefined fourth byte
+		for pixel in pixels.chunks_exact_mut(4) {
+			pixel.swap(0, 2);
+			pixel[3] = 0xff;
+		}
+
+		Ok(Self {
+			width: width as u32,
+			height: height as u32,
+			pixels,
+		})
+	}
+
+	pub fn save_png(&self, path: &Path) -> Result<()> {
+		let file = BufWriter::new(File::create(path)?);
+
+		let mut encoder = png::Encoder::new(file, self.width, self.height);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.write_header()?.write_image_data(&self.pixels)?;
+
+		Ok(())
+	}
+}
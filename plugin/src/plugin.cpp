@@ -2,10 +2,15 @@
 #include "config.hpp"
 
 #include <cstring>
+#include <string>
+#include <vector>
 
 #define COMMAND_PREFIX ".bars "
 #define COMMAND_PREFIX_LEN ((size_t)6)
 #define SCREEN_NAME "lighting control panel"
+// flight strip annotation in which stands are assigned, as by ground radar
+// plugins
+#define STAND_ANNOTATION 3
 
 Plugin::Plugin(client::Context *ctx)
 	: CPlugIn(
@@ -97,6 +102,70 @@ void Plugin::OnTimer(int) {
 
 	client::client_tick(ctx_);
 
+	std::vector<client::AircraftPosition> aircraft;
+	for (auto target = RadarTargetSelectFirst(); target.IsValid();
+	     target = RadarTargetSelectNext(target)) {
+		auto position = target.GetPosition().GetPosition();
+		aircraft.push_back(
+			{target.GetCallsign(), position.m_Latitude, position.m_Longitude}
+		);
+	}
+	client::client_set_aircraft(ctx_, aircraft.data(), aircraft.size());
+
+	// copied first, as the strings must outlive building the list passed on
+	struct Runway {
+		std::string icao, designator;
+		bool departure, arrival;
+	};
+	std::vector<Runway> active;
+	for (auto element =
+	         SectorFileElementSelectFirst(EuroScope::SECTOR_ELEMENT_RUNWAY);
+	     element.IsValid();
+	     element = SectorFileElementSelectNext(
+				 element, EuroScope::SECTOR_ELEMENT_RUNWAY
+			 )) {
+		for (int end = 0; end < 2; end++) {
+			bool departure = element.IsElementActive(true, end);
+			bool arrival = element.IsElementActive(false, end);
+			if (departure || arrival)
+				active.push_back(
+					{element.GetAirportName(), element.GetRunwayName(end), departure,
+				   arrival}
+				);
+		}
+	}
+
+	std::vector<client::ActiveRunway> runways;
+	for (const auto &runway : active)
+		runways.push_back(
+			{runway.icao.c_str(), runway.designator.c_str(), runway.departure,
+		   runway.arrival}
+		);
+	client::client_set_active_runways(ctx_, runways.data(), runways.size());
+
+	struct Stand {
+		std::string callsign, icao, stand;
+	};
+	std::vector<Stand> assigned;
+	for (auto plan = FlightPlanSelectFirst(); plan.IsValid();
+	     plan = FlightPlanSelectNext(plan)) {
+		const char *stand =
+			plan.GetControllerAssignedData().GetFlightStripAnnotation(
+				STAND_ANNOTATION
+			);
+		if (stand && *stand)
+			assigned.push_back(
+				{plan.GetCallsign(), plan.GetFlightPlanData().GetDestination(), stand}
+			);
+	}
+
+	std::vector<client::StandAssignment> stands;
+	for (const auto &stand : assigned)
+		stands.push_back(
+			{stand.callsign.c_str(), stand.icao.c_str(), stand.stand.c_str()}
+		);
+	client::client_set_stands(ctx_, stands.data(), stands.size());
+
 	const char *message;
 	while ((message = client::client_next_message(ctx_)))
 		display_error("Client", message);
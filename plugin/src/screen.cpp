@@ -1,10 +1,15 @@
 #include "screen.hpp"
+#include "config.hpp"
 
 #include <algorithm>
 #include <cctype>
 #include <cmath>
+#include <cstdio>
+#include <cstdlib>
+#include <cstring>
 #include <format>
 #include <optional>
+#include <string>
 
 #include <gdiplus.h>
 #include <gdiplusgraphics.h>
@@ -12,6 +17,15 @@
 #define SETTING_ACTIVE "aerodrome"
 #define SETTING_MENU_X "menuX"
 #define SETTING_MENU_Y "menuY"
+#define SETTING_BRIGHTNESS "brightness"
+#define SETTING_CONTRAST "contrast"
+#define SETTING_OVERLAYS "overlays"
+#define SETTING_CLAIM_VIEWPORT "claimViewport"
+#define SETTING_AIRCRAFT_CLEARANCE "aircraftClearance"
+#define SETTING_COLOR_VISION "colorVision"
+#define SETTING_RENDER_BACKEND "renderBackend"
+#define SETTING_AUDIT_LOG "auditLog"
+#define SETTING_DIAGNOSTICS "diagnostics"
 
 const int HEIGHT = 12;
 const int PADDING = 2;
@@ -22,6 +36,12 @@ const Gdiplus::Color COLOR_MENU_CONTROLLING(0x16, 0x65, 0x34);
 const Gdiplus::Color COLOR_MENU_FOREGROUND(0xcc, 0xcc, 0xcc);
 const Gdiplus::Color COLOR_MENU_MESSAGE(0xff, 0xff, 0xff);
 
+// key presses on the EuroScope thread are passed to the screen drawn last, as
+// only the displayed screen is drawn
+static HHOOK keyboard_hook = nullptr;
+static Screen *focused_screen = nullptr;
+static int screen_count = 0;
+
 const Gdiplus::Point ICON_DISCONNECTED[] = {
 	{4, 4}, {8, 8}, {6, 6}, {4, 8}, {8, 4}
 };
@@ -46,6 +66,8 @@ enum class TagFunctionType {
 	SubmitSelectPreset,
 	OpenSelectView,
 	SubmitSelectView,
+	OpenViewConnected,
+	SubmitRequestHandoff,
 };
 
 union TagFunction {
@@ -61,6 +83,28 @@ union TagFunction {
 	operator int() const { return value; }
 };
 
+static std::optional<client::ColorVision> parse_color_vision(const char *s) {
+	if (!std::strcmp(s, "normal"))
+		return client::ColorVision::Normal;
+	if (!std::strcmp(s, "protanopia"))
+		return client::ColorVision::Protanopia;
+	if (!std::strcmp(s, "deuteranopia"))
+		return client::ColorVision::Deuteranopia;
+
+	return std::nullopt;
+}
+
+static std::optional<client::RenderBackend> parse_render_backend(
+	const char *s
+) {
+	if (!std::strcmp(s, "gdi"))
+		return client::RenderBackend::Gdi;
+	if (!std::strcmp(s, "gdiplus"))
+		return client::RenderBackend::GdiPlus;
+
+	return std::nullopt;
+}
+
 static std::optional<std::string> normalise_icao(const char *icao) {
 	if (!icao || !icao[0])
 		return std::nullopt;
@@ -84,13 +128,49 @@ Screen::Screen(client::Context *ctx, bool geo, EuroScope::CPlugIn *plugin)
 
 	font_family_ = new FontFamily(L"EuroScope");
 	font_ = new Font(font_family_, HEIGHT, FontStyleRegular, UnitPixel);
+
+	if (!screen_count++)
+		keyboard_hook = SetWindowsHookExW(
+			WH_KEYBOARD, keyboard_proc, nullptr, GetCurrentThreadId()
+		);
 }
 
 Screen::~Screen() {
+	if (focused_screen == this)
+		focused_screen = nullptr;
+
+	if (!--screen_count && keyboard_hook) {
+		UnhookWindowsHookEx(keyboard_hook);
+		keyboard_hook = nullptr;
+	}
+
 	delete font_;
 	delete font_family_;
 }
 
+LRESULT CALLBACK
+Screen::keyboard_proc(int code, WPARAM wparam, LPARAM lparam) {
+	// only the first press of a key, not its repeats or release
+	bool pressed = !((lparam >> 30) & 1) && !((lparam >> 31) & 1);
+
+	if (code == HC_ACTION && pressed && focused_screen) {
+		auto screen = focused_screen;
+		auto view = client::client_get_view(screen->screen_);
+
+		bool shift = GetKeyState(VK_SHIFT) & 0x8000;
+		bool ctrl = GetKeyState(VK_CONTROL) & 0x8000;
+
+		if (client::client_handle_key(screen->screen_, wparam, shift, ctrl)) {
+			screen->switch_view(view);
+
+			screen->RequestRefresh();
+			return 1;
+		}
+	}
+
+	return CallNextHookEx(keyboard_hook, code, wparam, lparam);
+}
+
 void Screen::OnAsrContentLoaded(bool loaded) {
 	if (loaded) {
 		const char *s;
@@ -102,12 +182,61 @@ void Screen::OnAsrContentLoaded(bool loaded) {
 			menu_x = std::atoi(s);
 		if ((s = GetDataFromAsr(SETTING_MENU_Y)))
 			menu_y = std::atoi(s);
+
+		double brightness = 1.0, contrast = 1.0;
+		if ((s = GetDataFromAsr(SETTING_BRIGHTNESS)))
+			brightness = std::atof(s);
+		if ((s = GetDataFromAsr(SETTING_CONTRAST)))
+			contrast = std::atof(s);
+		client::client_set_color_adjustment(screen_, brightness, contrast);
+
+		// the default from the local config is kept unless chosen for this screen
+		if ((s = GetDataFromAsr(SETTING_COLOR_VISION))) {
+			if (auto vision = parse_color_vision(s))
+				client::client_set_color_vision(screen_, *vision);
+		}
+		if ((s = GetDataFromAsr(SETTING_RENDER_BACKEND))) {
+			if (auto backend = parse_render_backend(s))
+				client::client_set_render_backend(screen_, *backend);
+		}
+
+		if ((s = GetDataFromAsr(SETTING_CLAIM_VIEWPORT)))
+			client::client_set_claim_viewport(screen_, std::atoi(s));
+		if ((s = GetDataFromAsr(SETTING_AIRCRAFT_CLEARANCE)))
+			client::client_set_aircraft_clearance(screen_, std::atof(s));
+		if ((s = GetDataFromAsr(SETTING_AUDIT_LOG)))
+			client::client_set_audit_log(screen_, std::atoi(s));
+		if ((s = GetDataFromAsr(SETTING_DIAGNOSTICS)))
+			client::client_set_diagnostics(screen_, std::atoi(s));
+
+		// space-separated, with hidden overlays prefixed by '!'
+		if ((s = GetDataFromAsr(SETTING_OVERLAYS))) {
+			std::string overlays = s;
+			std::size_t start = 0;
+			while (start < overlays.size()) {
+				auto end = overlays.find(' ', start);
+				if (end == std::string::npos)
+					end = overlays.size();
+
+				auto icao = overlays.substr(start, end - start);
+				bool enabled = icao.empty() || icao[0] != '!';
+				if (!enabled)
+					icao.erase(0, 1);
+				if (!icao.empty())
+					client::client_set_overlay(screen_, icao.c_str(), enabled);
+
+				start = end + 1;
+			}
+		}
 	}
 }
 
 void Screen::OnRefresh(HDC hdc, int phase) {
 	using namespace Gdiplus;
 
+	// Windows scales displays relative to 96 DPI
+	client::client_set_dpi_scale(screen_, GetDeviceCaps(hdc, LOGPIXELSX) / 96.0);
+
 	if (phase == EuroScope::REFRESH_PHASE_BACK_BITMAP) {
 		const wchar_t *message = nullptr;
 		if (!geo_) {
@@ -140,15 +269,28 @@ void Screen::OnRefresh(HDC hdc, int phase) {
 			client::client_draw_background(screen_, hdc, viewport);
 		}
 	} else if (phase == EuroScope::REFRESH_PHASE_BEFORE_TAGS) {
+		focused_screen = this;
+
 		auto viewport = get_viewport();
 		client::client_set_viewport(screen_, viewport);
 
+		// the cursor may have left the click regions since it was last over one
+		POINT cursor;
+		if (GetCursorPos(&cursor) && ScreenToClient(WindowFromDC(hdc), &cursor))
+			client::client_set_cursor(screen_, cursor);
+
 		Graphics *ctx = Graphics::FromHDC(hdc);
 
+		bool animating = client::client_tick_animation(screen_);
+
 		auto hdc2 = ctx->GetHDC();
 		client::client_draw_foreground(screen_, hdc2);
 		ctx->ReleaseHDC(hdc2);
 
+		// keep drawing until any flashing nodes have finished
+		if (animating)
+			RequestRefresh();
+
 		size_t n;
 		const RECT *rects = client::client_get_click_regions(screen_, &n);
 		for (size_t i = 0; i < n; i++) {
@@ -157,6 +299,21 @@ void Screen::OnRefresh(HDC hdc, int phase) {
 
 		delete ctx;
 	} else if (phase == EuroScope::REFRESH_PHASE_AFTER_LISTS) {
+		// prompts once for each handoff requested of this controller
+		const char *requester = client::client_get_handoff_request(screen_);
+		if (!requester) {
+			handoff_prompted_.clear();
+		} else if (handoff_prompted_ != requester) {
+			handoff_prompted_ = requester;
+			auto prompt = std::format(
+				"{} requests a handoff: .bars handoff accept or .bars handoff deny",
+				requester
+			);
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Handoff", prompt.c_str(), true, true, true, true, false
+			);
+		}
+
 		Graphics *ctx = Graphics::FromHDC(hdc);
 
 		Color color_menu = COLOR_MENU_DISCONNECTED;
@@ -242,8 +399,36 @@ void Screen::OnRefresh(HDC hdc, int phase) {
 
 		ctx->DrawString(menu_text, AERODROME_SIZE, font_, origin, &brush_text);
 
+		// status strip, right-aligned beneath the menu, ending with the round trip
+		// to the server once it has been measured
+		std::string status = client::client_get_status(screen_);
+		int latency = client::client_get_latency(screen_);
+		if (latency >= 0)
+			status += std::format(" | {} ms", latency);
+		std::wstring status_text(status.cbegin(), status.cend());
+
+		PointF status_origin(rect.right - dx, rect.top + dy + rect_height);
+		ctx->MeasureString(
+			status_text.c_str(), -1, font_, status_origin, &text_bbox
+		);
+		status_origin.X -= text_bbox.Width + 2 * PADDING;
+
+		ctx->FillRectangle(
+			&brush_menu, (int)status_origin.X, (int)status_origin.Y,
+			(int)text_bbox.Width + 2 * PADDING, rect_height
+		);
+		status_origin.X += PADDING;
+		ctx->DrawString(
+			status_text.c_str(), -1, font_, status_origin, &brush_text
+		);
+
 		delete ctx;
 
+		if (snapshot_pending_) {
+			snapshot_pending_ = false;
+			client::client_export_png(screen_, hdc, GetRadarArea());
+		}
+
 		if (client::client_is_background_refresh_required(screen_))
 			RefreshMapContent();
 
@@ -257,17 +442,31 @@ void Screen::OnRefresh(HDC hdc, int phase) {
 
 void Screen::OnAsrContentToBeClosed() { delete this; }
 
+void Screen::OnOverScreenObject(int type, const char *, POINT point, RECT) {
+	if (
+		type == SCREEN_OBJECT_CLICK_REGION &&
+		client::client_set_cursor(screen_, point)
+	)
+		RequestRefresh();
+}
+
 void Screen::OnClickScreenObject(
 	int type, const char *, POINT point, RECT area, int button
 ) {
 	switch (type) {
-	case SCREEN_OBJECT_CLICK_REGION:
+	case SCREEN_OBJECT_CLICK_REGION: {
+		auto view = client::client_get_view(screen_);
+
 		client::client_handle_click(
 			screen_, point,
 			button == EuroScope::BUTTON_LEFT ? client::ClickType::Primary
 																			 : client::ClickType::Auxiliary
 		);
+
+		// the minimap jumps to another view
+		switch_view(view);
 		break;
+	}
 
 	case SCREEN_OBJECT_MENU: {
 		auto aerodrome = client::client_get_aerodrome(screen_);
@@ -356,6 +555,10 @@ void Screen::OnFunctionCall(
 				"Views", "", TagFunction(TagFunctionType::OpenSelectView, 0)
 			);
 
+		plugin_->AddPopupListElement(
+			"Connected", "", TagFunction(TagFunctionType::OpenViewConnected, 0)
+		);
+
 		break;
 	}
 
@@ -374,6 +577,9 @@ void Screen::OnFunctionCall(
 	case TagFunctionType::SubmitEditAerodrome: {
 		auto icao = normalise_icao(string);
 		client::client_set_aerodrome(screen_, icao ? icao->c_str() : nullptr);
+		view_anchor_.reset();
+		view_offset_ = {};
+		view_offsets_.clear();
 		SaveDataToAsr(
 			SETTING_ACTIVE, "Active aerodrome", icao ? icao->c_str() : ""
 		);
@@ -428,6 +634,8 @@ void Screen::OnFunctionCall(
 		if (function.data.payload) {
 			plugin_->OpenPopupList(area, "Select preset", 1);
 
+			const char *profile = client::client_get_profile_id(screen_);
+			preset_profile_ = profile ? profile : "";
 			auto presets = client::client_get_presets(screen_);
 			for (size_t i = 0; presets[i]; i++)
 				plugin_->AddPopupListElement(
@@ -441,7 +649,11 @@ void Screen::OnFunctionCall(
 		break;
 
 	case TagFunctionType::SubmitSelectPreset:
-		client::client_apply_preset(screen_, function.data.payload);
+		if (!client::client_apply_preset(screen_, preset_profile_.c_str(), string))
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Presets", "Profile changed, preset not applied", true,
+				true, false, false, false
+			);
 		break;
 
 	case TagFunctionType::OpenSelectView:
@@ -465,8 +677,56 @@ void Screen::OnFunctionCall(
 
 		break;
 
-	case TagFunctionType::SubmitSelectView:
+	case TagFunctionType::SubmitSelectView: {
+		auto view = client::client_get_view(screen_);
 		client::client_set_view(screen_, function.data.payload);
+		switch_view(view);
+		break;
+	}
+
+	case TagFunctionType::OpenViewConnected:
+		if (function.data.payload) {
+			plugin_->OpenPopupList(area, "Connected", 1);
+
+			// selecting a controller requests a handoff from them
+			auto controllers = client::client_get_controllers(screen_);
+			for (size_t i = 0; controllers[i]; i++)
+				plugin_->AddPopupListElement(
+					controllers[i], "Handoff",
+					TagFunction(TagFunctionType::SubmitRequestHandoff, i), false,
+					EuroScope::POPUP_ELEMENT_NO_CHECKBOX, !is_connected()
+				);
+
+			size_t count = client::client_get_observer_count(screen_);
+			auto observers = client::client_get_observers(screen_);
+			size_t listed = 0;
+			for (; observers[listed]; listed++)
+				plugin_->AddPopupListElement(
+					std::format("{} (observer)", observers[listed]).c_str(), "",
+					TagFunction(TagFunctionType::None, 0), false,
+					EuroScope::POPUP_ELEMENT_NO_CHECKBOX, true
+				);
+
+			if (count > listed)
+				plugin_->AddPopupListElement(
+					std::format("{} other observer(s)", count - listed).c_str(), "",
+					TagFunction(TagFunctionType::None, 0), false,
+					EuroScope::POPUP_ELEMENT_NO_CHECKBOX, true
+				);
+		} else {
+			pending_function_ =
+				new TagFunction(TagFunctionType::OpenViewConnected, 1);
+			pending_function_area_ = area;
+		}
+
+		break;
+
+	case TagFunctionType::SubmitRequestHandoff:
+		client::client_request_handoff(screen_, function.data.payload);
+		plugin_->DisplayUserMessage(
+			PLUGIN_NAME, "Handoff", "Handoff requested", true, true, false, false,
+			false
+		);
 		break;
 	}
 
@@ -474,6 +734,391 @@ void Screen::OnFunctionCall(
 		RefreshMapContent();
 }
 
+bool Screen::OnCompileCommand(const char *command) {
+	if (!std::strcmp(command, ".bars deselect")) {
+		if (client::client_deselect(screen_))
+			RequestRefresh();
+
+		return true;
+	}
+
+	if (!std::strcmp(command, ".bars undo")) {
+		if (client::client_undo(screen_))
+			RequestRefresh();
+
+		return true;
+	}
+
+	if (!std::strcmp(command, ".bars redo")) {
+		if (client::client_redo(screen_))
+			RequestRefresh();
+
+		return true;
+	}
+
+	if (!std::strcmp(command, ".bars simulate")) {
+		bool simulating = client::client_toggle_simulation(screen_);
+		plugin_->DisplayUserMessage(
+			PLUGIN_NAME, "Simulation",
+			simulating ? "Simulating aircraft" : "Not simulating aircraft", true,
+			true, false, false, false
+		);
+		RequestRefresh();
+
+		return true;
+	}
+
+	if (!std::strcmp(command, ".bars restore")) {
+		if (client::client_restore_session(screen_))
+			RequestRefresh();
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Session", "No lighting to restore", true, true, false,
+				false, false
+			);
+
+		return true;
+	}
+
+	// selects the profile suggested for the active runways
+	if (!std::strcmp(command, ".bars profile")) {
+		if (client::client_select_suggested_profile(screen_))
+			RequestRefresh();
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Profile", "No profile suggested", true, true, false,
+				false, false
+			);
+
+		return true;
+	}
+
+	// loads a changed config package without reconnecting
+	if (!std::strcmp(command, ".bars reload")) {
+		client::client_reload_config(screen_);
+		return true;
+	}
+
+	// takes over changing the aerodrome from other instances sharing this
+	// connection
+	if (!std::strcmp(command, ".bars takeover")) {
+		client::client_take_over(screen_);
+		return true;
+	}
+
+	if (!std::strcmp(command, ".bars snapshot")) {
+		snapshot_pending_ = true;
+		RequestRefresh();
+
+		return true;
+	}
+
+	// brightness, optionally followed by contrast, of the colours of this screen
+	double brightness, contrast = 1.0;
+	if (std::sscanf(
+				command, ".bars brightness %lf %lf", &brightness, &contrast
+			) >= 1) {
+		client::client_set_color_adjustment(screen_, brightness, contrast);
+
+		auto b = std::to_string(brightness);
+		auto c = std::to_string(contrast);
+
+		SaveDataToAsr(SETTING_BRIGHTNESS, "Colour brightness", b.c_str());
+		SaveDataToAsr(SETTING_CONTRAST, "Colour contrast", c.c_str());
+
+		RefreshMapContent();
+		return true;
+	}
+
+	// palette for a colour vision deficiency: normal, protanopia or deuteranopia
+	char arg[16];
+	if (std::sscanf(command, ".bars vision %15s", arg) == 1) {
+		if (auto vision = parse_color_vision(arg)) {
+			client::client_set_color_vision(screen_, *vision);
+			SaveDataToAsr(SETTING_COLOR_VISION, "Colour vision palette", arg);
+
+			RefreshMapContent();
+		}
+
+		return true;
+	}
+
+	// how the screen is drawn: gdi, or gdiplus to blend translucent colours
+	if (std::sscanf(command, ".bars render %15s", arg) == 1) {
+		if (auto backend = parse_render_backend(arg)) {
+			client::client_set_render_backend(screen_, *backend);
+			SaveDataToAsr(SETTING_RENDER_BACKEND, "Renderer", arg);
+
+			RefreshMapContent();
+		}
+
+		return true;
+	}
+
+	// whether a view takes every click while controlling, or only those near
+	// targets so that the rest reach EuroScope
+	bool claim_on = !std::strcmp(command, ".bars claim on");
+	if (!geo_ && (claim_on || !std::strcmp(command, ".bars claim off"))) {
+		client::client_set_claim_viewport(screen_, claim_on);
+		SaveDataToAsr(
+			SETTING_CLAIM_VIEWPORT, "Claim clicks on the whole view",
+			claim_on ? "1" : "0"
+		);
+
+		RefreshMapContent();
+		return true;
+	}
+
+	// lists the last changes to the lighting, or shows or hides them on the
+	// screen
+	if (!std::strcmp(command, ".bars log")) {
+		auto entries = client::client_get_audit_log(screen_);
+		for (std::size_t i = 0; entries[i] && i < 10; i++)
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Log", entries[i], true, true, false, false, false
+			);
+
+		return true;
+	}
+
+	bool log_on = !std::strcmp(command, ".bars log on");
+	if (log_on || !std::strcmp(command, ".bars log off")) {
+		client::client_set_audit_log(screen_, log_on);
+		SaveDataToAsr(
+			SETTING_AUDIT_LOG, "List recent lighting changes", log_on ? "1" : "0"
+		);
+
+		RequestRefresh();
+		return true;
+	}
+
+	// describes the connection, or shows or hides its state on the screen
+	if (!std::strcmp(command, ".bars diag")) {
+		plugin_->DisplayUserMessage(
+			PLUGIN_NAME, "Diagnostics", client::client_get_diagnostics(screen_),
+			true, true, false, false, false
+		);
+
+		return true;
+	}
+
+	bool diag_on = !std::strcmp(command, ".bars diag on");
+	if (diag_on || !std::strcmp(command, ".bars diag off")) {
+		client::client_set_diagnostics(screen_, diag_on);
+		SaveDataToAsr(
+			SETTING_DIAGNOSTICS, "Show connection diagnostics", diag_on ? "1" : "0"
+		);
+
+		RequestRefresh();
+		return true;
+	}
+
+	// pixels around aircraft in which clicks are left to EuroScope
+	double clearance;
+	if (geo_ && std::sscanf(command, ".bars clearance %lf", &clearance) == 1) {
+		client::client_set_aircraft_clearance(screen_, clearance);
+
+		auto value = std::to_string(clearance);
+		SaveDataToAsr(
+			SETTING_AIRCRAFT_CLEARANCE, "Click clearance around aircraft",
+			value.c_str()
+		);
+
+		RequestRefresh();
+		return true;
+	}
+
+	// shows another aerodrome beneath this one, or toggles it if already shown
+	if (std::sscanf(command, ".bars overlay remove %15s", arg) == 1) {
+		if (auto icao = normalise_icao(arg)) {
+			client::client_remove_overlay(screen_, icao->c_str());
+			save_overlays();
+
+			RequestRefresh();
+		}
+
+		return true;
+	}
+
+	if (geo_ && std::sscanf(command, ".bars overlay %15s", arg) == 1) {
+		auto icao = normalise_icao(arg);
+		if (!icao)
+			return true;
+
+		bool enabled = true;
+		auto overlays = client::client_get_overlays(screen_);
+		for (std::size_t i = 0; overlays[i]; i++) {
+			if (*icao == overlays[i])
+				enabled = !client::client_is_overlay_enabled(screen_, i);
+		}
+
+		client::client_set_overlay(screen_, icao->c_str(), enabled);
+		save_overlays();
+
+		RequestRefresh();
+		return true;
+	}
+
+	// turns off every edge of a block
+	char block[64];
+	if (std::sscanf(command, ".bars dark %63s", block) == 1) {
+		if (client::client_clear_block_edges(screen_, block))
+			RequestRefresh();
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Blocks", "Unknown block or not controlling", true, true,
+				false, false, false
+			);
+
+		return true;
+	}
+
+	// claims a block so that other controllers cannot change it, or releases it
+	bool claim = std::sscanf(command, ".bars block claim %63s", block) == 1;
+	if (claim || std::sscanf(command, ".bars block release %63s", block) == 1) {
+		if (!client::client_supports(screen_, client::Capability::Ownership))
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Blocks", "Server does not support claiming blocks", true,
+				true, false, false, false
+			);
+		else if (!client::client_set_block_claim(screen_, block, claim))
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Blocks", "Unknown block or not connected", true, true,
+				false, false, false
+			);
+
+		return true;
+	}
+
+	// answers the pending handoff request, if any
+	bool accept = !std::strcmp(command, ".bars handoff accept");
+	if (accept || !std::strcmp(command, ".bars handoff deny")) {
+		if (client::client_get_handoff_request(screen_))
+			client::client_respond_handoff(screen_, accept);
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Handoff", "No handoff requested", true, true, false,
+				false, false
+			);
+
+		return true;
+	}
+
+	// lists the nodes and blocks which will reset, or delays or stops the reset
+	// of one, such as to extend a crossing clearance
+	if (!std::strcmp(command, ".bars timers")) {
+		auto timers = client::client_get_timers(screen_);
+		if (!timers[0])
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Timers", "No timers running", true, true, false, false,
+				false
+			);
+
+		for (std::size_t i = 0; timers[i]; i++)
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Timers", timers[i], true, true, false, false, false
+			);
+
+		return true;
+	}
+
+	char target[64];
+	unsigned int secs;
+	if (std::sscanf(command, ".bars timer extend %63s %u", target, &secs) == 2) {
+		if (client::client_extend_timer(screen_, target, secs))
+			RequestRefresh();
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Timers", "No timer running or not controlling", true,
+				true, false, false, false
+			);
+
+		return true;
+	}
+
+	if (std::sscanf(command, ".bars timer cancel %63s", target) == 1) {
+		if (client::client_cancel_timer(screen_, target))
+			RequestRefresh();
+		else
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Timers", "No timer running or not controlling", true,
+				true, false, false, false
+			);
+
+		return true;
+	}
+
+	// closes or reopens a closed taxiway or works area of the aerodrome
+	char closure[64];
+	if (std::sscanf(command, ".bars closure %63s", closure) == 1) {
+		auto closures = client::client_get_closures(screen_);
+		for (std::size_t i = 0; closures[i]; i++) {
+			if (!std::strcmp(closure, closures[i]))
+				client::client_set_closure(
+					screen_, i, !client::client_is_closure_active(screen_, i)
+				);
+		}
+
+		RequestRefresh();
+		return true;
+	}
+
+	// lists the departure queue, or adds an aircraft at a holding point to the
+	// back of it or removes one
+	if (!std::strcmp(command, ".bars queue")) {
+		auto departures = client::client_get_departures(screen_);
+		if (!departures[0])
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Departures", "Queue is empty", true, true, false, false,
+				false
+			);
+
+		for (std::size_t i = 0; departures[i]; i++) {
+			auto line = std::format("{}. {}", i + 1, departures[i]);
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Departures", line.c_str(), true, true, false, false,
+				false
+			);
+		}
+
+		return true;
+	}
+
+	char callsign[16];
+	if (std::sscanf(command, ".bars queue remove %15s", callsign) == 1) {
+		client::client_remove_departure(screen_, callsign);
+		return true;
+	}
+
+	char node[64];
+	if (std::sscanf(command, ".bars queue %15s %63s", callsign, node) == 2) {
+		if (!client::client_queue_departure(screen_, callsign, node))
+			plugin_->DisplayUserMessage(
+				PLUGIN_NAME, "Departures", "Unknown holding point", true, true, false,
+				false, false
+			);
+
+		return true;
+	}
+
+	return false;
+}
+
+void Screen::save_overlays() {
+	std::string value;
+
+	auto overlays = client::client_get_overlays(screen_);
+	for (std::size_t i = 0; overlays[i]; i++) {
+		if (!value.empty())
+			value += ' ';
+		if (!client::client_is_overlay_enabled(screen_, i))
+			value += '!';
+		value += overlays[i];
+	}
+
+	SaveDataToAsr(SETTING_OVERLAYS, "Overlaid aerodromes", value.c_str());
+}
+
 client::Viewport Screen::get_viewport() {
 	client::Viewport viewport;
 	auto area = GetRadarArea();
@@ -517,11 +1162,57 @@ client::Viewport Screen::get_viewport() {
 
 		viewport.non_geo.size[0] = area.right - area.left;
 		viewport.non_geo.size[1] = area.bottom;
+
+		// follow the zoom (mouse wheel) and pan (drag) of the display, by where
+		// the anchored positions are now drawn
+		if (!view_anchor_) {
+			POINT min = {area.left, area.top}, max = {area.right, area.bottom};
+			view_anchor_ = ViewAnchor{
+				{min, max},
+				{ConvertCoordFromPixelToPosition(min),
+				 ConvertCoordFromPixelToPosition(max)},
+			};
+		}
+
+		auto &anchor = *view_anchor_;
+		POINT a = ConvertCoordFromPositionToPixel(anchor.positions[0]);
+		POINT b = ConvertCoordFromPositionToPixel(anchor.positions[1]);
+
+		double zoom = hypot(b.x - a.x, b.y - a.y) /
+		              hypot(
+		                anchor.pixels[1].x - anchor.pixels[0].x,
+		                anchor.pixels[1].y - anchor.pixels[0].y
+		              );
+
+		// then that of the view itself since it was last shown
+		viewport.non_geo.zoom = zoom * view_offset_.zoom;
+		viewport.non_geo.pan[0] =
+			a.x - zoom * anchor.pixels[0].x + zoom * view_offset_.pan[0];
+		viewport.non_geo.pan[1] =
+			a.y - zoom * anchor.pixels[0].y + zoom * view_offset_.pan[1];
+
+		shown_offset_.zoom = viewport.non_geo.zoom;
+		shown_offset_.pan[0] = viewport.non_geo.pan[0];
+		shown_offset_.pan[1] = viewport.non_geo.pan[1];
 	}
 
 	return viewport;
 }
 
+// keeps the zoom and pan of the previous view to restore when it is next shown,
+// and restores those of the current one, if the view has changed
+void Screen::switch_view(std::size_t previous) {
+	auto view = client::client_get_view(screen_);
+	if (view == previous)
+		return;
+
+	view_offsets_[previous] = shown_offset_;
+
+	auto offset = view_offsets_.find(view);
+	view_offset_ = offset != view_offsets_.end() ? offset->second : ViewOffset{};
+	view_anchor_.reset();
+}
+
 bool Screen::is_connected() {
 	switch (client::client_connection_state(ctx_)) {
 	case client::ConnectionState::Disconnected:
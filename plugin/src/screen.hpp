@@ -7,8 +7,26 @@
 
 #include <gdiplus.h>
 
+#include <map>
+#include <optional>
+#include <string>
+
 union TagFunction;
 
+// positions under two pixels of a non-geo view when it was last reset, which
+// track the zoom and pan of the display since
+struct ViewAnchor {
+	POINT pixels[2];
+	EuroScope::CPosition positions[2];
+};
+
+// zoom and pan of a non-geo view on top of those of the display, remembered
+// while other views are shown
+struct ViewOffset {
+	double zoom = 1.0;
+	double pan[2] = {0.0, 0.0};
+};
+
 class Screen : public EuroScope::CRadarScreen {
 private:
 	bool geo_;
@@ -24,6 +42,20 @@ private:
 	TagFunction *pending_function_ = nullptr;
 	RECT pending_function_area_;
 
+	std::optional<ViewAnchor> view_anchor_;
+	// offset of the current view, and as last drawn, and of each other view
+	ViewOffset view_offset_, shown_offset_;
+	std::map<std::size_t, ViewOffset> view_offsets_;
+
+	// ID of the profile of the presets last listed for selection
+	std::string preset_profile_;
+
+	// controller whose handoff request has been shown, if any
+	std::string handoff_prompted_;
+
+	// whether to save a snapshot once the screen has next been drawn
+	bool snapshot_pending_ = false;
+
 	EuroScope::CPlugIn *plugin_;
 
 public:
@@ -35,11 +67,17 @@ public:
 	void OnAsrContentLoaded(bool) override;
 	void OnRefresh(HDC, int) override;
 	void OnAsrContentToBeClosed() override;
+	void OnOverScreenObject(int, const char *, POINT, RECT) override;
 	void OnClickScreenObject(int, const char *, POINT, RECT, int) override;
 	void OnMoveScreenObject(int, const char *, POINT, RECT, bool) override;
 	void OnFunctionCall(int, const char *, POINT, RECT) override;
+	bool OnCompileCommand(const char *) override;
 
 private:
 	client::Viewport get_viewport();
 	bool is_connected();
+	void save_overlays();
+	void switch_view(std::size_t);
+
+	static LRESULT CALLBACK keyboard_proc(int, WPARAM, LPARAM);
 };
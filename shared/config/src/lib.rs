@@ -8,10 +8,11 @@ use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 static MAGIC: &[u8] = b"\xffBARS\x13eu";
-const VERSION: u16 = 0;
+const VERSION: u16 = 10;
 
 fn bincode_options() -> impl Options {
 	DefaultOptions::new().with_limit(0x100_0000)
@@ -46,7 +47,7 @@ impl Config {
 	}
 
 	pub fn save(&self, mut writer: impl Write) -> bincode::Result<()> {
-		writer.write_all(&MAGIC)?;
+		writer.write_all(MAGIC)?;
 		writer.write_all(&VERSION.to_be_bytes())?;
 
 		let writer = DeflateEncoder::new(writer, Compression::best());
@@ -62,18 +63,26 @@ pub struct Aerodrome {
 	pub nodes: Vec<Node>,
 	pub edges: Vec<Edge>,
 	pub blocks: Vec<Block>,
+	pub closures: Vec<Closure>,
 
 	pub profiles: Vec<Profile>,
 
 	pub maps: Vec<Map>,
 	pub views: Vec<View>,
 	pub styles: Vec<Style>,
+
+	/// images drawn beneath everything else on geographic displays
+	pub rasters: Vec<Raster>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Element {
 	pub id: String,
 	pub condition: ElementCondition,
+	/// what the element is, if it cannot be inferred from its condition
+	#[serde(default)]
+	pub kind: Option<ElementKind>,
 }
 
 #[derive(
@@ -88,6 +97,39 @@ pub struct Element {
 	Deserialize,
 	Serialize,
 )]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum ElementKind {
+	Stopbar,
+	LeadOn,
+	/// runway guard lights
+	Rgl,
+	TaxiwaySegment,
+}
+
+impl Element {
+	/// Nodes default to stopbars, and edges to taxiway segments.
+	pub fn kind(&self) -> Option<ElementKind> {
+		self.kind.or(match self.condition {
+			ElementCondition::Node(_) => Some(ElementKind::Stopbar),
+			ElementCondition::Edge(_) => Some(ElementKind::TaxiwaySegment),
+			ElementCondition::Fixed(_) => None,
+		})
+	}
+}
+
+#[derive(
+	Clone,
+	Copy,
+	Debug,
+	Hash,
+	PartialEq,
+	Eq,
+	PartialOrd,
+	Ord,
+	Deserialize,
+	Serialize,
+)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum ElementCondition {
 	Fixed(bool),
 	Node(usize),
@@ -100,6 +142,10 @@ pub struct Node {
 
 	pub scratchpad: Option<String>,
 	pub parent: Option<usize>,
+	/// lit again once an aircraft has crossed the lowered target
+	pub reset_on_crossing: bool,
+	/// children set together with the node
+	pub gang: bool,
 
 	pub display: NodeDisplay<GeoPoint>,
 }
@@ -114,6 +160,7 @@ pub struct Block {
 	pub id: String,
 
 	pub nodes: Vec<usize>,
+	/// edges lit by routes through the block, in order
 	pub edges: Vec<usize>,
 	pub non_routes: Vec<(usize, usize)>,
 
@@ -122,6 +169,18 @@ pub struct Block {
 	pub display: BlockDisplay<GeoPoint>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Closure {
+	pub id: String,
+
+	/// active until toggled off
+	pub active: bool,
+	/// edges which routes may not pass along while active
+	pub edges: Vec<usize>,
+
+	pub display: ClosureDisplay<GeoPoint>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Profile {
 	pub id: String,
@@ -132,6 +191,31 @@ pub struct Profile {
 	pub blocks: Vec<BlockCondition>,
 
 	pub presets: Vec<Preset>,
+	/// preset shown while no controller is connected to the aerodrome
+	pub unattended: Option<usize>,
+	/// runway configurations in which the profile is suggested
+	pub runways: Vec<RunwayRule>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RunwayRule {
+	pub departures: Vec<String>,
+	pub arrivals: Vec<String>,
+}
+
+impl RunwayRule {
+	pub fn matches(&self, departures: &[String], arrivals: &[String]) -> bool {
+		(!self.departures.is_empty() || !self.arrivals.is_empty())
+			&& self
+				.departures
+				.iter()
+				.all(|runway| departures.contains(runway))
+			&& self.arrivals.iter().all(|runway| arrivals.contains(runway))
+	}
+
+	pub fn runway_count(&self) -> usize {
+		self.departures.len() + self.arrivals.len()
+	}
 }
 
 #[derive(
@@ -235,6 +319,20 @@ pub struct Map {
 	pub nodes: Vec<NodeDisplay<Point>>,
 	pub edges: Vec<EdgeDisplay<Point>>,
 	pub blocks: Vec<BlockDisplay<Point>>,
+	pub closures: Vec<ClosureDisplay<Point>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Raster {
+	/// encoded PNG, JPEG, GIF or BMP image
+	pub image: Vec<u8>,
+	pub north: f32,
+	pub south: f32,
+	pub east: f32,
+	pub west: f32,
+	/// anticlockwise rotation of the box about its centre, in degrees
+	pub rotation: f32,
+	pub opacity: u8,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -276,9 +374,127 @@ pub struct GeoPoint {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "T: Quantize", into = "PackedPath", try_from = "PackedPath")]
 pub struct Path<T: Clone + Debug> {
 	pub points: Vec<T>,
 	pub style: usize,
+
+	/// resolution of the fixed-point coordinates the points are stored as
+	pub quantum: f32,
+}
+
+pub trait Quantize: Sized + Serialize + DeserializeOwned {
+	/// number of coordinates per point
+	const DIMENSIONS: usize;
+
+	fn to_fixed(&self, quantum: f32, fixed: &mut Vec<i64>);
+	fn from_fixed(fixed: &[i64], quantum: f32) -> Self;
+}
+
+// screen-space offsets are always stored to a sixteenth of a pixel
+const OFFSET_QUANTUM: f32 = 1.0 / 16.0;
+
+fn to_fixed(value: f32, quantum: f32) -> i64 {
+	(value as f64 / quantum as f64).round() as i64
+}
+
+fn from_fixed(value: i64, quantum: f32) -> f32 {
+	(value as f64 * quantum as f64) as f32
+}
+
+impl Quantize for Point {
+	const DIMENSIONS: usize = 2;
+
+	fn to_fixed(&self, quantum: f32, fixed: &mut Vec<i64>) {
+		fixed.extend([to_fixed(self.x, quantum), to_fixed(self.y, quantum)]);
+	}
+
+	fn from_fixed(fixed: &[i64], quantum: f32) -> Self {
+		Self {
+			x: from_fixed(fixed[0], quantum),
+			y: from_fixed(fixed[1], quantum),
+		}
+	}
+}
+
+impl Quantize for GeoPoint {
+	const DIMENSIONS: usize = 4;
+
+	fn to_fixed(&self, quantum: f32, fixed: &mut Vec<i64>) {
+		fixed.extend([
+			to_fixed(self.geo.lat, quantum),
+			to_fixed(self.geo.lon, quantum),
+		]);
+		self.offset.to_fixed(OFFSET_QUANTUM, fixed);
+	}
+
+	fn from_fixed(fixed: &[i64], quantum: f32) -> Self {
+		Self {
+			geo: Geo {
+				lat: from_fixed(fixed[0], quantum),
+				lon: from_fixed(fixed[1], quantum),
+			},
+			offset: Point::from_fixed(&fixed[2..], OFFSET_QUANTUM),
+		}
+	}
+}
+
+// serialised form of a path; each coordinate is stored relative to the same
+// coordinate of the previous point, so that bincode's variable-length integer
+// encoding keeps the deltas between nearby points small
+#[derive(Deserialize, Serialize)]
+struct PackedPath {
+	quantum: f32,
+	deltas: Vec<i64>,
+	style: usize,
+}
+
+impl<T: Clone + Debug + Quantize> From<Path<T>> for PackedPath {
+	fn from(path: Path<T>) -> Self {
+		let mut deltas = Vec::with_capacity(path.points.len() * T::DIMENSIONS);
+		for point in &path.points {
+			point.to_fixed(path.quantum, &mut deltas);
+		}
+
+		for i in (T::DIMENSIONS..deltas.len()).rev() {
+			deltas[i] -= deltas[i - T::DIMENSIONS];
+		}
+
+		Self {
+			quantum: path.quantum,
+			deltas,
+			style: path.style,
+		}
+	}
+}
+
+impl<T: Clone + Debug + Quantize> TryFrom<PackedPath> for Path<T> {
+	type Error = &'static str;
+
+	fn try_from(mut packed: PackedPath) -> Result<Self, Self::Error> {
+		if !(packed.quantum.is_finite() && packed.quantum > 0.0) {
+			return Err("invalid path quantum")
+		}
+
+		if !packed.deltas.len().is_multiple_of(T::DIMENSIONS) {
+			return Err("incomplete path point")
+		}
+
+		for i in T::DIMENSIONS..packed.deltas.len() {
+			packed.deltas[i] =
+				packed.deltas[i].wrapping_add(packed.deltas[i - T::DIMENSIONS]);
+		}
+
+		Ok(Self {
+			points: packed
+				.deltas
+				.chunks(T::DIMENSIONS)
+				.map(|fixed| T::from_fixed(fixed, packed.quantum))
+				.collect(),
+			style: packed.style,
+			quantum: packed.quantum,
+		})
+	}
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -287,6 +503,7 @@ pub struct Target<T: Clone + Debug> {
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(bound = "T: Quantize")]
 pub struct NodeDisplay<T: Clone + Debug> {
 	pub off: Vec<Path<T>>,
 	pub on: Vec<Path<T>>,
@@ -296,16 +513,30 @@ pub struct NodeDisplay<T: Clone + Debug> {
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(bound = "T: Quantize")]
 pub struct EdgeDisplay<T: Clone + Debug> {
 	pub off: Vec<Path<T>>,
 	pub on: Vec<Path<T>>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(bound = "T: Quantize")]
 pub struct BlockDisplay<T: Clone + Debug> {
+	/// drawn while the block is relaxed
+	pub relax: Vec<Path<T>>,
+	/// drawn while the block is routed between any of its nodes
+	pub route: Vec<Path<T>>,
+
 	pub target: Target<T>,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(bound = "T: Quantize")]
+pub struct ClosureDisplay<T: Clone + Debug> {
+	/// drawn above the basemap while the closure is active
+	pub area: Vec<Path<T>>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Style {
 	pub stroke_width: f32,
@@ -357,3 +588,152 @@ pub enum FillStyle {
 	HatchCross,
 	HatchDiagonalCross,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip<T: Clone + Debug + Quantize>(path: &Path<T>) -> Path<T> {
+		let bytes = bincode_options().serialize(path).unwrap();
+		bincode_options().deserialize(&bytes).unwrap()
+	}
+
+	fn points(coords: &[(f32, f32)]) -> Vec<Point> {
+		coords.iter().map(|&(x, y)| Point { x, y }).collect()
+	}
+
+	#[test]
+	fn deltas_are_relative() {
+		let path = Path {
+			points: points(&[(10.0, 10.0), (9.0, 12.0), (-3.0, 12.0)]),
+			style: 0,
+			quantum: 1.0,
+		};
+
+		let packed = PackedPath::from(path);
+		assert_eq!(packed.deltas, [10, 10, -1, 2, -12, 0]);
+	}
+
+	#[test]
+	fn point_path_round_trip() {
+		// multiples of the quantum, moving back and forth in both axes
+		let path = Path {
+			points: points(&[
+				(0.0, 0.0),
+				(12.25, -4.5),
+				(-7.75, 3.0),
+				(-7.75, -100.0),
+				(1024.5, 0.25),
+			]),
+			style: 3,
+			quantum: 0.25,
+		};
+
+		let unpacked = round_trip(&path);
+		assert_eq!(unpacked.points, path.points);
+		assert_eq!(unpacked.style, 3);
+		assert_eq!(unpacked.quantum, 0.25);
+	}
+
+	#[test]
+	fn points_snap_to_quantum() {
+		let path = Path {
+			points: points(&[(1.3, -1.3), (1.2, -0.7), (0.74, 0.76)]),
+			style: 0,
+			quantum: 0.5,
+		};
+
+		let unpacked = round_trip(&path);
+		assert_eq!(
+			unpacked.points,
+			points(&[(1.5, -1.5), (1.0, -0.5), (0.5, 1.0)])
+		);
+	}
+
+	#[test]
+	fn coarse_quantum() {
+		// coordinates under half of the quantum collapse onto the origin
+		let path = Path {
+			points: points(&[(4.0, -4.0), (-4.9, 4.9), (5.0, 15.0)]),
+			style: 0,
+			quantum: 10.0,
+		};
+
+		let unpacked = round_trip(&path);
+		assert_eq!(
+			unpacked.points,
+			points(&[(0.0, 0.0), (0.0, 0.0), (10.0, 20.0)]),
+		);
+	}
+
+	#[test]
+	fn geo_path_round_trip() {
+		let quantum = 1e-6;
+		let path = Path {
+			points: vec![
+				GeoPoint {
+					geo: Geo {
+						lat: 51.148_05,
+						lon: -0.190_277,
+					},
+					offset: Point { x: 2.5, y: -1.0625 },
+				},
+				GeoPoint {
+					geo: Geo {
+						lat: 51.147_91,
+						lon: -0.190_6,
+					},
+					offset: Point { x: -3.0, y: 0.0 },
+				},
+				GeoPoint {
+					geo: Geo {
+						lat: -33.946_1,
+						lon: 151.177_2,
+					},
+					offset: Point::default(),
+				},
+			],
+			style: 1,
+			quantum,
+		};
+
+		let unpacked = round_trip(&path);
+		assert_eq!(unpacked.points.len(), path.points.len());
+		for (unpacked, point) in unpacked.points.iter().zip(&path.points) {
+			assert!((unpacked.geo.lat - point.geo.lat).abs() <= quantum);
+			assert!((unpacked.geo.lon - point.geo.lon).abs() <= quantum);
+			// offsets are kept to a sixteenth of a pixel whatever the quantum
+			assert_eq!(unpacked.offset, point.offset);
+		}
+	}
+
+	#[test]
+	fn empty_path_round_trip() {
+		let path = Path::<Point> {
+			points: Vec::new(),
+			style: 0,
+			quantum: 1.0,
+		};
+
+		assert!(round_trip(&path).points.is_empty());
+	}
+
+	#[test]
+	fn invalid_packed_paths() {
+		for quantum in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+			let packed = PackedPath {
+				quantum,
+				deltas: vec![1, 2],
+				style: 0,
+			};
+			assert!(Path::<Point>::try_from(packed).is_err());
+		}
+
+		let packed = PackedPath {
+			quantum: 1.0,
+			deltas: vec![1, 2, 3],
+			style: 0,
+		};
+		assert!(Path::<Point>::try_from(packed).is_err());
+	}
+}
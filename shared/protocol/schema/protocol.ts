@@ -0,0 +1,283 @@
+// generated by `bars-conformance schema`; do not edit
+
+export type AircraftState = {
+	callsign: string;
+	/**
+	 * ground speed in knots
+	 */
+	groundSpeed?: number;
+	/**
+	 * true heading in degrees
+	 */
+	heading?: number;
+	lat: number;
+	lon: number;
+};
+
+export type BlockState = "clear" | "relax" | {
+	route: [string, string];
+};
+
+export type Capability = "INTENSITY" | "OWNERSHIP" | "BINARY_FRAMES" | "MULTIPLEX" | "OTHER";
+
+export type CloseReason = "CONTROLLER_LOGOFF" | "POSITION_HANDOFF" | "SERVER_RESTART" | "KICKED" | "OTHER";
+
+export type Controller = {
+	callsign?: string | null;
+	cid?: number | null;
+	controllerId: string;
+	position?: PositionType | null;
+};
+
+export type Downstream = {
+	type: "HEARTBEAT";
+} | {
+	data: Ping | null;
+	type: "HEARTBEAT_ACK";
+} | {
+	data: CloseReason | null;
+	type: "CLOSE";
+} | {
+	data: {
+		code?: ErrorCode;
+		detail?: string | null;
+	};
+	type: "ERROR";
+} | {
+	data: {
+		retryAfter: number;
+	};
+	type: "THROTTLED";
+} | {
+	data: Controller;
+	type: "CONTROLLER_CONNECT";
+} | {
+	data: {
+		controllerId: string;
+	};
+	type: "CONTROLLER_DISCONNECT";
+} | {
+	data: {
+		capabilities?: Capability[];
+		claims?: Record<string, string>;
+		connectionType: string;
+		controllerId?: string | null;
+		controllers?: Controller[];
+		objects: SceneryObject[];
+		sequence?: number | null;
+		sharedState: Patch;
+	};
+	type: "INITIAL_STATE";
+} | {
+	data: {
+		controllerId: string;
+		kind?: ObjectKind | null;
+		objectId: string;
+		sequence?: number | null;
+		state: boolean;
+		timestamp?: number | null;
+	};
+	type: "STATE_UPDATE";
+} | {
+	data: {
+		controllerId: string;
+		sequence?: number | null;
+		sharedStatePatch: Patch;
+		timestamp?: number | null;
+	};
+	type: "SHARED_STATE_UPDATE";
+} | {
+	data: {
+		controllerId: string;
+		preset: string;
+		sequence?: number | null;
+		sharedStatePatch: Patch;
+		timestamp?: number | null;
+	};
+	type: "PRESET_APPLIED";
+} | {
+	data: {
+		controllerId: string;
+		profile: string;
+		sequence?: number | null;
+		sharedStatePatch: Patch;
+		timestamp?: number | null;
+	};
+	type: "PROFILE_CHANGED";
+} | {
+	data: {
+		aircraft: AircraftState[];
+	};
+	type: "AIRCRAFT_UPDATE";
+} | {
+	data: {
+		blocks: string[];
+		controllerId: string;
+	};
+	type: "BLOCKS_CLAIMED";
+} | {
+	data: {
+		blocks: string[];
+		controllerId: string;
+	};
+	type: "BLOCKS_RELEASED";
+} | {
+	data: {
+		conflicts: Record<string, string>;
+	};
+	type: "CLAIM_REJECTED";
+} | {
+	data: {
+		holder: string;
+		requester: string;
+	};
+	type: "HANDOFF_REQUESTED";
+} | {
+	data: {
+		holder: string;
+		requester: string;
+	};
+	type: "HANDOFF_COMPLETED";
+} | {
+	data: {
+		holder: string;
+		requester: string;
+	};
+	type: "HANDOFF_DENIED";
+} | {
+	data: {
+		claims?: Record<string, string>;
+		objects: SceneryObject[];
+		sequence: number;
+		sharedState: Patch;
+	};
+	type: "SNAPSHOT";
+} | {
+	data: {
+		airport: string;
+		message: Downstream;
+	};
+	type: "MULTIPLEXED";
+};
+
+export type ErrorCode = "UNAUTHORIZED" | "UNKNOWN_AIRPORT" | "RATE_LIMITED" | "STALE_STATE" | "INVALID_MESSAGE" | "OTHER" | "AUTH_EXPIRED";
+
+export type ObjectKind = "STOPBAR" | "LEAD_ON" | "TAXIWAY_SEGMENT" | "OTHER" | "RGL";
+
+export type Patch = {
+	blocks?: Record<string, BlockState>;
+	/**
+	 * merged as JSON merge patches (RFC 7396)
+	 */
+	extras?: Record<string, unknown>;
+	nodes?: Record<string, boolean>;
+	profile?: string | null;
+	versions?: Versions;
+};
+
+export type Ping = {
+	/**
+	 * client-defined send time in milliseconds
+	 */
+	timestamp: number;
+};
+
+export type PositionType = "DELIVERY" | "GROUND" | "TOWER" | "APPROACH" | "CENTRE" | "OTHER";
+
+export type SceneryObject = {
+	id: string;
+	kind?: ObjectKind | null;
+	state: boolean;
+};
+
+export type State = {
+	airport?: string;
+	/**
+	 * milliseconds since the Unix epoch
+	 */
+	connected?: Record<string, number>;
+	controllers?: string[];
+	observerCount?: number;
+	observers?: string[];
+	offline?: boolean;
+	pilots?: string[];
+};
+
+export type Upstream = {
+	data: Ping | null;
+	type: "HEARTBEAT";
+} | {
+	type: "HEARTBEAT_ACK";
+} | {
+	data: CloseReason | null;
+	type: "CLOSE";
+} | {
+	data: {
+		kind?: ObjectKind | null;
+		objectId: string;
+		state: boolean;
+	};
+	type: "STATE_UPDATE";
+} | {
+	data: {
+		sharedStatePatch: Patch;
+	};
+	type: "SHARED_STATE_UPDATE";
+} | {
+	type: "RESYNC_REQUEST";
+} | {
+	data: {
+		preset: string;
+		sharedStatePatch: Patch;
+	};
+	type: "PRESET_APPLIED";
+} | {
+	data: {
+		blocks: string[];
+	};
+	type: "CLAIM_BLOCKS";
+} | {
+	data: {
+		blocks: string[];
+	};
+	type: "RELEASE_BLOCKS";
+} | {
+	data: {
+		holder: string;
+	};
+	type: "HANDOFF_REQUEST";
+} | {
+	data: {
+		accept: boolean;
+		requester: string;
+	};
+	type: "HANDOFF_RESPONSE";
+} | {
+	data: {
+		key: string;
+	};
+	type: "AUTHENTICATE";
+} | {
+	type: "JOIN";
+} | {
+	data: {
+		airport: string;
+		message: Upstream;
+	};
+	type: "MULTIPLEXED";
+};
+
+/**
+ * Writes with the same counter are ordered by controller ID.
+ */
+export type Version = {
+	controllerId: string;
+	counter: number;
+};
+
+export type Versions = {
+	blocks?: Record<string, Version>;
+	extras?: Record<string, Version>;
+	nodes?: Record<string, Version>;
+	profile?: Version | null;
+};
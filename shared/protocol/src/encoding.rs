@@ -0,0 +1,82 @@
+use std::fmt::{self, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// JSON is sent in text frames and other encodings in binary frames.
+#[derive(
+	Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub enum Encoding {
+	#[default]
+	#[serde(rename = "json")]
+	Json,
+	#[serde(rename = "msgpack")]
+	MessagePack,
+}
+
+impl Encoding {
+	pub const QUERY_PARAM: &'static str = "encoding";
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Json => "json",
+			Self::MessagePack => "msgpack",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"json" => Some(Self::Json),
+			"msgpack" => Some(Self::MessagePack),
+			_ => None,
+		}
+	}
+
+	pub fn is_binary(self) -> bool {
+		self != Self::Json
+	}
+
+	pub fn encode<T: Serialize>(
+		self,
+		message: &T,
+	) -> Result<Vec<u8>, CodecError> {
+		match self {
+			Self::Json => serde_json::to_vec(message).map_err(CodecError::Json),
+			// field names are kept so that messages stay self-describing
+			Self::MessagePack => {
+				rmp_serde::to_vec_named(message).map_err(CodecError::MessagePackEncode)
+			},
+		}
+	}
+
+	pub fn decode<'a, T: Deserialize<'a>>(
+		self,
+		data: &'a [u8],
+	) -> Result<T, CodecError> {
+		match self {
+			Self::Json => serde_json::from_slice(data).map_err(CodecError::Json),
+			Self::MessagePack => {
+				rmp_serde::from_slice(data).map_err(CodecError::MessagePackDecode)
+			},
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+	Json(serde_json::Error),
+	MessagePackEncode(rmp_serde::encode::Error),
+	MessagePackDecode(rmp_serde::decode::Error),
+}
+
+impl Display for CodecError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			Self::Json(err) => write!(f, "json: {err}"),
+			Self::MessagePackEncode(err) => write!(f, "msgpack: {err}"),
+			Self::MessagePackDecode(err) => write!(f, "msgpack: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for CodecError {}
@@ -1,33 +1,53 @@
+mod encoding;
+#[cfg(feature = "bars-config")]
+mod validate;
+
+pub use encoding::{CodecError, Encoding};
+#[cfg(feature = "bars-config")]
+pub use validate::PatchWarning;
+
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::sync::Arc;
 
-use serde::{Deserialize, Serialize};
+use serde::de::IgnoredAny;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use serde_json::{Map, Value};
 
 pub type NodeState = bool;
 
+pub type Id = Arc<str>;
+
 #[derive(
 	Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize,
 )]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum BlockState {
 	Clear,
 	Relax,
-	Route((String, String)),
+	Route((Id, Id)),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Aerodrome {
-	pub profile: String,
-	pub nodes: HashMap<String, NodeState>,
-	pub blocks: HashMap<String, BlockState>,
+	pub profile: Id,
+	pub nodes: HashMap<Id, NodeState>,
+	pub blocks: HashMap<Id, BlockState>,
+	pub extras: HashMap<String, Value>,
 	patch: Option<Patch>,
 }
 
 impl Aerodrome {
-	pub fn new(profile: String) -> Self {
+	pub fn new(profile: Id) -> Self {
 		Self {
 			profile,
 			nodes: HashMap::new(),
 			blocks: HashMap::new(),
+			extras: HashMap::new(),
 			patch: None,
 		}
 	}
@@ -36,21 +56,26 @@ impl Aerodrome {
 		self.patch.get_or_insert_default()
 	}
 
-	pub fn set_profile(&mut self, profile: String) {
+	pub fn set_profile(&mut self, profile: Id) {
 		self.patch().profile = Some(profile.clone());
 		self.profile = profile;
 	}
 
-	pub fn set_node(&mut self, id: String, state: NodeState) {
+	pub fn set_node(&mut self, id: Id, state: NodeState) {
 		self.patch().nodes.insert(id.clone(), state);
 		self.nodes.insert(id, state);
 	}
 
-	pub fn set_block(&mut self, id: String, state: BlockState) {
+	pub fn set_block(&mut self, id: Id, state: BlockState) {
 		self.patch().blocks.insert(id.clone(), state.clone());
 		self.blocks.insert(id, state);
 	}
 
+	pub fn set_extra(&mut self, key: String, value: Value) {
+		self.patch().merge_extra(key.clone(), value.clone());
+		merge_extra(&mut self.extras, key, value, false);
+	}
+
 	pub fn take_patch(&mut self) -> Option<Patch> {
 		std::mem::take(&mut self.patch)
 	}
@@ -60,17 +85,25 @@ impl Aerodrome {
 			self.profile = profile;
 		}
 
-		self.nodes.extend(patch.nodes.into_iter());
-		self.blocks.extend(patch.blocks.into_iter());
+		self.nodes.extend(patch.nodes);
+		self.blocks.extend(patch.blocks);
+
+		apply_extras(&mut self.extras, patch.extras);
 	}
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(default)]
 pub struct Patch {
-	pub profile: Option<String>,
-	pub nodes: HashMap<String, NodeState>,
-	pub blocks: HashMap<String, BlockState>,
+	pub profile: Option<Id>,
+	pub nodes: HashMap<Id, NodeState>,
+	pub blocks: HashMap<Id, BlockState>,
+	/// merged as JSON merge patches (RFC 7396)
+	#[serde(skip_serializing_if = "HashMap::is_empty")]
+	pub extras: HashMap<String, Value>,
+	#[serde(skip_serializing_if = "Versions::is_empty")]
+	pub versions: Versions,
 }
 
 impl Patch {
@@ -79,22 +112,47 @@ impl Patch {
 			self.profile = Some(profile);
 		}
 
-		self.nodes.extend(patch.nodes.into_iter());
-		self.blocks.extend(patch.blocks.into_iter());
+		self.nodes.extend(patch.nodes);
+		self.blocks.extend(patch.blocks);
+
+		for (key, value) in patch.extras {
+			self.merge_extra(key, value);
+		}
+
+		self.versions.extend(patch.versions);
+	}
+
+	/// Keeps any `null`, so that the removal is passed on.
+	pub fn merge_extra(&mut self, key: String, value: Value) {
+		merge_extra(&mut self.extras, key, value, true);
 	}
 
 	pub fn is_empty(&self) -> bool {
-		self.profile.is_none() && self.nodes.is_empty() && self.blocks.is_empty()
+		self.profile.is_none()
+			&& self.nodes.is_empty()
+			&& self.blocks.is_empty()
+			&& self.extras.is_empty()
 	}
-}
 
-impl Default for Patch {
-	fn default() -> Self {
-		Self {
-			profile: None,
-			nodes: HashMap::new(),
-			blocks: HashMap::new(),
+	/// Removes entries which would not change `current`.
+	pub fn minimize(&mut self, current: &Patch) {
+		if self.profile.is_some() && self.profile != current.profile {
+			return
 		}
+
+		self.profile = None;
+		self
+			.nodes
+			.retain(|id, state| current.nodes.get(id) != Some(state));
+		self
+			.blocks
+			.retain(|id, state| current.blocks.get(id) != Some(state));
+		self
+			.extras
+			.retain(|key, value| match current.extras.get(key) {
+				Some(current) => current != value,
+				None => !value.is_null(),
+			});
 	}
 }
 
@@ -104,33 +162,133 @@ impl From<Aerodrome> for Patch {
 			profile: Some(from.profile),
 			nodes: from.nodes,
 			blocks: from.blocks,
+			extras: from.extras,
+			versions: Versions::default(),
 		}
 	}
 }
 
+pub fn apply_extras(
+	extras: &mut HashMap<String, Value>,
+	patch: HashMap<String, Value>,
+) {
+	for (key, value) in patch {
+		merge_extra(extras, key, value, false);
+	}
+}
+
+fn merge_extra(
+	extras: &mut HashMap<String, Value>,
+	key: String,
+	value: Value,
+	keep_nulls: bool,
+) {
+	fn merge(target: &mut Value, source: Value, keep_nulls: bool) {
+		let Value::Object(source) = source else {
+			*target = source;
+			return
+		};
+
+		if !target.is_object() {
+			*target = Value::Object(Map::new());
+		}
+		let Value::Object(target) = target else {
+			unreachable!()
+		};
+
+		for (key, value) in source {
+			if value.is_null() && !keep_nulls {
+				target.remove(&key);
+			} else {
+				merge(target.entry(key).or_insert(Value::Null), value, keep_nulls);
+			}
+		}
+	}
+
+	if value.is_null() && !keep_nulls {
+		extras.remove(&key);
+	} else {
+		merge(extras.entry(key).or_insert(Value::Null), value, keep_nulls);
+	}
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(
+	remote = "Self",
 	rename_all = "SCREAMING_SNAKE_CASE",
 	rename_all_fields = "camelCase",
 	tag = "type",
 	content = "data"
 )]
 pub enum Upstream<P = Patch> {
-	Heartbeat,
+	Heartbeat(Option<Ping>),
 	HeartbeatAck,
-	Close,
+	Close(Option<CloseReason>),
 	StateUpdate {
 		object_id: String,
 		state: bool,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		kind: Option<ObjectKind>,
 	},
 	SharedStateUpdate {
 		#[serde(rename = "sharedStatePatch")]
 		patch: P,
 	},
+	ResyncRequest,
+	PresetApplied {
+		preset: String,
+		#[serde(rename = "sharedStatePatch")]
+		patch: P,
+	},
+	/// Granted only if no other controller holds any of the blocks.
+	ClaimBlocks {
+		blocks: Vec<String>,
+	},
+	ReleaseBlocks {
+		blocks: Vec<String>,
+	},
+	HandoffRequest {
+		holder: String,
+	},
+	HandoffResponse {
+		requester: String,
+		accept: bool,
+	},
+	Authenticate {
+		key: String,
+	},
+	Join,
+	/// A wrapped `Close` leaves only the aerodrome it is addressed to.
+	Multiplexed {
+		airport: String,
+		message: Box<Upstream<P>>,
+	},
+}
+
+// sent bare without a ping or reason, as older servers expect
+impl<P: Serialize> Serialize for Upstream<P> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match self {
+			Self::Heartbeat(None) => bare(serializer, "Upstream", "HEARTBEAT"),
+			Self::Close(None) => bare(serializer, "Upstream", "CLOSE"),
+			_ => Self::serialize(self, serializer),
+		}
+	}
+}
+
+impl<'de, P: Deserialize<'de>> Deserialize<'de> for Upstream<P> {
+	fn deserialize<D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Self, D::Error> {
+		Self::deserialize(deserializer)
+	}
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(
+	remote = "Self",
 	rename_all = "SCREAMING_SNAKE_CASE",
 	rename_all_fields = "camelCase",
 	tag = "type",
@@ -138,14 +296,23 @@ pub enum Upstream<P = Patch> {
 )]
 pub enum Downstream<P = Patch> {
 	Heartbeat,
-	HeartbeatAck,
-	Close,
+	HeartbeatAck(Option<Ping>),
+	Close(Option<CloseReason>),
 	Error {
-		message: String,
+		#[serde(default)]
+		code: ErrorCode,
+		#[serde(
+			default,
+			alias = "message",
+			skip_serializing_if = "Option::is_none"
+		)]
+		detail: Option<String>,
 	},
-	ControllerConnect {
-		controller_id: String,
+	/// The last update was ignored, and should be resent after `retry_after` ms.
+	Throttled {
+		retry_after: u64,
 	},
+	ControllerConnect(Controller),
 	ControllerDisconnect {
 		controller_id: String,
 	},
@@ -155,32 +322,809 @@ pub enum Downstream<P = Patch> {
 		scenery: Vec<SceneryObject>,
 		#[serde(rename = "sharedState")]
 		patch: P,
+		#[serde(default)]
+		controllers: Vec<Controller>,
+		#[serde(default)]
+		claims: HashMap<String, String>,
+		#[serde(default)]
+		controller_id: Option<String>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sequence: Option<u64>,
+		#[serde(default)]
+		capabilities: Vec<Capability>,
 	},
 	StateUpdate {
 		object_id: String,
 		state: bool,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		kind: Option<ObjectKind>,
 		controller_id: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		timestamp: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sequence: Option<u64>,
 	},
 	SharedStateUpdate {
 		#[serde(rename = "sharedStatePatch")]
 		patch: P,
 		controller_id: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		timestamp: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sequence: Option<u64>,
+	},
+	PresetApplied {
+		preset: String,
+		#[serde(rename = "sharedStatePatch")]
+		patch: P,
+		controller_id: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		timestamp: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sequence: Option<u64>,
+	},
+	/// Resets all state to the new profile's defaults, except as in `patch`.
+	ProfileChanged {
+		profile: Id,
+		#[serde(rename = "sharedStatePatch")]
+		patch: P,
+		controller_id: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		timestamp: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sequence: Option<u64>,
+	},
+	AircraftUpdate {
+		aircraft: Vec<AircraftState>,
+	},
+	BlocksClaimed {
+		blocks: Vec<String>,
+		controller_id: String,
+	},
+	BlocksReleased {
+		blocks: Vec<String>,
+		controller_id: String,
+	},
+	ClaimRejected {
+		conflicts: HashMap<String, String>,
+	},
+	HandoffRequested {
+		requester: String,
+		holder: String,
+	},
+	HandoffCompleted {
+		requester: String,
+		holder: String,
+	},
+	HandoffDenied {
+		requester: String,
+		holder: String,
+	},
+	Snapshot {
+		#[serde(rename = "objects")]
+		scenery: Vec<SceneryObject>,
+		#[serde(rename = "sharedState")]
+		patch: P,
+		#[serde(default)]
+		claims: HashMap<String, String>,
+		sequence: u64,
 	},
+	Multiplexed {
+		airport: String,
+		message: Box<Downstream<P>>,
+	},
+	/// Any message not otherwise understood, which cannot be encoded.
+	#[serde(untagged, skip_serializing)]
+	#[cfg_attr(feature = "schemars", schemars(skip))]
+	Other(IgnoredAny),
+}
+
+impl<P: Serialize> Serialize for Downstream<P> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match self {
+			Self::HeartbeatAck(None) => {
+				bare(serializer, "Downstream", "HEARTBEAT_ACK")
+			},
+			Self::Close(None) => bare(serializer, "Downstream", "CLOSE"),
+			_ => Self::serialize(self, serializer),
+		}
+	}
+}
+
+impl<'de, P: Deserialize<'de>> Deserialize<'de> for Downstream<P> {
+	fn deserialize<D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Self, D::Error> {
+		Self::deserialize(deserializer)
+	}
+}
+
+fn bare<S: Serializer>(
+	serializer: S,
+	name: &'static str,
+	tag: &'static str,
+) -> Result<S::Ok, S::Error> {
+	let mut message = serializer.serialize_struct(name, 1)?;
+	message.serialize_field("type", tag)?;
+	message.end()
+}
+
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+	Unauthorized,
+	/// The connection is closed unless a new key is sent with `AUTHENTICATE`.
+	AuthExpired,
+	UnknownAirport,
+	RateLimited,
+	StaleState,
+	InvalidMessage,
+	#[default]
+	#[serde(other)]
+	Other,
+}
+
+impl Display for ErrorCode {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Unauthorized => "unauthorised",
+			Self::AuthExpired => "authentication expired",
+			Self::UnknownAirport => "unknown airport",
+			Self::RateLimited => "rate limited",
+			Self::StaleState => "stale state",
+			Self::InvalidMessage => "invalid message",
+			Self::Other => "error",
+		})
+	}
+}
+
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CloseReason {
+	ControllerLogoff,
+	PositionHandoff,
+	ServerRestart,
+	Kicked,
+	#[default]
+	#[serde(other)]
+	Other,
+}
+
+impl Display for CloseReason {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::ControllerLogoff => "controller logged off",
+			Self::PositionHandoff => "position handed off",
+			Self::ServerRestart => "server restarting",
+			Self::Kicked => "removed by server",
+			Self::Other => "closed",
+		})
+	}
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Capability {
+	Intensity,
+	Ownership,
+	BinaryFrames,
+	Multiplex,
 	#[serde(other)]
 	Other,
 }
 
+/// Writes with the same counter are ordered by controller ID.
+#[derive(
+	Clone,
+	Debug,
+	Default,
+	Hash,
+	PartialEq,
+	Eq,
+	PartialOrd,
+	Ord,
+	Deserialize,
+	Serialize,
+)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+	pub counter: u64,
+	pub controller_id: String,
+}
+
+impl Version {
+	pub fn next(current: Option<&Version>, controller_id: &str) -> Self {
+		Self {
+			counter: current.map_or(0, |version| version.counter) + 1,
+			controller_id: controller_id.into(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Versions {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub profile: Option<Version>,
+	#[serde(skip_serializing_if = "HashMap::is_empty")]
+	pub nodes: HashMap<Id, Version>,
+	#[serde(skip_serializing_if = "HashMap::is_empty")]
+	pub blocks: HashMap<Id, Version>,
+	#[serde(skip_serializing_if = "HashMap::is_empty")]
+	pub extras: HashMap<String, Version>,
+}
+
+impl Versions {
+	pub fn is_empty(&self) -> bool {
+		self.profile.is_none()
+			&& self.nodes.is_empty()
+			&& self.blocks.is_empty()
+			&& self.extras.is_empty()
+	}
+
+	pub fn extend(&mut self, versions: Versions) {
+		if let Some(version) = versions.profile {
+			self.profile = Some(version);
+		}
+
+		self.nodes.extend(versions.nodes);
+		self.blocks.extend(versions.blocks);
+		self.extras.extend(versions.extras);
+	}
+}
+
+/// Keeps the write with the greatest `Version` of each entry.
+#[derive(Clone, Debug, Default)]
+pub struct SharedState {
+	state: Patch,
+}
+
+impl SharedState {
+	pub fn new(state: Patch) -> Self {
+		Self { state }
+	}
+
+	pub fn state(&self) -> &Patch {
+		&self.state
+	}
+
+	pub fn replace(&mut self, state: Patch) {
+		self.state = state;
+	}
+
+	pub fn stamp(&self, patch: &mut Patch, controller_id: &str) {
+		let current = &self.state.versions;
+		let versions = &mut patch.versions;
+
+		if patch.profile.is_some() && versions.profile.is_none() {
+			versions.profile =
+				Some(Version::next(current.profile.as_ref(), controller_id));
+		}
+
+		fn stamp_map<K: Clone + Eq + Hash, T>(
+			map: &HashMap<K, T>,
+			current: &HashMap<K, Version>,
+			versions: &mut HashMap<K, Version>,
+			controller_id: &str,
+		) {
+			for id in map.keys() {
+				versions
+					.entry(id.clone())
+					.or_insert_with(|| Version::next(current.get(id), controller_id));
+			}
+		}
+
+		stamp_map(
+			&patch.nodes,
+			&current.nodes,
+			&mut versions.nodes,
+			controller_id,
+		);
+		stamp_map(
+			&patch.blocks,
+			&current.blocks,
+			&mut versions.blocks,
+			controller_id,
+		);
+		stamp_map(
+			&patch.extras,
+			&current.extras,
+			&mut versions.extras,
+			controller_id,
+		);
+	}
+
+	/// Returns the entries which took effect. Unversioned entries are older
+	/// than any versioned one, and otherwise take effect in the order merged.
+	pub fn merge(&mut self, mut patch: Patch) -> Patch {
+		fn newer(current: Option<&Version>, version: Option<&Version>) -> bool {
+			match (current, version) {
+				(Some(current), Some(version)) => version > current,
+				(Some(_), None) => false,
+				(None, _) => true,
+			}
+		}
+
+		fn merge_map<K: Eq + Hash, T>(
+			map: &mut HashMap<K, T>,
+			current: &HashMap<K, Version>,
+			versions: &mut HashMap<K, Version>,
+		) {
+			map.retain(|id, _| newer(current.get(id), versions.get(id)));
+			versions.retain(|id, _| map.contains_key(id));
+		}
+
+		let current = &self.state.versions;
+		let versions = &mut patch.versions;
+
+		if !newer(current.profile.as_ref(), versions.profile.as_ref()) {
+			patch.profile = None;
+		}
+		if patch.profile.is_none() {
+			versions.profile = None;
+		}
+
+		merge_map(&mut patch.nodes, &current.nodes, &mut versions.nodes);
+		merge_map(&mut patch.blocks, &current.blocks, &mut versions.blocks);
+		merge_map(&mut patch.extras, &current.extras, &mut versions.extras);
+
+		self.state.apply_patch(patch.clone());
+
+		patch
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sequence {
+	Next,
+	Stale,
+	Gap,
+}
+
+impl Sequence {
+	/// Numbers wrap around, so up to half the range ahead counts as later.
+	pub fn check(last: &mut Option<u64>, sequence: Option<u64>) -> Self {
+		let Some(sequence) = sequence else {
+			return Self::Next
+		};
+
+		let res = match last.map(|last| sequence.wrapping_sub(last)) {
+			Some(ahead) if ahead == 0 || ahead > u64::MAX / 2 => return Self::Stale,
+			Some(ahead) if ahead > 1 => Self::Gap,
+			_ => Self::Next,
+		};
+
+		*last = Some(sequence);
+		res
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Controller {
+	pub controller_id: String,
+	#[serde(default)]
+	pub callsign: Option<String>,
+	#[serde(default)]
+	pub cid: Option<u32>,
+	#[serde(default)]
+	pub position: Option<PositionType>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PositionType {
+	Delivery,
+	Ground,
+	Tower,
+	Approach,
+	Centre,
+	#[serde(other)]
+	Other,
+}
+
+impl PositionType {
+	pub fn from_callsign(callsign: &str) -> Self {
+		match callsign.rsplit('_').next().unwrap_or_default() {
+			"DEL" => Self::Delivery,
+			"GND" => Self::Ground,
+			"TWR" => Self::Tower,
+			"APP" | "DEP" => Self::Approach,
+			"CTR" | "FSS" => Self::Centre,
+			_ => Self::Other,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Delivery => "Delivery",
+			Self::Ground => "Ground",
+			Self::Tower => "Tower",
+			Self::Approach => "Approach",
+			Self::Centre => "Centre",
+			Self::Other => "Other",
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Ping {
+	/// client-defined send time in milliseconds
+	pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AircraftState {
+	pub callsign: String,
+	pub lat: f64,
+	pub lon: f64,
+	/// true heading in degrees
+	#[serde(default)]
+	pub heading: f32,
+	/// ground speed in knots
+	#[serde(default)]
+	pub ground_speed: f32,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SceneryObject {
 	pub id: String,
 	pub state: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub kind: Option<ObjectKind>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ObjectKind {
+	Stopbar,
+	LeadOn,
+	/// runway guard lights
+	Rgl,
+	TaxiwaySegment,
+	#[serde(other)]
+	Other,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
-#[serde(default)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct State {
 	pub airport: String,
 	pub controllers: Vec<String>,
 	pub pilots: Vec<String>,
 	pub offline: bool,
+	pub observers: Vec<String>,
+	pub observer_count: usize,
+	/// milliseconds since the Unix epoch
+	pub connected: HashMap<String, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn json<T: Serialize>(message: &T) -> String {
+		serde_json::to_string(message).unwrap()
+	}
+
+	#[test]
+	fn bare_heartbeats() {
+		// as sent before pings were added
+		assert_eq!(
+			json(&Upstream::<Patch>::Heartbeat(None)),
+			r#"{"type":"HEARTBEAT"}"#
+		);
+		assert_eq!(
+			json(&Downstream::<Patch>::HeartbeatAck(None)),
+			r#"{"type":"HEARTBEAT_ACK"}"#,
+		);
+
+		let ping = Some(Ping { timestamp: 42 });
+		assert_eq!(
+			json(&Upstream::<Patch>::Heartbeat(ping)),
+			r#"{"type":"HEARTBEAT","data":{"timestamp":42}}"#,
+		);
+		assert_eq!(
+			json(&Downstream::<Patch>::HeartbeatAck(ping)),
+			r#"{"type":"HEARTBEAT_ACK","data":{"timestamp":42}}"#,
+		);
+
+		let message = r#"{"type":"HEARTBEAT"}"#;
+		let Ok(Upstream::<Patch>::Heartbeat(None)) = serde_json::from_str(message)
+		else {
+			panic!("bare heartbeat not read")
+		};
+		let message = r#"{"type":"HEARTBEAT_ACK"}"#;
+		let Ok(Downstream::<Patch>::HeartbeatAck(None)) =
+			serde_json::from_str(message)
+		else {
+			panic!("bare heartbeat acknowledgement not read")
+		};
+	}
+
+	#[test]
+	fn bare_heartbeats_round_trip() {
+		for encoding in [Encoding::Json, Encoding::MessagePack] {
+			let data = encoding
+				.encode(&Upstream::<Patch>::Heartbeat(None))
+				.unwrap();
+			let Ok(Upstream::<Patch>::Heartbeat(None)) = encoding.decode(&data)
+			else {
+				panic!("bare heartbeat not read back from {}", encoding.name())
+			};
+		}
+	}
+
+	#[test]
+	fn bare_closes() {
+		// as sent before reasons were added
+		assert_eq!(json(&Upstream::<Patch>::Close(None)), r#"{"type":"CLOSE"}"#);
+		assert_eq!(
+			json(&Downstream::<Patch>::Close(None)),
+			r#"{"type":"CLOSE"}"#
+		);
+
+		let reason = Some(CloseReason::ServerRestart);
+		assert_eq!(
+			json(&Upstream::<Patch>::Close(reason)),
+			r#"{"type":"CLOSE","data":"SERVER_RESTART"}"#,
+		);
+		assert_eq!(
+			json(&Downstream::<Patch>::Close(reason)),
+			r#"{"type":"CLOSE","data":"SERVER_RESTART"}"#,
+		);
+
+		let message = r#"{"type":"CLOSE"}"#;
+		let Ok(Upstream::<Patch>::Close(None)) = serde_json::from_str(message)
+		else {
+			panic!("bare close not read")
+		};
+		let Ok(Downstream::<Patch>::Close(None)) = serde_json::from_str(message)
+		else {
+			panic!("bare close not read")
+		};
+	}
+
+	#[test]
+	fn sequence_in_order() {
+		let mut last = None;
+		for sequence in 1..5 {
+			assert_eq!(Sequence::check(&mut last, Some(sequence)), Sequence::Next);
+			assert_eq!(last, Some(sequence));
+		}
+
+		// unnumbered messages leave the count alone
+		assert_eq!(Sequence::check(&mut last, None), Sequence::Next);
+		assert_eq!(last, Some(4));
+	}
+
+	#[test]
+	fn sequence_duplicate() {
+		let mut last = Some(7);
+		assert_eq!(Sequence::check(&mut last, Some(7)), Sequence::Stale);
+		assert_eq!(Sequence::check(&mut last, Some(3)), Sequence::Stale);
+		assert_eq!(last, Some(7));
+	}
+
+	#[test]
+	fn sequence_gap() {
+		let mut last = Some(7);
+		assert_eq!(Sequence::check(&mut last, Some(10)), Sequence::Gap);
+		assert_eq!(last, Some(10));
+
+		// the messages missed are stale once they arrive
+		assert_eq!(Sequence::check(&mut last, Some(9)), Sequence::Stale);
+		assert_eq!(Sequence::check(&mut last, Some(11)), Sequence::Next);
+	}
+
+	#[test]
+	fn sequence_wraparound() {
+		let mut last = Some(u64::MAX - 1);
+		assert_eq!(Sequence::check(&mut last, Some(u64::MAX)), Sequence::Next);
+		assert_eq!(Sequence::check(&mut last, Some(0)), Sequence::Next);
+		assert_eq!(Sequence::check(&mut last, Some(2)), Sequence::Gap);
+		assert_eq!(Sequence::check(&mut last, Some(u64::MAX)), Sequence::Stale);
+		assert_eq!(last, Some(2));
+	}
+
+	#[test]
+	fn sequence_reset() {
+		// an initial state or snapshot replaces the last number, after which
+		// numbering continues from there even if it is lower
+		let mut last = Some(500);
+		assert_eq!(Sequence::check(&mut last, Some(3)), Sequence::Stale);
+
+		last = Some(2);
+		assert_eq!(Sequence::check(&mut last, Some(3)), Sequence::Next);
+
+		// as does a new connection, before which nothing is known
+		last = None;
+		assert_eq!(Sequence::check(&mut last, Some(40)), Sequence::Next);
+		assert_eq!(last, Some(40));
+	}
+
+	fn version(counter: u64, controller_id: &str) -> Version {
+		Version {
+			counter,
+			controller_id: controller_id.into(),
+		}
+	}
+
+	// a patch setting node N, block B and extra X, with a version for each if
+	// given
+	fn write(
+		node: bool,
+		block: BlockState,
+		extra: i32,
+		version: Option<Version>,
+	) -> Patch {
+		let mut patch = Patch::default();
+		patch.nodes.insert("N".into(), node);
+		patch.blocks.insert("B".into(), block);
+		patch.extras.insert("X".into(), extra.into());
+
+		if let Some(version) = version {
+			patch.versions.nodes.insert("N".into(), version.clone());
+			patch.versions.blocks.insert("B".into(), version.clone());
+			patch.versions.extras.insert("X".into(), version);
+		}
+
+		patch
+	}
+
+	type Summary = (
+		HashMap<Id, NodeState>,
+		HashMap<Id, BlockState>,
+		HashMap<String, Value>,
+		Versions,
+	);
+
+	fn summary(state: &SharedState) -> Summary {
+		let state = state.state();
+		(
+			state.nodes.clone(),
+			state.blocks.clone(),
+			state.extras.clone(),
+			state.versions.clone(),
+		)
+	}
+
+	fn permutations(n: usize) -> Vec<Vec<usize>> {
+		if n == 0 {
+			return vec![Vec::new()]
+		}
+
+		permutations(n - 1)
+			.into_iter()
+			.flat_map(|order| {
+				(0..n).map(move |i| {
+					let mut order = order.clone();
+					order.insert(i, n - 1);
+					order
+				})
+			})
+			.collect()
+	}
+
+	// merges `patches` in every order, checking that each reaches the same
+	// state, which is returned
+	fn converge(patches: &[Patch]) -> Summary {
+		let mut states = permutations(patches.len()).into_iter().map(|order| {
+			let mut state = SharedState::default();
+			for i in order {
+				state.merge(patches[i].clone());
+			}
+			summary(&state)
+		});
+
+		let first = states.next().unwrap();
+		for state in states {
+			assert_eq!(state, first);
+		}
+		first
+	}
+
+	#[test]
+	fn merge_converges() {
+		let patches = [
+			write(true, BlockState::Clear, 1, Some(version(1, "c1"))),
+			write(false, BlockState::Relax, 2, Some(version(2, "c1"))),
+			write(true, BlockState::Relax, 3, Some(version(2, "c2"))),
+			write(false, BlockState::Clear, 4, Some(version(1, "c3"))),
+		];
+
+		// the greatest counter wins, and then the greatest controller ID
+		let (nodes, blocks, extras, versions) = converge(&patches);
+		assert!(nodes["N"]);
+		assert_eq!(blocks["B"], BlockState::Relax);
+		assert_eq!(extras["X"], 3);
+		assert_eq!(versions.nodes["N"], version(2, "c2"));
+	}
+
+	#[test]
+	fn merge_converges_with_unversioned() {
+		let patches = [
+			write(true, BlockState::Clear, 1, None),
+			write(false, BlockState::Relax, 2, Some(version(1, "c1"))),
+			write(true, BlockState::Relax, 3, Some(version(1, "c2"))),
+		];
+
+		// unversioned entries never override versioned ones
+		let (nodes, blocks, extras, versions) = converge(&patches);
+		assert!(nodes["N"]);
+		assert_eq!(blocks["B"], BlockState::Relax);
+		assert_eq!(extras["X"], 3);
+		assert_eq!(versions.nodes["N"], version(1, "c2"));
+	}
+
+	#[test]
+	fn merge_unversioned_in_order() {
+		let mut state = SharedState::default();
+		state.merge(write(true, BlockState::Clear, 1, None));
+		let merged = state.merge(write(false, BlockState::Relax, 2, None));
+
+		// as from a server predating versions, which orders writes itself
+		assert!(!merged.nodes["N"]);
+		assert!(!summary(&state).0["N"]);
+		assert!(summary(&state).3.is_empty());
+	}
+
+	#[test]
+	fn stamped_writes_converge() {
+		let base = write(true, BlockState::Clear, 1, Some(version(1, "c1")));
+
+		// two replicas write concurrently from the same state, then exchange
+		// their writes
+		let mut a = SharedState::default();
+		let mut b = SharedState::default();
+		a.merge(base.clone());
+		b.merge(base);
+
+		let mut from_a = write(false, BlockState::Relax, 2, None);
+		let mut from_b = write(true, BlockState::Relax, 3, None);
+		a.stamp(&mut from_a, "c1");
+		b.stamp(&mut from_b, "c2");
+		assert_eq!(from_a.versions.nodes["N"], version(2, "c1"));
+		assert_eq!(from_b.versions.nodes["N"], version(2, "c2"));
+
+		a.merge(from_a.clone());
+		b.merge(from_b.clone());
+		let merged = a.merge(from_b);
+		b.merge(from_a);
+
+		assert!(!merged.is_empty());
+		assert_eq!(summary(&a), summary(&b));
+		assert_eq!(summary(&a).2["X"], 3);
+	}
+
+	#[test]
+	fn replace_drops_older_writes() {
+		let mut state = SharedState::default();
+		state.replace(write(true, BlockState::Clear, 1, Some(version(3, "c1"))));
+
+		let merged =
+			state.merge(write(false, BlockState::Relax, 2, Some(version(2, "c2"))));
+		assert!(merged.is_empty());
+
+		let merged = state.merge(write(false, BlockState::Relax, 2, None));
+		assert!(merged.is_empty());
+		assert!(summary(&state).0["N"]);
+	}
 }
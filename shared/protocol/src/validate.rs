@@ -0,0 +1,188 @@
+use crate::{BlockState, Id, Patch};
+
+use std::fmt::{self, Display, Formatter};
+
+use bars_config::{Aerodrome, NodeCondition};
+
+/// An entry removed from a patch by `Patch::validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatchWarning {
+	UnknownProfile(Id),
+	UnknownNode(Id),
+	UnknownBlock(Id),
+	/// An endpoint is unknown, not in the block, or not a router in the profile.
+	InvalidRoute {
+		block: Id,
+		route: (Id, Id),
+	},
+}
+
+impl Display for PatchWarning {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			Self::UnknownProfile(id) => write!(f, "unknown profile {id}"),
+			Self::UnknownNode(id) => write!(f, "unknown node {id}"),
+			Self::UnknownBlock(id) => write!(f, "unknown block {id}"),
+			Self::InvalidRoute {
+				block,
+				route: (a, b),
+			} => write!(f, "invalid route {a} to {b} in block {block}"),
+		}
+	}
+}
+
+impl Patch {
+	/// Routes are checked against the profile the patch sets, or else `profile`.
+	pub fn validate(
+		mut self,
+		aerodrome: &Aerodrome,
+		profile: Option<&str>,
+	) -> (Self, Vec<PatchWarning>) {
+		let mut warnings = Vec::new();
+
+		if let Some(id) = &self.profile {
+			if !aerodrome.profiles.iter().any(|profile| *profile.id == **id) {
+				warnings.push(PatchWarning::UnknownProfile(id.clone()));
+				self.profile = None;
+			}
+		}
+
+		let profile = match self.profile.as_deref().or(profile) {
+			Some(id) => aerodrome.profiles.iter().find(|profile| profile.id == id),
+			None => aerodrome.profiles.first(),
+		};
+
+		let node_index = |id: &str| aerodrome.nodes.iter().position(|n| n.id == id);
+
+		self.nodes.retain(|id, _| {
+			let known = node_index(id).is_some();
+			if !known {
+				warnings.push(PatchWarning::UnknownNode(id.clone()));
+			}
+
+			known
+		});
+
+		self.blocks.retain(|id, state| {
+			let Some(block) = aerodrome.blocks.iter().find(|b| *b.id == **id) else {
+				warnings.push(PatchWarning::UnknownBlock(id.clone()));
+				return false
+			};
+
+			let BlockState::Route((a, b)) = state else {
+				return true
+			};
+
+			// the router only joins distinct router nodes of the same block, never
+			// across a non-route
+			let valid = node_index(a).zip(node_index(b)).is_some_and(|(a, b)| {
+				a != b
+					&& block.nodes.contains(&a)
+					&& block.nodes.contains(&b)
+					&& !block.non_routes.contains(&(a, b))
+					&& !block.non_routes.contains(&(b, a))
+					&& profile.is_none_or(|profile| {
+						[a, b].iter().all(|node| {
+							profile.nodes.get(*node) == Some(&NodeCondition::Router)
+						})
+					})
+			});
+
+			if !valid {
+				warnings.push(PatchWarning::InvalidRoute {
+					block: id.clone(),
+					route: (a.clone(), b.clone()),
+				});
+			}
+
+			valid
+		});
+
+		(self, warnings)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bars_config::{Block, Node, Profile, ResetCondition};
+
+	// a block of nodes N0 to N2, which are routers in profile P0 except N2
+	fn aerodrome() -> Aerodrome {
+		Aerodrome {
+			icao: "TEST".into(),
+			elements: Vec::new(),
+			nodes: (0..3)
+				.map(|i| Node {
+					id: format!("N{i}"),
+					scratchpad: None,
+					parent: None,
+					reset_on_crossing: false,
+					gang: false,
+					display: Default::default(),
+				})
+				.collect(),
+			edges: Vec::new(),
+			blocks: vec![Block {
+				id: "B0".into(),
+				nodes: vec![0, 1, 2],
+				edges: Vec::new(),
+				non_routes: Vec::new(),
+				stands: Vec::new(),
+				display: Default::default(),
+			}],
+			closures: Vec::new(),
+			profiles: vec![Profile {
+				id: "P0".into(),
+				name: "Test".into(),
+				nodes: vec![
+					NodeCondition::Router,
+					NodeCondition::Router,
+					NodeCondition::Direct {
+						reset: ResetCondition::None,
+					},
+				],
+				edges: Vec::new(),
+				blocks: Vec::new(),
+				presets: Vec::new(),
+				unattended: None,
+				runways: Vec::new(),
+			}],
+			maps: Vec::new(),
+			views: Vec::new(),
+			styles: Vec::new(),
+			rasters: Vec::new(),
+		}
+	}
+
+	fn route(a: &str, b: &str) -> Patch {
+		let mut patch = Patch::default();
+		patch
+			.blocks
+			.insert("B0".into(), BlockState::Route((a.into(), b.into())));
+		patch
+	}
+
+	#[test]
+	fn route_between_routers() {
+		let (patch, warnings) = route("N0", "N1").validate(&aerodrome(), None);
+
+		assert!(warnings.is_empty());
+		assert_eq!(patch.blocks.len(), 1);
+	}
+
+	#[test]
+	fn route_to_non_router() {
+		let (patch, warnings) = route("N0", "N2").validate(&aerodrome(), None);
+
+		assert_eq!(
+			warnings,
+			[PatchWarning::InvalidRoute {
+				block: "B0".into(),
+				route: ("N0".into(), "N2".into()),
+			}],
+		);
+		assert!(patch.blocks.is_empty());
+	}
+}
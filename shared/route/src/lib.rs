@@ -0,0 +1,491 @@
+//! Routing between router nodes, shared by the client which sets routes and
+//! confc which checks that they can be set, so that the two cannot disagree.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bars_config::{Aerodrome, Block, NodeCondition, Profile};
+
+/// Segments from the destination, with each node pair ordered from the origin.
+pub type Route = Vec<(usize, (usize, usize))>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteError {
+	/// no route reaches the destination
+	Unreachable,
+	/// more than one route reaches the destination equally directly
+	Ambiguous,
+}
+
+/// Each node has up to two sides, each bordering one block.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+	conns: Vec<[Vec<(usize, bool)>; 2]>,
+	blocks: Vec<[usize; 2]>,
+	member: Vec<bool>,
+	overfull: Vec<usize>,
+	children: HashMap<usize, Vec<usize>>,
+}
+
+impl Graph {
+	pub fn new(aerodrome: &Aerodrome) -> Self {
+		let len = aerodrome.nodes.len();
+		let mut this = Self {
+			conns: vec![[Vec::new(), Vec::new()]; len],
+			blocks: vec![[0; 2]; len],
+			member: vec![false; len],
+			overfull: Vec::new(),
+			children: HashMap::new(),
+		};
+
+		for (i, node) in aerodrome.nodes.iter().enumerate() {
+			if let Some(parent) = node.parent {
+				this.children.entry(parent).or_default().push(i);
+			}
+		}
+
+		let mut borders = vec![0; len];
+		for (i, block) in aerodrome.blocks.iter().enumerate() {
+			// nodes which already border two blocks are left out of the block
+			// entirely, so that no route can enter it through them
+			let (nodes, overfull) = block
+				.nodes
+				.iter()
+				.copied()
+				.partition::<Vec<_>, _>(|node| borders[*node] < 2);
+			this.overfull.extend(overfull);
+
+			let conns = nodes
+				.iter()
+				.map(|node| (*node, borders[*node] > 0))
+				.collect::<Vec<_>>();
+
+			for node in nodes {
+				let side = borders[node];
+
+				this.member[node] = true;
+				this.blocks[node][1] = i;
+				this.blocks[node][side] = i;
+
+				this.conns[node][side].extend(conns.iter().filter(|(other, _)| {
+					*other != node
+						&& !block.non_routes.contains(&(*other, node))
+						&& !block.non_routes.contains(&(node, *other))
+				}));
+
+				borders[node] += 1;
+			}
+		}
+
+		this
+	}
+
+	pub fn blocks(&self, node: usize) -> [usize; 2] {
+		self.blocks[node]
+	}
+
+	pub fn is_member(&self, node: usize) -> bool {
+		self.member[node]
+	}
+
+	/// Nodes bordering more than two blocks, connected only to the first two.
+	pub fn overfull(&self) -> &[usize] {
+		&self.overfull
+	}
+
+	pub fn children(&self, node: usize) -> Option<&Vec<usize>> {
+		self.children.get(&node)
+	}
+
+	pub fn candidates(
+		&self,
+		block: &Block,
+		(a, b): (usize, usize),
+	) -> Vec<(usize, usize)> {
+		let ao = vec![a];
+		let bo = vec![b];
+		let ac = self.children(a).unwrap_or(&ao);
+		let bc = self.children(b).unwrap_or(&bo);
+
+		ac.iter()
+			.flat_map(|a| bc.iter().map(move |b| (*a, *b)))
+			.filter(|(a, b)| {
+				!block.non_routes.contains(&(*a, *b))
+					&& !block.non_routes.contains(&(*b, *a))
+			})
+			.collect()
+	}
+
+	pub fn neighbours(&self, profile: &Profile, node: usize) -> Vec<usize> {
+		let mut queue = VecDeque::from([node]);
+		let mut visited = HashSet::from([node]);
+		let mut found = Vec::new();
+
+		while let Some(this) = queue.pop_front() {
+			for (next, _) in self.conns[this].iter().flatten() {
+				if !visited.insert(*next) {
+					continue
+				}
+
+				match profile.nodes[*next] {
+					NodeCondition::Router => found.push(*next),
+					NodeCondition::Fixed { state: true } => (),
+					_ => queue.push_back(*next),
+				}
+			}
+		}
+
+		found
+	}
+
+	/// Routes between router nodes only, as the client sets them.
+	pub fn find_route(
+		&self,
+		profile: &Profile,
+		(orgn, dest): (usize, usize),
+		is_open: impl Fn(usize, (usize, usize)) -> bool,
+	) -> Result<Route, RouteError> {
+		if profile.nodes[orgn] != NodeCondition::Router
+			|| profile.nodes[dest] != NodeCondition::Router
+		{
+			return Err(RouteError::Unreachable)
+		}
+
+		self.route(profile, (orgn, dest), is_open)
+	}
+
+	/// Passes through fixed-off nodes but not fixed-on ones.
+	pub fn route(
+		&self,
+		profile: &Profile,
+		(orgn, dest): (usize, usize),
+		is_open: impl Fn(usize, (usize, usize)) -> bool,
+	) -> Result<Route, RouteError> {
+		let mut nodes = VecDeque::from([(orgn, false, 0), (orgn, true, 0)]);
+		let mut visited = HashSet::from([(orgn, false), (orgn, true)]);
+		let mut chain = HashMap::new();
+		let mut list: Option<Vec<(usize, bool)>> = None;
+		let mut revisited = HashSet::new();
+
+		while let Some((node, direction, distance)) = nodes.pop_front() {
+			let condition = profile.nodes[node];
+			if condition == (NodeCondition::Fixed { state: true }) {
+				continue
+			}
+
+			let transparent = condition == NodeCondition::Fixed { state: false };
+
+			if node == dest {
+				if list.is_some() {
+					return Err(RouteError::Ambiguous)
+				}
+
+				let list = list.insert(Vec::new());
+				let mut prev = Some((node, direction));
+				while let Some(item) = prev {
+					list.push(item);
+					prev = chain.get(&item).copied();
+				}
+
+				if distance > 1 {
+					continue
+				} else {
+					break
+				}
+			}
+
+			let block = self.blocks[node][direction as usize];
+			for (next_node, next_dir) in &self.conns[node][direction as usize] {
+				if !is_open(block, (node, *next_node)) {
+					continue
+				}
+
+				let next_key = (*next_node, !next_dir);
+				let next = (*next_node, !next_dir, distance + !transparent as usize);
+
+				if visited.insert(next_key) {
+					chain.insert(next_key, (node, direction));
+					if transparent {
+						nodes.push_front(next);
+					} else {
+						nodes.push_back(next);
+					}
+				} else {
+					revisited.insert(next_key);
+				}
+			}
+		}
+
+		let list = list.ok_or(RouteError::Unreachable)?;
+		if list[..list.len() - 1]
+			.iter()
+			.any(|key| revisited.contains(key))
+		{
+			return Err(RouteError::Ambiguous)
+		}
+
+		Ok(
+			list
+				.windows(2)
+				.map(|pair| {
+					let [(node2, _), (node1, direction1)] = pair else {
+						unreachable!()
+					};
+
+					(self.blocks[*node1][*direction1 as usize], (*node1, *node2))
+				})
+				.collect(),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bars_config::{Node, ResetCondition};
+
+	use fastrand::Rng;
+
+	const CASES: usize = 2000;
+
+	fn aerodrome(nodes: usize, blocks: Vec<Vec<usize>>) -> Aerodrome {
+		Aerodrome {
+			icao: "TEST".into(),
+			elements: Vec::new(),
+			nodes: (0..nodes)
+				.map(|i| Node {
+					id: format!("N{i}"),
+					scratchpad: None,
+					parent: None,
+					reset_on_crossing: false,
+					gang: false,
+					display: Default::default(),
+				})
+				.collect(),
+			edges: Vec::new(),
+			blocks: blocks
+				.into_iter()
+				.enumerate()
+				.map(|(i, nodes)| Block {
+					id: format!("B{i}"),
+					nodes,
+					edges: Vec::new(),
+					non_routes: Vec::new(),
+					stands: Vec::new(),
+					display: Default::default(),
+				})
+				.collect(),
+			closures: Vec::new(),
+			profiles: Vec::new(),
+			maps: Vec::new(),
+			views: Vec::new(),
+			styles: Vec::new(),
+			rasters: Vec::new(),
+		}
+	}
+
+	fn profile(nodes: Vec<NodeCondition>) -> Profile {
+		Profile {
+			id: "test".into(),
+			name: "Test".into(),
+			nodes,
+			edges: Vec::new(),
+			blocks: Vec::new(),
+			presets: Vec::new(),
+			unattended: None,
+			runways: Vec::new(),
+		}
+	}
+
+	fn random(rng: &mut Rng) -> (Aerodrome, Profile) {
+		let len = rng.usize(2..12);
+
+		let blocks = (0..rng.usize(1..10))
+			.map(|_| {
+				let mut nodes = (0..rng.usize(2..5))
+					.map(|_| rng.usize(..len))
+					.collect::<Vec<_>>();
+				nodes.sort_unstable();
+				nodes.dedup();
+				nodes
+			})
+			.collect::<Vec<_>>();
+		let mut aerodrome = aerodrome(len, blocks);
+
+		for block in &mut aerodrome.blocks {
+			if block.nodes.len() > 2 && rng.u8(..4) == 0 {
+				block.non_routes.push((block.nodes[0], block.nodes[1]));
+			}
+		}
+
+		let nodes = (0..len)
+			.map(|_| match rng.u8(..8) {
+				0..4 => NodeCondition::Router,
+				4 | 5 => NodeCondition::Fixed { state: false },
+				6 => NodeCondition::Fixed { state: true },
+				_ => NodeCondition::Direct {
+					reset: ResetCondition::None,
+				},
+			})
+			.collect();
+
+		(aerodrome, profile(nodes))
+	}
+
+	fn routers(profile: &Profile) -> Vec<usize> {
+		(0..profile.nodes.len())
+			.filter(|node| profile.nodes[*node] == NodeCondition::Router)
+			.collect()
+	}
+
+	// the blocks of a route, in order from its origin
+	fn blocks(route: &Route) -> Vec<usize> {
+		route.iter().rev().map(|(block, _)| *block).collect()
+	}
+
+	#[test]
+	fn reverse_route_can_differ() {
+		// two equally direct routes between the routers, one through a
+		// transparent node, of which each direction finds a different one
+		let aerodrome = aerodrome(3, vec![vec![1, 2], vec![0, 1], vec![0, 2]]);
+		let profile = profile(vec![
+			NodeCondition::Router,
+			NodeCondition::Router,
+			NodeCondition::Fixed { state: false },
+		]);
+		let graph = Graph::new(&aerodrome);
+
+		let forward = graph.find_route(&profile, (0, 1), |_, _| true).unwrap();
+		let reverse = graph.find_route(&profile, (1, 0), |_, _| true).unwrap();
+		assert_eq!(blocks(&forward), [1]);
+		assert_eq!(blocks(&reverse), [0, 2]);
+	}
+
+	#[test]
+	fn routes_end_at_router_nodes() {
+		let aerodrome = aerodrome(3, vec![vec![0, 1], vec![1, 2]]);
+		let profile = profile(vec![
+			NodeCondition::Router,
+			NodeCondition::Router,
+			NodeCondition::Fixed { state: false },
+		]);
+		let graph = Graph::new(&aerodrome);
+
+		assert!(graph.route(&profile, (0, 2), |_, _| true).is_ok());
+		for ends in [(0, 2), (2, 0)] {
+			assert_eq!(
+				graph.find_route(&profile, ends, |_, _| true),
+				Err(RouteError::Unreachable),
+			);
+		}
+	}
+
+	#[test]
+	fn routes_pass_open_blocks_between_their_ends() {
+		let mut rng = Rng::with_seed(0x4891);
+
+		for _ in 0..CASES {
+			let (aerodrome, profile) = random(&mut rng);
+			let graph = Graph::new(&aerodrome);
+			let routers = routers(&profile);
+
+			for orgn in routers.iter().copied() {
+				for dest in routers.iter().copied().filter(|dest| *dest != orgn) {
+					let closed = rng.usize(..aerodrome.blocks.len());
+					let Ok(route) =
+						graph.route(&profile, (orgn, dest), |block, _| block != closed)
+					else {
+						continue
+					};
+
+					assert!(!blocks(&route).contains(&closed));
+
+					// each segment starts where the last one ended, within its block
+					let mut at = orgn;
+					for (block, (a, b)) in route.iter().rev() {
+						assert_eq!(*a, at);
+						assert!(aerodrome.blocks[*block].nodes.contains(a));
+						assert!(aerodrome.blocks[*block].nodes.contains(b));
+						at = *b;
+					}
+					assert_eq!(at, dest);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn cycle_is_ambiguous() {
+		// four blocks in a ring, with routers at opposite corners
+		let aerodrome =
+			aerodrome(4, vec![vec![0, 1], vec![1, 2], vec![2, 3], vec![3, 0]]);
+		let direct = NodeCondition::Direct {
+			reset: ResetCondition::None,
+		};
+		let profile = profile(vec![
+			NodeCondition::Router,
+			direct,
+			NodeCondition::Router,
+			direct,
+		]);
+		let graph = Graph::new(&aerodrome);
+
+		for ends in [(0, 2), (2, 0)] {
+			assert_eq!(
+				graph.route(&profile, ends, |_, _| true),
+				Err(RouteError::Ambiguous),
+			);
+		}
+
+		// closing one side of the ring leaves the other
+		let route = graph
+			.route(&profile, (0, 2), |block, _| block != 3)
+			.unwrap();
+		assert_eq!(blocks(&route), [0, 1]);
+	}
+
+	#[test]
+	fn long_cycle_terminates() {
+		// a ring of transparent nodes far longer than any real aerodrome, through
+		// which the first route found is taken
+		let len = 5000;
+		let aerodrome =
+			aerodrome(len, (0..len).map(|i| vec![i, (i + 1) % len]).collect());
+		let mut nodes = vec![NodeCondition::Fixed { state: false }; len];
+		nodes[0] = NodeCondition::Router;
+		nodes[len / 2] = NodeCondition::Router;
+		let profile = profile(nodes);
+		let graph = Graph::new(&aerodrome);
+
+		let route = graph.route(&profile, (0, len / 2), |_, _| true).unwrap();
+		assert_eq!(route.len(), len / 2);
+
+		let route = graph
+			.route(&profile, (0, len / 2), |block, _| block != len - 1)
+			.unwrap();
+		assert_eq!(blocks(&route), (0..len / 2).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn overfull_node_joins_first_two_blocks() {
+		// node 0 borders three blocks, so is cut off from the last
+		let aerodrome = aerodrome(4, vec![vec![0, 1], vec![0, 2], vec![0, 3]]);
+		let profile = profile(vec![NodeCondition::Router; 4]);
+		let graph = Graph::new(&aerodrome);
+
+		assert_eq!(graph.overfull(), [0]);
+		assert_eq!(graph.blocks(0), [0, 1]);
+		assert_eq!(graph.neighbours(&profile, 0), [1, 2]);
+
+		assert_eq!(
+			blocks(&graph.route(&profile, (1, 2), |_, _| true).unwrap()),
+			[0, 1]
+		);
+		for ends in [(0, 3), (3, 0), (1, 3)] {
+			assert_eq!(
+				graph.route(&profile, ends, |_, _| true),
+				Err(RouteError::Unreachable),
+			);
+		}
+	}
+}
@@ -0,0 +1,472 @@
+use bars_config::{Aerodrome, EdgeCondition, NodeCondition, Profile};
+
+use bars_route::{Graph, Route, RouteError};
+
+// a route which would fail at runtime, with nodes, blocks and profiles by
+// index and routes as (origin, destination)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+	Overfull {
+		node: usize,
+	},
+	NotMember {
+		profile: usize,
+		node: usize,
+	},
+	Unconnected {
+		profile: usize,
+		node: usize,
+	},
+	Unlit {
+		profile: usize,
+		route: (usize, usize),
+		block: usize,
+	},
+	Unreachable {
+		profile: usize,
+		route: (usize, usize),
+	},
+	Ambiguous {
+		profile: usize,
+		route: (usize, usize),
+	},
+}
+
+impl Diagnostic {
+	pub fn message(&self, aerodrome: &Aerodrome) -> String {
+		let node = |node: usize| &aerodrome.nodes[node].id;
+		let profile = |profile: usize| &aerodrome.profiles[profile].id;
+
+		match *self {
+			Self::Overfull { node: n } => {
+				format!("node {} borders more than two blocks", node(n))
+			},
+			Self::NotMember {
+				profile: p,
+				node: n,
+			} => format!(
+				"profile {}: router node {} is not a member of any block",
+				profile(p),
+				node(n),
+			),
+			Self::Unconnected {
+				profile: p,
+				node: n,
+			} => format!(
+				"profile {}: router node {} cannot reach any router node",
+				profile(p),
+				node(n),
+			),
+			Self::Unlit {
+				profile: p,
+				route: (a, b),
+				block,
+			} => format!(
+				"profile {}: route {}-{} lights no edges in block {}",
+				profile(p),
+				node(a),
+				node(b),
+				aerodrome.blocks[block].id,
+			),
+			Self::Unreachable {
+				profile: p,
+				route: (a, b),
+			} => format!(
+				"profile {}: route {}-{} cannot be set",
+				profile(p),
+				node(a),
+				node(b),
+			),
+			Self::Ambiguous {
+				profile: p,
+				route: (a, b),
+			} => format!(
+				"profile {}: route {}-{} is ambiguous",
+				profile(p),
+				node(a),
+				node(b),
+			),
+		}
+	}
+}
+
+// checks that routes between router nodes can be set by the client in each
+// profile, returning any which would fail at runtime
+pub fn check_routes(aerodrome: &Aerodrome) -> Vec<Diagnostic> {
+	let graph = Graph::new(aerodrome);
+	let mut diagnostics = graph
+		.overfull()
+		.iter()
+		.map(|node| Diagnostic::Overfull { node: *node })
+		.collect::<Vec<_>>();
+
+	for (p, profile) in aerodrome.profiles.iter().enumerate() {
+		let routers = (0..aerodrome.nodes.len())
+			.filter(|node| profile.nodes[*node] == NodeCondition::Router)
+			.collect::<Vec<_>>();
+
+		for orgn in routers.iter().copied() {
+			if !graph.is_member(orgn) {
+				diagnostics.push(Diagnostic::NotMember {
+					profile: p,
+					node: orgn,
+				});
+				continue
+			}
+
+			let neighbours = graph.neighbours(profile, orgn);
+			if neighbours.is_empty() {
+				diagnostics.push(Diagnostic::Unconnected {
+					profile: p,
+					node: orgn,
+				});
+				continue
+			}
+
+			for dest in routers.iter().copied().filter(|dest| *dest > orgn) {
+				let forward = graph.find_route(profile, (orgn, dest), |_, _| true);
+				// the client routes from whichever node is selected first, which
+				// finds a different route where two are equally direct
+				let reverse = graph.find_route(profile, (dest, orgn), |_, _| true);
+
+				// routes can pass router nodes to reach further ones, but are only
+				// expected to reach neighbouring ones
+				let unreachable = Err(RouteError::Unreachable);
+				if !neighbours.contains(&dest)
+					&& forward == unreachable
+					&& reverse == unreachable
+				{
+					continue
+				}
+				let same = match (&forward, &reverse) {
+					(Ok(forward), Ok(reverse)) => forward
+						.iter()
+						.rev()
+						.map(|(block, _)| block)
+						.eq(reverse.iter().map(|(block, _)| block)),
+					(forward, reverse) => forward == reverse,
+				};
+
+				check_route(
+					aerodrome,
+					&graph,
+					p,
+					(orgn, dest),
+					forward,
+					&mut diagnostics,
+				);
+				if !same {
+					check_route(
+						aerodrome,
+						&graph,
+						p,
+						(dest, orgn),
+						reverse,
+						&mut diagnostics,
+					);
+				}
+			}
+		}
+	}
+
+	diagnostics
+}
+
+// reports a route between two router nodes which cannot be set, or which
+// does not light every block it passes
+fn check_route(
+	aerodrome: &Aerodrome,
+	graph: &Graph,
+	profile: usize,
+	ends: (usize, usize),
+	route: Result<Route, RouteError>,
+	diagnostics: &mut Vec<Diagnostic>,
+) {
+	match route {
+		Ok(route) => {
+			for (block, pair) in route.into_iter().rev() {
+				let profile_ = &aerodrome.profiles[profile];
+				if !is_lit(aerodrome, graph, profile_, block, pair) {
+					diagnostics.push(Diagnostic::Unlit {
+						profile,
+						route: ends,
+						block,
+					});
+				}
+			}
+		},
+		Err(RouteError::Unreachable) => {
+			diagnostics.push(Diagnostic::Unreachable {
+				profile,
+				route: ends,
+			});
+		},
+		Err(RouteError::Ambiguous) => {
+			diagnostics.push(Diagnostic::Ambiguous {
+				profile,
+				route: ends,
+			});
+		},
+	}
+}
+
+// whether setting a route through a block lights any of its edges
+fn is_lit(
+	aerodrome: &Aerodrome,
+	graph: &Graph,
+	profile: &Profile,
+	block: usize,
+	route: (usize, usize),
+) -> bool {
+	let candidates = graph.candidates(&aerodrome.blocks[block], route);
+
+	profile.edges.iter().any(|edge| match edge {
+		EdgeCondition::Router {
+			block: edge_block,
+			routes,
+		} if *edge_block == block => candidates
+			.iter()
+			.any(|(a, b)| routes.contains(&(*a, *b)) || routes.contains(&(*b, *a))),
+		_ => false,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bars_config::{Block, Node, ResetCondition};
+
+	use fastrand::Rng;
+
+	fn aerodrome(
+		nodes: usize,
+		blocks: Vec<Vec<usize>>,
+		profile: Vec<NodeCondition>,
+	) -> Aerodrome {
+		Aerodrome {
+			icao: "TEST".into(),
+			elements: Vec::new(),
+			nodes: (0..nodes)
+				.map(|i| Node {
+					id: format!("N{i}"),
+					scratchpad: None,
+					parent: None,
+					reset_on_crossing: false,
+					gang: false,
+					display: Default::default(),
+				})
+				.collect(),
+			edges: Vec::new(),
+			blocks: blocks
+				.into_iter()
+				.enumerate()
+				.map(|(i, nodes)| Block {
+					id: format!("B{i}"),
+					nodes,
+					edges: Vec::new(),
+					non_routes: Vec::new(),
+					stands: Vec::new(),
+					display: Default::default(),
+				})
+				.collect(),
+			closures: Vec::new(),
+			profiles: vec![Profile {
+				id: "test".into(),
+				name: "Test".into(),
+				nodes: profile,
+				edges: Vec::new(),
+				blocks: Vec::new(),
+				presets: Vec::new(),
+				unattended: None,
+				runways: Vec::new(),
+			}],
+			maps: Vec::new(),
+			views: Vec::new(),
+			styles: Vec::new(),
+			rasters: Vec::new(),
+		}
+	}
+
+	// an aerodrome of a few nodes in overlapping blocks, with one profile in
+	// which some routes light edges
+	fn random(rng: &mut Rng) -> Aerodrome {
+		let len = rng.usize(2..12);
+
+		let blocks = (0..rng.usize(1..10))
+			.map(|_| {
+				let mut nodes = (0..rng.usize(2..5))
+					.map(|_| rng.usize(..len))
+					.collect::<Vec<_>>();
+				nodes.sort_unstable();
+				nodes.dedup();
+				nodes
+			})
+			.collect::<Vec<_>>();
+
+		let nodes = (0..len)
+			.map(|_| match rng.u8(..8) {
+				0..4 => NodeCondition::Router,
+				4 | 5 => NodeCondition::Fixed { state: false },
+				6 => NodeCondition::Fixed { state: true },
+				_ => NodeCondition::Direct {
+					reset: ResetCondition::None,
+				},
+			})
+			.collect();
+
+		let mut aerodrome = aerodrome(len, blocks, nodes);
+
+		for (i, block) in aerodrome.blocks.iter_mut().enumerate() {
+			if block.nodes.len() > 2 && rng.u8(..4) == 0 {
+				block.non_routes.push((block.nodes[0], block.nodes[1]));
+			}
+
+			let routes = block
+				.nodes
+				.iter()
+				.flat_map(|a| block.nodes.iter().map(move |b| (*a, *b)))
+				.filter(|(a, b)| a < b && rng.bool())
+				.collect::<Vec<_>>();
+			aerodrome.profiles[0]
+				.edges
+				.push(EdgeCondition::Router { block: i, routes });
+		}
+
+		aerodrome
+	}
+
+	#[test]
+	fn client_routes_are_checked() {
+		let mut rng = Rng::with_seed(0x4890);
+
+		for _ in 0..2000 {
+			let aerodrome = random(&mut rng);
+			let profile = &aerodrome.profiles[0];
+			let graph = Graph::new(&aerodrome);
+			let diagnostics = check_routes(&aerodrome);
+			let has = |diagnostic| diagnostics.contains(&diagnostic);
+
+			// diagnostics for a route, or for its reverse where that is the same
+			let checked = |ends: (usize, usize), reverse: bool| {
+				diagnostics
+					.iter()
+					.filter(move |diagnostic| match diagnostic {
+						Diagnostic::Unlit { route, .. }
+						| Diagnostic::Unreachable { route, .. }
+						| Diagnostic::Ambiguous { route, .. } => {
+							*route == ends || (reverse && *route == (ends.1, ends.0))
+						},
+						_ => false,
+					})
+			};
+
+			for orgn in 0..aerodrome.nodes.len() {
+				// the client only routes from router nodes
+				let router = profile.nodes[orgn] == NodeCondition::Router;
+				let neighbours = match router {
+					true => graph.neighbours(profile, orgn),
+					false => Vec::new(),
+				};
+
+				if router && !graph.is_member(orgn) {
+					assert!(has(Diagnostic::NotMember {
+						profile: 0,
+						node: orgn
+					}));
+				} else if router && neighbours.is_empty() {
+					assert!(has(Diagnostic::Unconnected {
+						profile: 0,
+						node: orgn
+					}));
+				}
+
+				for dest in 0..aerodrome.nodes.len() {
+					if dest == orgn {
+						continue
+					}
+
+					let route = graph.find_route(profile, (orgn, dest), |_, _| true);
+
+					match route {
+						Ok(route) => {
+							assert!(
+								checked((orgn, dest), false).all(|diagnostic| matches!(
+									diagnostic,
+									Diagnostic::Unlit { block, .. }
+										if route.iter().any(|(b, _)| b == block)
+								)),
+								"route {orgn}-{dest} can be set: {diagnostics:?}"
+							);
+
+							for (block, pair) in &route {
+								if !is_lit(&aerodrome, &graph, profile, *block, *pair) {
+									assert!(
+										checked((orgn, dest), true).any(|diagnostic| matches!(
+											diagnostic,
+											Diagnostic::Unlit { block: b, .. } if b == block
+										)),
+										"route {orgn}-{dest} not checked: {aerodrome:?}"
+									);
+								}
+							}
+						},
+						// routers which are not neighbours are not expected to be
+						// reachable
+						Err(RouteError::Unreachable) if !neighbours.contains(&dest) => (),
+						Err(RouteError::Unreachable) => assert!(
+							checked((orgn, dest), true).any(|diagnostic| matches!(
+								diagnostic,
+								Diagnostic::Unreachable { .. }
+							)),
+							"route {orgn}-{dest} cannot be set: {diagnostics:?}"
+						),
+						Err(RouteError::Ambiguous) => assert!(
+							checked((orgn, dest), true).any(|diagnostic| matches!(
+								diagnostic,
+								Diagnostic::Ambiguous { .. }
+							)),
+							"route {orgn}-{dest} is ambiguous: {diagnostics:?}"
+						),
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn reverse_route_is_checked() {
+		// two equally direct routes between the routers, one through a
+		// transparent node, of which each direction finds a different one
+		let aerodrome = aerodrome(
+			3,
+			vec![vec![1, 2], vec![0, 1], vec![0, 2]],
+			vec![
+				NodeCondition::Router,
+				NodeCondition::Router,
+				NodeCondition::Fixed { state: false },
+			],
+		);
+
+		assert_eq!(
+			check_routes(&aerodrome),
+			[
+				Diagnostic::Unlit {
+					profile: 0,
+					route: (0, 1),
+					block: 1,
+				},
+				Diagnostic::Unlit {
+					profile: 0,
+					route: (1, 0),
+					block: 0,
+				},
+				Diagnostic::Unlit {
+					profile: 0,
+					route: (1, 0),
+					block: 2,
+				},
+			]
+		);
+	}
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use reqwest::blocking::Client;
+
+use serde::Deserialize;
+
+static API_URL: &str = "https://api.figma.com/v1";
+static TOKEN_VAR: &str = "FIGMA_TOKEN";
+
+#[derive(Deserialize)]
+struct File {
+	name: String,
+	document: Node,
+}
+
+#[derive(Deserialize)]
+struct Node {
+	id: String,
+	name: String,
+	#[serde(rename = "type")]
+	kind: String,
+	#[serde(default)]
+	children: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Images {
+	err: Option<String>,
+	images: HashMap<String, Option<String>>,
+}
+
+// exports each top-level frame of a Figma file as `{frame name}.svg` in `dir`,
+// keeping layer names as element IDs so that the export can be used as a map
+pub fn run(key: &str, dir: &Path) -> Result<()> {
+	let token = std::env::var(TOKEN_VAR).with_context(|| {
+		format!("{TOKEN_VAR} must be set to a Figma access token")
+	})?;
+
+	let client = Client::new();
+	let get = |url: &str| {
+		client
+			.get(url)
+			.header("X-Figma-Token", &token)
+			.send()
+			.and_then(|response| response.error_for_status())
+			.with_context(|| format!("failed to fetch {url}"))
+	};
+
+	let file = get(&format!("{API_URL}/files/{key}?depth=2"))?
+		.json::<File>()
+		.context("invalid file response")?;
+
+	let frames = file
+		.document
+		.children
+		.iter()
+		.flat_map(|page| &page.children)
+		.filter(|node| node.kind == "FRAME")
+		.collect::<Vec<_>>();
+
+	if frames.is_empty() {
+		bail!("{} has no top-level frames", file.name);
+	}
+
+	let ids = frames
+		.iter()
+		.map(|frame| frame.id.as_str())
+		.collect::<Vec<_>>()
+		.join(",");
+	let images = get(&format!(
+		"{API_URL}/images/{key}?ids={ids}&format=svg&svg_include_id=true\
+		 &svg_outline_text=true"
+	))?
+	.json::<Images>()
+	.context("invalid image response")?;
+
+	if let Some(err) = images.err {
+		bail!("failed to export {}: {err}", file.name);
+	}
+
+	std::fs::create_dir_all(dir)
+		.with_context(|| format!("failed to create {}", dir.display()))?;
+
+	for frame in frames {
+		let Some(Some(url)) = images.images.get(&frame.id) else {
+			eprintln!("warning: frame {:?} was not exported", frame.name);
+			continue
+		};
+
+		let svg = get(url)?.bytes()?;
+		let path = dir.join(format!("{}.svg", file_name(&frame.name)));
+		std::fs::write(&path, svg)
+			.with_context(|| format!("failed to write {}", path.display()))?;
+
+		eprintln!("exported {:?} to {}", frame.name, path.display());
+	}
+
+	Ok(())
+}
+
+// frame names are free text, so replace anything that cannot appear in a
+// file name on Windows
+fn file_name(name: &str) -> String {
+	name
+		.trim()
+		.chars()
+		.map(|c| match c {
+			'<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+			c if c.is_control() => '_',
+			c => c,
+		})
+		.collect()
+}
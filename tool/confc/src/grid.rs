@@ -0,0 +1,244 @@
+use schemars::JsonSchema;
+
+use serde::Deserialize;
+
+/// Placement of an SVG drawn against a projected or local grid.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+pub struct Grid {
+	pub crs: Crs,
+	/// grid coordinates (easting, northing) of the SVG origin
+	pub origin: (f64, f64),
+	/// grid units (metres) per SVG unit
+	#[serde(default = "one")]
+	pub scale: f64,
+	/// clockwise rotation of grid north from SVG up, in degrees
+	#[serde(default)]
+	pub rotation: f64,
+	/// ellipsoid of the grid's datum
+	#[serde(default)]
+	pub ellipsoid: Ellipsoid,
+	/// transformation from the grid's datum to WGS84, if they differ
+	pub datum: Option<Helmert>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Crs {
+	/// Universal Transverse Mercator
+	Utm {
+		zone: u8,
+		#[serde(default)]
+		south: bool,
+	},
+	/// metres east and north of a reference point
+	Local { lat: f64, lon: f64 },
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ellipsoid {
+	#[default]
+	Wgs84,
+	Grs80,
+	International1924,
+	Airy1830,
+	Bessel1841,
+	Clarke1866,
+}
+
+/// Seven-parameter (position vector) Helmert transformation to WGS84.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+pub struct Helmert {
+	/// translation in metres
+	pub tx: f64,
+	pub ty: f64,
+	pub tz: f64,
+	/// rotation in arc-seconds
+	pub rx: f64,
+	pub ry: f64,
+	pub rz: f64,
+	/// scale in parts per million
+	pub s: f64,
+}
+
+fn one() -> f64 {
+	1.0
+}
+
+const UTM_SCALE: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+impl Ellipsoid {
+	// semi-major axis and flattening
+	fn parameters(self) -> (f64, f64) {
+		match self {
+			Self::Wgs84 => (6_378_137.0, 1.0 / 298.257_223_563),
+			Self::Grs80 => (6_378_137.0, 1.0 / 298.257_222_101),
+			Self::International1924 => (6_378_388.0, 1.0 / 297.0),
+			Self::Airy1830 => (6_377_563.396, 1.0 / 299.324_964_6),
+			Self::Bessel1841 => (6_377_397.155, 1.0 / 299.152_812_8),
+			Self::Clarke1866 => (6_378_206.4, 1.0 / 294.978_698_2),
+		}
+	}
+
+	fn e2(self) -> f64 {
+		let (_, f) = self.parameters();
+		f * (2.0 - f)
+	}
+}
+
+impl Grid {
+	/// Converts a point in SVG units to WGS84 latitude and longitude in degrees.
+	pub fn project(&self, (x, y): (f64, f64)) -> (f64, f64) {
+		let (sin, cos) = self.rotation.to_radians().sin_cos();
+		let (u, v) = (x * self.scale, -y * self.scale);
+
+		let easting = self.origin.0 + u * cos - v * sin;
+		let northing = self.origin.1 + u * sin + v * cos;
+
+		let (lat, lon) = match self.crs {
+			Crs::Utm { zone, south } => {
+				let northing = if south {
+					northing - UTM_FALSE_NORTHING_SOUTH
+				} else {
+					northing
+				};
+				let central = (zone as f64 * 6.0 - 183.0).to_radians();
+
+				inverse_tm(
+					self.ellipsoid,
+					central,
+					(easting - UTM_FALSE_EASTING) / UTM_SCALE,
+					northing / UTM_SCALE,
+				)
+			},
+			Crs::Local { lat, lon } => local(
+				self.ellipsoid,
+				lat.to_radians(),
+				lon.to_radians(),
+				easting,
+				northing,
+			),
+		};
+
+		let (lat, lon) = match self.datum {
+			Some(helmert) => helmert.apply(self.ellipsoid, lat, lon),
+			None => (lat, lon),
+		};
+
+		(lat.to_degrees(), lon.to_degrees())
+	}
+}
+
+// inverse transverse Mercator projection (Snyder, 1987) from unscaled
+// coordinates relative to the central meridian, giving radians
+fn inverse_tm(
+	ellipsoid: Ellipsoid,
+	central: f64,
+	x: f64,
+	y: f64,
+) -> (f64, f64) {
+	let (a, _) = ellipsoid.parameters();
+	let e2 = ellipsoid.e2();
+	let ep2 = e2 / (1.0 - e2);
+
+	let mu = y
+		/ (a * (1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2.powi(3) / 256.0));
+	let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+
+	let phi1 = mu
+		+ (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+		+ (21.0 * e1 * e1 / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+		+ (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+		+ (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+	let (sin1, cos1) = phi1.sin_cos();
+	let tan1 = phi1.tan();
+	let c1 = ep2 * cos1 * cos1;
+	let t1 = tan1 * tan1;
+	let n1 = a / (1.0 - e2 * sin1 * sin1).sqrt();
+	let r1 = a * (1.0 - e2) / (1.0 - e2 * sin1 * sin1).powf(1.5);
+	let d = x / n1;
+
+	let lat = phi1
+		- (n1 * tan1 / r1)
+			* (d * d / 2.0
+				- (5.0 + 3.0 * t1 + 10.0 * c1 - 4.0 * c1 * c1 - 9.0 * ep2) * d.powi(4)
+					/ 24.0
+				+ (61.0 + 90.0 * t1 + 298.0 * c1 + 45.0 * t1 * t1
+					- 252.0 * ep2
+					- 3.0 * c1 * c1)
+					* d.powi(6)
+					/ 720.0);
+	let lon = central
+		+ (d - (1.0 + 2.0 * t1 + c1) * d.powi(3) / 6.0
+			+ (5.0 - 2.0 * c1 + 28.0 * t1 - 3.0 * c1 * c1
+				+ 8.0 * ep2
+				+ 24.0 * t1 * t1)
+				* d.powi(5)
+				/ 120.0)
+			/ cos1;
+
+	(lat, lon)
+}
+
+// local tangent plane approximation, adequate over the extent of an aerodrome
+fn local(
+	ellipsoid: Ellipsoid,
+	lat: f64,
+	lon: f64,
+	east: f64,
+	north: f64,
+) -> (f64, f64) {
+	let (a, _) = ellipsoid.parameters();
+	let e2 = ellipsoid.e2();
+
+	let w2 = 1.0 - e2 * lat.sin().powi(2);
+	let meridional = a * (1.0 - e2) / w2.powf(1.5);
+	let normal = a / w2.sqrt();
+
+	(lat + north / meridional, lon + east / (normal * lat.cos()))
+}
+
+impl Helmert {
+	fn apply(&self, ellipsoid: Ellipsoid, lat: f64, lon: f64) -> (f64, f64) {
+		let (x, y, z) = to_cartesian(ellipsoid, lat, lon);
+
+		let s = 1.0 + self.s * 1e-6;
+		let [rx, ry, rz] =
+			[self.rx, self.ry, self.rz].map(|r| (r / 3600.0).to_radians());
+
+		let x2 = self.tx + s * (x - rz * y + ry * z);
+		let y2 = self.ty + s * (rz * x + y - rx * z);
+		let z2 = self.tz + s * (-ry * x + rx * y + z);
+
+		from_cartesian(Ellipsoid::Wgs84, x2, y2, z2)
+	}
+}
+
+fn to_cartesian(ellipsoid: Ellipsoid, lat: f64, lon: f64) -> (f64, f64, f64) {
+	let (a, _) = ellipsoid.parameters();
+	let e2 = ellipsoid.e2();
+	let n = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+
+	(
+		n * lat.cos() * lon.cos(),
+		n * lat.cos() * lon.sin(),
+		n * (1.0 - e2) * lat.sin(),
+	)
+}
+
+fn from_cartesian(ellipsoid: Ellipsoid, x: f64, y: f64, z: f64) -> (f64, f64) {
+	let (a, _) = ellipsoid.parameters();
+	let e2 = ellipsoid.e2();
+	let p = x.hypot(y);
+
+	let mut lat = z.atan2(p * (1.0 - e2));
+	for _ in 0..5 {
+		let n = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+		lat = (z + e2 * n * lat.sin()).atan2(p);
+	}
+
+	(lat, y.atan2(x))
+}
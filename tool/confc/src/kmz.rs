@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use bars_config::Raster;
+
+use kml::types::Element;
+use kml::{Kml as KmlItem, KmlReader};
+
+use zip::ZipArchive;
+
+static ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+// formats which GDI+ decodes on every supported system
+static IMAGE_MAGIC: &[&[u8]] = &[b"\x89PNG", b"\xff\xd8\xff", b"GIF8", b"BM"];
+
+// reads a KML or KMZ document, replacing NetworkLinks to local files with
+// folders containing the linked document; links are resolved against the
+// containing archive first, then the filesystem; every file read is appended
+// to `deps`; GroundOverlays are taken out of the document and returned as
+// rasters
+pub fn load(
+	path: &Path,
+	deps: &mut Vec<PathBuf>,
+) -> Result<(KmlItem<f32>, Vec<Raster>)> {
+	let mut loader = Loader::default();
+	let res = loader.load_path(path);
+	deps.append(&mut loader.deps);
+
+	Ok((res?, loader.rasters))
+}
+
+type Archive = HashMap<String, Vec<u8>>;
+
+struct Location<'a> {
+	dir: &'a Path,
+	archive: Option<(&'a Archive, &'a str)>,
+}
+
+#[derive(Default)]
+struct Loader {
+	stack: Vec<String>,
+	deps: Vec<PathBuf>,
+	rasters: Vec<Raster>,
+}
+
+impl Loader {
+	fn load_path(&mut self, path: &Path) -> Result<KmlItem<f32>> {
+		let canonical = path
+			.canonicalize()
+			.with_context(|| format!("failed to resolve {}", path.display()))?;
+		let data = std::fs::read(path)
+			.with_context(|| format!("failed to read {}", path.display()))?;
+		self.deps.push(path.to_path_buf());
+		let dir = path.parent().unwrap_or(Path::new(""));
+
+		self
+			.load_data(canonical.display().to_string(), data, dir)
+			.with_context(|| format!("in {}", path.display()))
+	}
+
+	fn load_data(
+		&mut self,
+		key: String,
+		data: Vec<u8>,
+		dir: &Path,
+	) -> Result<KmlItem<f32>> {
+		if self.stack.contains(&key) {
+			bail!("recursive network link to {key}");
+		}
+
+		self.stack.push(key);
+		let res = if data.starts_with(ZIP_MAGIC) {
+			let archive = read_archive(data)?;
+			let root = archive
+				.keys()
+				.filter(|name| name.to_ascii_lowercase().ends_with(".kml"))
+				.min_by_key(|name| (*name != "doc.kml", name.as_str()))
+				.context("archive contains no KML document")?;
+
+			let location = Location {
+				dir,
+				archive: Some((&archive, parent(root))),
+			};
+			self.parse(&archive[root], &location)
+		} else {
+			self.parse(&data, &Location { dir, archive: None })
+		};
+		self.stack.pop();
+
+		res
+	}
+
+	fn parse(
+		&mut self,
+		data: &[u8],
+		location: &Location,
+	) -> Result<KmlItem<f32>> {
+		let mut kml = KmlReader::<_, f32>::from_reader(Cursor::new(data)).read()?;
+
+		if let KmlItem::KmlDocument(document) = &mut kml {
+			self.resolve(&mut document.elements, location)?;
+		}
+
+		Ok(kml)
+	}
+
+	fn resolve(
+		&mut self,
+		items: &mut Vec<KmlItem<f32>>,
+		location: &Location,
+	) -> Result<()> {
+		for item in std::mem::take(items) {
+			match item {
+				KmlItem::KmlDocument(mut document) => {
+					self.resolve(&mut document.elements, location)?;
+					items.push(KmlItem::KmlDocument(document));
+				},
+				KmlItem::Document {
+					attrs,
+					mut elements,
+				} => {
+					self.resolve(&mut elements, location)?;
+					items.push(KmlItem::Document { attrs, elements });
+				},
+				KmlItem::Folder {
+					attrs,
+					mut elements,
+				} => {
+					self.resolve(&mut elements, location)?;
+					items.push(KmlItem::Folder { attrs, elements });
+				},
+				KmlItem::Element(element) if element.name == "NetworkLink" => {
+					if let Some(folder) = self.network_link(element, location)? {
+						items.push(folder);
+					}
+				},
+				KmlItem::Element(element) if element.name == "GroundOverlay" => {
+					self.ground_overlay(&element, location)?;
+				},
+				item => items.push(item),
+			}
+		}
+
+		Ok(())
+	}
+
+	fn network_link(
+		&mut self,
+		element: Element,
+		location: &Location,
+	) -> Result<Option<KmlItem<f32>>> {
+		let Some(href) = element
+			.children
+			.iter()
+			.find(|child| child.name == "Link" || child.name == "Url")
+			.and_then(|link| child(link, "href"))
+		else {
+			eprintln!("warning: network link has no href");
+			return Ok(None)
+		};
+
+		let href = href.strip_prefix("file://").unwrap_or(href);
+		if href.contains("://") {
+			eprintln!("warning: remote network link {href} ignored");
+			return Ok(None)
+		}
+
+		let kml = match location.archive.and_then(|(archive, base)| {
+			let name = join(base, href);
+			archive.get(&name).map(|data| (name, data))
+		}) {
+			Some((name, data)) => {
+				let key = format!("{}!{name}", self.stack.last().unwrap());
+				self
+					.load_data(key, data.clone(), location.dir)
+					.with_context(|| format!("in {name}"))?
+			},
+			None => self.load_path(&location.dir.join(href))?,
+		};
+
+		// keep the link's own name so that it can still identify a group
+		let mut elements = element
+			.children
+			.into_iter()
+			.filter(|child| child.name == "name")
+			.map(KmlItem::Element)
+			.collect::<Vec<_>>();
+
+		match kml {
+			KmlItem::KmlDocument(document) => elements.extend(document.elements),
+			kml => elements.push(kml),
+		}
+
+		Ok(Some(KmlItem::Folder {
+			attrs: element.attrs,
+			elements,
+		}))
+	}
+
+	fn ground_overlay(
+		&mut self,
+		element: &Element,
+		location: &Location,
+	) -> Result<()> {
+		let name = child(element, "name").unwrap_or_default();
+
+		let Some(href) = element
+			.children
+			.iter()
+			.find(|child| child.name == "Icon")
+			.and_then(|icon| child(icon, "href"))
+		else {
+			eprintln!("warning: ground overlay {name:?} has no image");
+			return Ok(())
+		};
+		let Some(bounds) = element
+			.children
+			.iter()
+			.find(|child| child.name == "LatLonBox")
+		else {
+			eprintln!("warning: ground overlay {name:?} has no LatLonBox");
+			return Ok(())
+		};
+
+		let href = href.strip_prefix("file://").unwrap_or(href);
+		if href.contains("://") {
+			eprintln!("warning: remote ground overlay image {href} ignored");
+			return Ok(())
+		}
+
+		let image = match location
+			.archive
+			.and_then(|(archive, base)| archive.get(&join(base, href)))
+		{
+			Some(data) => data.clone(),
+			None => {
+				let path = location.dir.join(href);
+				let data = std::fs::read(&path)
+					.with_context(|| format!("failed to read {}", path.display()))?;
+				self.deps.push(path);
+				data
+			},
+		};
+
+		if !IMAGE_MAGIC.iter().any(|magic| image.starts_with(magic)) {
+			eprintln!(
+				"warning: ground overlay {name:?} ignored; {href} is not a PNG, JPEG, \
+				 GIF or BMP image"
+			);
+			return Ok(())
+		}
+
+		let angle = |key: &str| -> Result<Option<f32>> {
+			child(bounds, key)
+				.map(|value| {
+					value.parse().with_context(|| {
+						format!("invalid {key} {value:?} in ground overlay {name:?}")
+					})
+				})
+				.transpose()
+		};
+		let edge = |key: &str| -> Result<f32> {
+			angle(key)?
+				.with_context(|| format!("ground overlay {name:?} has no {key} bound"))
+		};
+
+		// KML colours are aabbggrr
+		let opacity = match child(element, "color") {
+			Some(color) => u32::from_str_radix(color, 16)
+				.map(|color| (color >> 24) as u8)
+				.with_context(|| {
+					format!("invalid color {color:?} in ground overlay {name:?}")
+				})?,
+			None => 0xff,
+		};
+
+		self.rasters.push(Raster {
+			image,
+			north: edge("north")?,
+			south: edge("south")?,
+			east: edge("east")?,
+			west: edge("west")?,
+			rotation: angle("rotation")?.unwrap_or_default(),
+			opacity,
+		});
+
+		Ok(())
+	}
+}
+
+fn read_archive(data: Vec<u8>) -> Result<Archive> {
+	let mut zip = ZipArchive::new(Cursor::new(data))?;
+	let mut archive = Archive::new();
+
+	for i in 0..zip.len() {
+		let mut file = zip.by_index(i)?;
+		if file.is_dir() {
+			continue
+		}
+
+		let mut data = Vec::with_capacity(file.size() as usize);
+		std::io::copy(&mut file, &mut data)?;
+		archive.insert(file.name().to_string(), data);
+	}
+
+	Ok(archive)
+}
+
+fn child<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+	element
+		.children
+		.iter()
+		.find(|child| child.name == name)
+		.and_then(|child| child.content.as_deref())
+		.map(str::trim)
+}
+
+fn parent(name: &str) -> &str {
+	name.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn join(base: &str, href: &str) -> String {
+	let mut parts = base
+		.split('/')
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<_>>();
+
+	for part in href.split('/') {
+		match part {
+			"" | "." => (),
+			".." => {
+				parts.pop();
+			},
+			part => parts.push(part),
+		}
+	}
+
+	parts.join("/")
+}
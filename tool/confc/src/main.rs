@@ -1,26 +1,113 @@
+mod analysis;
+mod figma;
+mod grid;
+mod kmz;
+mod manifest;
 mod map;
+mod preview;
+mod stats;
+mod template;
 
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use bars_config::{self as lib, Config, Element};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-use kml::KmlReader;
+use schemars::JsonSchema;
 
 use serde::Deserialize;
 
 use usvg::Tree;
 
-/// Compile JSON files into a distributable BARS configuration package.
+const DEFAULT_TARGET_PADDING: f64 = 4.0;
+const DEFAULT_TARGET_PADDING_GEO: f64 = 5.0;
+const DEFAULT_VIEW_PADDING: f64 = 20.0;
+
+/// Compile aerodromes into a distributable BARS configuration package.
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[command(flatten)]
+	compile: CompileArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Build the package described by a manifest, reusing unchanged aerodromes.
+	Build {
+		/// path to the package manifest
+		#[arg(value_name = "FILE", default_value = "package.toml")]
+		manifest: PathBuf,
+
+		/// recompile every aerodrome, ignoring cached results
+		#[arg(short, long)]
+		force: bool,
+	},
+
+	/// Report per-aerodrome statistics for a compiled package.
+	Stats {
+		/// path to the package to inspect
+		#[arg(value_name = "FILE")]
+		file: PathBuf,
+	},
+
+	/// Render a view of a compiled package to a PNG image.
+	Preview {
+		/// path to the package to render
+		#[arg(value_name = "FILE")]
+		file: PathBuf,
+
+		/// write the image to FILE
+		#[arg(short, long, value_name = "FILE")]
+		output: PathBuf,
+
+		/// render aerodrome ICAO (default: first in package)
+		#[arg(short, long, value_name = "ICAO")]
+		aerodrome: Option<String>,
+
+		/// render the view named NAME (default: first view)
+		#[arg(long, value_name = "NAME")]
+		view: Option<String>,
+
+		/// use the profile with ID or name PROFILE (default: first profile)
+		#[arg(short, long, value_name = "PROFILE")]
+		profile: Option<String>,
+
+		/// apply the preset named NAME from the selected profile
+		#[arg(long, value_name = "NAME")]
+		preset: Option<String>,
+
+		/// image width in pixels
+		#[arg(long, value_name = "PX", default_value_t = 1280)]
+		width: u32,
+
+		/// image height in pixels
+		#[arg(long, value_name = "PX", default_value_t = 960)]
+		height: u32,
+	},
+
+	/// Print a JSON Schema describing the aerodrome input format.
+	Schema {
+		/// write output to FILE
+		#[arg(short, long, value_name = "FILE")]
+		output: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, clap::Args)]
+struct CompileArgs {
 	/// include NAME as the package name
 	#[arg(short = 'n', long, value_name = "NAME")]
 	pkg_name: Option<String>,
@@ -29,11 +116,27 @@ struct Args {
 	#[arg(short = 'v', long, value_name = "VERSION")]
 	pkg_version: Option<String>,
 
-	/// write output to FILE
+	/// write output to FILE (or directory, with --figma)
 	#[arg(short, long, value_name = "FILE")]
 	output: Option<PathBuf>,
 
-	/// paths to JSON files to process
+	/// export the frames of Figma file KEY as SVG, using FIGMA_TOKEN
+	#[arg(long, value_name = "KEY", conflicts_with = "files")]
+	figma: Option<String>,
+
+	/// pad generated schematic node targets by PX pixels
+	#[arg(long, value_name = "PX", default_value_t = DEFAULT_TARGET_PADDING)]
+	target_padding: f64,
+
+	/// pad generated geographic node targets by M metres
+	#[arg(long, value_name = "M", default_value_t = DEFAULT_TARGET_PADDING_GEO)]
+	target_padding_geo: f64,
+
+	/// pad views generated from `view-gen:` groups by PX pixels
+	#[arg(long, value_name = "PX", default_value_t = DEFAULT_VIEW_PADDING)]
+	view_padding: f64,
+
+	/// paths to JSON, YAML or TOML files to process
 	#[arg(value_name = "FILE")]
 	files: Vec<PathBuf>,
 }
@@ -41,190 +144,368 @@ struct Args {
 fn main() -> Result<()> {
 	let args = Args::parse();
 
-	let mut aerodromes = Vec::new();
+	match args.command {
+		Some(Command::Build { manifest, force }) => manifest::run(&manifest, force),
+		Some(Command::Stats { file }) => stats::run(&file),
+		Some(Command::Preview {
+			file,
+			output,
+			aerodrome,
+			view,
+			profile,
+			preset,
+			width,
+			height,
+		}) => preview::run(
+			&file,
+			&output,
+			preview::Options {
+				aerodrome: aerodrome.as_deref(),
+				view: view.as_deref(),
+				profile: profile.as_deref(),
+				preset: preset.as_deref(),
+				size: (width, height),
+			},
+		),
+		Some(Command::Schema { output }) => schema(output),
+		None => match &args.compile.figma {
+			Some(key) => figma::run(
+				key,
+				args.compile.output.as_deref().unwrap_or(Path::new(".")),
+			),
+			None => compile(args.compile),
+		},
+	}
+}
 
-	for file in args.files {
-		let dir = file.parent().unwrap();
+fn compile(args: CompileArgs) -> Result<()> {
+	let options = AerodromeOptions {
+		target_padding: args.target_padding,
+		target_padding_geo: args.target_padding_geo,
+		view_padding: args.view_padding,
+	};
 
-		let s = std::fs::read_to_string(&file)?;
-		let input = serde_json::from_str::<Aerodrome>(&s)?;
+	let aerodromes = args
+		.files
+		.iter()
+		.map(|file| Ok(compile_aerodrome(file, &options)?.0))
+		.collect::<Result<_>>()?;
 
-		let mut display = match input.display {
-			GeoMap::Geo(path) => {
-				let mut reader = KmlReader::<_, f32>::from_kmz_path(dir.join(path))?;
-				map::convert(map::Kml::new(reader.read()?).unwrap().input(), 0)
-			},
-			GeoMap::Flat { svg, lat, lon } => {
-				let s = std::fs::read_to_string(dir.join(svg))?;
-				let tree = Tree::from_str(&s, &Default::default())?;
-				map::convert(map::GeoSvg::new(&tree, lat, lon), 0)
-			},
-		};
-		let mut styles = display.styles;
+	let config = Config {
+		name: args.pkg_name,
+		version: args.pkg_version,
+		aerodromes,
+	};
 
-		let mut temp_maps = Vec::new();
-		for svg in input.maps {
-			let s = std::fs::read_to_string(dir.join(svg))?;
-			let tree = Tree::from_str(&s, &Default::default())?;
-			let mut map = map::convert(map::Svg::new(&tree), styles.len());
-			styles.append(&mut map.styles);
-			temp_maps.push(map);
-		}
+	if let Some(path) = args.output {
+		config.save(BufWriter::new(File::create(path)?))?;
+	} else {
+		config.save(std::io::stdout())?;
+	}
 
-		let mut nodes = Vec::new();
-		let mut node_ids = HashMap::new();
-		for node in input.nodes {
-			let parent = node.parent.map(|id| *node_ids.get(&id).unwrap());
-			let display = display.nodes.remove(&node.id).unwrap_or_default();
-
-			node_ids.insert(node.id.clone(), nodes.len());
-			nodes.push(lib::Node {
-				id: node.id.0,
-				scratchpad: node.scratchpad,
-				parent,
-				display,
-			});
+	Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AerodromeOptions {
+	pub target_padding: f64,
+	pub target_padding_geo: f64,
+	pub view_padding: f64,
+}
+
+impl Default for AerodromeOptions {
+	fn default() -> Self {
+		Self {
+			target_padding: DEFAULT_TARGET_PADDING,
+			target_padding_geo: DEFAULT_TARGET_PADDING_GEO,
+			view_padding: DEFAULT_VIEW_PADDING,
 		}
+	}
+}
+
+// compiles a single aerodrome file, also returning the paths of every file
+// which was read to produce it
+pub fn compile_aerodrome(
+	file: &Path,
+	options: &AerodromeOptions,
+) -> Result<(lib::Aerodrome, Vec<PathBuf>)> {
+	let dir = file.parent().unwrap();
+
+	let mut deps = Vec::new();
+
+	let input =
+		serde_json::from_value::<Aerodrome>(template::load(file, &mut deps)?)?;
+
+	let mut rasters = Vec::new();
+	let (display_path, mut display) = match input.display {
+		GeoMap::Geo(path) => {
+			let path = dir.join(path);
+			let (kml, overlays) = kmz::load(&path, &mut deps)?;
+			rasters = overlays;
+			(path, map::convert(map::Kml::new(kml).unwrap().input(), 0))
+		},
+		GeoMap::Flat { svg, lat, lon } => {
+			let path = dir.join(svg);
+			let s = std::fs::read_to_string(&path)?;
+			let tree = Tree::from_str(&s, &Default::default())?;
+			deps.push(path.clone());
+			(path, map::convert(map::GeoSvg::new(&tree, lat, lon), 0))
+		},
+		GeoMap::Grid { svg, grid } => {
+			let path = dir.join(svg);
+			let s = std::fs::read_to_string(&path)?;
+			let tree = Tree::from_str(&s, &Default::default())?;
+			deps.push(path.clone());
+			(path, map::convert(map::GeoSvg::with_grid(&tree, grid), 0))
+		},
+	};
+	warn_ids(
+		&display_path,
+		"degenerate transform on paths",
+		&display.degenerate,
+	);
+	let mut styles = std::mem::take(&mut display.styles);
+
+	let mut temp_maps = Vec::new();
+	for svg in input.maps {
+		let path = dir.join(svg);
+		let s = std::fs::read_to_string(&path)?;
+		let tree = Tree::from_str(&s, &Default::default())?;
+		deps.push(path.clone());
+		let mut map = map::convert(map::Svg::new(&tree), styles.len());
+		warn_ids(&path, "degenerate transform on paths", &map.degenerate);
+		styles.append(&mut map.styles);
+		temp_maps.push((path, map));
+	}
+
+	let diagnostics = Diagnostics::default();
+
+	// parents may be defined after their children
+	let mut node_ids = HashMap::new();
+	for node in &input.nodes {
+		diagnostics.define(&mut node_ids, "node", &node.id);
+	}
+
+	// child nodes are never drawn, so are not expected to have a display
+	let mut top_nodes = Vec::new();
+	let mut missing = Vec::new();
 
-		let mut edges = Vec::new();
-		let mut id_edges = Vec::new();
-		let mut edge_ids = HashMap::new();
-		for edge in input.edges {
-			let display = display.edges.remove(&edge.id).unwrap_or_default();
+	let mut nodes = Vec::new();
+	for node in input.nodes {
+		let parent = node.parent.as_ref().and_then(|id| {
+			diagnostics.resolve(&node_ids, "node", id, || format!("node {}", node.id))
+		});
+		let mut display = match display.nodes.remove(&node.id) {
+			Some(display) => display,
+			None => {
+				if parent.is_none() {
+					missing.push(node.id.clone());
+				}
+
+				Default::default()
+			},
+		};
+		map::generate_target(&mut display, options.target_padding_geo);
 
-			id_edges.push(edge.id.clone());
-			edge_ids.insert(edge.id, edges.len());
-			edges.push(lib::Edge { display });
+		if parent.is_none() {
+			top_nodes.push(node.id.clone());
 		}
 
-		let mut edge_conditions = HashMap::new();
-		let mut edge_blocks = HashMap::new();
-
-		let mut blocks = Vec::new();
-		let mut block_ids = HashMap::new();
-		for block in input.blocks {
-			let edges = HashMap::from_iter(block.edges.iter().map(|(id, edges)| {
-				(
-					*node_ids.get(id).unwrap(),
-					edges
-						.0
-						.iter()
-						.map(|id| *edge_ids.get(id).unwrap())
-						.collect(),
-				)
-			}));
-			let joins = block
-				.joins
-				.iter()
-				.map(|vertex| {
-					vertex
-						.iter()
-						.map(|edges| {
-							edges
-								.0
-								.iter()
-								.map(|id| *edge_ids.get(id).unwrap())
-								.collect()
-						})
-						.collect()
-				})
-				.collect();
+		nodes.push(lib::Node {
+			id: node.id.0,
+			scratchpad: node.scratchpad,
+			parent,
+			reset_on_crossing: node.reset_on_crossing,
+			gang: node.gang,
+			display,
+		});
+	}
 
-			let resolved = resolve_routes(&edges, &joins);
-			for id in resolved.conditions.keys() {
-				edge_blocks.insert(*id, blocks.len());
-			}
-			edge_conditions.extend(resolved.conditions.into_iter());
+	let mut edges = Vec::new();
+	let mut id_edges = Vec::new();
+	let mut edge_ids = HashMap::new();
+	for edge in input.edges {
+		let display = display.edges.remove(&edge.id).unwrap_or_else(|| {
+			missing.push(edge.id.clone());
+			Default::default()
+		});
+
+		id_edges.push(edge.id.clone());
+		diagnostics.define(&mut edge_ids, "edge", &edge.id);
+		edges.push(lib::Edge { display });
+	}
+
+	let mut edge_conditions = HashMap::new();
+	let mut edge_blocks = HashMap::new();
 
-			let nodes = block
-				.nodes
+	let mut blocks = Vec::new();
+	let mut block_ids = HashMap::new();
+	for block in input.blocks {
+		let context = || format!("block {}", block.id);
+		let resolve_edges = |edges: &IdList| {
+			edges
+				.0
 				.iter()
-				.map(|id| *node_ids.get(id).unwrap())
-				.collect();
-			let display = display.blocks.remove(&block.id).unwrap_or_default();
-
-			block_ids.insert(block.id.clone(), blocks.len());
-			blocks.push(lib::Block {
-				id: block.id.0,
-				nodes,
-				edges: Vec::new(), // defect: unused
-				non_routes: resolved.non_routes,
-				stands: block.stands,
-				display,
-			});
+				.filter_map(|id| diagnostics.resolve(&edge_ids, "edge", id, context))
+				.collect::<Vec<_>>()
+		};
+
+		let edges = block
+			.edges
+			.iter()
+			.filter_map(|(id, edges)| {
+				let edges = resolve_edges(edges);
+				let node = diagnostics.resolve(&node_ids, "node", id, context)?;
+				Some((node, edges))
+			})
+			.collect();
+		let joins = block
+			.joins
+			.iter()
+			.map(|vertex| vertex.iter().map(resolve_edges).collect())
+			.collect();
+
+		let resolved = resolve_routes(&edges, &joins);
+		let mut block_edges =
+			resolved.conditions.keys().copied().collect::<Vec<_>>();
+		block_edges.sort();
+		for id in &block_edges {
+			edge_blocks.insert(*id, blocks.len());
 		}
+		edge_conditions.extend(resolved.conditions);
+
+		let nodes = block
+			.nodes
+			.iter()
+			.filter_map(|id| diagnostics.resolve(&node_ids, "node", id, context))
+			.collect();
+		let display = display.blocks.remove(&block.id).unwrap_or_default();
+
+		diagnostics.define(&mut block_ids, "block", &block.id);
+		blocks.push(lib::Block {
+			id: block.id.0,
+			nodes,
+			edges: block_edges,
+			non_routes: resolved.non_routes,
+			stands: block.stands,
+			display,
+		});
+	}
 
-		let mut profiles = Vec::new();
-		for profile in input.profiles {
-			let default_node = profile
-				.nodes
-				.get(&IdList::wildcard())
-				.copied()
-				.unwrap_or_default();
-			let nodes = nodes
-				.iter()
-				.map(|node| {
-					profile
-						.nodes
-						.iter()
-						.find(|(ids, _)| ids.0.contains(&Id(node.id.clone())))
-						.map(|(_, node)| *node)
-						.unwrap_or(default_node)
-						.convert()
-				})
-				.collect::<Vec<_>>();
-
-			let default_edge = profile
-				.edges
-				.get(&IdList::wildcard())
-				.cloned()
-				.unwrap_or_default();
-			let edges = id_edges
-				.iter()
-				.enumerate()
-				.map(|(index, id)| {
-					profile
-						.edges
-						.iter()
-						.find(|(ids, _)| ids.0.contains(id))
-						.map(|(_, edge)| edge.clone())
-						.unwrap_or(default_edge.clone())
-						.convert(
-							&node_ids,
-							edge_blocks
-								.get(&index)
-								.copied()
-								.zip(edge_conditions.get(&index).cloned()),
-						)
-				})
-				.collect();
-
-			let default_block = profile
-				.blocks
-				.get(&IdList::wildcard())
-				.copied()
-				.unwrap_or_default();
-			let blocks = blocks
-				.iter()
-				.map(|block| {
-					profile
-						.blocks
-						.iter()
-						.find(|(ids, _)| ids.0.contains(&Id(block.id.clone())))
-						.map(|(_, block)| *block)
-						.unwrap_or(default_block)
-						.convert()
-				})
-				.collect();
+	let mut closures = Vec::new();
+	let mut closure_ids = HashMap::new();
+	for closure in input.closures {
+		let context = || format!("closure {}", closure.id);
+
+		let edges = closure
+			.edges
+			.iter()
+			.filter_map(|id| diagnostics.resolve(&edge_ids, "edge", id, context))
+			.collect();
+		let display = display.closures.remove(&closure.id).unwrap_or_else(|| {
+			missing.push(closure.id.clone());
+			Default::default()
+		});
 
-			let presets = profile
-				.presets
-				.into_iter()
-				.map(|preset| lib::Preset {
-					name: preset.name,
+		diagnostics.define(&mut closure_ids, "closure", &closure.id);
+		closures.push(lib::Closure {
+			id: closure.id.0,
+			active: !closure.open,
+			edges,
+			display,
+		});
+	}
+
+	let mut profiles = Vec::new();
+	for profile in input.profiles {
+		let context = || format!("profile {}", profile.id);
+
+		for (kind, ids, keys) in [
+			("node", &node_ids, profile.nodes.keys().collect::<Vec<_>>()),
+			("edge", &edge_ids, profile.edges.keys().collect()),
+			("block", &block_ids, profile.blocks.keys().collect()),
+		] {
+			for id in keys.into_iter().flat_map(|ids| &ids.0) {
+				diagnostics.resolve(ids, kind, id, context);
+			}
+		}
+
+		let default_node = profile
+			.nodes
+			.get(&IdList::wildcard())
+			.copied()
+			.unwrap_or_default();
+		let nodes = nodes
+			.iter()
+			.map(|node| {
+				profile
+					.nodes
+					.iter()
+					.find(|(ids, _)| ids.0.contains(&Id(node.id.clone())))
+					.map(|(_, node)| *node)
+					.unwrap_or(default_node)
+					.convert()
+			})
+			.collect::<Vec<_>>();
+
+		let default_edge = profile
+			.edges
+			.get(&IdList::wildcard())
+			.cloned()
+			.unwrap_or_default();
+		let edges = id_edges
+			.iter()
+			.enumerate()
+			.map(|(index, id)| {
+				profile
+					.edges
+					.iter()
+					.find(|(ids, _)| ids.0.contains(id))
+					.map(|(_, edge)| edge.clone())
+					.unwrap_or(default_edge.clone())
+					.convert(
+						&node_ids,
+						&diagnostics,
+						context,
+						edge_blocks
+							.get(&index)
+							.copied()
+							.zip(edge_conditions.get(&index).cloned()),
+					)
+			})
+			.collect();
+
+		let default_block = profile
+			.blocks
+			.get(&IdList::wildcard())
+			.copied()
+			.unwrap_or_default();
+		let blocks = blocks
+			.iter()
+			.map(|block| {
+				profile
+					.blocks
+					.iter()
+					.find(|(ids, _)| ids.0.contains(&Id(block.id.clone())))
+					.map(|(_, block)| *block)
+					.unwrap_or(default_block)
+					.convert()
+			})
+			.collect();
+
+		let unattended =
+			profile.presets.iter().position(|preset| preset.unattended);
+		let presets = profile
+			.presets
+			.into_iter()
+			.map(|preset| {
+				let context =
+					|| format!("profile {} preset {:?}", profile.id, preset.name);
+
+				lib::Preset {
 					nodes: preset
 						.nodes
-						.into_iter()
+						.iter()
 						.flat_map(|(ids, state)| {
 							let ids = if ids.0.is_empty() {
 								vec![u32::MAX as usize]
@@ -232,27 +513,27 @@ fn main() -> Result<()> {
 								ids
 									.0
 									.iter()
-									.map(|id| *node_ids.get(id).unwrap())
+									.filter_map(|id| {
+										diagnostics.resolve(&node_ids, "node", id, context)
+									})
 									.collect()
 							};
 
-							ids
-								.into_iter()
-								.map(|index| (index, state))
-								.collect::<Vec<_>>()
+							ids.into_iter().map(move |index| (index, *state))
 						})
 						.collect(),
 					blocks: preset
 						.blocks
-						.into_iter()
+						.iter()
 						.flat_map(|(ids, state)| {
 							let state = match state {
-								BlockState::Clear => lib::BlockState::Clear,
-								BlockState::Relax => lib::BlockState::Relax,
-								BlockState::Route((a, b)) => lib::BlockState::Route((
-									*node_ids.get(&a).unwrap(),
-									*node_ids.get(&b).unwrap(),
-								)),
+								BlockState::Clear => Some(lib::BlockState::Clear),
+								BlockState::Relax => Some(lib::BlockState::Relax),
+								BlockState::Route((a, b)) => {
+									let a = diagnostics.resolve(&node_ids, "node", a, context);
+									let b = diagnostics.resolve(&node_ids, "node", b, context);
+									a.zip(b).map(lib::BlockState::Route)
+								},
 							};
 
 							let ids = if ids.0.is_empty() {
@@ -260,87 +541,177 @@ fn main() -> Result<()> {
 							} else {
 								ids
 									.0
-									.into_iter()
-									.map(|id| *block_ids.get(&id).unwrap())
+									.iter()
+									.filter_map(|id| {
+										diagnostics.resolve(&block_ids, "block", id, context)
+									})
 									.collect()
 							};
 
 							ids
 								.into_iter()
-								.map(move |index| (index, state))
+								.filter_map(move |index| Some((index, state?)))
 						})
 						.collect(),
+					name: preset.name,
+				}
+			})
+			.collect();
+
+		profiles.push(lib::Profile {
+			id: profile.id.0,
+			name: profile.name,
+			nodes,
+			edges,
+			blocks,
+			presets,
+			unattended,
+			runways: profile
+				.runways
+				.into_iter()
+				.map(|rule| lib::RunwayRule {
+					departures: rule.departures,
+					arrivals: rule.arrivals,
 				})
-				.collect();
-
-			profiles.push(lib::Profile {
-				id: profile.id.0,
-				name: profile.name,
-				nodes,
-				edges,
-				blocks,
-				presets,
-			});
+				.collect(),
+		});
+	}
+
+	warn_ids(&display_path, "no display for IDs", &missing);
+	warn_ids(
+		&display_path,
+		"unknown IDs in display",
+		display
+			.nodes
+			.keys()
+			.chain(display.edges.keys())
+			.chain(display.blocks.keys())
+			.chain(display.closures.keys()),
+	);
+
+	let mut maps = Vec::new();
+	let mut views = Vec::new();
+	for (path, mut map) in temp_maps {
+		map::generate_views(&mut map, options.view_padding);
+
+		let missing = top_nodes
+			.iter()
+			.filter(|id| !map.nodes.contains_key(id))
+			.chain(id_edges.iter().filter(|id| !map.edges.contains_key(id)));
+		warn_ids(&path, "no display for IDs", missing);
+
+		let mut unknown = Vec::new();
+
+		let mut nodes = vec![Default::default(); nodes.len()];
+		for (id, mut node) in map.nodes {
+			let Some(index) = node_ids.get(&id) else {
+				unknown.push(id);
+				continue
+			};
+
+			map::generate_target(&mut node, options.target_padding);
+			nodes[*index] = node;
 		}
 
-		let mut maps = Vec::new();
-		let mut views = Vec::new();
-		for map in temp_maps {
-			let mut nodes = vec![Default::default(); nodes.len()];
-			for (id, node) in map.nodes {
-				nodes[*node_ids.get(&id).unwrap()] = node;
-			}
+		let mut edges = vec![Default::default(); edges.len()];
+		for (id, edge) in map.edges {
+			let Some(index) = edge_ids.get(&id) else {
+				unknown.push(id);
+				continue
+			};
 
-			let mut edges = vec![Default::default(); edges.len()];
-			for (id, edge) in map.edges {
-				edges[*edge_ids.get(&id).unwrap()] = edge;
-			}
+			edges[*index] = edge;
+		}
 
-			let mut blocks = vec![Default::default(); blocks.len()];
-			for (id, block) in map.blocks {
-				blocks[*block_ids.get(&id).unwrap()] = block;
-			}
+		let mut blocks = vec![Default::default(); blocks.len()];
+		for (id, block) in map.blocks {
+			let Some(index) = block_ids.get(&id) else {
+				unknown.push(id);
+				continue
+			};
 
-			for (name, (min, max)) in map.views {
-				views.push(lib::View {
-					name,
-					map: maps.len(),
-					bounds: lib::Box { min, max },
-				});
-			}
+			blocks[*index] = block;
+		}
+
+		let mut closure_displays = vec![Default::default(); closures.len()];
+		for (id, closure) in map.closures {
+			let Some(index) = closure_ids.get(&id) else {
+				unknown.push(id);
+				continue
+			};
+
+			closure_displays[*index] = closure;
+		}
 
-			maps.push(lib::Map {
-				background: Default::default(), // todo
-				base: map.base,
-				nodes,
-				edges,
-				blocks,
+		warn_ids(&path, "unknown IDs in display", &unknown);
+
+		for (name, (min, max)) in map.views {
+			views.push(lib::View {
+				name,
+				map: maps.len(),
+				bounds: lib::Box { min, max },
 			});
 		}
 
-		aerodromes.push(lib::Aerodrome {
-			icao: input.icao,
-			elements: input.elements,
+		maps.push(lib::Map {
+			background: Default::default(), // todo
+			base: map.base,
 			nodes,
 			edges,
 			blocks,
-			profiles,
-			maps,
-			views,
-			styles,
+			closures: closure_displays,
 		});
 	}
 
-	let config = Config {
-		name: args.pkg_name,
-		version: args.pkg_version,
-		aerodromes,
+	let aerodrome = lib::Aerodrome {
+		icao: input.icao,
+		elements: input.elements,
+		nodes,
+		edges,
+		blocks,
+		closures,
+		profiles,
+		maps,
+		views,
+		styles,
+		rasters,
 	};
 
-	if let Some(path) = args.output {
-		config.save(BufWriter::new(File::create(path)?))?;
+	diagnostics.finish(&aerodrome.icao)?;
+	for diagnostic in analysis::check_routes(&aerodrome) {
+		eprintln!(
+			"warning: {}: {}",
+			aerodrome.icao,
+			diagnostic.message(&aerodrome),
+		);
+	}
+
+	Ok((aerodrome, deps))
+}
+
+fn warn_ids<'a>(
+	path: &Path,
+	message: &str,
+	ids: impl IntoIterator<Item = &'a Id>,
+) {
+	let mut ids = ids.into_iter().map(|id| id.0.as_str()).collect::<Vec<_>>();
+	if ids.is_empty() {
+		return
+	}
+
+	ids.sort_unstable();
+	eprintln!("warning: {}: {message} {}", path.display(), ids.join(", "));
+}
+
+fn schema(output: Option<PathBuf>) -> Result<()> {
+	let mut schema = schemars::schema_for!(Aerodrome).to_value();
+	template::extend_schema(&mut schema);
+
+	if let Some(path) = output {
+		serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &schema)?;
 	} else {
-		config.save(std::io::stdout())?;
+		serde_json::to_writer_pretty(std::io::stdout(), &schema)?;
+		println!();
 	}
 
 	Ok(())
@@ -368,10 +739,10 @@ fn resolve_routes(
 			}
 
 			for edge in sector1 {
-				if conn1.contains_key(&edge) {
-					conn2.insert(edge, edges.clone());
+				if let Entry::Vacant(entry) = conn1.entry(edge) {
+					entry.insert(edges.clone());
 				} else {
-					conn1.insert(edge, edges.clone());
+					conn2.insert(edge, edges.clone());
 				}
 			}
 		}
@@ -440,18 +811,72 @@ fn resolve_routes(
 	}
 }
 
+// collects errors for undefined and duplicate IDs, so that all of them can be
+// reported together rather than only the first
+#[derive(Default)]
+struct Diagnostics(RefCell<Vec<String>>);
+
+impl Diagnostics {
+	fn define(&self, ids: &mut HashMap<Id, usize>, kind: &str, id: &Id) {
+		let index = ids.len();
+		if ids.insert(id.clone(), index).is_some() {
+			self.0.borrow_mut().push(format!("duplicate {kind} {id}"));
+		}
+	}
+
+	fn resolve(
+		&self,
+		ids: &HashMap<Id, usize>,
+		kind: &str,
+		id: &Id,
+		context: impl Fn() -> String,
+	) -> Option<usize> {
+		let index = ids.get(id).copied();
+		if index.is_none() {
+			let message = format!("{} references undefined {kind} {id}", context());
+			self.0.borrow_mut().push(message);
+		}
+
+		index
+	}
+
+	fn finish(self, icao: &str) -> Result<()> {
+		let errors = self.0.into_inner();
+		if errors.is_empty() {
+			return Ok(())
+		}
+
+		bail!(
+			"{icao}: {} ID errors\n  {}",
+			errors.len(),
+			errors.join("\n  ")
+		)
+	}
+}
+
 #[derive(Debug)]
 struct Resolved {
 	non_routes: Vec<(usize, usize)>,
 	conditions: HashMap<usize, Vec<(usize, usize)>>,
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(
+	Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize, JsonSchema,
+)]
 #[serde(transparent)]
 struct Id(String);
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
-#[serde(from = "&str")]
+impl std::fmt::Display for Id {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+#[derive(
+	Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize, JsonSchema,
+)]
+#[serde(from = "String")]
+#[schemars(description = "IDs separated by `+`, or empty to match all")]
 struct IdList(Vec<Id>);
 
 impl IdList {
@@ -460,8 +885,8 @@ impl IdList {
 	}
 }
 
-impl From<&str> for IdList {
-	fn from(s: &str) -> Self {
+impl From<String> for IdList {
+	fn from(s: String) -> Self {
 		if s.is_empty() {
 			Self(Vec::new())
 		} else {
@@ -470,7 +895,8 @@ impl From<&str> for IdList {
 	}
 }
 
-#[derive(Debug, Deserialize)]
+/// Aerodrome input file for confc.
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Aerodrome {
 	icao: String,
 
@@ -480,29 +906,40 @@ pub struct Aerodrome {
 	edges: Vec<Edge>,
 	#[serde(default)]
 	blocks: Vec<Block>,
+	/// closed taxiways and works areas, drawn from the `closures` layer
+	#[serde(default)]
+	closures: Vec<Closure>,
 
 	#[serde(default)]
 	profiles: Vec<Profile>,
 
+	/// KML/KMZ file, or placed SVG file
 	display: GeoMap,
+	/// SVG files for schematic displays
 	#[serde(default)]
 	maps: Vec<Map>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Node {
 	id: Id,
 
 	scratchpad: Option<String>,
 	parent: Option<Id>,
+	/// light the stopbar again once an aircraft has crossed it
+	#[serde(default)]
+	reset_on_crossing: bool,
+	/// set the child nodes together with this node
+	#[serde(default)]
+	gang: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Edge {
 	id: Id,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Block {
 	id: Id,
 
@@ -515,7 +952,19 @@ struct Block {
 	stands: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Closure {
+	id: Id,
+
+	/// edges which routes may not pass along while the closure is active
+	#[serde(default)]
+	edges: Vec<Id>,
+	/// start inactive, until closed at runtime
+	#[serde(default)]
+	open: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Profile {
 	id: Id,
 	name: String,
@@ -529,9 +978,20 @@ struct Profile {
 
 	#[serde(default)]
 	presets: Vec<Preset>,
+	#[serde(default)]
+	runways: Vec<RunwayRule>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+/// Runway designators, such as `27L`, which must all be active.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RunwayRule {
+	#[serde(default)]
+	departures: Vec<String>,
+	#[serde(default)]
+	arrivals: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
 #[serde(tag = "mode", rename_all = "snake_case")]
 enum NodeCondition {
 	Fixed { state: bool },
@@ -545,7 +1005,7 @@ impl NodeCondition {
 			Self::Fixed { state } => lib::NodeCondition::Fixed { state },
 			Self::Direct { timer } => lib::NodeCondition::Direct {
 				reset: timer
-					.map(|t| lib::ResetCondition::TimeSecs(t))
+					.map(lib::ResetCondition::TimeSecs)
 					.unwrap_or(lib::ResetCondition::None),
 			},
 			Self::Router => lib::NodeCondition::Router,
@@ -559,7 +1019,7 @@ impl Default for NodeCondition {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(tag = "mode", rename_all = "snake_case")]
 enum EdgeCondition {
 	Fixed { state: bool },
@@ -571,12 +1031,17 @@ impl EdgeCondition {
 	fn convert(
 		self,
 		node_ids: &HashMap<Id, usize>,
+		diagnostics: &Diagnostics,
+		context: impl Fn() -> String,
 		router: Option<(usize, Vec<(usize, usize)>)>,
 	) -> lib::EdgeCondition {
 		match self {
 			Self::Fixed { state } => lib::EdgeCondition::Fixed { state },
-			Self::Direct { node } => lib::EdgeCondition::Direct {
-				node: *node_ids.get(&node).unwrap(),
+			Self::Direct { node } => {
+				match diagnostics.resolve(node_ids, "node", &node, context) {
+					Some(node) => lib::EdgeCondition::Direct { node },
+					None => lib::EdgeCondition::Fixed { state: false },
+				}
 			},
 			Self::Router => {
 				if let Some((block, routes)) = router {
@@ -596,7 +1061,7 @@ impl Default for EdgeCondition {
 	}
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema)]
 struct BlockCondition {
 	timer: ResetCondition,
 }
@@ -606,23 +1071,20 @@ impl BlockCondition {
 		lib::BlockCondition {
 			reset: self
 				.timer
-				.map(|t| lib::ResetCondition::TimeSecs(t))
+				.map(lib::ResetCondition::TimeSecs)
 				.unwrap_or(lib::ResetCondition::None),
 		}
 	}
 }
 
-impl Default for BlockCondition {
-	fn default() -> Self {
-		Self { timer: None }
-	}
-}
-
 type ResetCondition = Option<u32>;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Preset {
 	name: String,
+	/// shown while no controller is connected
+	#[serde(default)]
+	unattended: bool,
 
 	#[serde(default)]
 	nodes: HashMap<IdList, NodeState>,
@@ -632,7 +1094,7 @@ struct Preset {
 
 type NodeState = bool;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 enum BlockState {
 	Clear,
@@ -641,7 +1103,7 @@ enum BlockState {
 	Route((Id, Id)),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(untagged)]
 enum GeoMap {
 	Geo(PathBuf),
@@ -650,6 +1112,11 @@ enum GeoMap {
 		lat: (f64, f64),
 		lon: (f64, f64),
 	},
+	Grid {
+		svg: PathBuf,
+		#[serde(flatten)]
+		grid: grid::Grid,
+	},
 }
 
 type Map = PathBuf;
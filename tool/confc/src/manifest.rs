@@ -0,0 +1,180 @@
+use crate::{compile_aerodrome, AerodromeOptions};
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use bars_config::{self as lib, Config};
+
+use anyhow::{Context, Result};
+
+use serde::{Deserialize, Serialize};
+
+static DEFAULT_CACHE_DIR: &str = ".confc-cache";
+
+/// Package manifest, conventionally `package.toml`.
+#[derive(Debug, Deserialize)]
+struct Manifest {
+	name: Option<String>,
+	version: Option<String>,
+
+	output: PathBuf,
+	cache: Option<PathBuf>,
+
+	#[serde(flatten)]
+	options: Overrides,
+
+	#[serde(default, rename = "aerodrome")]
+	aerodromes: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+	file: PathBuf,
+
+	#[serde(flatten)]
+	options: Overrides,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Overrides {
+	target_padding: Option<f64>,
+	target_padding_geo: Option<f64>,
+	view_padding: Option<f64>,
+}
+
+impl Overrides {
+	fn apply(&self, mut options: AerodromeOptions) -> AerodromeOptions {
+		if let Some(padding) = self.target_padding {
+			options.target_padding = padding;
+		}
+
+		if let Some(padding) = self.target_padding_geo {
+			options.target_padding_geo = padding;
+		}
+
+		if let Some(padding) = self.view_padding {
+			options.view_padding = padding;
+		}
+
+		options
+	}
+}
+
+// a compiled aerodrome, valid for as long as the inputs it was compiled from
+// are unchanged
+#[derive(Deserialize, Serialize)]
+struct Cached {
+	compiler: String,
+	options: u64,
+	deps: Vec<(PathBuf, u64)>,
+	aerodrome: lib::Aerodrome,
+}
+
+impl Cached {
+	fn is_fresh(&self, options: u64) -> bool {
+		self.compiler == env!("CARGO_PKG_VERSION")
+			&& self.options == options
+			&& self.deps.iter().all(|(path, hash)| {
+				std::fs::read(path)
+					.map(|data| hash_of(&data) == *hash)
+					.unwrap_or_default()
+			})
+	}
+}
+
+pub fn run(path: &Path, force: bool) -> Result<()> {
+	let s = std::fs::read_to_string(path)
+		.with_context(|| format!("failed to read {}", path.display()))?;
+	let manifest = toml::from_str::<Manifest>(&s)
+		.with_context(|| format!("failed to parse {}", path.display()))?;
+
+	let dir = path.parent().unwrap_or(Path::new(""));
+	let defaults = manifest.options.apply(AerodromeOptions::default());
+
+	let cache_dir =
+		dir.join(manifest.cache.unwrap_or_else(|| DEFAULT_CACHE_DIR.into()));
+	std::fs::create_dir_all(&cache_dir)
+		.with_context(|| format!("failed to create {}", cache_dir.display()))?;
+
+	let mut aerodromes = Vec::with_capacity(manifest.aerodromes.len());
+
+	for entry in &manifest.aerodromes {
+		let file = dir.join(&entry.file);
+		let options = entry.options.apply(defaults);
+		let options_hash = hash_of(&(
+			options.target_padding.to_bits(),
+			options.target_padding_geo.to_bits(),
+			options.view_padding.to_bits(),
+		));
+
+		let cache_path = cache_dir.join(format!("{:016x}.bin", hash_of(&file)));
+		let cached = (!force)
+			.then(|| load_cached(&cache_path))
+			.flatten()
+			.filter(|cached| cached.is_fresh(options_hash));
+
+		if let Some(cached) = cached {
+			eprintln!("unchanged {}", entry.file.display());
+			aerodromes.push(cached.aerodrome);
+			continue
+		}
+
+		eprintln!("compiling {}", entry.file.display());
+		let (aerodrome, deps) = compile_aerodrome(&file, &options)
+			.with_context(|| format!("failed to compile {}", file.display()))?;
+
+		let cached = Cached {
+			compiler: env!("CARGO_PKG_VERSION").into(),
+			options: options_hash,
+			deps: deps
+				.into_iter()
+				.map(|path| {
+					let data = std::fs::read(&path)?;
+					Ok((path, hash_of(&data)))
+				})
+				.collect::<Result<_>>()?,
+			aerodrome,
+		};
+
+		// a failure to write the cache only costs a rebuild next time
+		if let Err(err) = save_cached(&cache_path, &cached) {
+			eprintln!("warning: failed to write {}: {err}", cache_path.display());
+		}
+
+		aerodromes.push(cached.aerodrome);
+	}
+
+	let config = Config {
+		name: manifest.name,
+		version: manifest.version,
+		aerodromes,
+	};
+
+	let output = dir.join(&manifest.output);
+	let file = File::create(&output)
+		.with_context(|| format!("failed to create {}", output.display()))?;
+	config.save(BufWriter::new(file))?;
+
+	Ok(())
+}
+
+fn load_cached(path: &Path) -> Option<Cached> {
+	let reader = BufReader::new(File::open(path).ok()?);
+	lib::bincode::deserialize_from(reader).ok()
+}
+
+fn save_cached(path: &Path, cached: &Cached) -> Result<()> {
+	let writer = BufWriter::new(File::create(path)?);
+	lib::bincode::serialize_into(writer, cached)?;
+
+	Ok(())
+}
+
+fn hash_of(value: &(impl Hash + ?Sized)) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	value.hash(&mut hasher);
+	hasher.finish()
+}
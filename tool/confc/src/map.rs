@@ -1,3 +1,4 @@
+use crate::grid::Grid;
 use crate::Id;
 
 use std::borrow::Cow;
@@ -7,8 +8,8 @@ use std::fmt::Debug;
 use std::rc::Rc;
 
 use bars_config::{
-	BlockDisplay, Color, EdgeDisplay, FillStyle, Geo, GeoPoint, NodeDisplay,
-	Path, Point, Style, Target,
+	BlockDisplay, ClosureDisplay, Color, EdgeDisplay, FillStyle, Geo, GeoPoint,
+	NodeDisplay, Path, Point, Quantize, Style, Target,
 };
 
 use kml::types::{Geometry, Placemark, Style as KmlStyle, StyleMap};
@@ -17,9 +18,9 @@ use kml::{Kml as KmlItem, KmlDocument};
 use kurbo::PathEl;
 
 use usvg::tiny_skia_path::PathSegment;
-use usvg::{Group, Node, Paint, Tree};
+use usvg::{Group, Node, Opacity, Paint, Stop, Tree};
 
-pub fn convert<T: Clone + Debug + MinMax>(
+pub fn convert<T: Clone + Debug + MinMax + Planar + Quantize>(
 	input: impl Input<Point = T>,
 	styles_offset: usize,
 ) -> Map<T> {
@@ -34,20 +35,32 @@ pub fn convert<T: Clone + Debug + MinMax>(
 		NodesTarget,
 		EdgesOff,
 		EdgesOn,
+		BlocksRelax,
+		BlocksRoute,
 		BlocksTarget,
+		Closures,
 	}
 
-	fn visit<T: Clone + Debug + MinMax>(
+	fn visit<T: Clone + Debug + MinMax + Planar + Quantize>(
 		input: impl Input<Point = T>,
 		map: &mut Map<T>,
 		mut context: Context,
 		mut id: Cow<str>,
-		styles: &mut HashMap<TempStyle, usize>,
+		mut view_tags: Vec<String>,
+		styles: &mut HashMap<(TempStyle, FillStyle), usize>,
 		styles_offset: usize,
 	) {
 		static SPLIT_CHARS: &[char] = &['_', ' ']; // inserted by Figma
 
-		if let Some(group_id) = input.id() {
+		if let Some(name) = input
+			.id()
+			.and_then(|group_id| group_id.strip_prefix("view-gen:"))
+		{
+			// tags geometry, including that of any nested tags, without
+			// otherwise affecting how it is interpreted
+			let name = name.split_once(SPLIT_CHARS).map(|s| s.0).unwrap_or(name);
+			view_tags.push(name.into());
+		} else if let Some(group_id) = input.id() {
 			context = match group_id {
 				"basemap" => Context::Basemap,
 				"views" => Context::Views,
@@ -57,14 +70,17 @@ pub fn convert<T: Clone + Debug + MinMax>(
 				"nodes:target" => Context::NodesTarget,
 				"edges:off" => Context::EdgesOff,
 				"edges:on" => Context::EdgesOn,
+				"blocks:relax" => Context::BlocksRelax,
+				"blocks:route" => Context::BlocksRoute,
 				"blocks:target" => Context::BlocksTarget,
+				"closures" => Context::Closures,
 				_ => {
 					if let Some((_, group_id)) = group_id.split_once(':') {
 						id = Cow::Owned(
 							group_id
 								.split_once(SPLIT_CHARS)
 								.map(|s| s.0)
-								.unwrap_or(&group_id)
+								.unwrap_or(group_id)
 								.into(),
 						);
 					}
@@ -75,55 +91,65 @@ pub fn convert<T: Clone + Debug + MinMax>(
 		}
 
 		for input_path in input.paths() {
-			let id = if let Some((_, id)) = input_path
-				.id
-				.as_ref()
-				.map(|s| s.as_str())
-				.unwrap_or("")
-				.split_once(':')
+			if input_path.degenerate {
+				map.degenerate.push(Id(input_path.id.unwrap_or_default()));
+				continue
+			}
+
+			let id = if let Some((_, id)) =
+				input_path.id.as_deref().unwrap_or("").split_once(':')
 			{
-				id.split_once(SPLIT_CHARS).map(|s| s.0).unwrap_or(&id)
+				id.split_once(SPLIT_CHARS).map(|s| s.0).unwrap_or(id)
 			} else {
 				id.as_ref()
 			};
 
-			if id.len() > 0 && context == Context::Views {
-				map.views.push((
-					id.to_string(),
-					(
-						input_path
-							.points
-							.iter()
-							.cloned()
-							.reduce(|a, b| a.min(&b))
-							.unwrap(),
-						input_path
-							.points
-							.iter()
-							.cloned()
-							.reduce(|a, b| a.max(&b))
-							.unwrap(),
-					),
-				));
+			if !id.is_empty() && context == Context::Views {
+				if let Some(bounds) = bounds(&input_path.points) {
+					map.views.push((id.to_string(), bounds));
+				}
 
 				continue
 			}
 
-			let style = styles.entry(input_path.style).or_insert_with(|| {
-				map.styles.push(Style {
-					stroke_width: input_path.style.stroke_width as f32,
-					stroke_color: input_path.style.stroke_color,
-					fill_style: if input_path.style.fill.is_some() {
-						FillStyle::Solid
-					} else {
-						FillStyle::None
-					},
-					fill_color: input_path.style.fill.unwrap_or_default(),
-				});
+			if let Some((min, max)) = bounds(&input_path.points) {
+				for view in &view_tags {
+					match map
+						.generated_views
+						.iter_mut()
+						.find(|(name, _)| name == view)
+					{
+						Some((_, bounds)) => {
+							*bounds = (bounds.0.min(&min), bounds.1.max(&max))
+						},
+						None => map
+							.generated_views
+							.push((view.clone(), (min.clone(), max.clone()))),
+					}
+				}
+			}
 
-				styles_offset + map.styles.len() - 1
-			});
+			let fill_style = match (input_path.style.fill, context) {
+				(None, _) => FillStyle::None,
+				// closed areas are hatched so that the basemap shows through
+				(Some(_), Context::Closures) => FillStyle::HatchDiagonalCross,
+				(Some(_), _) => FillStyle::Solid,
+			};
+			let style =
+				styles
+					.entry((input_path.style, fill_style))
+					.or_insert_with(|| {
+						map.styles.push(Style {
+							stroke_width: input_path.style.stroke_width as f32,
+							stroke_color: input_path.style.stroke_color,
+							fill_style,
+							fill_color: input_path.style.fill.unwrap_or_default(),
+						});
+
+						styles_offset + map.styles.len() - 1
+					});
 			let path = Path {
+				quantum: fit_quantum(&input_path.points),
 				points: input_path.points,
 				style: *style,
 			};
@@ -175,15 +201,31 @@ pub fn convert<T: Clone + Debug + MinMax>(
 						_ => unreachable!(),
 					}
 				},
-				Context::BlocksTarget => {
-					map.blocks.insert(
-						id,
-						BlockDisplay {
-							target: Target {
+				Context::BlocksRelax | Context::BlocksRoute | Context::BlocksTarget => {
+					let ent = map.blocks.entry(id).or_insert_with(|| BlockDisplay {
+						relax: Vec::new(),
+						route: Vec::new(),
+						target: Target { points: Vec::new() },
+					});
+
+					match context {
+						Context::BlocksRelax => ent.relax.push(path),
+						Context::BlocksRoute => ent.route.push(path),
+						Context::BlocksTarget => {
+							ent.target = Target {
 								points: path.points,
-							},
+							}
 						},
-					);
+						_ => unreachable!(),
+					}
+				},
+				Context::Closures => {
+					map
+						.closures
+						.entry(id)
+						.or_insert_with(|| ClosureDisplay { area: Vec::new() })
+						.area
+						.push(path);
 				},
 				_ => unreachable!(),
 			}
@@ -195,6 +237,7 @@ pub fn convert<T: Clone + Debug + MinMax>(
 				map,
 				context,
 				Cow::Borrowed(&id),
+				view_tags.clone(),
 				styles,
 				styles_offset,
 			);
@@ -206,8 +249,11 @@ pub fn convert<T: Clone + Debug + MinMax>(
 		nodes: HashMap::new(),
 		edges: HashMap::new(),
 		blocks: HashMap::new(),
+		closures: HashMap::new(),
 		views: Vec::new(),
+		generated_views: Vec::new(),
 		styles: Vec::new(),
+		degenerate: Vec::new(),
 	};
 	let mut styles = HashMap::new();
 
@@ -216,6 +262,7 @@ pub fn convert<T: Clone + Debug + MinMax>(
 		&mut map,
 		Context::None,
 		Cow::Borrowed(""),
+		Vec::new(),
 		&mut styles,
 		styles_offset,
 	);
@@ -230,10 +277,16 @@ pub struct Map<T: Clone + Debug> {
 	pub nodes: HashMap<Id, NodeDisplay<T>>,
 	pub edges: HashMap<Id, EdgeDisplay<T>>,
 	pub blocks: HashMap<Id, BlockDisplay<T>>,
+	pub closures: HashMap<Id, ClosureDisplay<T>>,
 
 	pub views: Vec<(String, (T, T))>,
+	/// unpadded bounds of geometry in each `view-gen:` group
+	pub generated_views: Vec<(String, (T, T))>,
 
 	pub styles: Vec<Style>,
+
+	/// paths skipped as their transform could not be applied
+	pub degenerate: Vec<Id>,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -248,6 +301,8 @@ pub struct TempPath<T> {
 	id: Option<String>,
 	points: Vec<T>,
 	style: TempStyle,
+	// whether the transform of the path could not be applied
+	degenerate: bool,
 }
 
 /* impl<T> TempPath<T> {
@@ -267,6 +322,44 @@ pub struct TempPath<T> {
 	}
 } */
 
+fn bounds<T: Clone + MinMax>(points: &[T]) -> Option<(T, T)> {
+	let first = points.first()?;
+
+	Some(
+		points[1..]
+			.iter()
+			.fold((first.clone(), first.clone()), |(min, max), point| {
+				(min.min(point), max.max(point))
+			}),
+	)
+}
+
+// adds views generated from tagged geometry, padded by `padding` pixels on
+// each side; explicitly drawn views of the same name take precedence
+pub fn generate_views(map: &mut Map<Point>, padding: f64) {
+	let padding = padding as f32;
+
+	for (name, (min, max)) in std::mem::take(&mut map.generated_views) {
+		if map.views.iter().any(|(view, _)| *view == name) {
+			continue
+		}
+
+		map.views.push((
+			name,
+			(
+				Point {
+					x: min.x - padding,
+					y: min.y - padding,
+				},
+				Point {
+					x: max.x + padding,
+					y: max.y + padding,
+				},
+			),
+		));
+	}
+}
+
 pub trait MinMax {
 	fn min(&self, other: &Self) -> Self;
 	fn max(&self, other: &Self) -> Self;
@@ -299,6 +392,156 @@ impl MinMax for GeoPoint {
 	}
 }
 
+pub trait Planar: Sized {
+	/// maximum error introduced by storing points as fixed-point coordinates,
+	/// in planar units
+	const TOLERANCE: f64;
+
+	fn to_planar(&self, origin: &Self) -> (f64, f64);
+	fn from_planar(origin: &Self, point: (f64, f64)) -> Self;
+}
+
+impl Planar for Point {
+	const TOLERANCE: f64 = 1.0 / 64.0;
+
+	fn to_planar(&self, _origin: &Self) -> (f64, f64) {
+		(self.x as f64, self.y as f64)
+	}
+
+	fn from_planar(_origin: &Self, (x, y): (f64, f64)) -> Self {
+		Self {
+			x: x as f32,
+			y: y as f32,
+		}
+	}
+}
+
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+// local equirectangular projection in metres, adequate at aerodrome scale
+impl Planar for GeoPoint {
+	const TOLERANCE: f64 = 0.01;
+
+	fn to_planar(&self, origin: &Self) -> (f64, f64) {
+		let k = (origin.geo.lat as f64).to_radians().cos() * METRES_PER_DEGREE;
+
+		(
+			(self.geo.lon - origin.geo.lon) as f64 * k,
+			(self.geo.lat - origin.geo.lat) as f64 * METRES_PER_DEGREE,
+		)
+	}
+
+	fn from_planar(origin: &Self, (x, y): (f64, f64)) -> Self {
+		let k = (origin.geo.lat as f64).to_radians().cos() * METRES_PER_DEGREE;
+
+		Self {
+			geo: Geo {
+				lat: origin.geo.lat + (y / METRES_PER_DEGREE) as f32,
+				lon: origin.geo.lon + (x / k) as f32,
+			},
+			offset: origin.offset,
+		}
+	}
+}
+
+// coarsest power-of-two quantum, starting from one unit, which stores every
+// point of a path to within tolerance; paths drawn on a coarse grid (such as
+// whole SVG units) can then be stored with fewer bits per point
+fn fit_quantum<T: Planar + Quantize>(points: &[T]) -> f32 {
+	const MAX_HALVINGS: i32 = 40;
+
+	let mut fixed = Vec::with_capacity(T::DIMENSIONS);
+	let fits = |quantum: f32, fixed: &mut Vec<i64>| {
+		points.iter().all(|point| {
+			fixed.clear();
+			point.to_fixed(quantum, fixed);
+
+			let (x, y) = T::from_fixed(fixed, quantum).to_planar(point);
+			let (ox, oy) = point.to_planar(point);
+			(x - ox).hypot(y - oy) <= T::TOLERANCE
+		})
+	};
+
+	(0..MAX_HALVINGS)
+		.map(|i| 2.0f32.powi(-i))
+		.find(|quantum| fits(*quantum, &mut fixed))
+		.unwrap_or(2.0f32.powi(-MAX_HALVINGS))
+}
+
+const TARGET_SEGMENTS: usize = 8;
+
+// generate a click target for a node with no explicit one, by padding the
+// convex hull of its on/off geometry
+pub fn generate_target<T: Clone + Debug + Planar>(
+	display: &mut NodeDisplay<T>,
+	padding: f64,
+) {
+	if !display.target.points.is_empty() {
+		return
+	}
+
+	let mut points = display
+		.off
+		.iter()
+		.chain(&display.on)
+		.flat_map(|path| &path.points)
+		.peekable();
+	let Some(origin) = points.peek().cloned().cloned() else {
+		return
+	};
+
+	let mut planar = Vec::new();
+	for point in points {
+		let (x, y) = point.to_planar(&origin);
+
+		for i in 0..TARGET_SEGMENTS {
+			let angle = i as f64 * std::f64::consts::TAU / TARGET_SEGMENTS as f64;
+			planar.push((x + padding * angle.cos(), y + padding * angle.sin()));
+		}
+	}
+
+	display.target = Target {
+		points: convex_hull(planar)
+			.into_iter()
+			.map(|point| T::from_planar(&origin, point))
+			.collect(),
+	};
+}
+
+fn convex_hull(mut points: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+	fn chain<'a>(
+		points: impl Iterator<Item = &'a (f64, f64)>,
+	) -> Vec<(f64, f64)> {
+		let mut chain = Vec::<(f64, f64)>::new();
+
+		for &(x, y) in points {
+			while let [.., (ox, oy), (ax, ay)] = chain[..] {
+				if (ax - ox) * (y - oy) - (ay - oy) * (x - ox) > 0.0 {
+					break
+				}
+
+				chain.pop();
+			}
+
+			chain.push((x, y));
+		}
+
+		chain.pop();
+		chain
+	}
+
+	points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	points.dedup();
+
+	if points.len() < 3 {
+		return points
+	}
+
+	let mut hull = chain(points.iter());
+	hull.append(&mut chain(points.iter().rev()));
+	hull
+}
+
 pub trait Input: Sized {
 	type Point;
 
@@ -309,11 +552,62 @@ pub trait Input: Sized {
 
 pub struct Svg<'a> {
 	group: &'a Group,
+	opacity: f32,
 }
 
 impl<'a> Svg<'a> {
 	pub fn new(svg: &'a Tree) -> Self {
-		Self { group: svg.root() }
+		Self {
+			group: svg.root(),
+			opacity: svg.root().opacity().get(),
+		}
+	}
+
+	// named colours and `currentColor` are resolved by usvg, so only opacity
+	// and non-solid paints need to be handled here
+	fn color(&self, paint: &Paint, opacity: Opacity) -> Color {
+		let (color, alpha) = match paint {
+			Paint::Color(color) => (*color, 1.0),
+			Paint::LinearGradient(gradient) => Self::average_stops(gradient.stops()),
+			Paint::RadialGradient(gradient) => Self::average_stops(gradient.stops()),
+			Paint::Pattern(_) => {
+				eprintln!("warning: pattern paint is not supported");
+				(usvg::Color::black(), 1.0)
+			},
+		};
+
+		Color {
+			r: color.red,
+			g: color.green,
+			b: color.blue,
+			a: (alpha * opacity.get() * self.opacity * 255.0).round() as u8,
+		}
+	}
+
+	fn average_stops(stops: &[Stop]) -> (usvg::Color, f32) {
+		if stops.is_empty() {
+			return (usvg::Color::black(), 0.0)
+		}
+
+		let n = stops.len() as f32;
+		let sum = stops.iter().fold([0.0; 4], |[r, g, b, a], stop| {
+			let color = stop.color();
+			[
+				r + color.red as f32,
+				g + color.green as f32,
+				b + color.blue as f32,
+				a + stop.opacity().get(),
+			]
+		});
+
+		(
+			usvg::Color::new_rgb(
+				(sum[0] / n).round() as u8,
+				(sum[1] / n).round() as u8,
+				(sum[2] / n).round() as u8,
+			),
+			sum[3] / n,
+		)
 	}
 }
 
@@ -330,6 +624,9 @@ impl Input for Svg<'_> {
 	}
 
 	fn groups(&self) -> Vec<Self> {
+		// usvg has already materialised `use` references and nested `svg`
+		// elements into groups, so only text needs special handling here
+
 		self
 			.group
 			.children()
@@ -337,6 +634,11 @@ impl Input for Svg<'_> {
 			.filter_map(|node| match node {
 				Node::Group(group) => Some(Self {
 					group: group.as_ref(),
+					opacity: self.opacity * group.opacity().get(),
+				}),
+				Node::Text(text) => Some(Self {
+					group: text.flattened(),
+					opacity: self.opacity * text.flattened().opacity().get(),
 				}),
 				_ => None,
 			})
@@ -346,37 +648,42 @@ impl Input for Svg<'_> {
 	fn paths(&self) -> impl Iterator<Item = TempPath<Self::Point>> {
 		self.group.children().iter().filter_map(|node| {
 			if let Node::Path(path) = node {
+				if !path.is_visible() {
+					return None
+				}
+
+				// path data is in object space; resolve the full transform chain
+				// (including any viewBox mappings) into canvas space
+				let transform = path.abs_transform();
+				let (sx, sy) = transform.get_scale();
+				let Some(data) = path.data().clone().transform(transform) else {
+					return Some(TempPath {
+						id: Some(path.id().into()),
+						points: Vec::new(),
+						style: TempStyle {
+							stroke_width: 0,
+							stroke_color: Color::default(),
+							fill: None,
+						},
+						degenerate: true,
+					});
+				};
+
 				let mut style = TempStyle {
 					stroke_width: 0,
 					stroke_color: Color::default(),
-					fill: path.fill().map(|fill| {
-						let Paint::Color(color) = fill.paint() else {
-							unimplemented!()
-						};
-						Color {
-							r: color.red,
-							g: color.green,
-							b: color.blue,
-							a: fill.opacity().to_u8(),
-						}
-					}),
+					fill: path
+						.fill()
+						.map(|fill| self.color(fill.paint(), fill.opacity())),
 				};
 
 				if let Some(stroke) = path.stroke() {
-					style.stroke_width = stroke.width().get().ceil() as u8;
-
-					let Paint::Color(color) = stroke.paint() else {
-						unimplemented!()
-					};
-					style.stroke_color = Color {
-						r: color.red,
-						g: color.green,
-						b: color.blue,
-						a: stroke.opacity().to_u8(),
-					};
+					style.stroke_width =
+						(stroke.width().get() * (sx * sy).sqrt()).ceil() as u8;
+					style.stroke_color = self.color(stroke.paint(), stroke.opacity());
 				}
 
-				let mut data = path.data().segments();
+				let mut data = data.segments();
 				data.set_auto_close(true);
 
 				let mut points = Vec::new();
@@ -389,7 +696,7 @@ impl Input for Svg<'_> {
 				}
 
 				kurbo::flatten(
-					data.into_iter().map(|segment| match segment {
+					data.map(|segment| match segment {
 						PathSegment::MoveTo(p) => PathEl::MoveTo(c(p)),
 						PathSegment::LineTo(p) => PathEl::LineTo(c(p)),
 						PathSegment::QuadTo(p, q) => PathEl::QuadTo(c(p), c(q)),
@@ -418,6 +725,7 @@ impl Input for Svg<'_> {
 					},
 					points,
 					style,
+					degenerate: false,
 				})
 			} else {
 				None
@@ -524,7 +832,7 @@ impl Input for KmlInput<'_> {
 		self.children.iter().find_map(|kml| {
 			if let KmlItem::Element(element) = kml {
 				(element.name == "name")
-					.then_some(element.content.as_ref().map(|s| s.as_str()))
+					.then_some(element.content.as_deref())
 					.flatten()
 			} else {
 				None
@@ -572,7 +880,7 @@ impl Input for KmlInput<'_> {
 			}
 
 			let points = coords
-				.into_iter()
+				.iter()
 				.map(|point| GeoPoint {
 					geo: Geo {
 						lat: point.y,
@@ -586,6 +894,7 @@ impl Input for KmlInput<'_> {
 				id: id.clone(),
 				points,
 				style,
+				degenerate: false,
 			}]
 		}
 
@@ -603,7 +912,7 @@ impl Input for KmlInput<'_> {
 					let styles = self.styles.borrow();
 					let style = styles.get(style_url)?;
 
-					Some(convert_geometry(geom, &name, *style))
+					Some(convert_geometry(geom, name, *style))
 				} else {
 					None
 				}
@@ -614,7 +923,13 @@ impl Input for KmlInput<'_> {
 
 pub struct GeoSvg<'a> {
 	svg: Svg<'a>,
-	transform: [f64; 4],
+	transform: GeoTransform,
+}
+
+#[derive(Clone, Copy)]
+enum GeoTransform {
+	Bounds([f64; 4]),
+	Grid(Grid),
 }
 
 impl<'a> GeoSvg<'a> {
@@ -623,20 +938,35 @@ impl<'a> GeoSvg<'a> {
 
 		Self {
 			svg: Svg::new(svg),
-			transform: [
+			transform: GeoTransform::Bounds([
 				(lat.1 - lat.0) / size.height() as f64,
 				lat.0,
 				(lon.1 - lon.0) / size.width() as f64,
 				lon.0,
-			],
+			]),
+		}
+	}
+
+	pub fn with_grid(svg: &'a Tree, grid: Grid) -> Self {
+		Self {
+			svg: Svg::new(svg),
+			transform: GeoTransform::Grid(grid),
 		}
 	}
 
 	fn transform(&self, p: Point) -> GeoPoint {
+		let (lat, lon) = match &self.transform {
+			GeoTransform::Bounds(transform) => (
+				transform[0] * p.y as f64 + transform[1],
+				transform[2] * p.x as f64 + transform[3],
+			),
+			GeoTransform::Grid(grid) => grid.project((p.x as f64, p.y as f64)),
+		};
+
 		GeoPoint {
 			geo: Geo {
-				lat: (self.transform[0] * p.y as f64 + self.transform[1]) as f32,
-				lon: (self.transform[2] * p.x as f64 + self.transform[3]) as f32,
+				lat: lat as f32,
+				lon: lon as f32,
 			},
 			offset: Point::default(),
 		}
@@ -653,17 +983,11 @@ impl Input for GeoSvg<'_> {
 	fn groups(&self) -> Vec<Self> {
 		self
 			.svg
-			.group
-			.children()
-			.iter()
-			.filter_map(|node| match node {
-				Node::Group(group) => Some(Self {
-					svg: Svg {
-						group: group.as_ref(),
-					},
-					transform: self.transform,
-				}),
-				_ => None,
+			.groups()
+			.into_iter()
+			.map(|svg| Self {
+				svg,
+				transform: self.transform,
 			})
 			.collect()
 	}
@@ -677,6 +1001,114 @@ impl Input for GeoSvg<'_> {
 				.map(|point| self.transform(point))
 				.collect(),
 			style: path.style,
+			degenerate: path.degenerate,
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// styles of every path in a document, as flattened by `Svg`
+	fn styles(svg: &str) -> Vec<TempStyle> {
+		let tree = Tree::from_str(svg, &Default::default()).unwrap();
+
+		let mut styles = Vec::new();
+		let mut queue = vec![Svg::new(&tree)];
+		while let Some(svg) = queue.pop() {
+			styles.extend(svg.paths().map(|path| path.style));
+			queue.extend(svg.groups());
+		}
+
+		styles
+	}
+
+	fn color(r: u8, g: u8, b: u8, a: u8) -> Color {
+		Color { r, g, b, a }
+	}
+
+	#[test]
+	fn solid_fill() {
+		let styles = styles(
+			r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+				<rect width="10" height="10" fill="#336699" />
+			</svg>"##,
+		);
+
+		assert_eq!(styles.len(), 1);
+		assert_eq!(styles[0].fill, Some(color(0x33, 0x66, 0x99, 255)));
+		assert_eq!(styles[0].stroke_width, 0);
+	}
+
+	#[test]
+	fn paint_opacity() {
+		let styles = styles(
+			r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+				<rect width="10" height="10" fill="#ff0000" fill-opacity="0.5"
+					stroke="#00ff00" stroke-opacity="0.25" stroke-width="2" />
+			</svg>"##,
+		);
+
+		assert_eq!(styles.len(), 1);
+		assert_eq!(styles[0].fill, Some(color(255, 0, 0, 128)));
+		assert_eq!(styles[0].stroke_color, color(0, 255, 0, 64));
+		assert_eq!(styles[0].stroke_width, 2);
+	}
+
+	#[test]
+	fn inherited_opacity() {
+		let styles = styles(
+			r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+				<g id="outer" opacity="0.5">
+					<g id="inner" opacity="0.5">
+						<rect width="10" height="10" fill="#0000ff" fill-opacity="0.5" />
+					</g>
+				</g>
+			</svg>"##,
+		);
+
+		// 0.5 * 0.5 * 0.5 of full alpha
+		assert_eq!(styles.len(), 1);
+		assert_eq!(styles[0].fill, Some(color(0, 0, 255, 32)));
+	}
+
+	#[test]
+	fn gradient_average() {
+		let styles = styles(
+			r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+				<defs>
+					<linearGradient id="gradient">
+						<stop offset="0" stop-color="#ff0000" />
+						<stop offset="1" stop-color="#0000ff" stop-opacity="0.5" />
+					</linearGradient>
+				</defs>
+				<rect width="10" height="10" fill="url(#gradient)" />
+			</svg>"##,
+		);
+
+		// the mean of the stops, with the mean of their opacities
+		assert_eq!(styles.len(), 1);
+		assert_eq!(styles[0].fill, Some(color(128, 0, 128, 191)));
+	}
+
+	#[test]
+	fn gradient_with_opacity() {
+		let styles = styles(
+			r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+				<defs>
+					<radialGradient id="gradient">
+						<stop offset="0" stop-color="#ffffff" />
+						<stop offset="1" stop-color="#000000" />
+					</radialGradient>
+				</defs>
+				<g opacity="0.5">
+					<rect width="10" height="10" fill="url(#gradient)" />
+				</g>
+			</svg>"##,
+		);
+
+		assert_eq!(styles.len(), 1);
+		assert_eq!(styles[0].fill, Some(color(128, 128, 128, 128)));
+	}
+}
@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use bars_config::{
+	Aerodrome, BlockState, Color, Config, EdgeCondition, FillStyle,
+	NodeCondition, Path as LibPath, Point, Profile, ResetCondition, Style,
+};
+
+use anyhow::{bail, Context, Result};
+
+use tiny_skia::{
+	FillRule, LineCap, LineJoin, Mask, Paint, PathBuilder, Pixmap, Rect, Stroke,
+	Transform,
+};
+
+const HATCH_SPACING: f32 = 8.0;
+
+pub struct Options<'a> {
+	pub aerodrome: Option<&'a str>,
+	pub view: Option<&'a str>,
+	pub profile: Option<&'a str>,
+	pub preset: Option<&'a str>,
+	pub size: (u32, u32),
+}
+
+pub fn run(file: &Path, output: &Path, options: Options) -> Result<()> {
+	let config = Config::load(BufReader::new(File::open(file)?))?;
+
+	let aerodrome = match options.aerodrome {
+		Some(icao) => config
+			.aerodromes
+			.iter()
+			.find(|aerodrome| aerodrome.icao.eq_ignore_ascii_case(icao))
+			.with_context(|| format!("package has no aerodrome {icao}"))?,
+		None => config
+			.aerodromes
+			.first()
+			.context("package has no aerodromes")?,
+	};
+
+	let view = match options.view {
+		Some(name) => aerodrome
+			.views
+			.iter()
+			.find(|view| view.name == name)
+			.with_context(|| format!("{} has no view {name:?}", aerodrome.icao))?,
+		None => aerodrome
+			.views
+			.first()
+			.with_context(|| format!("{} has no views", aerodrome.icao))?,
+	};
+
+	let profile = match options.profile {
+		Some(name) => Some(
+			aerodrome
+				.profiles
+				.iter()
+				.find(|profile| profile.id == name || profile.name == name)
+				.with_context(|| {
+					format!("{} has no profile {name:?}", aerodrome.icao)
+				})?,
+		),
+		None => aerodrome.profiles.first(),
+	};
+
+	let mut state = State::new(aerodrome, profile);
+
+	if let Some(name) = options.preset {
+		let Some(profile) = profile else {
+			bail!("{} has no profiles", aerodrome.icao);
+		};
+
+		let Some(i) = profile
+			.presets
+			.iter()
+			.position(|preset| preset.name == name)
+		else {
+			bail!("profile {:?} has no preset {name:?}", profile.name);
+		};
+
+		state.apply_preset(i);
+	}
+
+	let (width, height) = options.size;
+	let Some(mut pixmap) = Pixmap::new(width, height) else {
+		bail!("invalid image size {width}x{height}");
+	};
+
+	let map = &aerodrome.maps[view.map];
+	let transform = view_transform(options.size, view.bounds);
+	let canvas = Canvas {
+		styles: &aerodrome.styles,
+		transform,
+	};
+
+	pixmap.fill(color(map.background));
+
+	for path in &map.base {
+		canvas.draw(&mut pixmap, path);
+	}
+
+	for (i, edge) in map.edges.iter().enumerate() {
+		if let Some(EdgeCondition::Fixed { state: false }) = state.edge_condition(i)
+		{
+			continue
+		}
+
+		let display = if state.edge(i) { &edge.on } else { &edge.off };
+		for path in display {
+			canvas.draw(&mut pixmap, path);
+		}
+	}
+
+	for (i, node) in map.nodes.iter().enumerate() {
+		if aerodrome.nodes[i].parent.is_some() {
+			continue
+		}
+
+		if let Some(NodeCondition::Fixed { state: false }) = state.node_condition(i)
+		{
+			continue
+		}
+
+		let display = if state.node(i) { &node.on } else { &node.off };
+		for path in display {
+			canvas.draw(&mut pixmap, path);
+		}
+	}
+
+	pixmap
+		.save_png(output)
+		.with_context(|| format!("failed to write {}", output.display()))?;
+
+	Ok(())
+}
+
+// equivalent to the client's fit-to-viewport transform for schematic views
+fn view_transform(
+	(width, height): (u32, u32),
+	bounds: bars_config::Box,
+) -> Transform {
+	let (width, height) = (width as f32, height as f32);
+	let bounds_w = bounds.max.x - bounds.min.x;
+	let bounds_h = bounds.max.y - bounds.min.y;
+
+	let (scale, offset_x, offset_y) = if bounds_w / bounds_h > width / height {
+		let scale = width / bounds_w;
+		(scale, 0.0, (height - bounds_h * scale) * 0.5)
+	} else {
+		let scale = height / bounds_h;
+		(scale, (width - bounds_w * scale) * 0.5, 0.0)
+	};
+
+	Transform::from_row(
+		scale,
+		0.0,
+		0.0,
+		scale,
+		scale * -bounds.min.x + offset_x,
+		scale * -bounds.min.y + offset_y,
+	)
+}
+
+// GDI ignores alpha, so the preview does too
+fn color(color: Color) -> tiny_skia::Color {
+	tiny_skia::Color::from_rgba8(color.r, color.g, color.b, 0xff)
+}
+
+struct Canvas<'a> {
+	styles: &'a [Style],
+	transform: Transform,
+}
+
+impl Canvas<'_> {
+	fn draw(&self, pixmap: &mut Pixmap, path: &LibPath<Point>) {
+		let Some(style) = self.styles.get(path.style) else {
+			return
+		};
+
+		let filled = style.fill_style != FillStyle::None;
+
+		let mut builder = PathBuilder::new();
+		for (i, point) in path.points.iter().enumerate() {
+			let (x, y) = self.project(point);
+
+			if i == 0 {
+				builder.move_to(x, y);
+			} else {
+				builder.line_to(x, y);
+			}
+		}
+
+		if filled {
+			builder.close();
+		}
+
+		let Some(shape) = builder.finish() else {
+			return
+		};
+
+		let mut paint = Paint {
+			anti_alias: true,
+			..Default::default()
+		};
+
+		match style.fill_style {
+			FillStyle::None => (),
+			FillStyle::Solid => {
+				paint.set_color(color(style.fill_color));
+				pixmap.fill_path(
+					&shape,
+					&paint,
+					FillRule::EvenOdd,
+					Transform::identity(),
+					None,
+				);
+			},
+			hatch => {
+				if let Some(mask) = mask(pixmap, &shape) {
+					paint.set_color(color(style.fill_color));
+					hatch_fill(pixmap, &paint, hatch, shape.bounds(), &mask);
+				}
+			},
+		}
+
+		if style.stroke_width > 0.0 {
+			paint.set_color(color(style.stroke_color));
+
+			let stroke = Stroke {
+				width: style.stroke_width.ceil(),
+				line_cap: LineCap::Round,
+				line_join: LineJoin::Round,
+				..Default::default()
+			};
+
+			pixmap.stroke_path(&shape, &paint, &stroke, Transform::identity(), None);
+		}
+	}
+
+	fn project(&self, point: &Point) -> (f32, f32) {
+		let mut point = tiny_skia::Point::from_xy(point.x, point.y);
+		self.transform.map_point(&mut point);
+		(point.x, point.y)
+	}
+}
+
+fn mask(pixmap: &Pixmap, shape: &tiny_skia::Path) -> Option<Mask> {
+	let mut mask = Mask::new(pixmap.width(), pixmap.height())?;
+	mask.fill_path(shape, FillRule::EvenOdd, true, Transform::identity());
+	Some(mask)
+}
+
+fn hatch_fill(
+	pixmap: &mut Pixmap,
+	paint: &Paint,
+	hatch: FillStyle,
+	bounds: Rect,
+	mask: &Mask,
+) {
+	let (horizontal, vertical, forward, backward) = match hatch {
+		FillStyle::HatchHorizontal => (true, false, false, false),
+		FillStyle::HatchVertical => (false, true, false, false),
+		FillStyle::HatchForwardDiagonal => (false, false, true, false),
+		FillStyle::HatchBackwardDiagonal => (false, false, false, true),
+		FillStyle::HatchCross => (true, true, false, false),
+		FillStyle::HatchDiagonalCross => (false, false, true, true),
+		FillStyle::None | FillStyle::Solid => return,
+	};
+
+	// lines are aligned to the pixel grid, as GDI hatch brushes are
+	let (left, top) = (
+		(bounds.left() / HATCH_SPACING).floor() * HATCH_SPACING,
+		(bounds.top() / HATCH_SPACING).floor() * HATCH_SPACING,
+	);
+	let (right, bottom) = (bounds.right(), bounds.bottom());
+	let extent = (right - left) + (bottom - top);
+
+	let mut builder = PathBuilder::new();
+	let mut line = |x0: f32, y0: f32, x1: f32, y1: f32| {
+		builder.move_to(x0, y0);
+		builder.line_to(x1, y1);
+	};
+
+	let mut offset = 0.0;
+	while offset <= extent {
+		if horizontal && top + offset <= bottom {
+			line(left, top + offset, right, top + offset);
+		}
+
+		if vertical && left + offset <= right {
+			line(left + offset, top, left + offset, bottom);
+		}
+
+		if forward {
+			line(left + offset, top, left + offset - extent, top + extent);
+		}
+
+		if backward {
+			let x = left + offset - (bottom - top);
+			line(x, top, x + extent, top + extent);
+		}
+
+		offset += HATCH_SPACING;
+	}
+
+	let Some(lines) = builder.finish() else {
+		return
+	};
+
+	pixmap.stroke_path(
+		&lines,
+		paint,
+		&Stroke::default(),
+		Transform::identity(),
+		Some(mask),
+	);
+}
+
+// a simplified model of the client's lighting state, which evaluates routes
+// without considering child nodes or adjacent blocks
+struct State<'a> {
+	aerodrome: &'a Aerodrome,
+	profile: Option<&'a Profile>,
+	nodes: Vec<bool>,
+	blocks: Vec<BlockState>,
+}
+
+impl<'a> State<'a> {
+	fn new(aerodrome: &'a Aerodrome, profile: Option<&'a Profile>) -> Self {
+		let nodes = (0..aerodrome.nodes.len())
+			.map(|i| match profile.map(|profile| profile.nodes[i]) {
+				Some(NodeCondition::Fixed { state }) => state,
+				Some(NodeCondition::Direct { reset }) => reset != ResetCondition::None,
+				_ => true,
+			})
+			.collect();
+
+		Self {
+			aerodrome,
+			profile,
+			nodes,
+			blocks: vec![BlockState::Clear; aerodrome.blocks.len()],
+		}
+	}
+
+	fn apply_preset(&mut self, i: usize) {
+		let preset = &self.profile.unwrap().presets[i];
+
+		apply(&mut self.nodes, &preset.nodes);
+		apply(&mut self.blocks, &preset.blocks);
+	}
+
+	fn node_condition(&self, node: usize) -> Option<NodeCondition> {
+		self.profile.map(|profile| profile.nodes[node])
+	}
+
+	fn edge_condition(&self, edge: usize) -> Option<&'a EdgeCondition> {
+		self.profile.map(|profile| &profile.edges[edge])
+	}
+
+	fn node(&self, node: usize) -> bool {
+		match self.node_condition(node) {
+			Some(NodeCondition::Fixed { state }) => state,
+			Some(NodeCondition::Router) => self
+				.aerodrome
+				.blocks
+				.iter()
+				.enumerate()
+				.filter(|(_, block)| block.nodes.contains(&node))
+				.any(|(i, _)| match self.blocks[i] {
+					BlockState::Clear => true,
+					BlockState::Relax => false,
+					BlockState::Route((a, b)) => a != node && b != node,
+				}),
+			_ => self.nodes[node],
+		}
+	}
+
+	fn edge(&self, edge: usize) -> bool {
+		match self.edge_condition(edge) {
+			Some(EdgeCondition::Fixed { state }) => *state,
+			Some(EdgeCondition::Direct { node }) => !self.node(*node),
+			Some(EdgeCondition::Router { block, routes }) => {
+				match self.blocks[*block] {
+					BlockState::Clear => false,
+					BlockState::Relax => true,
+					BlockState::Route((a, b)) => {
+						routes.contains(&(a, b)) || routes.contains(&(b, a))
+					},
+				}
+			},
+			None => false,
+		}
+	}
+}
+
+// a wildcard index (u32::MAX) applies to all items not already set
+fn apply<T: Copy>(items: &mut [T], changes: &[(usize, T)]) {
+	let mut set = vec![false; items.len()];
+
+	for (i, state) in changes {
+		if (*i as u32) < u32::MAX {
+			items[*i] = *state;
+			set[*i] = true;
+		} else {
+			for (item, set) in items.iter_mut().zip(&mut set) {
+				if !*set {
+					*item = *state;
+					*set = true;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,175 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use bars_config::bincode::{DefaultOptions, Options};
+use bars_config::{
+	Aerodrome, BlockDisplay, ClosureDisplay, Config, EdgeDisplay, NodeDisplay,
+	Path as LibPath,
+};
+
+use anyhow::Result;
+
+pub fn run(file: &Path) -> Result<()> {
+	let compressed = std::fs::metadata(file)?.len();
+	let config = Config::load(BufReader::new(File::open(file)?))?;
+
+	println!(
+		"package {} {}",
+		config.name.as_deref().unwrap_or("(unnamed)"),
+		config.version.as_deref().unwrap_or("(unversioned)"),
+	);
+	println!("{compressed} bytes on disk");
+
+	let mut total = 0;
+
+	for aerodrome in &config.aerodromes {
+		let size = DefaultOptions::new().serialized_size(aerodrome)?;
+		total += size;
+
+		println!();
+		print_aerodrome(aerodrome, size);
+	}
+
+	println!();
+	println!(
+		"{} aerodromes, {total} bytes uncompressed ({:.1}x compression)",
+		config.aerodromes.len(),
+		total as f64 / compressed.max(1) as f64,
+	);
+
+	Ok(())
+}
+
+fn print_aerodrome(aerodrome: &Aerodrome, size: u64) {
+	let presets = aerodrome
+		.profiles
+		.iter()
+		.map(|profile| profile.presets.len())
+		.sum::<usize>();
+
+	println!("{} ({size} bytes uncompressed)", aerodrome.icao);
+	println!(
+		"  {} elements, {} nodes, {} edges, {} blocks, {} closures",
+		aerodrome.elements.len(),
+		aerodrome.nodes.len(),
+		aerodrome.edges.len(),
+		aerodrome.blocks.len(),
+		aerodrome.closures.len(),
+	);
+	println!(
+		"  {} profiles, {presets} presets, {} styles",
+		aerodrome.profiles.len(),
+		aerodrome.styles.len(),
+	);
+
+	let geo = Counts::default()
+		.nodes(aerodrome.nodes.iter().map(|node| &node.display))
+		.edges(aerodrome.edges.iter().map(|edge| &edge.display))
+		.blocks(aerodrome.blocks.iter().map(|block| &block.display))
+		.closures(aerodrome.closures.iter().map(|closure| &closure.display));
+	println!("  geo: {geo}");
+
+	for (i, map) in aerodrome.maps.iter().enumerate() {
+		let views = aerodrome
+			.views
+			.iter()
+			.filter(|view| view.map == i)
+			.map(|view| view.name.as_str())
+			.collect::<Vec<_>>();
+
+		let counts = Counts::default()
+			.paths(&map.base)
+			.nodes(map.nodes.iter())
+			.edges(map.edges.iter())
+			.blocks(map.blocks.iter())
+			.closures(map.closures.iter());
+		println!("  map {i} [{}]: {counts}", views.join(", "));
+	}
+}
+
+#[derive(Default)]
+struct Counts {
+	paths: usize,
+	points: usize,
+	targets: usize,
+	target_points: usize,
+}
+
+impl Counts {
+	fn paths<'a, T: Clone + Debug + 'a>(
+		mut self,
+		paths: impl IntoIterator<Item = &'a LibPath<T>>,
+	) -> Self {
+		for path in paths {
+			self.paths += 1;
+			self.points += path.points.len();
+		}
+
+		self
+	}
+
+	fn target<T>(&mut self, points: &[T]) {
+		if !points.is_empty() {
+			self.targets += 1;
+			self.target_points += points.len();
+		}
+	}
+
+	fn nodes<'a, T: Clone + Debug + 'a>(
+		mut self,
+		nodes: impl Iterator<Item = &'a NodeDisplay<T>>,
+	) -> Self {
+		for node in nodes {
+			self.target(&node.target.points);
+			self = self.paths(node.off.iter().chain(&node.on).chain(&node.selected));
+		}
+
+		self
+	}
+
+	fn edges<'a, T: Clone + Debug + 'a>(
+		mut self,
+		edges: impl Iterator<Item = &'a EdgeDisplay<T>>,
+	) -> Self {
+		for edge in edges {
+			self = self.paths(edge.off.iter().chain(&edge.on));
+		}
+
+		self
+	}
+
+	fn blocks<'a, T: Clone + Debug + 'a>(
+		mut self,
+		blocks: impl Iterator<Item = &'a BlockDisplay<T>>,
+	) -> Self {
+		for block in blocks {
+			self.target(&block.target.points);
+			self = self.paths(block.relax.iter().chain(&block.route));
+		}
+
+		self
+	}
+
+	fn closures<'a, T: Clone + Debug + 'a>(
+		mut self,
+		closures: impl Iterator<Item = &'a ClosureDisplay<T>>,
+	) -> Self {
+		for closure in closures {
+			self = self.paths(&closure.area);
+		}
+
+		self
+	}
+}
+
+impl std::fmt::Display for Counts {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} paths ({} points), {} targets ({} points)",
+			self.paths, self.points, self.targets, self.target_points,
+		)
+	}
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use serde_json::{json, Map, Value};
+
+static INCLUDE_KEY: &str = "$include";
+static VARS_KEY: &str = "$vars";
+// definitions added to generated schemas
+static INCLUDE_DEF: &str = "Include";
+static VARS_DEF: &str = "Vars";
+
+type Vars = HashMap<String, String>;
+
+// loads a JSON, YAML or TOML file, expanding `{ "$include": "file.json",
+// "$vars": { .. } }` objects (merging any sibling keys over the included
+// object, or splicing included arrays into a parent array) and `${NAME}`
+// references in strings and keys; every file read is appended to `deps`
+pub fn load(path: &Path, deps: &mut Vec<PathBuf>) -> Result<Value> {
+	let mut expander = Expander::default();
+	let res = expander.load(path, &Vars::new());
+	deps.append(&mut expander.deps);
+
+	res
+}
+
+#[derive(Default)]
+struct Expander {
+	stack: Vec<PathBuf>,
+	deps: Vec<PathBuf>,
+}
+
+impl Expander {
+	fn load(&mut self, path: &Path, vars: &Vars) -> Result<Value> {
+		let canonical = path
+			.canonicalize()
+			.with_context(|| format!("failed to resolve {}", path.display()))?;
+
+		if self.stack.contains(&canonical) {
+			bail!("recursive include of {}", path.display());
+		}
+
+		let s = std::fs::read_to_string(path)
+			.with_context(|| format!("failed to read {}", path.display()))?;
+		self.deps.push(path.to_path_buf());
+		let mut value = parse(path, &s)
+			.with_context(|| format!("failed to parse {}", path.display()))?;
+
+		let mut vars = vars.clone();
+		if let Value::Object(object) = &mut value {
+			if let Some(own) = object.remove(VARS_KEY) {
+				vars.extend(parse_vars(own, &vars)?);
+			}
+		}
+
+		self.stack.push(canonical);
+		let dir = path.parent().unwrap_or(Path::new(""));
+		let res = self
+			.expand(value, dir, &vars)
+			.with_context(|| format!("in {}", path.display()));
+		self.stack.pop();
+
+		res
+	}
+
+	fn expand(&mut self, value: Value, dir: &Path, vars: &Vars) -> Result<Value> {
+		Ok(match value {
+			Value::String(s) => Value::String(substitute(&s, vars)?),
+			Value::Array(items) => {
+				let mut out = Vec::with_capacity(items.len());
+
+				for item in items {
+					let splice = is_include(&item);
+
+					match self.expand(item, dir, vars)? {
+						Value::Array(included) if splice => out.extend(included),
+						item => out.push(item),
+					}
+				}
+
+				Value::Array(out)
+			},
+			Value::Object(mut object) => {
+				let include = object.remove(INCLUDE_KEY);
+				let mut vars = vars.clone();
+				if let Some(own) = object.remove(VARS_KEY) {
+					vars.extend(parse_vars(own, &vars)?);
+				}
+
+				let mut expanded = Map::new();
+				for (key, value) in object {
+					expanded
+						.insert(substitute(&key, &vars)?, self.expand(value, dir, &vars)?);
+				}
+
+				let Some(include) = include else {
+					return Ok(Value::Object(expanded))
+				};
+
+				let Value::String(file) = include else {
+					bail!("{INCLUDE_KEY} must be a string");
+				};
+				let file = dir.join(substitute(&file, &vars)?);
+
+				match self.load(&file, &vars)? {
+					Value::Object(mut included) => {
+						included.extend(expanded);
+						Value::Object(included)
+					},
+					included if expanded.is_empty() => included,
+					_ => bail!(
+						"cannot merge keys into non-object include {}",
+						file.display(),
+					),
+				}
+			},
+			value => value,
+		})
+	}
+}
+
+// extends a schema of the expanded document to accept the templated one: any
+// value may instead be an include, objects may define variables and the root
+// may be included with all of its required keys
+pub fn extend_schema(schema: &mut Value) {
+	extend(schema);
+
+	let Some(root) = schema.as_object_mut() else {
+		return
+	};
+
+	if let Some(required) = root.remove("required") {
+		root.insert(
+			"anyOf".to_string(),
+			json!([{ "required": required }, { "required": [INCLUDE_KEY] }]),
+		);
+	}
+	if let Some(Value::Object(properties)) = root.get_mut("properties") {
+		properties.insert(INCLUDE_KEY.to_string(), json!({ "type": "string" }));
+	}
+
+	let defs = root
+		.entry("$defs")
+		.or_insert_with(|| Value::Object(Map::new()));
+	defs[INCLUDE_DEF] = json!({
+		"description": "file whose contents replace this value, with any other \
+			keys merged over it, or spliced into the parent array",
+		"type": "object",
+		"properties": {
+			INCLUDE_KEY: { "type": "string" },
+			VARS_KEY: { "$ref": format!("#/$defs/{VARS_DEF}") },
+		},
+		"required": [INCLUDE_KEY],
+	});
+	defs[VARS_DEF] = json!({
+		"description": "variables substituted for `${NAME}` in strings and keys",
+		"type": "object",
+		"additionalProperties": { "type": ["string", "number", "boolean"] },
+	});
+}
+
+fn extend(schema: &mut Value) {
+	let Some(object) = schema.as_object_mut() else {
+		return
+	};
+
+	for (key, value) in object.iter_mut() {
+		match (key.as_str(), value) {
+			("properties" | "patternProperties", Value::Object(schemas)) => {
+				schemas.values_mut().for_each(include);
+			},
+			("items" | "additionalProperties", value @ Value::Object(_)) => {
+				include(value);
+			},
+			("$defs", Value::Object(schemas)) => {
+				schemas.values_mut().for_each(extend);
+			},
+			("anyOf" | "oneOf" | "allOf", Value::Array(schemas)) => {
+				schemas.iter_mut().for_each(extend);
+			},
+			_ => (),
+		}
+	}
+
+	if let Some(Value::Object(properties)) = object.get_mut("properties") {
+		properties.insert(
+			VARS_KEY.to_string(),
+			json!({ "$ref": format!("#/$defs/{VARS_DEF}") }),
+		);
+	}
+}
+
+fn include(schema: &mut Value) {
+	extend(schema);
+
+	let own = schema.take();
+	*schema = json!({
+		"anyOf": [own, { "$ref": format!("#/$defs/{INCLUDE_DEF}") }],
+	});
+}
+
+// YAML and TOML documents are read into the same representation as JSON, so
+// that templating and deserialization are independent of the input format
+fn parse(path: &Path, s: &str) -> Result<Value> {
+	let extension = path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.map(str::to_ascii_lowercase);
+
+	Ok(match extension.as_deref() {
+		Some("yaml" | "yml") => {
+			let mut value = serde_yaml::from_str::<serde_yaml::Value>(s)?;
+			value.apply_merge()?;
+			serde_json::to_value(value)?
+		},
+		Some("toml") => toml::from_str::<Value>(s)?,
+		_ => serde_json::from_str::<Value>(s)?,
+	})
+}
+
+fn is_include(value: &Value) -> bool {
+	value
+		.as_object()
+		.map(|object| object.contains_key(INCLUDE_KEY))
+		.unwrap_or_default()
+}
+
+fn parse_vars(value: Value, outer: &Vars) -> Result<Vars> {
+	let Value::Object(object) = value else {
+		bail!("{VARS_KEY} must be an object");
+	};
+
+	object
+		.into_iter()
+		.map(|(key, value)| match value {
+			Value::String(s) => Ok((key, substitute(&s, outer)?)),
+			Value::Number(n) => Ok((key, n.to_string())),
+			Value::Bool(b) => Ok((key, b.to_string())),
+			_ => bail!("variable {key} must be a string, number or boolean"),
+		})
+		.collect()
+}
+
+fn substitute(s: &str, vars: &Vars) -> Result<String> {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+
+	while let Some(start) = rest.find("${") {
+		out.push_str(&rest[..start]);
+
+		let Some(len) = rest[start + 2..].find('}') else {
+			bail!("unterminated variable reference in {s:?}");
+		};
+
+		let name = &rest[start + 2..][..len];
+		let Some(value) = vars.get(name) else {
+			bail!("undefined variable {name:?}");
+		};
+
+		out.push_str(value);
+		rest = &rest[start + 2 + len + 1..];
+	}
+
+	out.push_str(rest);
+
+	Ok(out)
+}
@@ -0,0 +1,237 @@
+use crate::load_fixtures;
+use crate::message::Message;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use bars_protocol::{Downstream, Encoding, Upstream};
+
+use anyhow::{anyhow, bail, Result};
+
+use fastrand::Rng;
+
+use serde_json::{Map, Value};
+
+// decodes fixtures with random structural and byte-level changes, in both
+// encodings and as both directions; the seed is printed so that a failure can
+// be reproduced
+pub fn run(dir: &Path, iterations: u64, seed: u64) -> Result<()> {
+	let mut corpus = Vec::new();
+	for direction in ["upstream", "downstream"] {
+		let fixtures = load_fixtures(&dir.join(direction))?;
+		corpus.extend(fixtures.into_iter().map(|(_, fixture)| fixture));
+	}
+
+	if corpus.is_empty() {
+		bail!("no fixtures in {}", dir.display());
+	}
+
+	// keys and strings from the fixtures make mutations likelier to reach
+	// deeper than the type tag
+	let mut words = Vec::new();
+	for fixture in &corpus {
+		collect_words(fixture, &mut words);
+	}
+	words.sort();
+	words.dedup();
+
+	eprintln!("fuzzing with seed {seed}");
+
+	let mut rng = Rng::with_seed(seed);
+	let mut decoded = 0;
+
+	for i in 0..iterations {
+		let mut value = corpus[rng.usize(..corpus.len())].clone();
+		for _ in 0..rng.usize(1..=4) {
+			mutate(&mut rng, &mut value, &words);
+		}
+
+		let encoding = if rng.bool() {
+			Encoding::Json
+		} else {
+			Encoding::MessagePack
+		};
+		let mut data = encoding.encode(&value)?;
+		if rng.u8(..4) == 0 {
+			mutate_bytes(&mut rng, &mut data);
+		}
+
+		let res = panic::catch_unwind(AssertUnwindSafe(|| {
+			Ok(
+				decode::<Upstream>(encoding, &data)?
+					+ decode::<Downstream>(encoding, &data)?,
+			)
+		}))
+		.unwrap_or_else(|_| Err(anyhow!("decoding panicked")));
+
+		match res {
+			Ok(n) => decoded += n,
+			Err(err) => {
+				let input = if encoding.is_binary() {
+					data.iter().map(|byte| format!("{byte:02x}")).collect()
+				} else {
+					String::from_utf8_lossy(&data).into_owned()
+				};
+
+				bail!(
+					"input {i} (seed {seed}): {err}\n{} input: {input}",
+					encoding.name(),
+				);
+			},
+		}
+	}
+
+	eprintln!("{iterations} inputs, {decoded} decoded");
+
+	Ok(())
+}
+
+// checks that a decoded message encodes and decodes again unchanged
+fn decode<M: Message>(encoding: Encoding, data: &[u8]) -> Result<usize> {
+	let Ok(message) = encoding.decode::<M>(data) else {
+		return Ok(0)
+	};
+
+	// anything decodes as the catch-all, and it cannot be encoded
+	if message.is_unknown() {
+		return Ok(0)
+	}
+
+	let expected = serde_json::to_value(&message)?;
+
+	for encoding in [Encoding::Json, Encoding::MessagePack] {
+		let data = encoding.encode(&message)?;
+		let decoded = encoding.decode::<M>(&data).map_err(|err| {
+			anyhow!(
+				"{} {} does not decode again from {}: {err}",
+				M::DIRECTION,
+				message.tag(),
+				encoding.name(),
+			)
+		})?;
+
+		if !equivalent(&serde_json::to_value(&decoded)?, &expected) {
+			bail!(
+				"{} {} changed when re-encoded as {}",
+				M::DIRECTION,
+				message.tag(),
+				encoding.name(),
+			);
+		}
+	}
+
+	Ok(1)
+}
+
+// equality, except that floats need only be close, as serde_json does not
+// parse them exactly
+fn equivalent(a: &Value, b: &Value) -> bool {
+	match (a, b) {
+		(Value::Array(a), Value::Array(b)) => {
+			a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equivalent(a, b))
+		},
+		(Value::Object(a), Value::Object(b)) => {
+			a.len() == b.len()
+				&& a
+					.iter()
+					.all(|(key, a)| b.get(key).is_some_and(|b| equivalent(a, b)))
+		},
+		(Value::Number(a), Value::Number(b)) if a.is_f64() || b.is_f64() => {
+			let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+			(a - b).abs() <= f64::EPSILON * a.abs().max(b.abs()).max(1.0)
+		},
+		(a, b) => a == b,
+	}
+}
+
+fn collect_words(value: &Value, words: &mut Vec<String>) {
+	match value {
+		Value::String(string) => words.push(string.clone()),
+		Value::Array(array) => {
+			for value in array {
+				collect_words(value, words);
+			}
+		},
+		Value::Object(map) => {
+			for (key, value) in map {
+				words.push(key.clone());
+				collect_words(value, words);
+			}
+		},
+		_ => (),
+	}
+}
+
+fn mutate(rng: &mut Rng, value: &mut Value, words: &[String]) {
+	let mut node = value;
+	loop {
+		let len = match &node {
+			Value::Array(array) => array.len(),
+			Value::Object(map) => map.len(),
+			_ => 0,
+		};
+
+		if len == 0 || rng.u8(..3) == 0 {
+			break
+		}
+
+		let i = rng.usize(..len);
+		node = match node {
+			Value::Array(array) => &mut array[i],
+			Value::Object(map) => map.values_mut().nth(i).unwrap(),
+			_ => unreachable!(),
+		};
+	}
+
+	match (rng.u8(..4), node) {
+		(0, Value::Object(map)) if !map.is_empty() => {
+			let key = map.keys().nth(rng.usize(..map.len())).unwrap().clone();
+			map.remove(&key);
+		},
+		(1, Value::Object(map)) => {
+			let key = word(rng, words);
+			map.insert(key, random_value(rng, words));
+		},
+		(_, node) => *node = random_value(rng, words),
+	}
+}
+
+fn mutate_bytes(rng: &mut Rng, data: &mut Vec<u8>) {
+	match rng.u8(..4) {
+		_ if data.is_empty() => data.push(rng.u8(..)),
+		0 => data.truncate(rng.usize(..data.len())),
+		1 => {
+			let i = rng.usize(..data.len());
+			data[i] ^= 1 << rng.u8(..8);
+		},
+		2 => data.insert(rng.usize(..=data.len()), rng.u8(..)),
+		_ => {
+			data.remove(rng.usize(..data.len()));
+		},
+	}
+}
+
+fn random_value(rng: &mut Rng, words: &[String]) -> Value {
+	match rng.u8(..8) {
+		0 => Value::Null,
+		1 => Value::Bool(rng.bool()),
+		2 => rng.i64(..).into(),
+		3 => rng.u64(..).into(),
+		4 => ((rng.f64() - 0.5) * 1e6).into(),
+		5 => word(rng, words).into(),
+		6 => (0..rng.usize(..8))
+			.map(|_| rng.alphanumeric())
+			.collect::<String>()
+			.into(),
+		_ if rng.bool() => Value::Array(Vec::new()),
+		_ => Value::Object(Map::new()),
+	}
+}
+
+fn word(rng: &mut Rng, words: &[String]) -> String {
+	if words.is_empty() {
+		String::new()
+	} else {
+		words[rng.usize(..words.len())].clone()
+	}
+}
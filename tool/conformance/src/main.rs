@@ -0,0 +1,272 @@
+mod fuzz;
+mod message;
+mod schema;
+
+use message::Message;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use bars_protocol::{Downstream, Encoding, Upstream};
+
+use anyhow::{bail, Context, Result};
+
+use clap::{Parser, Subcommand};
+
+use serde_json::Value;
+
+/// Check the BARS protocol types against fixture messages.
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Round-trip each fixture, and check that every message type has one.
+	Check {
+		/// read fixtures from DIR
+		#[arg(value_name = "DIR", default_value = "tool/conformance/fixtures")]
+		dir: PathBuf,
+	},
+
+	/// Decode mutated fixtures, checking that decoding never panics.
+	Fuzz {
+		/// read fixtures from DIR
+		#[arg(value_name = "DIR", default_value = "tool/conformance/fixtures")]
+		dir: PathBuf,
+
+		/// decode N inputs
+		#[arg(short = 'n', long, value_name = "N", default_value_t = 100_000)]
+		iterations: u64,
+
+		/// seed the mutator with SEED (default: random)
+		#[arg(short, long, value_name = "SEED")]
+		seed: Option<u64>,
+	},
+
+	/// Generate JSON Schemas and TypeScript definitions of the messages.
+	Schema {
+		/// write files to DIR
+		#[arg(value_name = "DIR", default_value = "shared/protocol/schema")]
+		dir: PathBuf,
+
+		/// check that the files in DIR are up to date instead of writing them
+		#[arg(long)]
+		check: bool,
+	},
+}
+
+fn main() -> ExitCode {
+	let args = Args::parse();
+
+	let res = match args.command {
+		Command::Check { dir } => check(&dir),
+		Command::Fuzz {
+			dir,
+			iterations,
+			seed,
+		} => fuzz::run(&dir, iterations, seed.unwrap_or_else(|| fastrand::u64(..))),
+		Command::Schema { dir, check } => schema::run(&dir, check),
+	};
+
+	match res {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(err) => {
+			eprintln!("error: {err:#}");
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn check(dir: &Path) -> Result<()> {
+	let failures = check_direction::<Upstream>(&dir.join("upstream"))?
+		+ check_direction::<Downstream>(&dir.join("downstream"))?;
+
+	if failures > 0 {
+		bail!("{failures} fixture(s) failed");
+	}
+
+	Ok(())
+}
+
+fn check_direction<M: Message>(dir: &Path) -> Result<usize> {
+	let mut failures = 0;
+	let mut seen = HashSet::new();
+
+	for (path, fixture) in load_fixtures(dir)? {
+		let name = path.display();
+
+		match check_fixture::<M>(&path, &fixture) {
+			Ok(tag) => {
+				seen.insert(tag);
+				eprintln!("ok {name}");
+			},
+			Err(err) => {
+				failures += 1;
+				eprintln!("FAILED {name}: {err:#}");
+			},
+		}
+	}
+
+	for tag in M::TAGS.iter().filter(|tag| !seen.contains(*tag)) {
+		failures += 1;
+		eprintln!("FAILED {}: no fixture for {tag}", M::DIRECTION);
+	}
+
+	Ok(failures)
+}
+
+fn load_fixtures(dir: &Path) -> Result<Vec<(PathBuf, Value)>> {
+	let mut paths = std::fs::read_dir(dir)
+		.with_context(|| format!("failed to read {}", dir.display()))?
+		.map(|entry| Ok(entry?.path()))
+		.filter(|path| {
+			path
+				.as_ref()
+				.map(|path| path.extension().is_some_and(|ext| ext == "json"))
+				.unwrap_or(true)
+		})
+		.collect::<Result<Vec<_>>>()?;
+	paths.sort();
+
+	paths
+		.into_iter()
+		.map(|path| {
+			let data = std::fs::read(&path)
+				.with_context(|| format!("failed to read {}", path.display()))?;
+			let fixture = serde_json::from_slice(&data)
+				.with_context(|| format!("failed to parse {}", path.display()))?;
+
+			Ok((path, fixture))
+		})
+		.collect()
+}
+
+// decodes a fixture, which must be named after its message type, and checks
+// that re-encoding it in each encoding gives back the same message
+fn check_fixture<M: Message>(
+	path: &Path,
+	fixture: &Value,
+) -> Result<&'static str> {
+	let message =
+		serde_json::from_value::<M>(fixture.clone()).context("failed to decode")?;
+
+	let tag = message.tag();
+	let stem = path
+		.file_stem()
+		.and_then(|stem| stem.to_str())
+		.unwrap_or_default()
+		.to_ascii_uppercase();
+	if stem != tag {
+		bail!("decoded as {tag}");
+	}
+
+	// unknown types decode to a catch-all, which cannot be re-encoded as sent
+	if message.is_unknown() {
+		return Ok(tag)
+	}
+
+	let encoded = serde_json::to_value(&message)?;
+	compare(fixture, &encoded, "")?;
+
+	let data = Encoding::MessagePack
+		.encode(&message)
+		.context("failed to encode as MessagePack")?;
+	let decoded = Encoding::MessagePack
+		.decode::<M>(&data)
+		.context("failed to decode MessagePack")?;
+	if serde_json::to_value(&decoded)? != encoded {
+		bail!("MessagePack round trip changed the message");
+	}
+
+	Ok(tag)
+}
+
+// fields of the fixture which are not re-encoded are reported but allowed, as
+// servers may send more than the client reads; anything else that differs is
+// likely a renamed field
+fn compare(fixture: &Value, encoded: &Value, at: &str) -> Result<()> {
+	match (fixture, encoded) {
+		(Value::Object(fixture), Value::Object(encoded)) => {
+			for (key, value) in encoded {
+				let at = format!("{at}/{key}");
+				match fixture.get(key) {
+					Some(expected) => compare(expected, value, &at)?,
+					// absent optional fields decode as null
+					None if value.is_null() => (),
+					None => bail!("{at} was encoded but is not in the fixture"),
+				}
+			}
+
+			for key in fixture.keys().filter(|key| !encoded.contains_key(*key)) {
+				eprintln!("warning: {at}/{key} is not read");
+			}
+
+			Ok(())
+		},
+		(Value::Array(fixture), Value::Array(encoded))
+			if fixture.len() == encoded.len() =>
+		{
+			for (i, (expected, value)) in fixture.iter().zip(encoded).enumerate() {
+				compare(expected, value, &format!("{at}/{i}"))?;
+			}
+
+			Ok(())
+		},
+		(Value::Number(fixture), Value::Number(encoded))
+			if fixture.as_f64() == encoded.as_f64() =>
+		{
+			Ok(())
+		},
+		(fixture, encoded) if fixture == encoded => Ok(()),
+		(fixture, encoded) => {
+			bail!("{at} was encoded as {encoded} but is {fixture} in the fixture")
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fixtures() -> PathBuf {
+		Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+	}
+
+	#[test]
+	fn fixtures_round_trip() {
+		check(&fixtures()).unwrap();
+	}
+
+	#[test]
+	fn renamed_field_fails() {
+		let path = fixtures().join("downstream/state_update.json");
+		let mut fixture = load_fixtures(&fixtures().join("downstream"))
+			.unwrap()
+			.into_iter()
+			.find_map(|(p, fixture)| (p == path).then_some(fixture))
+			.unwrap();
+
+		let data = fixture["data"].as_object_mut().unwrap();
+		let id = data.remove("objectId").unwrap();
+		data.insert("object_id".into(), id);
+
+		assert!(check_fixture::<Downstream>(&path, &fixture).is_err());
+	}
+
+	#[test]
+	fn fuzz_fixtures() {
+		fuzz::run(&fixtures(), 10_000, 0x4835).unwrap();
+	}
+
+	#[test]
+	fn schema_up_to_date() {
+		let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("../../shared/protocol/schema");
+		schema::run(&dir, true).unwrap();
+	}
+}
@@ -0,0 +1,121 @@
+use bars_protocol::{Downstream, Upstream};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// `tag` matches exhaustively, so that each new variant needs a fixture.
+pub trait Message: Serialize + DeserializeOwned {
+	const DIRECTION: &'static str;
+	const TAGS: &'static [&'static str];
+
+	fn tag(&self) -> &'static str;
+
+	/// Unknown messages, or those wrapping one, cannot be encoded.
+	fn is_unknown(&self) -> bool;
+}
+
+impl Message for Upstream {
+	const DIRECTION: &'static str = "upstream";
+	const TAGS: &'static [&'static str] = &[
+		"HEARTBEAT",
+		"HEARTBEAT_ACK",
+		"CLOSE",
+		"STATE_UPDATE",
+		"SHARED_STATE_UPDATE",
+		"RESYNC_REQUEST",
+		"PRESET_APPLIED",
+		"CLAIM_BLOCKS",
+		"RELEASE_BLOCKS",
+		"HANDOFF_REQUEST",
+		"HANDOFF_RESPONSE",
+		"AUTHENTICATE",
+		"JOIN",
+		"MULTIPLEXED",
+	];
+
+	fn tag(&self) -> &'static str {
+		match self {
+			Self::Heartbeat(_) => "HEARTBEAT",
+			Self::HeartbeatAck => "HEARTBEAT_ACK",
+			Self::Close(_) => "CLOSE",
+			Self::StateUpdate { .. } => "STATE_UPDATE",
+			Self::SharedStateUpdate { .. } => "SHARED_STATE_UPDATE",
+			Self::ResyncRequest => "RESYNC_REQUEST",
+			Self::PresetApplied { .. } => "PRESET_APPLIED",
+			Self::ClaimBlocks { .. } => "CLAIM_BLOCKS",
+			Self::ReleaseBlocks { .. } => "RELEASE_BLOCKS",
+			Self::HandoffRequest { .. } => "HANDOFF_REQUEST",
+			Self::HandoffResponse { .. } => "HANDOFF_RESPONSE",
+			Self::Authenticate { .. } => "AUTHENTICATE",
+			Self::Join => "JOIN",
+			Self::Multiplexed { .. } => "MULTIPLEXED",
+		}
+	}
+
+	fn is_unknown(&self) -> bool {
+		false
+	}
+}
+
+impl Message for Downstream {
+	const DIRECTION: &'static str = "downstream";
+	const TAGS: &'static [&'static str] = &[
+		"HEARTBEAT",
+		"HEARTBEAT_ACK",
+		"CLOSE",
+		"ERROR",
+		"THROTTLED",
+		"CONTROLLER_CONNECT",
+		"CONTROLLER_DISCONNECT",
+		"INITIAL_STATE",
+		"STATE_UPDATE",
+		"SHARED_STATE_UPDATE",
+		"PRESET_APPLIED",
+		"PROFILE_CHANGED",
+		"AIRCRAFT_UPDATE",
+		"BLOCKS_CLAIMED",
+		"BLOCKS_RELEASED",
+		"CLAIM_REJECTED",
+		"HANDOFF_REQUESTED",
+		"HANDOFF_COMPLETED",
+		"HANDOFF_DENIED",
+		"SNAPSHOT",
+		"MULTIPLEXED",
+		"OTHER",
+	];
+
+	fn tag(&self) -> &'static str {
+		match self {
+			Self::Heartbeat => "HEARTBEAT",
+			Self::HeartbeatAck(_) => "HEARTBEAT_ACK",
+			Self::Close(_) => "CLOSE",
+			Self::Error { .. } => "ERROR",
+			Self::Throttled { .. } => "THROTTLED",
+			Self::ControllerConnect(_) => "CONTROLLER_CONNECT",
+			Self::ControllerDisconnect { .. } => "CONTROLLER_DISCONNECT",
+			Self::InitialState { .. } => "INITIAL_STATE",
+			Self::StateUpdate { .. } => "STATE_UPDATE",
+			Self::SharedStateUpdate { .. } => "SHARED_STATE_UPDATE",
+			Self::PresetApplied { .. } => "PRESET_APPLIED",
+			Self::ProfileChanged { .. } => "PROFILE_CHANGED",
+			Self::AircraftUpdate { .. } => "AIRCRAFT_UPDATE",
+			Self::BlocksClaimed { .. } => "BLOCKS_CLAIMED",
+			Self::BlocksReleased { .. } => "BLOCKS_RELEASED",
+			Self::ClaimRejected { .. } => "CLAIM_REJECTED",
+			Self::HandoffRequested { .. } => "HANDOFF_REQUESTED",
+			Self::HandoffCompleted { .. } => "HANDOFF_COMPLETED",
+			Self::HandoffDenied { .. } => "HANDOFF_DENIED",
+			Self::Snapshot { .. } => "SNAPSHOT",
+			Self::Multiplexed { .. } => "MULTIPLEXED",
+			Self::Other(_) => "OTHER",
+		}
+	}
+
+	fn is_unknown(&self) -> bool {
+		match self {
+			Self::Multiplexed { message, .. } => message.is_unknown(),
+			Self::Other(_) => true,
+			_ => false,
+		}
+	}
+}
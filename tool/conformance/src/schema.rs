@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use bars_protocol::{Downstream, State, Upstream};
+
+use anyhow::{bail, Context, Result};
+
+use schemars::{JsonSchema, Schema};
+
+use serde_json::{Map, Value};
+
+static TS_FILE: &str = "protocol.ts";
+
+// writes a JSON Schema for each message type and a TypeScript module defining
+// all of them to `dir`, or with `check`, fails if those already in `dir` are
+// out of date
+pub fn run(dir: &Path, check: bool) -> Result<()> {
+	let roots = [
+		("upstream", schema_for::<Upstream>()),
+		("downstream", schema_for::<Downstream>()),
+		("state", schema_for::<State>()),
+	];
+
+	let mut files = Vec::new();
+	for (name, schema) in &roots {
+		let mut json = serde_json::to_string_pretty(schema)?;
+		json.push('\n');
+		files.push((format!("{name}.schema.json"), json));
+	}
+	files.push((TS_FILE.into(), typescript(&roots)?));
+
+	if check {
+		let mut stale = 0;
+		for (name, contents) in &files {
+			let path = dir.join(name);
+			if std::fs::read_to_string(&path).ok().as_ref() != Some(contents) {
+				stale += 1;
+				eprintln!("FAILED {}: out of date", path.display());
+			}
+		}
+
+		if stale > 0 {
+			bail!("{stale} file(s) out of date; regenerate them with `schema`");
+		}
+
+		return Ok(())
+	}
+
+	std::fs::create_dir_all(dir)
+		.with_context(|| format!("failed to create {}", dir.display()))?;
+
+	for (name, contents) in &files {
+		let path = dir.join(name);
+		std::fs::write(&path, contents)
+			.with_context(|| format!("failed to write {}", path.display()))?;
+		eprintln!("wrote {}", path.display());
+	}
+
+	Ok(())
+}
+
+fn schema_for<T: JsonSchema>() -> Value {
+	let schema: Schema = schemars::schema_for!(T);
+	schema.to_value()
+}
+
+fn typescript(roots: &[(&str, Value)]) -> Result<String> {
+	// every root shares the same definitions, so they are merged by name
+	let mut types = BTreeMap::new();
+	for (_, root) in roots {
+		let Value::Object(root) = root else {
+			bail!("root schema is not an object");
+		};
+
+		let name = root
+			.get("title")
+			.and_then(Value::as_str)
+			.context("root schema has no title")?;
+		let mut schema = root.clone();
+		for key in ["$schema", "$defs", "title"] {
+			schema.remove(key);
+		}
+		let mut schema = Value::Object(schema);
+		name_root(&mut schema, name);
+		types.insert(name.to_string(), schema);
+
+		if let Some(Value::Object(defs)) = root.get("$defs") {
+			for (def_name, def) in defs {
+				let mut def = def.clone();
+				name_root(&mut def, name);
+				types.insert(def_name.clone(), def);
+			}
+		}
+	}
+
+	let mut out = format!(
+		"// generated by `{} schema`; do not edit\n",
+		env!("CARGO_PKG_NAME"),
+	);
+	for (name, schema) in &types {
+		out.push('\n');
+		doc(&mut out, schema, 0);
+		writeln!(out, "export type {name} = {};", ts(schema, 0))?;
+	}
+
+	Ok(out)
+}
+
+// replaces references to the root, as made by recursive types, with its name
+fn name_root(schema: &mut Value, name: &str) {
+	match schema {
+		Value::Object(map) => {
+			for (key, value) in map.iter_mut() {
+				if key == "$ref" && value == "#" {
+					*value = format!("#/$defs/{name}").into();
+				} else {
+					name_root(value, name);
+				}
+			}
+		},
+		Value::Array(values) => {
+			for value in values {
+				name_root(value, name);
+			}
+		},
+		_ => (),
+	}
+}
+
+fn doc(out: &mut String, schema: &Value, depth: usize) {
+	let Some(description) = schema.get("description").and_then(Value::as_str)
+	else {
+		return
+	};
+
+	let indent = "\t".repeat(depth);
+	out.push_str(&indent);
+	out.push_str("/**\n");
+	for line in description.lines() {
+		out.push_str(&indent);
+		out.push_str(" *");
+		if !line.is_empty() {
+			out.push(' ');
+			out.push_str(line);
+		}
+		out.push('\n');
+	}
+	out.push_str(&indent);
+	out.push_str(" */\n");
+}
+
+// renders the type of values matching `schema`, which is always one of those
+// generated by schemars for serde types
+fn ts(schema: &Value, depth: usize) -> String {
+	let schema = match schema {
+		Value::Bool(true) => return "unknown".into(),
+		Value::Object(schema) => schema,
+		_ => return "never".into(),
+	};
+
+	if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+		return target.rsplit('/').next().unwrap_or(target).into()
+	}
+
+	if let Some(value) = schema.get("const") {
+		return value.to_string()
+	}
+
+	if let Some(Value::Array(values)) = schema.get("enum") {
+		return union(values.iter().map(Value::to_string))
+	}
+
+	for key in ["oneOf", "anyOf"] {
+		if let Some(Value::Array(variants)) = schema.get(key) {
+			return union(variants.iter().map(|variant| ts(variant, depth)))
+		}
+	}
+
+	match schema.get("type") {
+		Some(Value::Array(kinds)) => union(
+			kinds
+				.iter()
+				.filter_map(Value::as_str)
+				.map(|kind| primitive(kind, schema, depth)),
+		),
+		Some(Value::String(kind)) => primitive(kind, schema, depth),
+		_ => "unknown".into(),
+	}
+}
+
+fn primitive(kind: &str, schema: &Map<String, Value>, depth: usize) -> String {
+	match kind {
+		"null" => "null".into(),
+		"boolean" => "boolean".into(),
+		"integer" | "number" => "number".into(),
+		"string" => "string".into(),
+		"array" => match (schema.get("prefixItems"), schema.get("items")) {
+			(Some(Value::Array(items)), _) => format!(
+				"[{}]",
+				items
+					.iter()
+					.map(|item| ts(item, depth))
+					.collect::<Vec<_>>()
+					.join(", "),
+			),
+			(_, Some(items)) => {
+				let item = ts(items, depth);
+				if item.contains(' ') {
+					format!("({item})[]")
+				} else {
+					format!("{item}[]")
+				}
+			},
+			_ => "unknown[]".into(),
+		},
+		"object" => object(schema, depth),
+		_ => "unknown".into(),
+	}
+}
+
+fn object(schema: &Map<String, Value>, depth: usize) -> String {
+	let properties = match schema.get("properties") {
+		Some(Value::Object(properties)) if !properties.is_empty() => properties,
+		_ => {
+			return match schema.get("additionalProperties") {
+				Some(Value::Bool(false)) => "Record<string, never>".into(),
+				Some(Value::Bool(true)) | None => "Record<string, unknown>".into(),
+				Some(value) => format!("Record<string, {}>", ts(value, depth)),
+			}
+		},
+	};
+
+	let required = match schema.get("required") {
+		Some(Value::Array(required)) => {
+			required.iter().filter_map(Value::as_str).collect()
+		},
+		_ => Vec::new(),
+	};
+
+	let indent = "\t".repeat(depth + 1);
+	let mut out = String::from("{\n");
+	for (name, property) in properties {
+		doc(&mut out, property, depth + 1);
+		let optional = if required.contains(&name.as_str()) {
+			""
+		} else {
+			"?"
+		};
+		let _ = writeln!(
+			out,
+			"{indent}{name}{optional}: {};",
+			ts(property, depth + 1)
+		);
+	}
+	out.push_str(&"\t".repeat(depth));
+	out.push('}');
+
+	out
+}
+
+fn union(types: impl Iterator<Item = String>) -> String {
+	let mut types = types.collect::<Vec<_>>();
+	types.dedup();
+	types.join(" | ")
+}
@@ -1,11 +1,18 @@
 use std::collections::{HashMap, HashSet};
-use std::io::stderr;
+use std::fs::File;
+use std::io::{stderr, BufReader};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use bars_protocol::SceneryObject;
+use bars_config as lib;
+use bars_protocol::{
+	Capability, CloseReason, Controller, Downstream, Encoding, ErrorCode, Patch,
+	PositionType, SceneryObject, SharedState, Upstream,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use clap::Parser;
 
@@ -18,12 +25,15 @@ use hyper::{header, Method, Request, Response, StatusCode, Version};
 
 use hyper_util::rt::TokioIo;
 
-use serde_json::{json, Value};
+use serde_json::json;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
 use tokio_tungstenite::tungstenite::protocol::{Message, Role};
@@ -35,8 +45,7 @@ use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::fmt::time::ChronoUtc;
 use tracing_subscriber::FmtSubscriber;
 
-type Downstream = bars_protocol::Downstream<Value>;
-type Upstream = bars_protocol::Upstream<Value>;
+const SHUTDOWN_DELAY: Duration = Duration::from_millis(500);
 
 /// Serve a local version of the BARS server.
 #[derive(Debug, Parser)]
@@ -50,6 +59,14 @@ struct Args {
 	#[arg(short = 'o', long = "observer", value_name = "KEY")]
 	observer_keys: Vec<String>,
 
+	/// throttle connections sending more than N updates per second
+	#[arg(short = 'r', long = "rate-limit", value_name = "N")]
+	rate_limit: Option<u32>,
+
+	/// drop changes to anything not defined by the config FILE
+	#[arg(short = 'a', long = "aerodromes", value_name = "FILE")]
+	aerodromes: Option<PathBuf>,
+
 	/// bind server to ADDRESS
 	#[arg(value_name = "ADDRESS")]
 	bind: SocketAddr,
@@ -58,6 +75,8 @@ struct Args {
 struct Config {
 	controller_keys: HashSet<String>,
 	observer_keys: HashSet<String>,
+	rate_limit: Option<u32>,
+	aerodromes: HashMap<String, lib::Aerodrome>,
 }
 
 type State = HashMap<String, StateEntry>;
@@ -79,35 +98,83 @@ impl Default for StateEntry {
 
 #[derive(Clone, Default)]
 struct Aerodrome {
-	controllers: HashSet<String>,
-	objects: HashMap<String, bool>,
-	state: Value,
+	controllers: HashMap<String, Controller>,
+	// callsign of each observer, if given
+	observers: HashMap<String, Option<String>>,
+	// time each connection was made
+	connected: HashMap<String, u64>,
+	claims: HashMap<String, String>,
+	// pending handoff requests, from requester to holder
+	handoffs: HashMap<String, String>,
+	// controllers which have handed off, and may no longer change state
+	handed_off: HashSet<String>,
+	objects: HashMap<String, SceneryObject>,
+	state: SharedState,
+	sequence: u64,
 }
 
 impl Aerodrome {
-	fn merge_state(&mut self, state: Value) {
-		fn merge(target: &mut Value, source: Value) {
-			if target.is_object() && source.is_object() {
-				let Value::Object(target) = target else {
-					unreachable!()
-				};
-				let Value::Object(source) = source else {
-					unreachable!()
-				};
+	fn scenery(&self) -> Vec<SceneryObject> {
+		self.objects.values().cloned().collect()
+	}
 
-				for (key, value) in source {
-					if let Some(target) = target.get_mut(&key) {
-						merge(target, value);
-					} else {
-						target.insert(key, value);
-					}
-				}
-			} else {
-				*target = source;
+	// merges changes by controller `id`, returning those which took effect
+	fn merge(
+		&mut self,
+		mut patch: Patch,
+		id: &str,
+		layout: Option<&lib::Aerodrome>,
+	) -> Patch {
+		self.strip_claimed(&mut patch, id);
+
+		if let Some(layout) = layout {
+			let (valid, warnings) =
+				patch.validate(layout, self.state.state().profile.as_deref());
+			for warning in warnings {
+				warn!("ignoring {warning}");
 			}
+
+			patch = valid;
 		}
 
-		merge(&mut self.state, state);
+		// changes from clients which predate versioning are versioned here
+		self.state.stamp(&mut patch, id);
+		self.state.merge(patch)
+	}
+
+	// removes changes to blocks claimed by other controllers
+	fn strip_claimed(&self, patch: &mut Patch, id: &str) {
+		patch.blocks.retain(|block, _| {
+			let owner = self.claims.get(&**block);
+			if owner.is_some_and(|owner| owner != id) {
+				debug!("ignoring change to claimed block {block}");
+				false
+			} else {
+				true
+			}
+		});
+	}
+
+	fn release(&mut self, blocks: Option<Vec<String>>, id: &str) -> Vec<String> {
+		let blocks =
+			blocks.unwrap_or_else(|| self.claims.keys().cloned().collect());
+
+		blocks
+			.into_iter()
+			.filter(|block| {
+				if self.claims.get(block).is_some_and(|owner| owner == id) {
+					self.claims.remove(block);
+					true
+				} else {
+					false
+				}
+			})
+			.collect()
+	}
+
+	fn next_sequence(&mut self) -> Option<u64> {
+		self.sequence += 1;
+		Some(self.sequence)
 	}
 }
 
@@ -129,9 +196,29 @@ async fn main() -> Result<()> {
 
 	let listener = TcpListener::bind(args.bind).await?;
 
+	let aerodromes = match args.aerodromes {
+		Some(path) => {
+			let file = File::open(&path)
+				.with_context(|| format!("failed to open {}", path.display()))?;
+			let config = lib::Config::load(BufReader::new(file))
+				.with_context(|| format!("failed to load {}", path.display()))?;
+
+			info!("loaded {} aerodromes", config.aerodromes.len());
+
+			config
+				.aerodromes
+				.into_iter()
+				.map(|aerodrome| (aerodrome.icao.clone(), aerodrome))
+				.collect()
+		},
+		None => HashMap::new(),
+	};
+
 	let config: &'static _ = Box::leak(Box::new(Config {
 		controller_keys: HashSet::from_iter(args.controller_keys),
 		observer_keys: HashSet::from_iter(args.observer_keys),
+		rate_limit: args.rate_limit,
+		aerodromes,
 	}));
 	let state = Arc::new(Mutex::new(State::new()));
 
@@ -140,7 +227,10 @@ async fn main() -> Result<()> {
 	}
 
 	loop {
-		let (stream, remote) = listener.accept().await?;
+		let (stream, remote) = tokio::select! {
+			res = listener.accept() => res?,
+			_ = tokio::signal::ctrl_c() => break,
+		};
 
 		let stream = TokioIo::new(stream);
 		let id = remote.to_string();
@@ -162,13 +252,27 @@ async fn main() -> Result<()> {
 			}
 		});
 	}
+
+	info!("shutting down");
+
+	// the server may be started again, so clients are told to reconnect
+	for entry in state.lock().await.values() {
+		let _ = entry
+			.broadcast
+			.send(Downstream::Close(Some(CloseReason::ServerRestart)));
+	}
+
+	// leave time for connections to pass on the close
+	tokio::time::sleep(SHUTDOWN_DELAY).await;
+
+	Ok(())
 }
 
 #[instrument(skip_all)]
 async fn handle(
 	req: Request<Incoming>,
 	id: String,
-	config: &Config,
+	config: &'static Config,
 	state: Arc<Mutex<State>>,
 ) -> Result<Response<String>> {
 	debug!("{} {}", req.method(), req.uri().path());
@@ -189,6 +293,14 @@ async fn handle(
 				},
 			);
 
+			// unsupported encodings fall back to JSON, as for the real server
+			let encoding = query_param(&req, Encoding::QUERY_PARAM)
+				.and_then(Encoding::from_name)
+				.unwrap_or_default();
+
+			// identities are not verified, as there is no account to look up
+			let callsign = query_param(&req, "callsign").map(str::to_string);
+
 			if let Some((accept_key, (icao, key))) = params {
 				let controller = config.controller_keys.contains(key);
 				let observer = config.observer_keys.contains(key);
@@ -196,55 +308,26 @@ async fn handle(
 				if controller || observer {
 					let state = state.clone();
 					let icao = icao.to_string();
+					let client = Client {
+						id,
+						controller,
+						callsign,
+					};
 
 					tokio::spawn(async move {
 						match hyper::upgrade::on(req).await {
 							Ok(stream) => {
-								let entry = {
-									let mut state = state.lock().await;
-									let state = state.entry(icao.clone()).or_default();
-
-									if controller {
-										let mut aerodrome = state.aerodrome.lock().await;
-										aerodrome.controllers.insert(id.clone());
-
-										let _ =
-											state.broadcast.send(Downstream::ControllerConnect {
-												controller_id: id.clone(),
-											});
-									}
-
-									state.clone()
-								};
-
 								let stream = TokioIo::new(stream);
 								let conn =
 									WebSocketStream::from_raw_socket(stream, Role::Server, None)
 										.await;
 
-								let id_opt = controller.then_some(&id);
-
-								if let Err(err) = handle_socket(conn, id_opt, entry).await {
+								if let Err(err) =
+									handle_socket(conn, client, icao, state, config, encoding)
+										.await
+								{
 									error!("handling error: {err}");
 								}
-
-								if controller {
-									let state = state.lock().await;
-									let state = state.get(&icao).unwrap();
-									let mut aerodrome = state.aerodrome.lock().await;
-
-									if aerodrome.controllers.remove(&id)
-										&& aerodrome.controllers.is_empty()
-									{
-										aerodrome.objects.clear();
-										aerodrome.state = Value::Null;
-									}
-
-									let _ =
-										state.broadcast.send(Downstream::ControllerDisconnect {
-											controller_id: id.clone(),
-										});
-								}
 							},
 							Err(err) => error!("failed to upgrade: {err}"),
 						}
@@ -287,25 +370,39 @@ async fn handle(
 					Aerodrome::default()
 				};
 
-				let objects = aerodrome
-					.objects
-					.iter()
-					.map(|(id, state)| {
-						json!({
-							"id": id,
-							"state": state,
-						})
-					})
-					.collect::<Vec<_>>();
+				let objects = aerodrome.scenery();
+				let name = |id: &String| {
+					aerodrome
+						.controllers
+						.get(id)
+						.and_then(|c| c.callsign.clone())
+						.unwrap_or_else(|| id.clone())
+				};
 
 				Response::builder()
 					.header(header::CONTENT_TYPE, "application/json")
 					.body(serde_json::to_string(&json!({
 						"airport": icao,
-						"controllers": aerodrome.controllers,
+						"controllers": aerodrome
+							.controllers
+							.values()
+							.map(|c| c.callsign.as_ref().unwrap_or(&c.controller_id))
+							.collect::<Vec<_>>(),
 						"pilots": [],
 						"objects": objects,
 						"offline": aerodrome.controllers.is_empty(),
+						"observers": aerodrome
+							.observers
+							.iter()
+							.map(|(id, callsign)| callsign.as_ref().unwrap_or(id))
+							.collect::<Vec<_>>(),
+						"observerCount": aerodrome.observers.len(),
+						"connected": aerodrome
+							.connected
+							.iter()
+							.filter(|(id, _)| aerodrome.controllers.contains_key(*id))
+							.map(|(id, time)| (name(id), *time))
+							.collect::<HashMap<_, _>>(),
 					}))?)?
 			} else {
 				Response::builder()
@@ -353,108 +450,688 @@ fn get_websocket_request(req: &Request<Incoming>) -> Option<String> {
 		.map(|key| derive_accept_key(key.as_bytes()))
 }
 
-#[instrument(skip_all)]
-async fn handle_socket<S>(
-	mut conn: WebSocketStream<S>,
-	controller: Option<&String>,
-	state: StateEntry,
+// fixed one-second window of updates
+struct RateLimiter {
+	limit: u32,
+	start: Instant,
+	count: u32,
+}
+
+impl RateLimiter {
+	const WINDOW: Duration = Duration::from_secs(1);
+
+	fn new(limit: u32) -> Self {
+		Self {
+			limit,
+			start: Instant::now(),
+			count: 0,
+		}
+	}
+
+	// counts an update, returning how long until the next is accepted if this
+	// one is over the limit
+	fn check(&mut self) -> Option<Duration> {
+		let elapsed = self.start.elapsed();
+		if elapsed >= Self::WINDOW {
+			self.start = Instant::now();
+			self.count = 0;
+		}
+
+		if self.count < self.limit {
+			self.count += 1;
+			None
+		} else {
+			Some(Self::WINDOW.saturating_sub(self.start.elapsed()))
+		}
+	}
+}
+
+fn timestamp() -> Option<u64> {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.ok()
+		.map(|time| time.as_millis() as u64)
+}
+
+fn query_param<'a>(req: &'a Request<Incoming>, name: &str) -> Option<&'a str> {
+	req.uri().query().and_then(|query| {
+		query
+			.split('&')
+			.filter_map(|tuple| tuple.split_once('='))
+			.find_map(|(k, v)| (k == name).then_some(v))
+	})
+}
+
+// the client of a connection, which is the same for each aerodrome it joins
+struct Client {
+	id: String,
+	controller: bool,
+	callsign: Option<String>,
+}
+
+// adds the client to an aerodrome, returning the aerodrome
+async fn register(
+	state: &Mutex<State>,
+	icao: &str,
+	client: &Client,
+) -> StateEntry {
+	let id = &client.id;
+	let mut state = state.lock().await;
+	let state = state.entry(icao.to_string()).or_default();
+	let mut aerodrome = state.aerodrome.lock().await;
+
+	if let Some(time) = timestamp() {
+		aerodrome.connected.insert(id.clone(), time);
+	}
+
+	if client.controller {
+		let controller = Controller {
+			controller_id: id.clone(),
+			position: client.callsign.as_deref().map(PositionType::from_callsign),
+			callsign: client.callsign.clone(),
+			cid: None,
+		};
+		aerodrome.controllers.insert(id.clone(), controller.clone());
+
+		let _ = state
+			.broadcast
+			.send(Downstream::ControllerConnect(controller));
+	} else {
+		aerodrome
+			.observers
+			.insert(id.clone(), client.callsign.clone());
+	}
+
+	drop(aerodrome);
+	state.clone()
+}
+
+// removes the client from an aerodrome, along with its claims and handoffs
+async fn unregister(state: &Mutex<State>, icao: &str, client: &Client) {
+	let id = &client.id;
+	let state = state.lock().await;
+	let state = state.get(icao).unwrap();
+	let mut aerodrome = state.aerodrome.lock().await;
+	aerodrome.connected.remove(id);
+	aerodrome.observers.remove(id);
+
+	if client.controller {
+		if aerodrome.controllers.remove(id).is_some()
+			&& aerodrome.controllers.is_empty()
+		{
+			aerodrome.objects.clear();
+			aerodrome.state = SharedState::default();
+		}
+
+		let blocks = aerodrome.release(None, id);
+		if !blocks.is_empty() {
+			let _ = state.broadcast.send(Downstream::BlocksReleased {
+				blocks,
+				controller_id: id.clone(),
+			});
+		}
+
+		// a holder leaving hands off to anyone waiting for it
+		aerodrome.handed_off.remove(id);
+		aerodrome.handoffs.remove(id);
+		for (requester, _) in
+			aerodrome.handoffs.extract_if(|_, holder| holder == id)
+		{
+			let _ = state.broadcast.send(Downstream::HandoffCompleted {
+				requester,
+				holder: id.clone(),
+			});
+		}
+
+		let _ = state.broadcast.send(Downstream::ControllerDisconnect {
+			controller_id: id.clone(),
+		});
+	}
+}
+
+async fn send<S>(
+	conn: &mut WebSocketStream<S>,
+	encoding: Encoding,
+	message: &Downstream,
 ) -> Result<()>
 where
 	S: AsyncRead + AsyncWrite + Unpin,
 {
-	async fn send<S>(
-		conn: &mut WebSocketStream<S>,
-		message: &Downstream,
-	) -> Result<()>
-	where
-		S: AsyncRead + AsyncWrite + Unpin,
-	{
-		let message = serde_json::to_string(message).unwrap();
-		if let Err(err) = conn.send(message.into()).await {
-			error!("failed to send websocket message: {err}");
-
-			let _ = conn.close(None).await;
-
-			Err(err)?
+	let data = encoding.encode(message).unwrap();
+	let message = if encoding.is_binary() {
+		Message::Binary(data.into())
+	} else {
+		Message::Text(String::from_utf8(data)?.into())
+	};
+
+	if let Err(err) = conn.send(message).await {
+		error!("failed to send websocket message: {err}");
+
+		let _ = conn.close(None).await;
+
+		Err(err)?
+	} else {
+		Ok(())
+	}
+}
+
+// a client's socket, over which it may join aerodromes besides the one it
+// connected to
+struct Connection<S> {
+	conn: WebSocketStream<S>,
+	encoding: Encoding,
+	client: Client,
+	// aerodrome connected to, whose messages are not wrapped
+	primary: String,
+	members: HashMap<String, Member>,
+	// broadcasts of the aerodromes joined, with the aerodrome of each
+	forward: UnboundedSender<(String, Downstream)>,
+	state: Arc<Mutex<State>>,
+	config: &'static Config,
+}
+
+// an aerodrome joined over a connection
+struct Member {
+	entry: StateEntry,
+	layout: Option<&'static lib::Aerodrome>,
+	// task passing on the aerodrome's broadcasts to the connection
+	forwarding: JoinHandle<()>,
+}
+
+impl<S> Connection<S>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	// sends a message of an aerodrome, wrapping it unless the aerodrome is the
+	// one connected to
+	async fn send(&mut self, icao: &str, message: Downstream) -> Result<()> {
+		let message = if icao == self.primary {
+			message
 		} else {
-			Ok(())
-		}
+			Downstream::Multiplexed {
+				airport: icao.into(),
+				message: Box::new(message),
+			}
+		};
+
+		send(&mut self.conn, self.encoding, &message).await
 	}
 
-	let tx = state.broadcast;
-	let mut rx = tx.subscribe();
-
-	{
-		let aerodrome = state.aerodrome.lock().await;
-
-		send(
-			&mut conn,
-			&Downstream::InitialState {
-				connection_type: controller
-					.map(|_| "controller")
-					.unwrap_or("observer")
-					.into(),
-				scenery: aerodrome
-					.objects
-					.iter()
-					.map(|(id, state)| SceneryObject {
-						id: id.clone(),
-						state: *state,
-					})
-					.collect(),
-				patch: aerodrome.state.clone(),
+	async fn join(&mut self, icao: String) -> Result<()> {
+		let entry = register(&self.state, &icao, &self.client).await;
+
+		let mut rx = entry.broadcast.subscribe();
+		let forwarding = {
+			let icao = icao.clone();
+			let forward = self.forward.clone();
+			tokio::spawn(async move {
+				loop {
+					match rx.recv().await {
+						Ok(message) => {
+							if forward.send((icao.clone(), message)).is_err() {
+								break
+							}
+						},
+						// gaps are found by clients from the sequence numbers
+						Err(RecvError::Lagged(_)) => (),
+						Err(RecvError::Closed) => break,
+					}
+				}
+			})
+		};
+
+		let layout = self.config.aerodromes.get(&icao);
+		self.members.insert(
+			icao.clone(),
+			Member {
+				entry: entry.clone(),
+				layout,
+				forwarding,
 			},
-		)
-		.await?;
+		);
+
+		let initial = {
+			let aerodrome = entry.aerodrome.lock().await;
+			Downstream::InitialState {
+				connection_type: if self.client.controller {
+					"controller"
+				} else {
+					"observer"
+				}
+				.into(),
+				scenery: aerodrome.scenery(),
+				patch: aerodrome.state.state().clone(),
+				controllers: aerodrome.controllers.values().cloned().collect(),
+				claims: aerodrome.claims.clone(),
+				controller_id: self.client.controller.then(|| self.client.id.clone()),
+				sequence: Some(aerodrome.sequence),
+				// lighting is not modelled, so there is no intensity to report
+				capabilities: vec![
+					Capability::Ownership,
+					Capability::BinaryFrames,
+					Capability::Multiplex,
+				],
+			}
+		};
+		self.send(&icao, initial).await?;
+
+		// there are never any pilots, but pushing the empty list spares clients
+		// from polling for them
+		self
+			.send(
+				&icao,
+				Downstream::AircraftUpdate {
+					aircraft: Vec::new(),
+				},
+			)
+			.await
+	}
+
+	// leaves an aerodrome, returning whether none are left
+	async fn leave(&mut self, icao: &str) -> bool {
+		if let Some(member) = self.members.remove(icao) {
+			member.forwarding.abort();
+			unregister(&self.state, icao, &self.client).await;
+		}
+
+		self.members.is_empty()
+	}
+}
+
+#[instrument(skip_all)]
+async fn handle_socket<S>(
+	conn: WebSocketStream<S>,
+	client: Client,
+	icao: String,
+	state: Arc<Mutex<State>>,
+	config: &'static Config,
+	encoding: Encoding,
+) -> Result<()>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let (forward, mut forwarded) = mpsc::unbounded_channel();
+	let mut connection = Connection {
+		conn,
+		encoding,
+		client,
+		primary: icao.clone(),
+		members: HashMap::new(),
+		forward,
+		state,
+		config,
+	};
+
+	let mut res = connection.join(icao).await;
+	if res.is_ok() {
+		res = serve_socket(&mut connection, &mut forwarded).await;
+	}
+
+	let icaos = connection.members.keys().cloned().collect::<Vec<_>>();
+	for icao in icaos {
+		connection.leave(&icao).await;
 	}
 
+	res
+}
+
+async fn serve_socket<S>(
+	connection: &mut Connection<S>,
+	forwarded: &mut UnboundedReceiver<(String, Downstream)>,
+) -> Result<()>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let primary = connection.primary.clone();
+	let controller = connection
+		.client
+		.controller
+		.then(|| connection.client.id.clone());
+	let controller = controller.as_ref();
+	// keys which may renew this one, being those of the same role
+	let keys = if controller.is_some() {
+		&connection.config.controller_keys
+	} else {
+		&connection.config.observer_keys
+	};
+	let mut limiter = connection.config.rate_limit.map(RateLimiter::new);
+
 	loop {
 		tokio::select! {
-			Ok(message) = rx.recv() => {
-				send(&mut conn, &message).await?;
+			Some((icao, message)) = forwarded.recv() => {
+				let close = matches!(message, Downstream::Close(_));
+				connection.send(&icao, message).await?;
+
+				if close && connection.leave(&icao).await {
+					connection.conn.close(None).await?;
+
+					break
+				}
 			},
-			message = conn.next() => {
+			message = connection.conn.next() => {
 				match message {
-					Some(Ok(Message::Text(message))) => {
-						let Ok(message) = serde_json::from_str(&message) else {
-							send(&mut conn, &Downstream::Error {
-								message: "malformed message".into(),
+					Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+						let frame_encoding = if frame.is_binary() {
+							connection.encoding
+						} else {
+							Encoding::Json
+						};
+
+						let Ok(message) = frame_encoding.decode(&frame.into_data()) else {
+							connection.send(&primary, Downstream::Error {
+								code: ErrorCode::InvalidMessage,
+								detail: Some("malformed message".into()),
 							}).await?;
 
 							continue
 						};
 
-						match (message, controller) {
-							(Upstream::Heartbeat, _) =>
-								send(&mut conn, &Downstream::HeartbeatAck).await?,
-							(Upstream::HeartbeatAck, _) => warn!("unexpected HEARTBEAT_ACK"),
-							(Upstream::Close, _) => {
-								debug!("closing websocket");
+						// messages of the aerodrome connected to need not be wrapped
+						let (airport, message) = match message {
+							Upstream::Multiplexed { airport, message } => (Some(airport), *message),
+							message => (None, message),
+						};
+						let icao = airport.clone().unwrap_or_else(|| primary.clone());
+
+						// messages about the connection as a whole
+						match message {
+							Upstream::Heartbeat(ping) => {
+								connection.send(&primary, Downstream::HeartbeatAck(ping)).await?;
+								continue
+							},
+							Upstream::HeartbeatAck => {
+								warn!("unexpected HEARTBEAT_ACK");
+								continue
+							},
+							Upstream::Authenticate { key } => {
+								if keys.contains(&key) {
+									debug!("renewed key");
+								} else {
+									connection.send(&primary, Downstream::Error {
+										code: ErrorCode::Unauthorized,
+										detail: Some("invalid key".into()),
+									}).await?;
+								}
+								continue
+							},
+							Upstream::Close(reason) if airport.is_none() => {
+								debug!("closing websocket: {}", reason.unwrap_or_default());
 
-								conn.close(None).await?;
+								connection.conn.close(None).await?;
 
 								break
 							},
-							(Upstream::StateUpdate { object_id, state: os }, Some(id)) => {
+							Upstream::Close(reason) => {
+								debug!("leaving {icao}: {}", reason.unwrap_or_default());
+
+								if connection.leave(&icao).await {
+									connection.conn.close(None).await?;
+
+									break
+								}
+								continue
+							},
+							Upstream::Join if connection.members.contains_key(&icao) => {
+								connection.send(&icao, Downstream::Error {
+									code: ErrorCode::InvalidMessage,
+									detail: Some(format!("{icao} already joined")),
+								}).await?;
+								continue
+							},
+							Upstream::Join => {
+								debug!("joining {icao}");
+								connection.join(icao).await?;
+								continue
+							},
+							Upstream::Multiplexed { .. } => {
+								connection.send(&primary, Downstream::Error {
+									code: ErrorCode::InvalidMessage,
+									detail: Some("nested message".into()),
+								}).await?;
+								continue
+							},
+							_ => (),
+						}
+
+						let Some(member) = connection.members.get(&icao) else {
+							connection.send(&icao, Downstream::Error {
+								code: ErrorCode::InvalidMessage,
+								detail: Some(format!("{icao} not joined")),
+							}).await?;
+
+							continue
+						};
+						let state = member.entry.clone();
+						let layout = member.layout;
+						let tx = &state.broadcast;
+
+						let writer = match controller {
+							Some(id) if state.aerodrome.lock().await.handed_off.contains(id) => None,
+							controller => controller,
+						};
+
+						let is_update = matches!(
+							message,
+							Upstream::StateUpdate { .. }
+								| Upstream::SharedStateUpdate { .. }
+								| Upstream::PresetApplied { .. }
+						);
+						if let Some(limiter) = limiter.as_mut().filter(|_| is_update) {
+							if let Some(retry_after) = limiter.check() {
+								debug!("throttling for {retry_after:?}");
+
+								connection.send(&icao, Downstream::Throttled {
+									retry_after: retry_after.as_millis() as u64,
+								}).await?;
+
+								continue
+							}
+						}
+
+						match (message, writer) {
+							(Upstream::StateUpdate { object_id, state: os, kind }, Some(id)) => {
 								let mut aerodrome = state.aerodrome.lock().await;
-								aerodrome.objects.insert(object_id.clone(), os);
+								aerodrome.objects.insert(object_id.clone(), SceneryObject {
+									id: object_id.clone(),
+									state: os,
+									kind,
+								});
 
 								let _ = tx.send(Downstream::StateUpdate {
 									object_id,
 									state: os,
+									kind,
 									controller_id: id.clone(),
+									timestamp: timestamp(),
+									sequence: aerodrome.next_sequence(),
 								});
 							},
 							(Upstream::SharedStateUpdate { patch }, Some(id)) => {
 								let mut aerodrome = state.aerodrome.lock().await;
-								aerodrome.merge_state(patch.clone());
+								let previous = aerodrome.state.state().profile.clone();
+
+								let patch = aerodrome.merge(patch, id, layout);
+								if patch.is_empty() {
+									debug!("ignoring superseded changes from {id}");
+									continue
+								}
+
+								let profile = patch.profile.clone().filter(|profile| {
+									previous.as_ref() != Some(profile)
+								});
+
+								let controller_id = id.clone();
+								let timestamp = timestamp();
+								let sequence = aerodrome.next_sequence();
+								let _ = tx.send(if let Some(profile) = profile {
+									info!("{id} changed profile to {profile}");
+
+									Downstream::ProfileChanged {
+										profile,
+										patch,
+										controller_id,
+										timestamp,
+										sequence,
+									}
+								} else {
+									Downstream::SharedStateUpdate {
+										patch,
+										controller_id,
+										timestamp,
+										sequence,
+									}
+								});
+							},
+							(Upstream::PresetApplied { preset, patch }, Some(id)) => {
+								info!("{id} applied preset {preset}");
+
+								let mut aerodrome = state.aerodrome.lock().await;
+								let patch = aerodrome.merge(patch, id, layout);
 
-								let _ = tx.send(Downstream::SharedStateUpdate {
-									patch, controller_id: id.clone(),
+								let _ = tx.send(Downstream::PresetApplied {
+									preset,
+									patch,
+									controller_id: id.clone(),
+									timestamp: timestamp(),
+									sequence: aerodrome.next_sequence(),
 								});
 							},
-							_ => send(&mut conn, &Downstream::Error {
-								message: "invalid message".into(),
+							(Upstream::ResyncRequest, _) => {
+								let snapshot = {
+									let aerodrome = state.aerodrome.lock().await;
+									Downstream::Snapshot {
+										scenery: aerodrome.scenery(),
+										patch: aerodrome.state.state().clone(),
+										claims: aerodrome.claims.clone(),
+										sequence: aerodrome.sequence,
+									}
+								};
+
+								connection.send(&icao, snapshot).await?;
+							},
+							(Upstream::ClaimBlocks { blocks }, Some(id)) => {
+								let mut aerodrome = state.aerodrome.lock().await;
+
+								// claims are all or nothing, so that a controller never holds
+								// only part of what they asked for
+								let conflicts = blocks
+									.iter()
+									.filter_map(|block| {
+										let owner = aerodrome.claims.get(block)?;
+										(owner != id).then(|| (block.clone(), owner.clone()))
+									})
+									.collect::<HashMap<_, _>>();
+
+								if conflicts.is_empty() {
+									info!("{id} claimed {}", blocks.join(", "));
+
+									for block in &blocks {
+										aerodrome.claims.insert(block.clone(), id.clone());
+									}
+
+									let _ = tx.send(Downstream::BlocksClaimed {
+										blocks,
+										controller_id: id.clone(),
+									});
+								} else {
+									drop(aerodrome);
+									connection.send(&icao, Downstream::ClaimRejected {
+										conflicts,
+									}).await?;
+								}
+							},
+							(Upstream::ReleaseBlocks { blocks }, Some(id)) => {
+								let blocks = state.aerodrome.lock().await.release(Some(blocks), id);
+
+								if !blocks.is_empty() {
+									info!("{id} released {}", blocks.join(", "));
+
+									let _ = tx.send(Downstream::BlocksReleased {
+										blocks,
+										controller_id: id.clone(),
+									});
+								}
+							},
+							(Upstream::HandoffRequest { holder }, Some(id)) => {
+								let mut aerodrome = state.aerodrome.lock().await;
+
+								if holder == *id
+									|| !aerodrome.controllers.contains_key(&holder)
+									|| aerodrome.handed_off.contains(&holder)
+								{
+									drop(aerodrome);
+									connection.send(&icao, Downstream::Error {
+										code: ErrorCode::InvalidMessage,
+										detail: Some(format!("{holder} cannot hand off")),
+									}).await?;
+								} else {
+									info!("{id} requested handoff from {holder}");
+
+									aerodrome.handoffs.insert(id.clone(), holder.clone());
+									let _ = tx.send(Downstream::HandoffRequested {
+										requester: id.clone(),
+										holder,
+									});
+								}
+							},
+							(Upstream::HandoffResponse { requester, accept }, Some(id)) => {
+								let mut aerodrome = state.aerodrome.lock().await;
+
+								if aerodrome.handoffs.get(&requester) != Some(id) {
+									drop(aerodrome);
+									connection.send(&icao, Downstream::Error {
+										code: ErrorCode::InvalidMessage,
+										detail: Some(format!("{requester} has not requested handoff")),
+									}).await?;
+								} else if accept {
+									info!("{id} handed off to {requester}");
+
+									aerodrome.handoffs.remove(&requester);
+									aerodrome.handed_off.insert(id.clone());
+
+									let blocks = aerodrome.release(None, id);
+									if !blocks.is_empty() {
+										for block in &blocks {
+											aerodrome.claims.insert(block.clone(), requester.clone());
+										}
+
+										let _ = tx.send(Downstream::BlocksReleased {
+											blocks: blocks.clone(),
+											controller_id: id.clone(),
+										});
+										let _ = tx.send(Downstream::BlocksClaimed {
+											blocks,
+											controller_id: requester.clone(),
+										});
+									}
+
+									let _ = tx.send(Downstream::HandoffCompleted {
+										requester,
+										holder: id.clone(),
+									});
+								} else {
+									info!("{id} denied handoff to {requester}");
+
+									aerodrome.handoffs.remove(&requester);
+									let _ = tx.send(Downstream::HandoffDenied {
+										requester,
+										holder: id.clone(),
+									});
+								}
+							},
+							// handled with the connection above
+							(
+								Upstream::Heartbeat(_)
+								| Upstream::HeartbeatAck
+								| Upstream::Close(_)
+								| Upstream::Authenticate { .. }
+								| Upstream::Join
+								| Upstream::Multiplexed { .. },
+								_,
+							) => unreachable!(),
+							(_, None) => connection.send(&icao, Downstream::Error {
+								code: ErrorCode::Unauthorized,
+								detail: Some("observers cannot change state".into()),
 							}).await?,
 						}
 					},
@@ -463,18 +1140,19 @@ where
 
 						break
 					},
-					Some(Ok(Message::Binary(_) | Message::Frame(_))) => {
-						warn!("non-text message received");
+					Some(Ok(Message::Frame(_))) => {
+						warn!("raw frame received");
 
-						send(&mut conn, &Downstream::Error {
-							message: "invalid websocket frame".into(),
+						connection.send(&primary, Downstream::Error {
+							code: ErrorCode::InvalidMessage,
+							detail: Some("invalid websocket frame".into()),
 						}).await?;
 					},
 					Some(Ok(Message::Ping(_) | Message::Pong(_))) => (),
 					Some(Err(err)) => {
 						error!("websocket error: {err}");
 
-						let _ = conn.close(None).await;
+						let _ = connection.conn.close(None).await;
 
 						break
 					},